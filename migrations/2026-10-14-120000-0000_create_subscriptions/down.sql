@@ -0,0 +1,2 @@
+DROP TYPE IF EXISTS DeliveryType CASCADE;
+DROP TABLE IF EXISTS subscriptions;
\ No newline at end of file
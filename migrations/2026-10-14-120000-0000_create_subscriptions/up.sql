@@ -0,0 +1,12 @@
+CREATE TYPE DeliveryType AS ENUM (
+  'dm',
+  'thread'
+);
+
+CREATE TABLE subscriptions (
+  user_id varchar not null,
+  talkgroup integer not null,
+  delivery DeliveryType not null,
+  channel_id varchar default null,
+  primary key (user_id, talkgroup)
+);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS discovered_talkgroups;
\ No newline at end of file
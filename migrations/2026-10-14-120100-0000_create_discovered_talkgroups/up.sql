@@ -0,0 +1,7 @@
+CREATE TABLE discovered_talkgroups (
+  talkgroup integer primary key,
+  short_name varchar not null,
+  first_seen timestamptz not null,
+  last_seen timestamptz not null,
+  call_count integer not null default 1
+);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS emergencies;
\ No newline at end of file
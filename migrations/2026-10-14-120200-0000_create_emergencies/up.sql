@@ -0,0 +1,10 @@
+CREATE TABLE emergencies (
+  id bigserial primary key,
+  call_id varchar not null references calls(filename),
+  talkgroup integer not null references talkgroups(talkgroup),
+  src integer default null references sources(src),
+  short_name varchar not null,
+  declared_at timestamptz not null
+);
+
+CREATE INDEX emergencies_declared_at_idx ON emergencies (declared_at);
\ No newline at end of file
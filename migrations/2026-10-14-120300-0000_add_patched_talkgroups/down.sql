@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS calls_talkgroup_start_time_idx;
+ALTER TABLE calls DROP COLUMN IF EXISTS patched_talkgroups;
\ No newline at end of file
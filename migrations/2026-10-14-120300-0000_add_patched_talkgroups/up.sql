@@ -0,0 +1,3 @@
+ALTER TABLE calls ADD COLUMN patched_talkgroups integer[] not null default '{}';
+
+CREATE INDEX calls_talkgroup_start_time_idx ON calls (talkgroup, start_time);
\ No newline at end of file
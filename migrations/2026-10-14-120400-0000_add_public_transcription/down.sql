@@ -0,0 +1 @@
+ALTER TABLE calls DROP COLUMN IF EXISTS public_transcription;
\ No newline at end of file
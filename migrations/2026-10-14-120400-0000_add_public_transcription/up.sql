@@ -0,0 +1 @@
+ALTER TABLE calls ADD COLUMN public_transcription varchar;
\ No newline at end of file
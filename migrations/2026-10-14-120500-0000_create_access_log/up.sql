@@ -0,0 +1,10 @@
+CREATE TABLE access_log (
+  id bigserial primary key,
+  accessed_at timestamptz not null default now(),
+  principal varchar not null,
+  method varchar not null,
+  endpoint varchar not null,
+  call_ids text[] not null default '{}'
+);
+
+CREATE INDEX access_log_accessed_at_idx ON access_log (accessed_at);
\ No newline at end of file
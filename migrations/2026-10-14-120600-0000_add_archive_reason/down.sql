@@ -0,0 +1,2 @@
+ALTER TABLE calls DROP COLUMN IF EXISTS archive_reason;
+DROP TYPE IF EXISTS ArchiveReason;
\ No newline at end of file
@@ -0,0 +1,7 @@
+CREATE TYPE ArchiveReason AS ENUM (
+  'backfill',
+  'encrypted',
+  'manual'
+);
+
+ALTER TABLE calls ADD COLUMN archive_reason ArchiveReason default null;
\ No newline at end of file
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS calls_tags_idx;
+ALTER TABLE calls DROP COLUMN IF EXISTS tags;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE calls ADD COLUMN tags jsonb not null default '{}';
+
+CREATE INDEX calls_tags_idx ON calls USING gin (tags);
\ No newline at end of file
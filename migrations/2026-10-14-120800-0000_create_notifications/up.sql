@@ -0,0 +1,11 @@
+CREATE TABLE notifications (
+  id bigserial primary key,
+  call_id varchar not null,
+  destination varchar not null,
+  payload jsonb not null,
+  status integer default null,
+  error varchar default null,
+  sent_at timestamptz not null
+);
+
+CREATE INDEX notifications_call_id_idx ON notifications (call_id);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS notification_keys;
\ No newline at end of file
@@ -0,0 +1,6 @@
+CREATE TABLE notification_keys (
+  call_id varchar not null,
+  destination varchar not null,
+  claimed_at timestamptz not null,
+  primary key (call_id, destination)
+);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE compressed_transcriptions;
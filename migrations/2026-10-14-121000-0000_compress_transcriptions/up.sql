@@ -0,0 +1,9 @@
+-- Long transcriptions are stored zstd compressed here, out of the calls rows
+-- every list query reads. The calls columns hold a marker in their place.
+-- Rows stored before this are compressed by the processor in small batches
+-- once TRANSCRIPTION_COMPRESS_SIZE is set, so no one statement locks calls.
+CREATE TABLE compressed_transcriptions (
+  call_id varchar primary key references calls(filename),
+  transcription bytea not null,
+  public_transcription bytea
+);
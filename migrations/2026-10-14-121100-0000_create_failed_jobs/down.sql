@@ -0,0 +1 @@
+DROP TABLE IF EXISTS failed_jobs;
\ No newline at end of file
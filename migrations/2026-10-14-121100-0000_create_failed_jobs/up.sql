@@ -0,0 +1,17 @@
+CREATE TABLE failed_jobs (
+  id bigserial primary key,
+  json_name varchar not null,
+  metadata text not null,
+  audio_name varchar not null,
+  audio_path varchar not null,
+  tags jsonb not null,
+  version varchar default null,
+  language varchar default null,
+  archive_reason archivereason default null,
+  error varchar not null,
+  attempts integer not null,
+  failed_at timestamptz not null,
+  next_attempt_at timestamptz default null
+);
+
+CREATE INDEX failed_jobs_next_attempt_at_idx ON failed_jobs (next_attempt_at);
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE calls DROP COLUMN IF EXISTS filter_rule;
+ALTER TABLE calls DROP COLUMN IF EXISTS filter_action;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE calls ADD COLUMN filter_action varchar;
+ALTER TABLE calls ADD COLUMN filter_rule varchar;
\ No newline at end of file
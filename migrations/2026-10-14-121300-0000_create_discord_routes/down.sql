@@ -0,0 +1 @@
+DROP TABLE IF EXISTS discord_routes;
\ No newline at end of file
@@ -0,0 +1,8 @@
+CREATE TABLE discord_routes (
+  id serial primary key,
+  talkgroup integer unique default null,
+  talkgroup_group varchar unique default null,
+  webhook_url varchar not null,
+  created_at timestamptz not null default now(),
+  CHECK ((talkgroup IS NULL) <> (talkgroup_group IS NULL))
+);
\ No newline at end of file
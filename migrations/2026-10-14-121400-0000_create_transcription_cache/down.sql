@@ -0,0 +1 @@
+DROP TABLE IF EXISTS transcription_cache;
\ No newline at end of file
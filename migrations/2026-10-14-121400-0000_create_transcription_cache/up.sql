@@ -0,0 +1,11 @@
+CREATE TABLE transcription_cache (
+  sha256 varchar not null,
+  provider varchar not null,
+  language varchar not null,
+  transcription text not null,
+  hits integer not null default 0,
+  created_at timestamptz not null,
+  primary key (sha256, provider, language)
+);
+
+CREATE INDEX transcription_cache_created_at_idx ON transcription_cache (created_at);
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS systems;
\ No newline at end of file
@@ -0,0 +1,8 @@
+CREATE TABLE systems (
+  short_name varchar primary key,
+  system_type varchar not null,
+  talkgroups_file varchar default null,
+  unit_tags_file varchar default null,
+  rdio_system_id integer default null,
+  imported_at timestamptz not null default now()
+);
\ No newline at end of file
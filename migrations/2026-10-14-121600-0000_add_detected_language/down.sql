@@ -0,0 +1,6 @@
+ALTER TABLE transcription_cache DROP COLUMN IF EXISTS language_fallback;
+ALTER TABLE transcription_cache DROP COLUMN IF EXISTS language_probability;
+ALTER TABLE transcription_cache DROP COLUMN IF EXISTS detected_language;
+ALTER TABLE calls DROP COLUMN IF EXISTS language_fallback;
+ALTER TABLE calls DROP COLUMN IF EXISTS language_probability;
+ALTER TABLE calls DROP COLUMN IF EXISTS detected_language;
\ No newline at end of file
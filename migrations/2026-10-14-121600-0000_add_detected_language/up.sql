@@ -0,0 +1,9 @@
+-- language_fallback marks calls transcribed in TRANSCRIPTION_FALLBACK_LANGUAGE
+-- because auto-detection was unsure
+ALTER TABLE calls ADD COLUMN detected_language varchar;
+ALTER TABLE calls ADD COLUMN language_probability real;
+ALTER TABLE calls ADD COLUMN language_fallback boolean not null default false;
+
+ALTER TABLE transcription_cache ADD COLUMN detected_language varchar;
+ALTER TABLE transcription_cache ADD COLUMN language_probability real;
+ALTER TABLE transcription_cache ADD COLUMN language_fallback boolean not null default false;
\ No newline at end of file
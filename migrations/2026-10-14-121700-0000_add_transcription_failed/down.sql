@@ -0,0 +1 @@
+ALTER TABLE calls DROP COLUMN IF EXISTS transcription_failed;
@@ -0,0 +1,3 @@
+-- transcription_failed marks calls stored untranscribed because the
+-- transcription provider kept failing, so they can be transcribed later
+ALTER TABLE calls ADD COLUMN transcription_failed boolean not null default false;
@@ -0,0 +1,2 @@
+ALTER TABLE srclist DROP COLUMN IF EXISTS hash_version;
+ALTER TABLE freqlist DROP COLUMN IF EXISTS hash_version;
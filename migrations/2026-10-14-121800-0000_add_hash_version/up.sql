@@ -0,0 +1,4 @@
+-- hash_version records which HASH_ALGORITHM keyed each row; rows from before
+-- it was stored were keyed by DefaultHasher, version 0
+ALTER TABLE srclist ADD COLUMN hash_version smallint not null default 0;
+ALTER TABLE freqlist ADD COLUMN hash_version smallint not null default 0;
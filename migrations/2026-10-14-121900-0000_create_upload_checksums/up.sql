@@ -0,0 +1,10 @@
+-- the UploadData::checksum of the upload that stored each call, its JSON and
+-- audio digests, so an identical re-upload can be recognised
+CREATE TABLE upload_checksums (
+  checksum varchar primary key,
+  call_id varchar not null references calls(filename),
+  duplicates integer not null default 0,
+  last_duplicate_at timestamptz default null
+);
+
+CREATE INDEX upload_checksums_call_id_idx ON upload_checksums (call_id);
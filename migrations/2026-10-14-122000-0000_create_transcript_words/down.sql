@@ -0,0 +1,2 @@
+ALTER TABLE transcription_cache DROP COLUMN IF EXISTS words;
+DROP TABLE IF EXISTS transcript_words;
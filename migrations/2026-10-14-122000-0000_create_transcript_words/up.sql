@@ -0,0 +1,11 @@
+-- word timings from providers that give them, with WORD_TIMESTAMPS
+CREATE TABLE transcript_words (
+  call_id varchar not null references calls(filename),
+  position integer not null,
+  word varchar not null,
+  start_ms integer not null,
+  end_ms integer not null,
+  primary key (call_id, position)
+);
+
+ALTER TABLE transcription_cache ADD COLUMN words jsonb;
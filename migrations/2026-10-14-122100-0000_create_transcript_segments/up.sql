@@ -0,0 +1,11 @@
+-- the transcription split between the radios heard, with SPEAKER_SEGMENTS
+CREATE TABLE transcript_segments (
+  call_id varchar not null references calls(filename),
+  position integer not null,
+  src integer not null,
+  tag varchar,
+  start_ms integer not null,
+  end_ms integer not null,
+  text varchar not null,
+  primary key (call_id, position)
+);
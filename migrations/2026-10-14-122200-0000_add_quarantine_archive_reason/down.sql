@@ -0,0 +1,12 @@
+-- postgres can't drop an enum value, so the type is rebuilt without it
+UPDATE calls SET archive_reason = 'manual' WHERE archive_reason = 'quarantine';
+UPDATE failed_jobs SET archive_reason = 'manual' WHERE archive_reason = 'quarantine';
+ALTER TYPE ArchiveReason RENAME TO ArchiveReason_old;
+CREATE TYPE ArchiveReason AS ENUM (
+  'backfill',
+  'encrypted',
+  'manual'
+);
+ALTER TABLE calls ALTER COLUMN archive_reason TYPE ArchiveReason USING archive_reason::text::ArchiveReason;
+ALTER TABLE failed_jobs ALTER COLUMN archive_reason TYPE ArchiveReason USING archive_reason::text::ArchiveReason;
+DROP TYPE ArchiveReason_old;
@@ -0,0 +1 @@
+ALTER TYPE ArchiveReason ADD VALUE IF NOT EXISTS 'quarantine';
@@ -0,0 +1 @@
+ALTER TABLE upload_checksums DROP COLUMN IF EXISTS stored_at;
@@ -0,0 +1,4 @@
+-- when the call was stored, so reconcile can tell an upload still being
+-- committed from one whose audio is gone. Calls stored before this count as old.
+ALTER TABLE upload_checksums ADD COLUMN stored_at timestamptz NOT NULL DEFAULT 'epoch';
+ALTER TABLE upload_checksums ALTER COLUMN stored_at SET DEFAULT now();
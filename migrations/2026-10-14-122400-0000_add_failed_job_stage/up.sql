@@ -0,0 +1,4 @@
+-- which stage of the job failed: a job that failed notifying has its call
+-- stored, with the audio at audio_path where it was committed
+CREATE TYPE jobstage AS ENUM ('store', 'notify');
+ALTER TABLE failed_jobs ADD COLUMN stage jobstage not null default 'store';
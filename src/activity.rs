@@ -0,0 +1,104 @@
+use crate::common::EmbedFooter;
+use crate::config::ProcessorConfig;
+use crate::model::AudioMetadata;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+// Start times of the calls each talkgroup carried in the last ACTIVITY_WINDOW,
+// oldest first, for the embed footer
+#[derive(Debug)]
+pub struct TalkgroupActivity {
+    window: TimeDelta,
+    calls: HashMap<i32, VecDeque<(DateTime<Utc>, String)>>,
+}
+
+impl TalkgroupActivity {
+    pub fn new(window: Duration) -> Self {
+        TalkgroupActivity {
+            window: TimeDelta::from_std(window).unwrap_or(TimeDelta::MAX),
+            calls: HashMap::new(),
+        }
+    }
+
+    fn evict(&mut self) {
+        let since = Utc::now() - self.window;
+        self.calls.retain(|_, calls| {
+            while calls.front().is_some_and(|(start, _)| *start < since) {
+                calls.pop_front();
+            }
+            !calls.is_empty()
+        });
+    }
+
+    // A call stored again, as a retried upload is, is only counted once
+    pub fn record(&mut self, m: &AudioMetadata) {
+        self.evict();
+        let calls = self.calls.entry(m.talkgroup.talkgroup).or_default();
+        if calls.iter().any(|(_, name)| *name == m.call.filename) {
+            return;
+        }
+        let at = calls
+            .iter()
+            .rposition(|(start, _)| *start <= m.call.start_time)
+            .map_or(0, |i| i + 1);
+        calls.insert(at, (m.call.start_time, m.call.filename.clone()));
+    }
+
+    // Where the call falls among its talkgroup's calls in the window ending
+    // at its start, or None when it wasn't recorded
+    pub fn position(&self, m: &AudioMetadata) -> Option<usize> {
+        let calls = self.calls.get(&m.talkgroup.talkgroup)?;
+        let since = m.call.start_time - self.window;
+        let mut position = 0;
+        for (start, name) in calls {
+            if *start >= since && *start <= m.call.start_time {
+                position += 1;
+            }
+            if *name == m.call.filename {
+                return Some(position);
+            }
+        }
+        None
+    }
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+fn window(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        s if s % 3600 == 0 => format!("{} h", s / 3600),
+        s if s % 60 == 0 => format!("{} min", s / 60),
+        s => format!("{} s", s),
+    }
+}
+
+// "3rd call on this talkgroup in the last 10 min", with ACTIVITY_WINDOW set
+pub fn footer(c: &ProcessorConfig, m: &AudioMetadata) -> Option<EmbedFooter> {
+    let activity = c.activity.as_ref()?;
+    let window_length = c.env.activity_window?;
+    let position = activity
+        .lock()
+        .expect("talkgroup activity poisoned")
+        .position(m)?;
+    Some(EmbedFooter {
+        text: format!(
+            "{} call on this talkgroup in the last {}",
+            ordinal(position),
+            window(window_length)
+        ),
+    })
+}
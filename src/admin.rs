@@ -0,0 +1,565 @@
+use crate::audit;
+use crate::auth::{Access, require_admin};
+use crate::capture::{self, Capture};
+use crate::config::{self, ProcessorConfig, ReloadReport};
+use crate::dead_letter;
+use crate::discord_routing;
+use crate::discovery::{self, DiscoveryReportEntry};
+use crate::error::Result;
+use crate::export::{self, ExportFormat, ExportReport};
+use crate::integrity::{self, IntegrityReport};
+use crate::jobs::{self, JobStatus, JobView};
+use crate::model::{DiscordRoute, FailedJob, Notification, System};
+use crate::notifications;
+use crate::reconcile::{ReconcileReport, reconcile};
+use crate::rename::{self, RenameReport, Renames};
+use crate::renotify::{self, RenotifyReport};
+use crate::retention::{self, RetentionReport};
+use crate::retranscribe::{self, RetranscribeReport, Selection};
+use crate::switches::{SwitchState, SwitchUpdate};
+use crate::testcall::{self, TestCall, TestCallReport};
+use crate::tr_config;
+use crate::transcription_cache::{self, CacheStats, Invalidated};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ReconcileParams {
+    repair: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntegrityParams {
+    repair: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetentionParams {
+    dry_run: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameParams {
+    dry_run: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestCallParams {
+    talkgroup: i32,
+    system: Option<String>,
+    duration: Option<u32>,
+    emergency: Option<bool>,
+    src: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiscoveryParams {
+    include_resolved: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FailedJobParams {
+    dead: Option<bool>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobParams {
+    status: Option<JobStatus>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationParams {
+    call: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    format: ExportFormat,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenotifyParams {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    // comma-separated notifier names, every registered notifier if omitted
+    destinations: Option<String>,
+    dry_run: Option<bool>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetranscribeParams {
+    file: Option<String>,
+    talkgroup: Option<i32>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    failed: Option<bool>,
+    dry_run: Option<bool>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessLogParams {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    principal: Option<String>,
+    limit: Option<i64>,
+    format: Option<String>,
+}
+
+pub async fn reconcile_report(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<Option<ReconcileReport>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(config.reconcile_report.read().await.clone()))
+}
+
+pub async fn reconcile_now(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<ReconcileParams>,
+) -> Result<Json<ReconcileReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let repair = params.repair.unwrap_or(config.env.reconcile_repair);
+    let report = reconcile(&config, repair).await?;
+    *config.reconcile_report.write().await = Some(report.clone());
+
+    Ok(Json(report))
+}
+
+pub async fn integrity_check(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<IntegrityParams>,
+) -> Result<Json<IntegrityReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let repair = params.repair.unwrap_or(config.env.integrity_repair);
+    Ok(Json(integrity::check(&config, repair)?))
+}
+
+pub async fn run_retention(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<RetentionParams>,
+) -> Result<Json<RetentionReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let dry_run = params.dry_run.unwrap_or(config.env.retention_dry_run);
+    Ok(Json(retention::run(&config, dry_run).await?))
+}
+
+// As SIGHUP: swaps in FILTER_* and the notifiers from CONFIG_FILE, or answers
+// 500 with every problem and keeps the running configuration
+pub async fn reload_config(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<ReloadReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    Ok(Json(config::reload(&config)?))
+}
+
+pub async fn discovered_talkgroups(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<DiscoveryParams>,
+) -> Result<Json<Vec<DiscoveryReportEntry>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(discovery::report(
+        &config,
+        params.include_resolved.unwrap_or(false),
+    )?))
+}
+
+pub async fn access_log(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<AccessLogParams>,
+) -> Result<Response> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let entries = audit::export(
+        &config,
+        params.since,
+        params.until,
+        params.principal.as_deref(),
+        params.limit,
+    )?;
+
+    Ok(match params.format.as_deref() {
+        Some("csv") => (
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            audit::to_csv(&entries),
+        )
+            .into_response(),
+        _ => Json(entries).into_response(),
+    })
+}
+
+pub async fn list_notifications(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<NotificationParams>,
+) -> Result<Json<Vec<Notification>>> {
+    require_admin(&headers, &config)?;
+
+    let entries = notifications::list(&config, params.call.as_deref(), params.limit)?;
+    audit::record(
+        &config,
+        &Access::Admin,
+        &method,
+        &uri,
+        entries.iter().map(|n| n.call_id.clone()),
+    );
+
+    Ok(Json(entries))
+}
+
+pub async fn export_calls(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<ExportParams>,
+) -> Result<Json<ExportReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    Ok(Json(
+        export::export(
+            &config,
+            params.format,
+            params.since,
+            params.until,
+            params.limit,
+        )
+        .await?,
+    ))
+}
+
+pub async fn renotify_calls(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<RenotifyParams>,
+) -> Result<Json<RenotifyReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    Ok(Json(
+        renotify::renotify(
+            &config,
+            params.since,
+            params.until,
+            params.destinations.as_deref(),
+            params.dry_run.unwrap_or(false),
+            params.limit,
+        )
+        .await?,
+    ))
+}
+
+pub async fn retranscribe_calls(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<RetranscribeParams>,
+) -> Result<Json<RetranscribeReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, params.file.clone());
+
+    let selection = Selection {
+        file: params.file,
+        talkgroup: params.talkgroup,
+        since: params.since,
+        until: params.until,
+        failed: params.failed.unwrap_or(false),
+        limit: params.limit,
+    };
+    Ok(Json(
+        retranscribe::retranscribe(&config, &selection, params.dry_run.unwrap_or(false)).await?,
+    ))
+}
+
+pub async fn list_discord_routes(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<Vec<DiscordRoute>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(discord_routing::list(&config)?))
+}
+
+// Applies a rename-groups mapping, for when a fleetmap is reorganized
+pub async fn rename_groups(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<RenameParams>,
+    Json(renames): Json<Renames>,
+) -> Result<Json<RenameReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(rename::apply(
+        &config,
+        &renames,
+        params.dry_run.unwrap_or(false),
+    )?))
+}
+
+// Sends a made-up call through the pipeline, to check filters and
+// destinations end to end
+pub async fn test_call(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<TestCallParams>,
+) -> Result<Json<TestCallReport>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    let test = TestCall {
+        talkgroup: params.talkgroup,
+        short_name: params.system,
+        duration_secs: params.duration,
+        emergency: params.emergency.unwrap_or(false),
+        src: params.src,
+    };
+    Ok(Json(testcall::inject(&config, &test).await?))
+}
+
+// Systems recorded by import-tr-config
+pub async fn list_systems(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<Vec<System>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(tr_config::list(&config)?))
+}
+
+pub async fn set_discord_route(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Json(route): Json<DiscordRoute>,
+) -> Result<Json<DiscordRoute>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(discord_routing::upsert(&config, &route)?))
+}
+
+pub async fn delete_discord_route(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<i32>,
+) -> Result<StatusCode> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    discord_routing::remove(&config, id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_switches(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<SwitchState>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(config.switches.state()))
+}
+
+pub async fn set_switches(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Json(update): Json<SwitchUpdate>,
+) -> Result<Json<SwitchState>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(config.switches.apply(&update)))
+}
+
+pub async fn list_failed_jobs(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<FailedJobParams>,
+) -> Result<Json<Vec<FailedJob>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(dead_letter::list(&config, params.dead, params.limit)?))
+}
+
+pub async fn requeue_failed_job(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<i64>,
+) -> Result<(StatusCode, String)> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    dead_letter::requeue_now(&config, id).await?;
+    Ok((StatusCode::ACCEPTED, "Failed job requeued".to_string()))
+}
+
+// Uploads queued, being processed and kept after failing
+pub async fn list_jobs(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<JobParams>,
+) -> Result<Json<Vec<JobView>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(jobs::list(&config, params.status, params.limit)?))
+}
+
+pub async fn retry_job(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, String)> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    jobs::retry(&config, &id).await?;
+    Ok((StatusCode::ACCEPTED, "Job requeued".to_string()))
+}
+
+pub async fn cancel_job(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, String)> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    jobs::cancel(&config, &id).await?;
+    Ok((StatusCode::OK, "Job cancelled".to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheInvalidateParams {
+    sha256: Option<String>,
+    before: Option<DateTime<Utc>>,
+}
+
+pub async fn transcription_cache_stats(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<CacheStats>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(transcription_cache::stats(&config)?))
+}
+
+// With no parameters every cached transcription is dropped
+pub async fn invalidate_transcription_cache(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<CacheInvalidateParams>,
+) -> Result<Json<Invalidated>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    let deleted =
+        transcription_cache::invalidate(&config, params.sha256.as_deref(), params.before)?;
+    Ok(Json(Invalidated { deleted }))
+}
+
+pub async fn list_captures(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Result<Json<Vec<Capture>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    Ok(Json(capture::list(&config).await?))
+}
+
+pub async fn replay_capture(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, String)> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    capture::replay(&config, &id).await
+}
+
+pub async fn delete_capture(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+    capture::remove(&config, &id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,97 @@
+use crate::config::ProcessorConfig;
+use crate::error::Result;
+use crate::trace;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::BTreeMap, time::Duration};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    fn label(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+// An operational alert in the shape Alertmanager's /api/v2/alerts accepts.
+// Alerts resolve on their own at `endsAt` unless raised again before then.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostableAlert {
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+    starts_at: DateTime<Utc>,
+    ends_at: DateTime<Utc>,
+}
+
+pub struct Alert {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub summary: String,
+    pub description: String,
+    pub labels: Vec<(&'static str, String)>,
+    pub expires_in: Duration,
+}
+
+impl Alert {
+    fn postable(&self) -> PostableAlert {
+        let now = Utc::now();
+        let mut labels = BTreeMap::from([
+            ("alertname".to_string(), self.name.to_string()),
+            ("service".to_string(), "trunk-processor".to_string()),
+            ("severity".to_string(), self.severity.label().to_string()),
+        ]);
+        labels.extend(self.labels.iter().map(|(k, v)| (k.to_string(), v.clone())));
+
+        PostableAlert {
+            labels,
+            annotations: BTreeMap::from([
+                ("summary".to_string(), self.summary.clone()),
+                ("description".to_string(), self.description.clone()),
+            ]),
+            starts_at: now,
+            ends_at: now + self.expires_in,
+        }
+    }
+}
+
+async fn post(c: &ProcessorConfig, url: &str, alert: &Alert) -> Result<()> {
+    trace::inject(c.http_client.post(url))
+        .json(&[alert.postable()])
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// Sends an alert to ALERTMANAGER_URL when configured; failures are logged
+// rather than returned so ops alerting never breaks the caller
+pub async fn raise(c: &ProcessorConfig, alert: Alert) {
+    let Some(url) = &c.env.alertmanager_url else {
+        return;
+    };
+    if let Err(e) = post(c, url, &alert).await {
+        warn!(alert = alert.name, error = %e, "Failed to send alert to Alertmanager");
+    }
+}
+
+// Raises an alert without waiting on Alertmanager, for callers on a request
+// or upload path
+pub fn spawn(c: &ProcessorConfig, alert: Alert) {
+    if c.env.alertmanager_url.is_none() {
+        return;
+    }
+    let c = c.clone();
+    c.background
+        .clone()
+        .spawn(async move { raise(&c, alert).await });
+}
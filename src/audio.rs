@@ -0,0 +1,173 @@
+use crate::audit;
+use crate::auth::read_access;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::query::load_call;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use object_store::{GetOptions, GetRange, ObjectStore, path::Path as ObjectPath};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Deserialize)]
+pub struct CallAudioParams {
+    redirect: Option<bool>,
+}
+
+// A single `bytes=` range. Anything else is ignored and the whole object sent,
+// which a client has to accept.
+fn parse_range(headers: &HeaderMap) -> Option<GetRange> {
+    let value = headers.get(header::RANGE)?.to_str().ok()?;
+    let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+    match (start.trim(), end.trim()) {
+        ("", "") => None,
+        ("", suffix) => Some(GetRange::Suffix(suffix.parse().ok()?)),
+        (start, "") => Some(GetRange::Offset(start.parse().ok()?)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            (start <= end).then(|| GetRange::Bounded(start..end.saturating_add(1)))
+        }
+    }
+}
+
+fn not_satisfiable(size: u64) -> Response {
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+    )
+        .into_response()
+}
+
+// A presigned URL for a stored call's audio to put in notifications, with
+// NOTIFICATION_AUDIO_URLS. A failure to sign only drops the link.
+pub async fn notification_url(c: &ProcessorConfig, filename: &str) -> Option<String> {
+    if !c.env.notification_audio_urls {
+        return None;
+    }
+    let signer = c.signer.as_ref()?;
+    let signed = match ObjectPath::parse(filename) {
+        Ok(location) => {
+            signer
+                .signed_url(Method::GET, &location, c.env.notification_audio_url_expiry)
+                .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    match signed {
+        Ok(url) => Some(url.to_string()),
+        Err(e) => {
+            warn!(file = %filename, error = %e, "Failed to presign audio URL for notifications");
+            None
+        }
+    }
+}
+
+async fn redirect(c: &ProcessorConfig, location: &ObjectPath) -> Result<Response> {
+    let signer = c.signer.as_ref().ok_or_else(|| {
+        Error::InvalidRequest("the storage backend cannot sign audio URLs".to_string())
+    })?;
+    let url = signer
+        .signed_url(Method::GET, location, c.env.audio_url_expiry)
+        .await?;
+
+    Ok((
+        StatusCode::TEMPORARY_REDIRECT,
+        [
+            (header::LOCATION, url.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+async fn stream(
+    c: &ProcessorConfig,
+    location: &ObjectPath,
+    name: &str,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let range = match parse_range(headers) {
+        Some(range) => {
+            let size = c.s3_client.head(location).await?.size;
+            match range.as_range(size) {
+                Ok(range) => Some(range),
+                Err(_) => return Ok(not_satisfiable(size)),
+            }
+        }
+        None => None,
+    };
+
+    let result = c
+        .s3_client
+        .get_opts(
+            location,
+            GetOptions {
+                range: range.clone().map(GetRange::from),
+                ..Default::default()
+            },
+        )
+        .await?;
+    let size = result.meta.size;
+    let served = result.range.clone();
+    let e_tag = result.meta.e_tag.clone();
+    let body = Body::from_stream(result.into_stream());
+
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("audio/mp4"));
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::CONTENT_LENGTH, (served.end - served.start).into());
+    if let Ok(disposition) = format!("inline; filename=\"{}\"", name).parse() {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    if let Some(e_tag) = e_tag.and_then(|e| e.parse().ok()) {
+        headers.insert(header::ETAG, e_tag);
+    }
+    if range.is_some() {
+        headers.insert(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", served.start, served.end - 1, size)
+                .parse()
+                .expect("content range is a valid header"),
+        );
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    }
+    Ok(response)
+}
+
+// A call's audio, streamed through the processor or, with AUDIO_REDIRECT or
+// ?redirect=true, as a redirect to a presigned URL valid for AUDIO_URL_EXPIRY
+pub async fn call_audio(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+    Query(params): Query<CallAudioParams>,
+) -> Result<Response> {
+    let access = read_access(&headers, &config)?;
+    let call = load_call(&config, &access, &id)?;
+    audit::record(&config, &access, &method, &uri, [call.filename.clone()]);
+
+    let location = ObjectPath::parse(&call.filename)?;
+    let name = call.filename.rsplit('/').next().unwrap_or(&call.filename);
+    let response = if params.redirect.unwrap_or(config.env.audio_redirect) {
+        info!(call = %call.filename, "Redirecting to presigned call audio");
+        redirect(&config, &location).await
+    } else {
+        debug!(call = %call.filename, "Streaming call audio");
+        stream(&config, &location, name, &headers).await
+    };
+
+    match response {
+        Err(Error::S3Upload(object_store::Error::NotFound { .. })) => {
+            Err(Error::NotFound(format!("audio for call {}", id)))
+        }
+        response => response,
+    }
+}
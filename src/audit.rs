@@ -0,0 +1,164 @@
+use crate::auth::Access;
+use crate::common::ago;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::AccessLogEntry;
+use crate::schema::access_log;
+
+use axum::http::{Method, Uri};
+use chrono::{DateTime, Utc};
+use diesel::{delete, insert_into, prelude::*};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const DEFAULT_EXPORT_LIMIT: i64 = 1000;
+const MAX_EXPORT_LIMIT: i64 = 100_000;
+
+pub fn record<I>(c: &ProcessorConfig, access: &Access, method: &Method, uri: &Uri, call_ids: I)
+where
+    I: IntoIterator<Item = String>,
+{
+    if !c.env.access_log {
+        return;
+    }
+    let Some(principal) = access.principal() else {
+        return;
+    };
+
+    insert(
+        c,
+        AccessLogEntry {
+            id: 0,
+            accessed_at: Utc::now(),
+            principal: principal.to_string(),
+            method: method.to_string(),
+            endpoint: uri.to_string(),
+            call_ids: call_ids.into_iter().map(Some).collect(),
+        },
+    );
+}
+
+// Records calls touched by a background task, such as retention, under the
+// task's name rather than an API principal
+pub fn record_task<I>(c: &ProcessorConfig, task: &str, action: &str, endpoint: &str, call_ids: I)
+where
+    I: IntoIterator<Item = String>,
+{
+    if !c.env.access_log {
+        return;
+    }
+    insert(
+        c,
+        AccessLogEntry {
+            id: 0,
+            accessed_at: Utc::now(),
+            principal: task.to_string(),
+            method: action.to_string(),
+            endpoint: endpoint.to_string(),
+            call_ids: call_ids.into_iter().map(Some).collect(),
+        },
+    );
+}
+
+fn insert(c: &ProcessorConfig, entry: AccessLogEntry) {
+    let result = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))
+        .and_then(|mut connection| {
+            insert_into(access_log::table)
+                .values(&entry)
+                .execute(&mut connection)
+                .map_err(|e| Error::Database(e.to_string()))
+        });
+
+    if let Err(e) = result {
+        warn!(principal = %entry.principal, endpoint = %entry.endpoint, error = %e, "Failed to record API access");
+    }
+}
+
+pub fn export(
+    c: &ProcessorConfig,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    principal: Option<&str>,
+    limit: Option<i64>,
+) -> Result<Vec<AccessLogEntry>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = access_log::table
+        .select(AccessLogEntry::as_select())
+        .order(access_log::accessed_at.desc())
+        .limit(
+            limit
+                .unwrap_or(DEFAULT_EXPORT_LIMIT)
+                .clamp(1, MAX_EXPORT_LIMIT),
+        )
+        .into_boxed();
+
+    if let Some(since) = since {
+        query = query.filter(access_log::accessed_at.ge(since));
+    }
+    if let Some(until) = until {
+        query = query.filter(access_log::accessed_at.lt(until));
+    }
+    if let Some(principal) = principal {
+        query = query.filter(access_log::principal.eq(principal.to_string()));
+    }
+
+    query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(entries: &[AccessLogEntry]) -> String {
+    let mut out = String::from("id,accessed_at,principal,method,endpoint,call_ids\n");
+    for e in entries {
+        let call_ids: Vec<&str> = e.call_ids.iter().flatten().map(String::as_str).collect();
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            e.id,
+            e.accessed_at.to_rfc3339(),
+            csv_field(&e.principal),
+            csv_field(&e.method),
+            csv_field(&e.endpoint),
+            csv_field(&call_ids.join(";")),
+        ));
+    }
+    out
+}
+
+fn prune(c: &ProcessorConfig, retention: Duration) -> Result<usize> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    delete(access_log::table.filter(access_log::accessed_at.lt(ago(retention))))
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+pub async fn run_periodic(c: ProcessorConfig, retention: Duration, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+        match prune(&c, retention) {
+            Ok(0) => {}
+            Ok(removed) => info!(removed = removed, "Pruned expired access log entries"),
+            Err(e) => error!(error = %e, "Failed to prune access log"),
+        }
+    }
+}
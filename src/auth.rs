@@ -0,0 +1,115 @@
+use crate::config::{ProcessorConfig, ReadKey, UploadKey};
+use crate::error::{Error, Result};
+
+use axum::http::HeaderMap;
+use ring::{hmac, rand::SystemRandom};
+use std::sync::LazyLock;
+
+// Keys are compared through an HMAC under this, as ring's verify takes the
+// same time however much of the tag matches
+static COMPARE_KEY: LazyLock<hmac::Key> = LazyLock::new(|| {
+    hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+        .expect("failed to generate key comparison secret")
+});
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    Admin,
+    Reader(String, Option<Vec<String>>),
+    Public(Vec<String>),
+}
+
+impl Access {
+    pub fn groups(&self) -> Option<&[String]> {
+        match self {
+            Access::Admin => None,
+            Access::Reader(_, groups) => groups.as_deref(),
+            Access::Public(groups) => Some(groups),
+        }
+    }
+    pub fn is_public(&self) -> bool {
+        matches!(self, Access::Public(_))
+    }
+    pub fn principal(&self) -> Option<&str> {
+        match self {
+            Access::Admin => Some("admin"),
+            Access::Reader(id, _) => Some(id),
+            Access::Public(_) => None,
+        }
+    }
+}
+
+// Compares a provided key or token in time that doesn't depend on where it
+// first differs, so it can't be guessed a byte at a time
+pub fn key_matches(provided: Option<&str>, key: &str) -> bool {
+    let tag = hmac::sign(&COMPARE_KEY, key.as_bytes());
+    provided.is_some_and(|p| hmac::verify(&COMPARE_KEY, p.as_bytes(), tag.as_ref()).is_ok())
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+pub fn read_access(headers: &HeaderMap, c: &ProcessorConfig) -> Result<Access> {
+    if require_admin(headers, c).is_ok() {
+        return Ok(Access::Admin);
+    }
+
+    let provided = bearer_token(headers);
+    if let Some(ReadKey { id, groups, .. }) =
+        c.read_keys.iter().find(|k| key_matches(provided, &k.key))
+    {
+        return Ok(Access::Reader(id.clone(), groups.clone()));
+    }
+
+    if c.env.public_mode {
+        Ok(Access::Public(
+            c.env.public_groups.clone().unwrap_or_default(),
+        ))
+    } else {
+        Err(Error::Unauthorized("Invalid API key".to_string()))
+    }
+}
+
+pub fn require_private_read(headers: &HeaderMap, c: &ProcessorConfig) -> Result<Access> {
+    let access = read_access(headers, c)?;
+    if access.is_public() {
+        return Err(Error::Unauthorized(
+            "Endpoint is not available in public mode".to_string(),
+        ));
+    }
+    Ok(access)
+}
+
+pub fn require_admin(headers: &HeaderMap, c: &ProcessorConfig) -> Result<()> {
+    let token = c
+        .env
+        .admin_token
+        .as_deref()
+        .ok_or_else(|| Error::Unauthorized("Admin endpoints are disabled".to_string()))?;
+
+    if !key_matches(bearer_token(headers), token) {
+        return Err(Error::Unauthorized("Invalid admin token".to_string()));
+    }
+    Ok(())
+}
+
+// The API_KEYS entry an upload's key matches. Uploads are open, and this is
+// None, when API_KEYS is unset.
+pub fn upload_key<'a>(
+    c: &'a ProcessorConfig,
+    provided: Option<&str>,
+) -> Result<Option<&'a UploadKey>> {
+    if c.upload_keys.is_empty() {
+        return Ok(None);
+    }
+    let provided = provided.ok_or_else(|| Error::InvalidApiKey("missing API key".to_string()))?;
+    c.upload_keys
+        .iter()
+        .find(|k| key_matches(Some(provided), &k.key))
+        .map(Some)
+        .ok_or_else(|| Error::InvalidApiKey("unknown API key".to_string()))
+}
@@ -0,0 +1,210 @@
+use crate::common::{UploadData, UploadedFile};
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::ArchiveReason;
+use crate::switches::SwitchUpdate;
+use crate::upload::ingest;
+use crate::workspace;
+
+use axum::body::Bytes;
+use flate2::read::GzDecoder;
+use futures::{StreamExt, stream};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tempfile::TempDir;
+use tracing::{info, warn};
+
+// Audio trunk-recorder may write beside a call's JSON, in order of preference
+const AUDIO_EXTENSIONS: [&str; 3] = ["m4a", "wav", "mp3"];
+
+#[derive(Debug, Default, Serialize)]
+pub struct BackfillReport {
+    pub calls_read: usize,
+    pub imported: usize,
+    // JSON files with no audio beside them
+    pub skipped: Vec<String>,
+    pub failed: Vec<String>,
+    pub duration_ms: u128,
+}
+
+// Every call JSON under `dir`, in path order so calls go in roughly as they
+// were recorded
+fn find_calls(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::Import(format!("could not read {}: {}", dir.display(), e)))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::Import(format!("could not read {}: {}", dir.display(), e)))?
+            .path();
+        if path.is_dir() {
+            find_calls(&path, found)?;
+        } else if path.extension().is_some_and(|e| e == "json") {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn unpack_tar(archive: impl Read, dir: &Path) -> std::io::Result<()> {
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        // links could point the import at files outside the archive
+        if entry.header().entry_type().is_file() {
+            entry.unpack_in(dir)?;
+        }
+    }
+    Ok(())
+}
+
+fn unpack_zip(archive: File, dir: &Path) -> zip::result::ZipResult<()> {
+    let mut archive = zip::ZipArchive::new(archive)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(name) = entry.enclosed_name().filter(|_| entry.is_file()) else {
+            continue;
+        };
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut entry, &mut File::create(path)?)?;
+    }
+    Ok(())
+}
+
+// Unpacks a .tar, .tar.gz, .tgz or .zip of trunk-recorder output into a
+// directory under WORKSPACE_DIR, removed once the import is done
+fn unpack(c: &ProcessorConfig, archive: &Path) -> Result<TempDir> {
+    let failed =
+        |e: String| Error::Import(format!("could not unpack {}: {}", archive.display(), e));
+    let name = archive.to_string_lossy().to_lowercase();
+    let root = workspace::root(c);
+    std::fs::create_dir_all(&root).map_err(|e| failed(e.to_string()))?;
+    let dir = tempfile::Builder::new()
+        .prefix(&format!("{}backfill-", workspace::PREFIX))
+        .tempdir_in(root)
+        .map_err(|e| failed(e.to_string()))?;
+    let file = File::open(archive).map_err(|e| failed(e.to_string()))?;
+
+    if name.ends_with(".zip") {
+        unpack_zip(file, dir.path()).map_err(|e| failed(e.to_string()))?;
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        unpack_tar(GzDecoder::new(file), dir.path()).map_err(|e| failed(e.to_string()))?;
+    } else if name.ends_with(".tar") {
+        unpack_tar(file, dir.path()).map_err(|e| failed(e.to_string()))?;
+    } else {
+        return Err(Error::Import(format!(
+            "{} is not a directory, .tar, .tar.gz, .tgz or .zip",
+            archive.display()
+        )));
+    }
+    Ok(dir)
+}
+
+fn read(path: &Path) -> Result<UploadedFile> {
+    let data = std::fs::read(path)
+        .map_err(|e| Error::Import(format!("could not read {}: {}", path.display(), e)))?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(UploadedFile::in_memory(name, Bytes::from(data)))
+}
+
+async fn import_call(
+    c: &ProcessorConfig,
+    json: &Path,
+    archive: Option<ArchiveReason>,
+) -> Result<bool> {
+    let Some(audio) = AUDIO_EXTENSIONS
+        .iter()
+        .map(|ext| json.with_extension(ext))
+        .find(|path| path.is_file())
+    else {
+        warn!(file = %json.display(), "Call has no audio, skipping");
+        return Ok(false);
+    };
+
+    let mut files = UploadData {
+        json: read(json)?,
+        audio: read(&audio)?,
+        tags: [("imported".to_string(), "trunk-recorder".to_string())].into(),
+        version: None,
+        language: None,
+    };
+    ingest(c, &mut files, archive).await?;
+    Ok(true)
+}
+
+// Imports a directory or archive of trunk-recorder output, QUEUE_WORKERS
+// calls at a time. Calls are archived as a backfill unless `transcribe` is
+// set, when they go through the filters and transcription as uploads do.
+// Nothing is notified either way, as the calls are long over.
+pub async fn import(
+    c: &ProcessorConfig,
+    source: &Path,
+    transcribe: bool,
+) -> Result<BackfillReport> {
+    let start = Instant::now();
+    info!(source = %source.display(), transcribe, "Starting backfill import");
+
+    let unpacked = match source.is_dir() {
+        true => None,
+        false => Some(unpack(c, source)?),
+    };
+    let dir = unpacked.as_ref().map_or(source, |d| d.path());
+
+    c.switches.apply(&SwitchUpdate {
+        notifications: Some(false),
+        ..Default::default()
+    });
+    let archive = (!transcribe).then_some(ArchiveReason::Backfill);
+
+    let mut calls = Vec::new();
+    find_calls(dir, &mut calls)?;
+    calls.sort();
+    let mut report = BackfillReport {
+        calls_read: calls.len(),
+        ..Default::default()
+    };
+
+    let mut results = stream::iter(calls)
+        .map(|json| async move {
+            let result = import_call(c, &json, archive).await;
+            (json, result)
+        })
+        .buffer_unordered(c.env.queue_workers.max(1));
+    while let Some((json, result)) = results.next().await {
+        // calls from an archive are named by their path inside it
+        let name = match &unpacked {
+            Some(_) => json.strip_prefix(dir).unwrap_or(&json),
+            None => &json,
+        }
+        .display()
+        .to_string();
+        match result {
+            Ok(true) => report.imported += 1,
+            Ok(false) => report.skipped.push(name),
+            Err(e) => {
+                warn!(file = %name, error = %e, "Failed to import call");
+                report.failed.push(name);
+            }
+        }
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        read = report.calls_read,
+        imported = report.imported,
+        skipped = report.skipped.len(),
+        failed = report.failed.len(),
+        duration_ms = report.duration_ms,
+        "Backfill import completed"
+    );
+    Ok(report)
+}
@@ -0,0 +1,154 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::upload::handle_upload;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, FromRequest, Multipart, Request},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use object_store::{ObjectStore, PutPayload, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Reverse, collections::BTreeMap};
+use tracing::{info, warn};
+
+// Not kept with a capture: credentials, and the length of a body that is
+// stored alongside anyway
+const DROPPED_HEADERS: [&str; 3] = ["authorization", "cookie", "content-length"];
+
+// An upload that failed before it was queued, kept under CAPTURE_PREFIX as
+// <id>/capture.json beside the raw multipart body in <id>/request
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Capture {
+    pub id: String,
+    pub received_at: DateTime<Utc>,
+    pub error: String,
+    pub size: usize,
+    pub headers: BTreeMap<String, String>,
+}
+
+fn location(c: &ProcessorConfig, id: &str, name: &str) -> Result<Path> {
+    // ids are the capture time in microseconds
+    if id.parse::<i64>().is_err() {
+        return Err(Error::NotFound(format!("capture {}", id)));
+    }
+    Ok(Path::parse(format!(
+        "{}/{}/{}",
+        c.env.capture_prefix, id, name
+    ))?)
+}
+
+// A bad key or a full queue is not a bug to replay past
+pub fn worth_keeping(e: &Error) -> bool {
+    !matches!(
+        e,
+        Error::Unauthorized(_) | Error::InvalidApiKey(_) | Error::Unavailable(_)
+    )
+}
+
+// Parses a body already read into memory, within UPLOAD_BODY_LIMIT
+pub async fn multipart(c: &ProcessorConfig, headers: HeaderMap, body: Bytes) -> Result<Multipart> {
+    let mut request = Request::new(Body::from(body));
+    *request.headers_mut() = headers;
+    DefaultBodyLimit::disable().apply(&mut request);
+    Multipart::from_request(request, c)
+        .await
+        .map_err(|e| Error::InvalidRequest(e.body_text()))
+}
+
+pub async fn save(c: &ProcessorConfig, headers: &HeaderMap, body: Bytes, e: &Error) {
+    let id = Utc::now().timestamp_micros().to_string();
+    let capture = Capture {
+        id: id.clone(),
+        received_at: Utc::now(),
+        error: e.to_string(),
+        size: body.len(),
+        headers: headers
+            .iter()
+            .filter(|(name, _)| !DROPPED_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+    };
+
+    let result = async {
+        let json = serde_json::to_vec_pretty(&capture)?;
+        c.s3_client
+            .put(&location(c, &id, "request")?, PutPayload::from(body))
+            .await?;
+        c.s3_client
+            .put(&location(c, &id, "capture.json")?, PutPayload::from(json))
+            .await?;
+        Result::Ok(())
+    }
+    .await;
+    match result {
+        Ok(()) => info!(id = %id, error = %capture.error, "Captured failed upload"),
+        Err(err) => warn!(error = %err, "Failed to capture failed upload"),
+    }
+}
+
+pub async fn list(c: &ProcessorConfig) -> Result<Vec<Capture>> {
+    let prefix = Path::parse(&c.env.capture_prefix)?;
+    let objects: Vec<_> = c.s3_client.list(Some(&prefix)).try_collect().await?;
+
+    let mut captures = Vec::new();
+    for object in objects {
+        if object.location.filename() != Some("capture.json") {
+            continue;
+        }
+        let data = c.s3_client.get(&object.location).await?.bytes().await?;
+        match serde_json::from_slice::<Capture>(&data) {
+            Ok(capture) => captures.push(capture),
+            Err(e) => warn!(path = %object.location, error = %e, "Skipping unreadable capture"),
+        }
+    }
+    captures.sort_by_key(|capture| Reverse(capture.received_at));
+    Ok(captures)
+}
+
+pub async fn remove(c: &ProcessorConfig, id: &str) -> Result<()> {
+    for name in ["request", "capture.json"] {
+        match c.s3_client.delete(&location(c, id, name)?).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+// Sends a capture through the upload handler again, as its original sender
+// did minus their key, and drops it once it is accepted. Replays are trusted
+// to be admin-initiated, so the upload key is not checked.
+pub async fn replay(c: &ProcessorConfig, id: &str) -> Result<(StatusCode, String)> {
+    let capture: Capture = match c.s3_client.get(&location(c, id, "capture.json")?).await {
+        Ok(data) => serde_json::from_slice(&data.bytes().await?)?,
+        Err(object_store::Error::NotFound { .. }) => {
+            return Err(Error::NotFound(format!("capture {}", id)));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let body = c
+        .s3_client
+        .get(&location(c, id, "request")?)
+        .await?
+        .bytes()
+        .await?;
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in &capture.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+
+    let m = multipart(c, headers.clone(), body).await?;
+    let response = handle_upload(c.clone(), headers, m, true).await?;
+    info!(id = %id, "Replayed captured upload");
+    remove(c, id).await?;
+    Ok(response)
+}
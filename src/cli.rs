@@ -0,0 +1,175 @@
+use crate::MIGRATIONS;
+use crate::backfill;
+use crate::config::{self, ProcessorConfig};
+use crate::error::{Error, Result};
+use crate::rdio;
+use crate::readiness;
+use crate::rename;
+use crate::retention;
+use crate::tr_config;
+
+use clap::{Parser, Subcommand};
+use diesel_migrations::MigrationHarness;
+use serde::Serialize;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "trunk-processor",
+    version,
+    about = "Stores, transcribes and notifies trunk-recorder calls",
+    long_about = "Stores, transcribes and notifies trunk-recorder calls. Configuration comes from the environment and CONFIG_FILE, see .env.example."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Run the HTTP server, the default without a command")]
+    Serve,
+    #[command(about = "Run pending database migrations and exit")]
+    Migrate,
+    #[command(
+        about = "Validate the configuration and check storage and the database can be reached"
+    )]
+    CheckConfig,
+    #[command(
+        about = "Import a directory, or a .tar, .tar.gz, .tgz or .zip archive, of trunk-recorder call JSON and audio"
+    )]
+    Backfill {
+        source: PathBuf,
+        #[arg(
+            long,
+            help = "Filter and transcribe the calls as uploads are, rather than archive them"
+        )]
+        transcribe: bool,
+    },
+    #[command(about = "Import an rdio-scanner database as a backfill")]
+    ImportRdio {
+        database: String,
+        #[arg(help = "Where audio is read from by audioName when the database holds none")]
+        audio_dir: Option<PathBuf>,
+    },
+    #[command(about = "Import systems and talkgroups from a trunk-recorder config.json")]
+    ImportTrConfig {
+        file: String,
+        #[arg(help = "Directory talkgroup CSV paths are relative to")]
+        base_dir: Option<PathBuf>,
+    },
+    #[command(about = "Rename talkgroup groups and group tags from a JSON mapping file")]
+    RenameGroups {
+        file: PathBuf,
+        #[arg(long, help = "Report what would be renamed without renaming it")]
+        dry_run: bool,
+    },
+    #[command(about = "Apply RETENTION_POLICIES once")]
+    Retention {
+        #[arg(long, help = "Report what would be deleted without deleting it")]
+        dry_run: bool,
+    },
+}
+
+fn print(report: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(report)?);
+    Ok(())
+}
+
+// Runs a command that works on the initialized, migrated service and exits
+pub async fn run(config: &ProcessorConfig, command: Command) -> Result<()> {
+    match command {
+        Command::Serve | Command::Migrate | Command::CheckConfig => Ok(()),
+        Command::Backfill { source, transcribe } => {
+            print(&backfill::import(config, &source, transcribe).await?)
+        }
+        Command::ImportRdio {
+            database,
+            audio_dir,
+        } => print(&rdio::import(config, &database, audio_dir).await?),
+        Command::ImportTrConfig { file, base_dir } => {
+            print(&tr_config::import(config, &file, base_dir)?)
+        }
+        Command::RenameGroups { file, dry_run } => {
+            print(&rename::apply(config, &rename::read(&file)?, dry_run)?)
+        }
+        Command::Retention { dry_run } => print(&retention::run(config, dry_run).await?),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    check: &'static str,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn new(check: &'static str, result: Result<Option<String>>) -> Self {
+        match result {
+            Ok(detail) => Check {
+                check,
+                ok: true,
+                detail,
+            },
+            Err(e) => {
+                warn!(check, error = %e, "Check failed");
+                Check {
+                    check,
+                    ok: false,
+                    detail: Some(e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+async fn check_storage(c: &ProcessorConfig) -> Result<Option<String>> {
+    readiness::storage(c).await?;
+    Ok(Some(c.s3_client.to_string()))
+}
+
+fn check_database(
+    pool: &diesel::r2d2::Pool<diesel::r2d2::ConnectionManager<diesel::PgConnection>>,
+) -> Result<Option<String>> {
+    readiness::database(pool)?;
+    let mut connection = pool.get().map_err(|e| Error::Database(e.to_string()))?;
+    let pending = connection
+        .pending_migrations(MIGRATIONS)
+        .map_err(Error::Migration)?;
+    Ok(Some(format!("{} pending migrations", pending.len())))
+}
+
+// Loads and validates the configuration, then checks storage and the
+// database answer, without migrating or changing anything. Fails when any
+// check does, so it can gate a deploy.
+pub async fn check_config() -> Result<()> {
+    let config = match config::initialize() {
+        Ok(config) => config,
+        Err(e) => {
+            print(&[Check::new("configuration", Err(e))])?;
+            return Err(Error::Configuration("configuration is invalid".to_string()));
+        }
+    };
+    let mut checks = vec![
+        Check::new("configuration", Ok(None)),
+        Check::new("storage", check_storage(&config).await),
+        Check::new("database", check_database(&config.db_pool)),
+    ];
+    if config.env.database_read_url.is_some() {
+        checks.push(Check::new(
+            "read_database",
+            check_database(&config.read_pool),
+        ));
+    }
+    print(&checks)?;
+
+    let failed = checks.iter().filter(|c| !c.ok).count();
+    if failed > 0 {
+        return Err(Error::Configuration(format!("{} checks failed", failed)));
+    }
+    info!("Configuration checks passed");
+    Ok(())
+}
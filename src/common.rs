@@ -1,21 +1,78 @@
 use crate::error::{Error, Result};
-use crate::model::{AudioMetadata, AudioMetadataRaw};
+use crate::model::{AudioMetadata, MetadataVersion};
+use crate::trace;
 
 use axum::body::Bytes;
 use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use futures::TryStreamExt;
+use object_store::{ObjectStore, path::Path};
+use reqwest::Client;
+use ring::digest::{Context, Digest, SHA256, digest};
 use serde::{Deserialize, Deserializer, Serialize, de};
+use serde_with::skip_serializing_none;
+use std::collections::BTreeMap;
 use tracing::info;
 
+#[derive(Clone)]
+pub enum FileData {
+    Memory(Bytes),
+    // streamed to object storage as the upload was read, never held in memory
+    Staged(Path),
+}
+
 #[derive(Clone)]
 pub struct UploadedFile {
     pub name: String,
-    pub data: Bytes,
+    pub data: FileData,
+    pub size: usize,
+    pub sha256: Digest,
+}
+
+impl UploadedFile {
+    pub fn in_memory(name: String, data: Bytes) -> Self {
+        UploadedFile {
+            name,
+            size: data.len(),
+            sha256: digest(&SHA256, &data),
+            data: FileData::Memory(data),
+        }
+    }
+
+    // A file already in object storage, e.g. one kept for a failed job
+    pub async fn staged(s3: &dyn ObjectStore, name: String, location: Path) -> Result<Self> {
+        let size = s3.head(&location).await?.size as usize;
+        Ok(UploadedFile {
+            name,
+            size,
+            sha256: object_digest(s3, &location).await?,
+            data: FileData::Staged(location),
+        })
+    }
+
+    pub async fn bytes(&self, s3: &dyn ObjectStore) -> Result<Bytes> {
+        match &self.data {
+            FileData::Memory(data) => Ok(data.clone()),
+            FileData::Staged(location) => Ok(s3.get(location).await?.bytes().await?),
+        }
+    }
+}
+
+pub async fn object_digest(s3: &dyn ObjectStore, location: &Path) -> Result<Digest> {
+    let mut stream = s3.get(location).await?.into_stream();
+    let mut context = Context::new(&SHA256);
+    while let Some(chunk) = stream.try_next().await? {
+        context.update(&chunk);
+    }
+    Ok(context.finish())
 }
 
 pub struct UploadData {
     pub json: UploadedFile,
     pub audio: UploadedFile,
+    pub tags: BTreeMap<String, String>,
+    pub version: Option<MetadataVersion>,
+    pub language: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -25,12 +82,36 @@ pub struct Webhook {
     pub embeds: Vec<WebhookEmbed>,
 }
 
+impl Webhook {
+    pub fn new(embeds: Vec<WebhookEmbed>) -> Self {
+        Webhook {
+            username: "Trunk Recorder".to_owned(),
+            avatar_url: "https://raw.githubusercontent.com/TrunkRecorder/trunkrecorder.github.io/refs/heads/main/static/img/radio.png".to_owned(),
+            embeds,
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct DiscordMessage {
+    pub content: Option<String>,
+    pub embeds: Vec<WebhookEmbed>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct WebhookEmbed {
     pub color: String,
     pub timestamp: String,
     pub title: String,
     pub fields: Vec<EmbedField>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub footer: Option<EmbedFooter>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbedFooter {
+    pub text: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -44,6 +125,8 @@ pub enum EmbedFieldType {
     Timestamp(String),
     RadioIds(Vec<i32>),
     Transcription(String),
+    AudioUrl(String),
+    Keywords(String),
 }
 
 impl EmbedFieldType {
@@ -65,14 +148,37 @@ impl EmbedFieldType {
                 name: "Transcription:".to_string(),
                 value: text,
             },
+            EmbedFieldType::AudioUrl(url) => EmbedField {
+                name: "Audio:".to_string(),
+                value: format!("[Listen]({})", url),
+            },
+            EmbedFieldType::Keywords(keywords) => EmbedField {
+                name: "Keywords:".to_string(),
+                value: keywords,
+            },
         }
     }
 }
 
 impl UploadData {
+    pub fn checksum(&self) -> String {
+        let mut context = Context::new(&SHA256);
+        context.update(self.json.sha256.as_ref());
+        context.update(self.audio.sha256.as_ref());
+        hex::encode(context.finish())
+    }
+
     pub fn deserialize_json(&self) -> Result<AudioMetadata> {
-        let raw: AudioMetadataRaw =
-            serde_json::from_slice(&self.json.data).map_err(Error::JsonParsing)?;
+        let FileData::Memory(data) = &self.json.data else {
+            return Err(Error::Multipart("json field was not buffered".to_string()));
+        };
+        let version = match self.version {
+            Some(version) => version,
+            None => {
+                MetadataVersion::detect(&serde_json::from_slice(data).map_err(Error::JsonParsing)?)
+            }
+        };
+        let raw = version.parse(data)?;
         let (src_list, sources) = raw.split_src_list();
         Ok(AudioMetadata {
             call: raw.call,
@@ -84,6 +190,42 @@ impl UploadData {
     }
 }
 
+pub async fn send_embed(client: &Client, url: &str, embed: WebhookEmbed) -> Result<()> {
+    trace::inject(client.post(url))
+        .json(&Webhook::new(vec![embed]))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+pub fn parse_duration(s: &str) -> Result<TimeDelta> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: i64 = value
+        .parse()
+        .map_err(|_| Error::InvalidRequest(format!("Invalid duration: {}", s)))?;
+
+    match unit.trim() {
+        "ms" => Some(TimeDelta::milliseconds(value)),
+        "" | "s" => TimeDelta::try_seconds(value),
+        "m" => TimeDelta::try_minutes(value),
+        "h" => TimeDelta::try_hours(value),
+        "d" => TimeDelta::try_days(value),
+        _ => None,
+    }
+    .ok_or_else(|| Error::InvalidRequest(format!("Invalid duration: {}", s)))
+}
+
+pub fn ago(d: std::time::Duration) -> DateTime<Utc> {
+    TimeDelta::from_std(d)
+        .ok()
+        .and_then(|d| Utc::now().checked_sub_signed(d))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
 pub fn format_timestamp_from_datetime(dt: DateTime<Utc>) -> String {
     dt.to_rfc3339_opts(SecondsFormat::Millis, true)
 }
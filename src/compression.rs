@@ -0,0 +1,240 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Call, CompressedTranscription};
+use crate::schema::{calls, compressed_transcriptions};
+
+use diesel::prelude::*;
+use diesel::sql_types::{Nullable, Varchar};
+use diesel::{delete, insert_into, update};
+use tracing::{error, info, warn};
+
+// What calls.transcription and public_transcription hold for a call whose
+// text is in compressed_transcriptions, so filters on whether a call was
+// transcribed still work
+pub const MARKER: &str = "zstd:v1";
+
+// zstd's default level, quick enough to run on every upload
+const LEVEL: i32 = 3;
+
+// calls compressed per transaction by compress_stored, so no one transaction
+// holds locks on many rows
+const BATCH: i64 = 200;
+
+define_sql_function!(fn octet_length(text: Nullable<Varchar>) -> Nullable<Integer>);
+
+fn pack(c: &ProcessorConfig, text: &str, filename: &str) -> Result<Vec<u8>> {
+    let packed = zstd::encode_all(text.as_bytes(), LEVEL)
+        .map_err(|e| Error::Database(format!("Failed to compress transcription: {}", e)))?;
+    match &c.transcript_cipher {
+        Some(cipher) => cipher.seal_bytes(&packed, filename),
+        None => Ok(packed),
+    }
+}
+
+fn unpack(c: &ProcessorConfig, stored: &[u8], filename: &str) -> Result<String> {
+    let failed = |e: String| {
+        Error::Database(format!(
+            "Failed to decompress transcription of {}: {}",
+            filename, e
+        ))
+    };
+    let opened;
+    let packed = match &c.transcript_cipher {
+        Some(cipher) => {
+            opened = cipher.open_bytes(stored, filename)?;
+            &opened[..]
+        }
+        None => stored,
+    };
+    let text = zstd::decode_all(packed).map_err(|e| failed(e.to_string()))?;
+    String::from_utf8(text).map_err(|e| failed(e.to_string()))
+}
+
+// Compresses a transcription longer than TRANSCRIPTION_COMPRESS_SIZE, and its
+// scrubbed copy with it, leaving MARKER in their place. The returned row is
+// written with the call by store.
+pub fn compress(
+    c: &ProcessorConfig,
+    filename: &str,
+    transcription: &mut Option<String>,
+    public: &mut Option<String>,
+) -> Result<Option<CompressedTranscription>> {
+    let Some(limit) = c.env.transcription_compress_size else {
+        return Ok(None);
+    };
+    let Some(text) = transcription
+        .as_deref()
+        .filter(|text| text.len() > limit && *text != MARKER)
+    else {
+        return Ok(None);
+    };
+
+    let row = CompressedTranscription {
+        call_id: filename.to_string(),
+        transcription: pack(c, text, filename)?,
+        public_transcription: public
+            .as_deref()
+            .map(|p| pack(c, p, filename))
+            .transpose()?,
+    };
+    *transcription = Some(MARKER.to_string());
+    if public.is_some() {
+        *public = Some(MARKER.to_string());
+    }
+    Ok(Some(row))
+}
+
+// Replaces a call's compressed transcription. Run in the transaction writing
+// the call, so the row and the markers change together.
+pub fn store(
+    conn: &mut PgConnection,
+    filename: &str,
+    row: Option<&CompressedTranscription>,
+) -> QueryResult<()> {
+    delete(compressed_transcriptions::table.find(filename)).execute(conn)?;
+    if let Some(row) = row {
+        insert_into(compressed_transcriptions::table)
+            .values(row)
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+// Puts a call's compressed transcription back in place of the markers. This
+// runs whether or not TRANSCRIPTION_COMPRESS_SIZE is set, so calls compressed
+// before it was unset stay readable.
+pub fn expand(c: &ProcessorConfig, call: &mut Call) -> Result<()> {
+    let marked = |text: &Option<String>| text.as_deref() == Some(MARKER);
+    if !marked(&call.transcription) && !marked(&call.public_transcription) {
+        return Ok(());
+    }
+
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let row: CompressedTranscription = compressed_transcriptions::table
+        .find(&call.filename)
+        .select(CompressedTranscription::as_select())
+        .first(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    if marked(&call.transcription) {
+        call.transcription = Some(unpack(c, &row.transcription, &call.filename)?);
+    }
+    if marked(&call.public_transcription) {
+        call.public_transcription = row
+            .public_transcription
+            .map(|p| unpack(c, &p, &call.filename))
+            .transpose()?;
+    }
+    Ok(())
+}
+
+// Compresses the next BATCH calls after `after` that were stored as text,
+// each updated only if its transcription hasn't changed since it was read.
+// Returns the last filename looked at and how many were compressed.
+fn compress_batch(
+    c: &ProcessorConfig,
+    limit: usize,
+    after: &str,
+) -> Result<Option<(String, usize)>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    // a sealed transcription is longer than its text, so this only narrows
+    // down the calls to open
+    let batch: Vec<(String, Option<String>, Option<String>)> = calls::table
+        .filter(calls::filename.gt(after))
+        .filter(calls::transcription.ne(MARKER))
+        .filter(octet_length(calls::transcription).gt(limit as i32))
+        .select((
+            calls::filename,
+            calls::transcription,
+            calls::public_transcription,
+        ))
+        .order(calls::filename.asc())
+        .limit(BATCH)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let Some((last, _, _)) = batch.last() else {
+        return Ok(None);
+    };
+    let last = last.clone();
+
+    let mut rows = Vec::new();
+    for (filename, stored, public) in batch {
+        let (mut text, mut public) = match &c.transcript_cipher {
+            Some(cipher) => {
+                let open = |t: Option<String>| t.map(|t| cipher.decrypt(&t, &filename)).transpose();
+                match (open(stored.clone()), open(public)) {
+                    (Ok(text), Ok(public)) => (text, public),
+                    _ => {
+                        warn!(file = %filename, "Skipping compression of undecryptable transcription");
+                        continue;
+                    }
+                }
+            }
+            None => (stored.clone(), public),
+        };
+        if let Some(row) = compress(c, &filename, &mut text, &mut public)? {
+            rows.push((stored, public, row));
+        }
+    }
+
+    let compressed = connection
+        .transaction(|conn| {
+            let mut compressed = 0;
+            for (stored, public, row) in &rows {
+                let updated = update(
+                    calls::table
+                        .find(&row.call_id)
+                        .filter(calls::transcription.eq(stored)),
+                )
+                .set((
+                    calls::transcription.eq(MARKER),
+                    calls::public_transcription.eq(public),
+                ))
+                .execute(conn)?;
+                // re-transcribed since it was read, and stored by that instead
+                if updated == 0 {
+                    continue;
+                }
+                store(conn, &row.call_id, Some(row))?;
+                compressed += 1;
+            }
+            QueryResult::Ok(compressed)
+        })
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(Some((last, compressed)))
+}
+
+// Compresses the long transcriptions of calls stored before
+// TRANSCRIPTION_COMPRESS_SIZE was set, a batch at a time in filename order,
+// so nothing locks the calls table for long
+pub async fn compress_stored(c: ProcessorConfig) {
+    let Some(limit) = c.env.transcription_compress_size else {
+        return;
+    };
+    let mut after = String::new();
+    let mut total = 0;
+    while c.switches.database() {
+        match compress_batch(&c, limit, &after) {
+            Ok(Some((last, compressed))) => {
+                after = last;
+                total += compressed;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                error!(error = %e, "Failed to compress stored transcriptions");
+                return;
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+    if total > 0 {
+        info!(compressed = total, "Compressed stored transcriptions");
+    }
+}
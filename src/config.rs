@@ -1,28 +1,645 @@
+use arc_swap::ArcSwap;
 use diesel::{
     PgConnection,
     r2d2::{self, ConnectionManager, Pool},
 };
-use object_store::aws::{AmazonS3, AmazonS3Builder};
-use reqwest::Client;
-use serde::Deserialize;
-use std::time::Duration;
+use object_store::{
+    ObjectStore, aws::AmazonS3Builder, azure::MicrosoftAzureBuilder,
+    gcp::GoogleCloudStorageBuilder, local::LocalFileSystem, signer::Signer,
+};
+use reqwest::{Client, Url};
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::RwLock,
+};
+use tracing::{error, info, warn};
+
+use crate::activity::TalkgroupActivity;
+use crate::common::parse_duration;
+use crate::crypto::TranscriptCipher;
+use crate::hashing::HashAlgorithm;
+use crate::layers::Layers;
+use crate::model::AudioMetadata;
+use crate::notifier::Notifiers;
+use crate::policy::{self, UploadPolicy};
+use crate::pools::{WorkerPool, WorkerPools};
+use crate::priority;
+use crate::put_queue::PutQueue;
+use crate::reconcile::ReconcileReport;
+use crate::replay::ReplayCache;
+use crate::retention::{self, RetentionPolicy};
+use crate::shutdown::Background;
+use crate::switches::{SwitchState, Switches};
+use crate::transcribe::{self, Transcriber};
+use crate::worker::JobQueue;
 
 #[derive(Clone, Debug)]
 pub struct ProcessorConfig {
-    pub s3_client: AmazonS3,
+    pub s3_client: Arc<dyn ObjectStore>,
+    // the same store, for backends that can presign URLs
+    pub signer: Option<Arc<dyn Signer>>,
     pub http_client: Client,
     pub env: EnvConfig,
-    pub filter: FilterConfig,
+    // swapped by reload, like notifiers
+    pub filter: Arc<ArcSwap<FilterConfig>>,
     pub db_pool: Pool<ConnectionManager<PgConnection>>,
+    // for the read endpoints: DATABASE_READ_URL's replica, or db_pool
+    pub read_pool: Pool<ConnectionManager<PgConnection>>,
+    pub reconcile_report: Arc<RwLock<Option<ReconcileReport>>>,
+    pub replay_cache: Option<Arc<Mutex<ReplayCache>>>,
+    pub activity: Option<Arc<Mutex<TalkgroupActivity>>>,
+    pub read_keys: Vec<ReadKey>,
+    pub retention: Vec<RetentionPolicy>,
+    pub talkgroup_weights: HashMap<i32, i32>,
+    pub talkgroup_languages: HashMap<i32, String>,
+    pub upload_policy: UploadPolicy,
+    pub upload_keys: Vec<UploadKey>,
+    pub transcript_cipher: Option<TranscriptCipher>,
+    pub pools: Arc<WorkerPools>,
+    pub switches: Arc<Switches>,
+    pub notifiers: Arc<ArcSwap<Notifiers>>,
+    pub transcriber: Arc<dyn Transcriber>,
+    pub queue: Arc<JobQueue>,
+    pub events: Arc<CallEvents>,
+    pub background: Arc<Background>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReadKey {
+    pub key: String,
+    pub id: String,
+    pub groups: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadKey {
+    pub key: String,
+    pub id: String,
+    pub systems: Option<Vec<String>>,
+}
+
+impl UploadKey {
+    pub fn allows(&self, system: &str) -> bool {
+        self.systems
+            .as_ref()
+            .is_none_or(|systems| systems.iter().any(|s| s == system))
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct EnvConfig {
-    pub transcription_endpoint: String,
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    #[serde(default)]
+    pub transcription_provider: TranscriptionProvider,
+    pub transcription_endpoint: Option<String>,
+    #[serde(default)]
     pub bucket_name: String,
+    pub storage_root: Option<String>,
+    #[serde(default)]
+    pub audio_redirect: bool,
+    #[serde(default = "default_audio_url_expiry", deserialize_with = "secs")]
+    pub audio_url_expiry: Duration,
+    #[serde(default)]
+    pub notification_audio_urls: bool,
+    #[serde(
+        default = "default_notification_audio_url_expiry",
+        deserialize_with = "secs"
+    )]
+    pub notification_audio_url_expiry: Duration,
     pub discord_webhook: String,
-    pub model_name: String,
+    pub model_name: Option<String>,
+    pub transcription_api_key: Option<String>,
+    pub transcription_temperatures: Option<Vec<f32>>,
+    #[serde(default = "default_transcription_logprob_threshold")]
+    pub transcription_logprob_threshold: f64,
+    #[serde(default = "default_transcription_compression_ratio_threshold")]
+    pub transcription_compression_ratio_threshold: f64,
+    #[serde(default = "default_transcription_language")]
+    pub transcription_language: String,
+    pub transcription_language_talkgroups: Option<Vec<String>>,
+    #[serde(default = "default_transcription_fallback_language")]
+    pub transcription_fallback_language: String,
+    #[serde(default = "default_transcription_language_threshold")]
+    pub transcription_language_threshold: f32,
+    #[serde(default)]
+    pub word_timestamps: bool,
+    pub transcript_keywords: Option<Vec<String>>,
+    #[serde(default)]
+    pub speaker_segments: bool,
+    #[serde(default = "default_transcription_retries")]
+    pub transcription_retries: u32,
+    #[serde(
+        default = "default_transcription_retry_delay",
+        deserialize_with = "secs"
+    )]
+    pub transcription_retry_delay: Duration,
+    #[serde(
+        default = "default_transcription_retry_budget",
+        deserialize_with = "secs"
+    )]
+    pub transcription_retry_budget: Duration,
     pub database_url: String,
+    pub database_read_url: Option<String>,
+    pub discord_public_key: Option<String>,
+    pub discord_bot_token: Option<String>,
+    #[serde(default = "default_staging_prefix")]
+    pub staging_prefix: String,
+    #[serde(default = "default_export_prefix")]
+    pub export_prefix: String,
+    #[serde(default)]
+    pub upload_capture: bool,
+    #[serde(default = "default_capture_prefix")]
+    pub capture_prefix: String,
+    pub admin_token: Option<String>,
+    pub alertmanager_url: Option<String>,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub reconcile_interval_secs: Option<Duration>,
+    #[serde(default)]
+    pub reconcile_repair: bool,
+    #[serde(default)]
+    pub integrity_check: bool,
+    #[serde(default)]
+    pub integrity_repair: bool,
+    #[serde(default)]
+    pub path_collision_policy: CollisionPolicy,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    #[serde(default)]
+    pub tdma_slot_names: bool,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub replay_window_secs: Option<Duration>,
+    #[serde(default = "default_replay_cache_size")]
+    pub replay_cache_size: usize,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub activity_window: Option<Duration>,
+    #[serde(default = "default_deduplicate_uploads")]
+    pub deduplicate_uploads: bool,
+    pub upload_policy: Option<Vec<String>>,
+    pub upload_systems: Option<Vec<String>>,
+    #[serde(default = "default_upload_time_tolerance", deserialize_with = "secs")]
+    pub upload_time_tolerance: Duration,
+    #[serde(default)]
+    pub notify_discovered_talkgroups: bool,
+    #[serde(default)]
+    pub notify_talkgroup_changes: bool,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub anomaly_interval_secs: Option<Duration>,
+    #[serde(default = "default_anomaly_window_secs", deserialize_with = "secs")]
+    pub anomaly_window_secs: Duration,
+    #[serde(default = "default_anomaly_baseline_days", deserialize_with = "days")]
+    pub anomaly_baseline_days: Duration,
+    #[serde(default = "default_anomaly_threshold")]
+    pub anomaly_threshold: f64,
+    #[serde(
+        default = "default_anomaly_min_airtime_secs",
+        deserialize_with = "secs"
+    )]
+    pub anomaly_min_airtime_secs: Duration,
+    #[serde(default)]
+    pub public_mode: bool,
+    pub public_groups: Option<Vec<String>>,
+    pub read_keys: Option<Vec<String>>,
+    pub api_keys: Option<Vec<String>>,
+    pub transcription_key: Option<String>,
+    #[serde(default, deserialize_with = "optional_size")]
+    pub transcription_compress_size: Option<usize>,
+    #[serde(default)]
+    pub transcription_cache: bool,
+    #[serde(default, deserialize_with = "optional_days")]
+    pub transcription_cache_ttl_days: Option<Duration>,
+    #[serde(default)]
+    pub pii_scrubbing: bool,
+    #[serde(default)]
+    pub archive_notifications: bool,
+    #[serde(default)]
+    pub access_log: bool,
+    #[serde(default, deserialize_with = "optional_days")]
+    pub access_log_retention_days: Option<Duration>,
+    pub retention_policies: Option<Vec<String>>,
+    #[serde(default = "default_retention_interval", deserialize_with = "secs")]
+    pub retention_interval: Duration,
+    #[serde(default)]
+    pub retention_dry_run: bool,
+    #[serde(default = "default_max_upload_size", deserialize_with = "size")]
+    pub max_upload_size: usize,
+    #[serde(default, deserialize_with = "optional_size")]
+    pub upload_body_limit: Option<usize>,
+    #[serde(default = "default_body_limit", deserialize_with = "size")]
+    pub body_limit: usize,
+    #[serde(default)]
+    pub disable_transcription: bool,
+    #[serde(default)]
+    pub disable_notifications: bool,
+    #[serde(default)]
+    pub disable_db_writes: bool,
+    #[serde(default = "default_transcription_workers")]
+    pub transcription_workers: usize,
+    #[serde(default = "default_storage_workers")]
+    pub storage_workers: usize,
+    #[serde(default = "default_storage_put_workers")]
+    pub storage_put_workers: usize,
+    #[serde(default = "default_storage_put_queue")]
+    pub storage_put_queue: usize,
+    #[serde(default = "default_notification_workers")]
+    pub notification_workers: usize,
+    pub post_upload_hook: Option<String>,
+    #[serde(
+        default = "default_post_upload_hook_timeout",
+        deserialize_with = "secs"
+    )]
+    pub post_upload_hook_timeout: Duration,
+    pub openmhz_api_key: Option<String>,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub upload_deadline: Option<Duration>,
+    #[serde(default = "default_ffmpeg_path")]
+    pub ffmpeg_path: String,
+    pub testcall_audio: Option<String>,
+    pub report_template: Option<String>,
+    pub pdf_renderer: Option<String>,
+    pub notifiers: Option<Vec<String>>,
+    #[serde(default = "default_queue_workers")]
+    pub queue_workers: usize,
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,
+    #[serde(default = "default_queue_retries")]
+    pub queue_retries: u32,
+    #[serde(default = "default_queue_retry_delay", deserialize_with = "secs")]
+    pub queue_retry_delay: Duration,
+    #[serde(default = "default_failed_job_retries")]
+    pub failed_job_retries: u32,
+    #[serde(default = "default_failed_job_backoff", deserialize_with = "secs")]
+    pub failed_job_backoff: Duration,
+    #[serde(default = "default_failed_job_prefix")]
+    pub failed_job_prefix: String,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub queue_max_age: Option<Duration>,
+    pub queue_max_length: Option<usize>,
+    #[serde(default)]
+    pub queue_readiness: bool,
+    #[serde(default)]
+    pub queue_priority: bool,
+    pub queue_priority_talkgroups: Option<Vec<String>>,
+    #[serde(default = "default_readiness_timeout", deserialize_with = "secs")]
+    pub readiness_timeout: Duration,
+    #[serde(default)]
+    pub readiness_transcription: bool,
+    #[serde(default = "default_http_timeout", deserialize_with = "secs")]
+    pub http_timeout: Duration,
+    #[serde(default = "default_http_connect_timeout", deserialize_with = "secs")]
+    pub http_connect_timeout: Duration,
+    #[serde(default)]
+    pub server_disable_http2: bool,
+    #[serde(default = "default_server_http2_max_streams")]
+    pub server_http2_max_streams: u32,
+    #[serde(default)]
+    pub server_http2_adaptive_window: bool,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub server_http2_keepalive_interval: Option<Duration>,
+    #[serde(
+        default = "default_server_http2_keepalive_timeout",
+        deserialize_with = "secs"
+    )]
+    pub server_http2_keepalive_timeout: Duration,
+    #[serde(default)]
+    pub server_disable_keepalive: bool,
+    #[serde(default = "default_server_header_timeout", deserialize_with = "secs")]
+    pub server_header_timeout: Duration,
+    #[serde(default, deserialize_with = "optional_secs")]
+    pub server_body_timeout: Option<Duration>,
+    #[serde(default = "default_shutdown_drain_timeout", deserialize_with = "secs")]
+    pub shutdown_drain_timeout: Duration,
+    pub workspace_dir: Option<String>,
+    #[serde(default = "default_workspace_max_size", deserialize_with = "size")]
+    pub workspace_max_size: usize,
+    pub metrics_talkgroups: Option<Vec<i32>>,
+    #[serde(default = "default_metrics_talkgroup_top")]
+    pub metrics_talkgroup_top: usize,
+}
+
+fn default_replay_cache_size() -> usize {
+    4096
+}
+
+fn default_deduplicate_uploads() -> bool {
+    true
+}
+
+fn default_upload_time_tolerance() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn default_anomaly_window_secs() -> Duration {
+    Duration::from_secs(3600)
+}
+
+fn default_anomaly_baseline_days() -> Duration {
+    DAY * 7
+}
+
+fn default_anomaly_threshold() -> f64 {
+    3.0
+}
+
+fn default_anomaly_min_airtime_secs() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_transcription_logprob_threshold() -> f64 {
+    -1.0
+}
+
+fn default_transcription_compression_ratio_threshold() -> f64 {
+    2.4
+}
+
+fn default_transcription_language() -> String {
+    "en".to_string()
+}
+
+fn default_transcription_fallback_language() -> String {
+    "en".to_string()
+}
+
+fn default_transcription_language_threshold() -> f32 {
+    0.5
+}
+
+fn default_transcription_retries() -> u32 {
+    3
+}
+
+fn default_transcription_retry_delay() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_transcription_retry_budget() -> Duration {
+    Duration::from_secs(120)
+}
+
+fn default_max_upload_size() -> usize {
+    50 * 1024 * 1024
+}
+
+fn default_workspace_max_size() -> usize {
+    512 * 1024 * 1024
+}
+
+fn default_body_limit() -> usize {
+    256 * 1024
+}
+
+fn default_transcription_workers() -> usize {
+    4
+}
+
+fn default_storage_workers() -> usize {
+    16
+}
+
+fn default_storage_put_workers() -> usize {
+    8
+}
+
+fn default_storage_put_queue() -> usize {
+    256
+}
+
+fn default_notification_workers() -> usize {
+    8
+}
+
+fn default_post_upload_hook_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_ffmpeg_path() -> String {
+    "ffmpeg".to_string()
+}
+
+fn default_queue_workers() -> usize {
+    4
+}
+
+fn default_queue_capacity() -> usize {
+    256
+}
+
+fn default_queue_retries() -> u32 {
+    2
+}
+
+fn default_queue_retry_delay() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_failed_job_retries() -> u32 {
+    5
+}
+
+fn default_failed_job_backoff() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_failed_job_prefix() -> String {
+    "failed-jobs".to_string()
+}
+
+fn default_audio_url_expiry() -> Duration {
+    Duration::from_secs(900)
+}
+
+fn default_notification_audio_url_expiry() -> Duration {
+    Duration::from_secs(86400)
+}
+
+fn default_retention_interval() -> Duration {
+    Duration::from_secs(86400)
+}
+
+fn default_readiness_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_http_timeout() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_http_connect_timeout() -> Duration {
+    Duration::from_secs(20)
+}
+
+fn default_server_http2_max_streams() -> u32 {
+    200
+}
+
+fn default_server_http2_keepalive_timeout() -> Duration {
+    Duration::from_secs(20)
+}
+
+fn default_server_header_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_shutdown_drain_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_metrics_talkgroup_top() -> usize {
+    10
+}
+
+const SECOND: Duration = Duration::from_secs(1);
+const DAY: Duration = Duration::from_secs(86400);
+// the longest S3 accepts for a SigV4 presigned URL
+const MAX_AUDIO_URL_EXPIRY: Duration = Duration::from_secs(7 * 86400);
+
+// Bare numbers are read in the unit named by the variable (e.g. *_SECS, *_DAYS),
+// anything else goes through parse_duration ("500ms", "30s", "5m", "12h", "7d")
+fn parse_config_duration(s: &str, unit: Duration) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(n) = s.parse::<u32>() {
+        return Ok(unit * n);
+    }
+    parse_duration(s)
+        .ok()
+        .and_then(|d| d.to_std().ok())
+        .ok_or_else(|| format!("invalid duration {:?}", s))
+}
+
+// Sizes accept an optional B, KB, MB or GB suffix (binary multiples, case-insensitive)
+fn parse_size(s: &str) -> std::result::Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: usize = value.parse().map_err(|_| format!("invalid size {:?}", s))?;
+
+    let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        _ => return Err(format!("invalid size {:?}", s)),
+    };
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+fn secs<'de, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_config_duration(&String::deserialize(deserializer)?, SECOND).map_err(de::Error::custom)
+}
+
+fn days<'de, D>(deserializer: D) -> std::result::Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_config_duration(&String::deserialize(deserializer)?, DAY).map_err(de::Error::custom)
+}
+
+fn optional_secs<'de, D>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    secs(deserializer).map(Some)
+}
+
+fn optional_days<'de, D>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    days(deserializer).map(Some)
+}
+
+fn size<'de, D>(deserializer: D) -> std::result::Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    parse_size(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+fn optional_size<'de, D>(deserializer: D) -> std::result::Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    size(deserializer).map(Some)
+}
+
+// Room for the json and audio files plus multipart framing
+const UPLOAD_BODY_OVERHEAD: usize = 64 * 1024;
+
+impl EnvConfig {
+    pub fn upload_body_limit(&self) -> usize {
+        self.upload_body_limit.unwrap_or(
+            self.max_upload_size
+                .saturating_mul(2)
+                .saturating_add(UPLOAD_BODY_OVERHEAD),
+        )
+    }
+
+    // Where a stored object lives, as a URL in the backend's usual scheme
+    pub fn object_url(&self, path: &str) -> String {
+        match self.storage_backend {
+            StorageBackend::S3 => format!("s3://{}/{}", self.bucket_name, path),
+            StorageBackend::Gcs => format!("gs://{}/{}", self.bucket_name, path),
+            StorageBackend::Azure => format!("az://{}/{}", self.bucket_name, path),
+            StorageBackend::Local => {
+                let root = self.storage_root.as_deref().unwrap_or_default();
+                format!("file://{}/{}", root.trim_end_matches('/'), path)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    S3,
+    Gcs,
+    Azure,
+    Local,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionProvider {
+    #[default]
+    Openai,
+    FasterWhisper,
+    AwsTranscribe,
+    Deepgram,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    #[default]
+    Overwrite,
+    Suffix,
+    Reject,
+}
+
+fn default_staging_prefix() -> String {
+    "staging".to_string()
+}
+
+fn default_export_prefix() -> String {
+    "export".to_string()
+}
+
+fn default_capture_prefix() -> String {
+    "captures".to_string()
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -31,6 +648,23 @@ pub struct FilterConfig {
     tg_id: Option<Vec<String>>,
 }
 
+// Whether a call is transcribed, and the rule that decided it. Stored with the
+// call so skipped transcriptions can be explained later.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterDecision {
+    pub transcribe: bool,
+    pub rule: String,
+}
+
+impl FilterDecision {
+    pub fn new(transcribe: bool, rule: impl Into<String>) -> Self {
+        FilterDecision {
+            transcribe,
+            rule: rule.into(),
+        }
+    }
+}
+
 impl FilterConfig {
     pub fn enabled(&self) -> bool {
         self.tg_group.is_some() || self.tg_id.is_some()
@@ -49,32 +683,646 @@ impl FilterConfig {
             Vec::new()
         }
     }
+
+    // Only meaningful when enabled; without filters nothing is transcribed
+    pub fn decide(&self, tgid: i32, group: &str) -> FilterDecision {
+        let tgid_as_string = &tgid.to_string();
+        let deny_tgid = format!("!{}", tgid_as_string);
+
+        if !self.tgid().is_empty() {
+            // if tgid filter contains negated tgid, !do_transcription early
+            if self.tgid().contains(&deny_tgid) {
+                return FilterDecision::new(false, format!("tg_id:{}", deny_tgid));
+            }
+            // if tgid filter contains tgid, do_transcription
+            else if self.tgid().contains(tgid_as_string) {
+                return FilterDecision::new(true, format!("tg_id:{}", tgid_as_string));
+            }
+        };
+
+        // if group in include list, do_transcription
+        if self.group().iter().any(|g| g == group) {
+            return FilterDecision::new(true, format!("tg_group:{}", group));
+        };
+
+        // false if not negated by previous tgid include, or group include
+        FilterDecision::new(false, "unmatched")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    Discord,
+    Mqtt,
+    Slack,
+    Webhook,
+}
+
+// One entry of NOTIFIERS, read from the NOTIFIER_<NAME>_* variables
+#[derive(Clone, Debug, Deserialize)]
+pub struct NotifierConfig {
+    pub kind: NotifierKind,
+    pub url: String,
+    pub tg_id: Option<Vec<String>>,
+    pub tg_group: Option<Vec<String>>,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "default_retry_delay", deserialize_with = "secs")]
+    pub retry_delay: Duration,
+    // discord only: send to the call's discord_routes webhook when one matches
+    #[serde(default)]
+    pub routed: bool,
+    // slack only: a bot token and channel id to upload the call audio with,
+    // since incoming webhooks can't carry files
+    pub token: Option<String>,
+    pub channel: Option<String>,
+    // webhook only: signs each body with HMAC-SHA256
+    pub secret: Option<String>,
+    // mqtt only: see mqtt::topic
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    // also send storage lifecycle events, such as retention deleting calls
+    #[serde(default)]
+    pub storage_events: bool,
+    // mqtt only: where storage events are published
+    #[serde(default = "default_storage_topic")]
+    pub storage_topic: String,
+    // also send this percentage of all calls, whatever the filters say
+    pub canary_percent: Option<f64>,
+}
+
+fn default_topic() -> String {
+    "trunk/{short_name}/{talkgroup}".to_string()
+}
+
+fn default_storage_topic() -> String {
+    "trunk/storage".to_string()
+}
+
+fn default_retry_delay() -> Duration {
+    Duration::from_secs(2)
+}
+
+impl NotifierConfig {
+    pub fn accepts(&self, m: &AudioMetadata) -> bool {
+        self.accepts_talkgroup(m.talkgroup.talkgroup, &m.talkgroup.talkgroup_group)
+    }
+
+    pub fn accepts_talkgroup(&self, tgid: i32, group: &str) -> bool {
+        notifier_accepts(self.tg_id.as_deref(), self.tg_group.as_deref(), tgid, group)
+    }
+
+    // Whether a canary takes this call as part of its sample. Calls are picked
+    // by a hash of the filename, so a call is in or out of the sample every
+    // time it is sent, re-sent or re-notified.
+    pub fn samples(&self, filename: &str) -> bool {
+        let Some(percent) = self.canary_percent else {
+            return false;
+        };
+        let hash = digest(&SHA256, filename.as_bytes());
+        let mut first = [0u8; 4];
+        first.copy_from_slice(&hash.as_ref()[..4]);
+        let bucket = u32::from_be_bytes(first) % 10000;
+        f64::from(bucket) < percent * 100.0
+    }
+
+    // The calls its filters accept, and a canary's sample of the rest
+    pub fn receives(&self, m: &AudioMetadata) -> bool {
+        self.accepts(m) || self.samples(&m.call.filename)
+    }
+}
+
+// Without filters every call is sent. `!id` entries exclude a talkgroup;
+// otherwise a call is sent when its talkgroup or group is listed.
+pub fn notifier_accepts(
+    tg_id: Option<&[String]>,
+    tg_group: Option<&[String]>,
+    tgid: i32,
+    group: &str,
+) -> bool {
+    let tgid = tgid.to_string();
+    let ids = tg_id.unwrap_or_default();
+    if ids
+        .iter()
+        .any(|id| id.strip_prefix('!') == Some(tgid.as_str()))
+    {
+        return false;
+    }
+
+    let includes: Vec<&String> = ids.iter().filter(|id| !id.starts_with('!')).collect();
+    if includes.is_empty() && tg_group.is_none() {
+        return true;
+    }
+    includes.iter().any(|id| **id == tgid)
+        || tg_group.unwrap_or_default().iter().any(|g| g == group)
 }
 
 use crate::error::{Error, Result};
+use crate::events::CallEvents;
+
+#[derive(Debug, Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn push(&mut self, field: &str, message: impl std::fmt::Display) {
+        self.0.push(format!("{}: {}", field, message));
+    }
 
-fn init_env() -> Result<EnvConfig> {
-    envy::from_env::<EnvConfig>()
-        .map_err(|e| Error::Configuration(format!("Environment configuration error: {}", e)))
+    fn check<T>(&mut self, field: &str, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(Error::Configuration(msg)) => {
+                self.push(field, msg);
+                None
+            }
+            Err(e) => {
+                self.push(field, e);
+                None
+            }
+        }
+    }
+
+    // What a settings layer couldn't deserialize, stopping on None
+    fn load<T>(&mut self, result: std::result::Result<T, Vec<(String, String)>>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(problems) => {
+                for (field, message) in problems {
+                    self.push(&field, message);
+                }
+                None
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn into_error(self) -> Error {
+        for problem in &self.0 {
+            error!(problem = %problem, "Invalid configuration");
+        }
+        Error::Configuration(format!(
+            "{} problem(s) found: {}",
+            self.0.len(),
+            self.0.join("; ")
+        ))
+    }
 }
 
-fn init_filter() -> Result<FilterConfig> {
-    envy::prefixed("FILTER_")
-        .from_env::<FilterConfig>()
-        .map_err(|e| Error::Configuration(format!("Environment configuration error: {}", e)))
+// Why `value` can't be used as a destination URL, if it can't
+pub fn url_problem(value: &str) -> Option<String> {
+    match Url::parse(value) {
+        Ok(url) if !matches!(url.scheme(), "http" | "https") => Some(format!(
+            "expected an http(s) URL, got scheme {}",
+            url.scheme()
+        )),
+        Ok(url) if url.host_str().is_none() => Some("URL has no host".to_string()),
+        Ok(_) => None,
+        Err(e) => Some(format!("invalid URL: {}", e)),
+    }
 }
 
-fn init_s3_client(b: &str) -> Result<AmazonS3> {
-    AmazonS3Builder::from_env()
-        .with_bucket_name(b)
-        .build()
-        .map_err(|e| Error::Configuration(format!("S3 client configuration error: {}", e)))
+fn check_url(problems: &mut Problems, field: &str, value: &str) {
+    if let Some(problem) = url_problem(value) {
+        problems.push(field, problem);
+    }
+}
+
+fn validate_filter(filter: &FilterConfig, problems: &mut Problems) {
+    for id in filter.tgid() {
+        if id.strip_prefix('!').unwrap_or(&id).parse::<i32>().is_err() {
+            problems.push(
+                "FILTER_TG_ID",
+                format!("expected a talkgroup id or !id, got {:?}", id),
+            );
+        }
+    }
+    if filter.group().iter().any(|g| g.trim().is_empty()) {
+        problems.push("FILTER_TG_GROUP", "contains an empty group name");
+    }
+}
+
+fn validate(env: &EnvConfig, filter: &FilterConfig, problems: &mut Problems) {
+    if let Some(url) = &env.transcription_endpoint {
+        check_url(problems, "TRANSCRIPTION_ENDPOINT", url);
+    }
+    check_url(problems, "DISCORD_WEBHOOK", &env.discord_webhook);
+    if let Some(url) = &env.alertmanager_url {
+        check_url(problems, "ALERTMANAGER_URL", url);
+    }
+    match (env.storage_backend, &env.storage_root) {
+        (StorageBackend::Local, None) => {
+            problems.push("STORAGE_ROOT", "required for STORAGE_BACKEND=local")
+        }
+        (StorageBackend::Local, Some(_)) => {}
+        (_, _) if env.bucket_name.is_empty() => {
+            problems.push("BUCKET_NAME", "required but not set")
+        }
+        (_, Some(_)) => warn!("STORAGE_ROOT is only used with STORAGE_BACKEND=local"),
+        (_, None) => {}
+    }
+    if env.audio_redirect && env.storage_backend == StorageBackend::Local {
+        problems.push(
+            "AUDIO_REDIRECT",
+            "needs a storage backend that can sign URLs, not STORAGE_BACKEND=local",
+        );
+    }
+    if env.notification_audio_urls && env.storage_backend == StorageBackend::Local {
+        problems.push(
+            "NOTIFICATION_AUDIO_URLS",
+            "needs a storage backend that can sign URLs, not STORAGE_BACKEND=local",
+        );
+    }
+    for (field, expiry) in [
+        ("AUDIO_URL_EXPIRY", env.audio_url_expiry),
+        (
+            "NOTIFICATION_AUDIO_URL_EXPIRY",
+            env.notification_audio_url_expiry,
+        ),
+    ] {
+        if expiry.is_zero() || expiry > MAX_AUDIO_URL_EXPIRY {
+            problems.push(field, "must be between 1s and 7 days");
+        }
+    }
+    if env.transcription_provider == TranscriptionProvider::AwsTranscribe
+        && env.storage_backend != StorageBackend::S3
+    {
+        problems.push(
+            "TRANSCRIPTION_PROVIDER",
+            "aws_transcribe needs STORAGE_BACKEND=s3 to read the audio from",
+        );
+    }
+
+    if let Some(temperatures) = &env.transcription_temperatures {
+        if env.transcription_provider != TranscriptionProvider::Openai {
+            problems.push(
+                "TRANSCRIPTION_TEMPERATURES",
+                "is only supported by the openai provider",
+            );
+        }
+        if temperatures.is_empty() || temperatures.iter().any(|t| !(0.0..=1.0).contains(t)) {
+            problems.push(
+                "TRANSCRIPTION_TEMPERATURES",
+                "expected temperatures between 0 and 1",
+            );
+        } else if temperatures.windows(2).any(|w| w[0] >= w[1]) {
+            problems.push("TRANSCRIPTION_TEMPERATURES", "must be in increasing order");
+        }
+    }
+
+    if env.transcription_language != transcribe::AUTO_LANGUAGE
+        && !transcribe::is_language_code(&env.transcription_language)
+    {
+        problems.push(
+            "TRANSCRIPTION_LANGUAGE",
+            "expected an ISO 639 language code or auto",
+        );
+    }
+    if !transcribe::is_language_code(&env.transcription_fallback_language) {
+        problems.push(
+            "TRANSCRIPTION_FALLBACK_LANGUAGE",
+            "expected an ISO 639 language code",
+        );
+    }
+    if !(0.0..=1.0).contains(&env.transcription_language_threshold) {
+        problems.push(
+            "TRANSCRIPTION_LANGUAGE_THRESHOLD",
+            "expected a value between 0 and 1",
+        );
+    }
+
+    let postgres = |url: &str| url.starts_with("postgres://") || url.starts_with("postgresql://");
+    if !postgres(&env.database_url) {
+        problems.push(
+            "DATABASE_URL",
+            "expected a postgres:// or postgresql:// URL",
+        );
+    }
+    if env
+        .database_read_url
+        .as_deref()
+        .is_some_and(|url| !postgres(url))
+    {
+        problems.push(
+            "DATABASE_READ_URL",
+            "expected a postgres:// or postgresql:// URL",
+        );
+    }
+
+    validate_filter(filter, problems);
+
+    if env.staging_prefix.is_empty() || env.staging_prefix.contains("//") {
+        problems.push("STAGING_PREFIX", "must be a non-empty object path");
+    }
+    if env.export_prefix.is_empty() || env.export_prefix.contains("//") {
+        problems.push("EXPORT_PREFIX", "must be a non-empty object path");
+    } else if env.export_prefix == env.staging_prefix {
+        problems.push("EXPORT_PREFIX", "must differ from STAGING_PREFIX");
+    }
+    if env.capture_prefix.is_empty() || env.capture_prefix.contains("//") {
+        problems.push("CAPTURE_PREFIX", "must be a non-empty object path");
+    } else if [&env.staging_prefix, &env.export_prefix].contains(&&env.capture_prefix) {
+        problems.push(
+            "CAPTURE_PREFIX",
+            "must differ from STAGING_PREFIX and EXPORT_PREFIX",
+        );
+    }
+    if env.failed_job_prefix.is_empty() || env.failed_job_prefix.contains("//") {
+        problems.push("FAILED_JOB_PREFIX", "must be a non-empty object path");
+    } else if [&env.staging_prefix, &env.export_prefix, &env.capture_prefix]
+        .contains(&&env.failed_job_prefix)
+    {
+        problems.push(
+            "FAILED_JOB_PREFIX",
+            "must differ from STAGING_PREFIX, EXPORT_PREFIX and CAPTURE_PREFIX",
+        );
+    }
+    if let Some(key) = &env.discord_public_key
+        && hex::decode(key).map(|k| k.len()) != Ok(32)
+    {
+        problems.push("DISCORD_PUBLIC_KEY", "expected 32 bytes of hex");
+    }
+    if env
+        .upload_body_limit
+        .is_some_and(|limit| limit < env.max_upload_size)
+    {
+        problems.push(
+            "UPLOAD_BODY_LIMIT",
+            "must be at least MAX_UPLOAD_SIZE or uploads at the file limit are rejected",
+        );
+    }
+    for (field, workers) in [
+        ("TRANSCRIPTION_WORKERS", env.transcription_workers),
+        ("STORAGE_WORKERS", env.storage_workers),
+        ("STORAGE_PUT_WORKERS", env.storage_put_workers),
+        ("NOTIFICATION_WORKERS", env.notification_workers),
+        ("QUEUE_WORKERS", env.queue_workers),
+        ("QUEUE_CAPACITY", env.queue_capacity),
+        (
+            "SERVER_HTTP2_MAX_STREAMS",
+            env.server_http2_max_streams as usize,
+        ),
+    ] {
+        if workers == 0 {
+            problems.push(field, "must be at least 1");
+        }
+    }
+    if env.retention_interval.is_zero() {
+        problems.push("RETENTION_INTERVAL", "must be longer than 0s");
+    }
+    if env
+        .testcall_audio
+        .as_deref()
+        .is_some_and(|path| !path.ends_with(".m4a"))
+    {
+        problems.push("TESTCALL_AUDIO", "must be an .m4a file");
+    }
+    if env.queue_readiness && env.queue_max_age.is_none() && env.queue_max_length.is_none() {
+        problems.push(
+            "QUEUE_READINESS",
+            "has no effect unless QUEUE_MAX_AGE or QUEUE_MAX_LENGTH is set",
+        );
+    }
+    if env.queue_priority_talkgroups.is_some() && !env.queue_priority {
+        problems.push(
+            "QUEUE_PRIORITY_TALKGROUPS",
+            "has no effect unless QUEUE_PRIORITY is set",
+        );
+    }
+    if env.speaker_segments && !env.word_timestamps {
+        problems.push(
+            "SPEAKER_SEGMENTS",
+            "has no effect unless WORD_TIMESTAMPS is set",
+        );
+    }
+    if env.transcript_keywords.is_some() && !env.word_timestamps {
+        problems.push(
+            "TRANSCRIPT_KEYWORDS",
+            "has no effect unless WORD_TIMESTAMPS is set",
+        );
+    }
+    if env.readiness_timeout.is_zero() {
+        problems.push("READINESS_TIMEOUT", "must be longer than 0s");
+    }
+    if env.readiness_transcription && env.transcription_endpoint.is_none() {
+        problems.push(
+            "READINESS_TRANSCRIPTION",
+            "has no effect unless TRANSCRIPTION_ENDPOINT is set",
+        );
+    }
+    if env.activity_window.is_some_and(|w| w.is_zero()) {
+        problems.push("ACTIVITY_WINDOW", "must be longer than 0s");
+    }
+    if env.replay_cache_size == 0 {
+        problems.push("REPLAY_CACHE_SIZE", "must be greater than 0");
+    }
+    if env.anomaly_threshold <= 0.0 {
+        problems.push("ANOMALY_THRESHOLD", "must be greater than 0");
+    }
+    if env.public_mode && env.public_groups.as_deref().is_none_or(|g| g.is_empty()) {
+        problems.push(
+            "PUBLIC_GROUPS",
+            "must list at least one group when PUBLIC_MODE is enabled",
+        );
+    }
+    if env.access_log_retention_days.is_some() && !env.access_log {
+        problems.push(
+            "ACCESS_LOG_RETENTION_DAYS",
+            "has no effect unless ACCESS_LOG is enabled",
+        );
+    }
 }
 
-fn init_http_client() -> Client {
+fn key_id(key: &str) -> String {
+    format!(
+        "key:{}",
+        &hex::encode(digest(&SHA256, key.as_bytes()))[..12]
+    )
+}
+
+fn init_read_keys(entries: &[String]) -> Result<Vec<ReadKey>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (key, groups) = entry.split_once('=').ok_or_else(|| {
+                Error::Configuration(format!("READ_KEYS entry must be key=groups: {}", entry))
+            })?;
+            if key.is_empty() {
+                return Err(Error::Configuration(
+                    "READ_KEYS entry has an empty key".to_string(),
+                ));
+            }
+            Ok(ReadKey {
+                key: key.to_string(),
+                id: key_id(key),
+                groups: match groups {
+                    "*" => None,
+                    groups => Some(groups.split('|').map(str::to_string).collect()),
+                },
+            })
+        })
+        .collect()
+}
+
+// A bare key may upload for every system; key=sys1|sys2 only for those
+fn init_upload_keys(entries: &[String]) -> Result<Vec<UploadKey>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (key, systems) = entry.split_once('=').unwrap_or((entry, "*"));
+            if key.is_empty() {
+                return Err(Error::Configuration(
+                    "API_KEYS entry has an empty key".to_string(),
+                ));
+            }
+            Ok(UploadKey {
+                key: key.to_string(),
+                id: key_id(key),
+                systems: match systems {
+                    "*" => None,
+                    systems => Some(systems.split('|').map(str::to_string).collect()),
+                },
+            })
+        })
+        .collect()
+}
+
+// Without NOTIFIERS, transcribed calls go to DISCORD_WEBHOOK as before under
+// the "webhook" destination key
+fn init_notifiers(env: &EnvConfig, layers: &Layers, problems: &mut Problems) -> Notifiers {
+    let mut notifiers = Notifiers::default();
+    let Some(names) = &env.notifiers else {
+        problems.check(
+            "DISCORD_WEBHOOK",
+            notifiers.register(
+                "webhook",
+                NotifierConfig {
+                    kind: NotifierKind::Discord,
+                    url: env.discord_webhook.clone(),
+                    tg_id: None,
+                    tg_group: None,
+                    retries: 0,
+                    retry_delay: default_retry_delay(),
+                    routed: true,
+                    token: None,
+                    channel: None,
+                    secret: None,
+                    topic: default_topic(),
+                    storage_events: false,
+                    storage_topic: default_storage_topic(),
+                    canary_percent: None,
+                },
+            ),
+        );
+        return notifiers;
+    };
+
+    for name in names {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            problems.push(
+                "NOTIFIERS",
+                format!("expected names of letters, digits and _, got {:?}", name),
+            );
+            continue;
+        }
+        let prefix = format!("NOTIFIER_{}_", name.to_ascii_uppercase());
+        let Some(config) = problems.load(layers.deserialize::<NotifierConfig>(&prefix)) else {
+            continue;
+        };
+        // mqtt:// and mqtts:// URLs are checked when the notifier is registered
+        if config.kind != NotifierKind::Mqtt {
+            check_url(problems, &format!("{}URL", prefix), &config.url);
+        }
+        if config.routed && config.kind != NotifierKind::Discord {
+            problems.push(
+                &format!("{}ROUTED", prefix),
+                "discord_routes only apply to discord notifiers",
+            );
+        }
+        if config.token.is_some() != config.channel.is_some() {
+            problems.push(
+                &format!("{}TOKEN", prefix),
+                "TOKEN and CHANNEL are set together to upload audio",
+            );
+        } else if config.token.is_some() && config.kind != NotifierKind::Slack {
+            problems.push(
+                &format!("{}TOKEN", prefix),
+                "audio uploads only apply to slack notifiers",
+            );
+        }
+        if config
+            .canary_percent
+            .is_some_and(|p| !(p > 0.0 && p <= 100.0))
+        {
+            problems.push(
+                &format!("{}CANARY_PERCENT", prefix),
+                "must be more than 0 and at most 100",
+            );
+        }
+        if config.secret.is_some() && config.kind != NotifierKind::Webhook {
+            problems.push(
+                &format!("{}SECRET", prefix),
+                "signing only applies to webhook notifiers",
+            );
+        }
+        problems.check("NOTIFIERS", notifiers.register(name, config));
+    }
+    notifiers
+}
+
+type Store = (Arc<dyn ObjectStore>, Option<Arc<dyn Signer>>);
+
+// BUCKET_NAME is the bucket, or the container for azure. Credentials come from
+// each backend's usual AWS_*, GOOGLE_* or AZURE_STORAGE_* variables.
+fn build_store(env: &EnvConfig) -> object_store::Result<Store> {
+    let b = &env.bucket_name;
+    Ok(match env.storage_backend {
+        StorageBackend::S3 => {
+            let store = Arc::new(AmazonS3Builder::from_env().with_bucket_name(b).build()?);
+            (store.clone(), Some(store))
+        }
+        StorageBackend::Gcs => {
+            let store = Arc::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(b)
+                    .build()?,
+            );
+            (store.clone(), Some(store))
+        }
+        StorageBackend::Azure => {
+            let store = Arc::new(
+                MicrosoftAzureBuilder::from_env()
+                    .with_container_name(b)
+                    .build()?,
+            );
+            (store.clone(), Some(store))
+        }
+        // objects are laid out under the root exactly as they are in a bucket
+        StorageBackend::Local => {
+            let root = env.storage_root.as_deref().unwrap_or_default();
+            std::fs::create_dir_all(root).map_err(|e| object_store::Error::Generic {
+                store: "LocalFileSystem",
+                source: Box::new(e),
+            })?;
+            let store =
+                Arc::new(LocalFileSystem::new_with_prefix(root)?.with_automatic_cleanup(true));
+            (store, None)
+        }
+    })
+}
+
+fn init_store(env: &EnvConfig) -> Result<Store> {
+    build_store(env)
+        .map_err(|e| Error::Configuration(format!("storage client configuration error: {}", e)))
+}
+
+fn init_http_client(env: &EnvConfig) -> Client {
     Client::builder()
-        .timeout(Duration::from_secs(60))
-        .connect_timeout(Duration::from_secs(20))
+        .timeout(env.http_timeout)
+        .connect_timeout(env.http_connect_timeout)
         .build()
         .expect("Failed to create HTTP client")
 }
@@ -88,15 +1336,281 @@ fn init_db_pool(url: &str) -> Result<Pool<ConnectionManager<PgConnection>>> {
 }
 
 pub fn initialize() -> Result<ProcessorConfig> {
-    let env = init_env()?;
-    let s3_client = init_s3_client(&env.bucket_name)?;
+    let mut problems = Problems::default();
+    let Some(layers) = problems.check("CONFIG_FILE", Layers::load()) else {
+        return Err(problems.into_error());
+    };
+
+    let env = problems.load(layers.deserialize::<EnvConfig>(""));
+    let filter = problems.load(layers.deserialize::<FilterConfig>("FILTER_"));
+    let (Some(env), Some(filter)) = (env, filter) else {
+        return Err(problems.into_error());
+    };
+
+    validate(&env, &filter, &mut problems);
+    let read_keys = problems.check(
+        "READ_KEYS",
+        init_read_keys(env.read_keys.as_deref().unwrap_or_default()),
+    );
+    let transcript_cipher = problems.check(
+        "TRANSCRIPTION_KEY",
+        env.transcription_key
+            .as_deref()
+            .map(TranscriptCipher::new)
+            .transpose(),
+    );
+    let notifiers = init_notifiers(&env, &layers, &mut problems);
+    let transcriber = problems.check("TRANSCRIPTION_PROVIDER", transcribe::init(&env));
+    let upload_keys = problems.check(
+        "API_KEYS",
+        init_upload_keys(env.api_keys.as_deref().unwrap_or_default()),
+    );
+    let retention = problems.check(
+        "RETENTION_POLICIES",
+        retention::parse(env.retention_policies.as_deref().unwrap_or_default()),
+    );
+    let talkgroup_weights = problems.check(
+        "QUEUE_PRIORITY_TALKGROUPS",
+        priority::parse(env.queue_priority_talkgroups.as_deref().unwrap_or_default()),
+    );
+    let talkgroup_languages = problems.check(
+        "TRANSCRIPTION_LANGUAGE_TALKGROUPS",
+        transcribe::parse_languages(
+            env.transcription_language_talkgroups
+                .as_deref()
+                .unwrap_or_default(),
+        ),
+    );
+    let upload_policy = problems.check(
+        "UPLOAD_POLICY",
+        policy::parse(env.upload_policy.as_deref().unwrap_or_default()),
+    );
+    let (
+        Some(read_keys),
+        Some(upload_keys),
+        Some(transcript_cipher),
+        Some(transcriber),
+        Some(retention),
+        Some(talkgroup_weights),
+        Some(talkgroup_languages),
+        Some(upload_policy),
+    ) = (
+        read_keys,
+        upload_keys,
+        transcript_cipher,
+        transcriber,
+        retention,
+        talkgroup_weights,
+        talkgroup_languages,
+        upload_policy,
+    )
+    else {
+        return Err(problems.into_error());
+    };
+    if !problems.is_empty() {
+        return Err(problems.into_error());
+    }
+
+    let (s3_client, signer) = init_store(&env)?;
     let db_pool = init_db_pool(&env.database_url)?;
+    let read_pool = match &env.database_read_url {
+        Some(url) => init_db_pool(url)?,
+        None => db_pool.clone(),
+    };
+    let replay_cache = env
+        .replay_window_secs
+        .map(|window| Arc::new(Mutex::new(ReplayCache::new(window, env.replay_cache_size))));
+    let activity = env
+        .activity_window
+        .map(|window| Arc::new(Mutex::new(TalkgroupActivity::new(window))));
+
+    let pools = Arc::new(WorkerPools {
+        transcription: WorkerPool::new("transcription", env.transcription_workers),
+        storage: WorkerPool::new("storage", env.storage_workers),
+        notifications: WorkerPool::new("notifications", env.notification_workers),
+        puts: Arc::new(WorkerPool::new("storage_puts", env.storage_put_workers)),
+    });
+    let s3_client: Arc<dyn ObjectStore> = Arc::new(PutQueue::new(
+        s3_client,
+        pools.puts.clone(),
+        env.storage_put_queue,
+    ));
+
+    let switches = Arc::new(Switches::new(SwitchState {
+        transcription: !env.disable_transcription,
+        notifications: !env.disable_notifications,
+        database: !env.disable_db_writes,
+    }));
+    let state = switches.state();
+    if !(state.transcription && state.notifications && state.database) {
+        warn!(switches = ?state, "Starting with degraded processing");
+    }
+    let queue = Arc::new(JobQueue::new(env.queue_capacity));
 
     Ok(ProcessorConfig {
+        http_client: init_http_client(&env),
         env,
         s3_client,
+        signer,
         db_pool,
-        http_client: init_http_client(),
-        filter: init_filter()?,
+        read_pool,
+        filter: Arc::new(ArcSwap::from_pointee(filter)),
+        reconcile_report: Arc::new(RwLock::new(None)),
+        replay_cache,
+        activity,
+        read_keys,
+        retention,
+        talkgroup_weights,
+        talkgroup_languages,
+        upload_policy,
+        upload_keys,
+        transcript_cipher,
+        pools,
+        switches,
+        notifiers: Arc::new(ArcSwap::from_pointee(notifiers)),
+        transcriber,
+        queue,
+        events: Arc::new(CallEvents::new()),
+        background: Arc::new(Background::default()),
     })
 }
+
+// scheme://host[:port] of `url`, leaving out credentials, webhook tokens in
+// the path and keys in the query
+fn origin(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}://{}:{}", url.scheme(), host, port),
+            (Some(host), None) => format!("{}://{}", url.scheme(), host),
+            (None, _) => url.scheme().to_string(),
+        },
+        Err(_) => "invalid URL".to_string(),
+    }
+}
+
+// host:port/database of a postgres URL
+fn database(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => format!(
+            "{}:{}{}",
+            url.host_str().unwrap_or("localhost"),
+            url.port().unwrap_or(5432),
+            url.path()
+        ),
+        Err(_) => "invalid URL".to_string(),
+    }
+}
+
+// What the processor is running with, logged once at startup so a
+// misconfiguration shows in the first lines. Secrets are only reported as
+// set or not.
+pub fn log_summary(c: &ProcessorConfig) {
+    let env = &c.env;
+    let storage = match env.storage_backend {
+        StorageBackend::Local => env.storage_root.clone().unwrap_or_default(),
+        StorageBackend::S3 => match std::env::var("AWS_ENDPOINT") {
+            Ok(endpoint) => format!("{} at {}", env.bucket_name, origin(&endpoint)),
+            Err(_) => env.bucket_name.clone(),
+        },
+        _ => env.bucket_name.clone(),
+    };
+    let notifiers = c.notifiers.load();
+    let destinations: Vec<String> = notifiers
+        .names()
+        .into_iter()
+        .filter_map(|name| {
+            let config = notifiers.config(name)?;
+            let kind = format!("{:?}", config.kind).to_ascii_lowercase();
+            Some(format!("{} ({} {})", name, kind, origin(&config.url)))
+        })
+        .collect();
+    let filter = c.filter.load();
+
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        storage_backend = ?env.storage_backend,
+        storage = %storage,
+        database = %database(&env.database_url),
+        read_replica = env.database_read_url.as_deref().map(database),
+        transcription_provider = c.transcriber.name(),
+        transcription_endpoint = env.transcription_endpoint.as_deref().map(origin),
+        transcription_model = env.model_name.as_deref(),
+        transcription_language = %env.transcription_language,
+        transcription_language_talkgroups = c.talkgroup_languages.len(),
+        transcription_enabled = !env.disable_transcription,
+        notifiers = ?destinations,
+        filter_tg_ids = filter.tgid().len(),
+        filter_tg_groups = filter.group().len(),
+        retention_policies = c.retention.len(),
+        queue_workers = env.queue_workers,
+        hash_algorithm = env.hash_algorithm.name(),
+        upload_keys = c.upload_keys.len(),
+        upload_policy = ?c.upload_policy.enforced(),
+        read_keys = c.read_keys.len(),
+        admin_token = env.admin_token.is_some(),
+        transcription_key = c.transcript_cipher.is_some(),
+        transcription_compress_size = env.transcription_compress_size,
+        config_file = std::env::var("CONFIG_FILE").ok(),
+        "Effective configuration"
+    );
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadReport {
+    pub filter_tg_id: Vec<String>,
+    pub filter_tg_group: Vec<String>,
+    pub notifiers: Vec<String>,
+}
+
+// Re-reads FILTER_* and the notifiers from CONFIG_FILE and the environment and
+// swaps them in, leaving everything else as it started. Nothing changes when
+// any of it is invalid. The environment of a running process is fixed, so in
+// practice this picks up edits to CONFIG_FILE.
+pub fn reload(c: &ProcessorConfig) -> Result<ReloadReport> {
+    let mut problems = Problems::default();
+    let Some(layers) = problems.check("CONFIG_FILE", Layers::load()) else {
+        return Err(problems.into_error());
+    };
+    let env = problems.load(layers.deserialize::<EnvConfig>(""));
+    let filter = problems.load(layers.deserialize::<FilterConfig>("FILTER_"));
+    let (Some(env), Some(filter)) = (env, filter) else {
+        return Err(problems.into_error());
+    };
+    validate_filter(&filter, &mut problems);
+    check_url(&mut problems, "DISCORD_WEBHOOK", &env.discord_webhook);
+    let notifiers = init_notifiers(&env, &layers, &mut problems);
+    if !problems.is_empty() {
+        return Err(problems.into_error());
+    }
+
+    let report = ReloadReport {
+        filter_tg_id: filter.tgid(),
+        filter_tg_group: filter.group(),
+        notifiers: notifiers.names().into_iter().map(str::to_string).collect(),
+    };
+    c.filter.store(Arc::new(filter));
+    c.notifiers.store(Arc::new(notifiers));
+    info!(
+        tgid = report.filter_tg_id.join(", "),
+        group = report.filter_tg_group.join(", "),
+        notifiers = ?report.notifiers,
+        "Reloaded filter and notifier configuration"
+    );
+    Ok(report)
+}
+
+// Reloads on every SIGHUP
+pub async fn reload_on_hangup(c: ProcessorConfig) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!(error = %e, "Failed to listen for SIGHUP, reload with POST /admin/reload");
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        if let Err(e) = reload(&c) {
+            error!(error = %e, "Failed to reload configuration, keeping the current one");
+        }
+    }
+}
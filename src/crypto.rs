@@ -0,0 +1,146 @@
+use crate::compression;
+use crate::error::{Error, Result};
+use crate::model::Call;
+
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+#[derive(Clone)]
+pub struct TranscriptCipher {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for TranscriptCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TranscriptCipher(..)")
+    }
+}
+
+impl TranscriptCipher {
+    pub fn new(hex_key: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_key)
+            .map_err(|e| Error::Configuration(format!("Invalid TRANSCRIPTION_KEY: {}", e)))?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| {
+            Error::Configuration("TRANSCRIPTION_KEY must be 32 bytes of hex".to_string())
+        })?;
+
+        Ok(TranscriptCipher {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn encrypt(&self, plaintext: &str, aad: &str) -> Result<String> {
+        Ok(format!(
+            "{}{}",
+            ENCRYPTED_PREFIX,
+            hex::encode(self.seal_bytes(plaintext.as_bytes(), aad)?)
+        ))
+    }
+
+    pub fn decrypt(&self, stored: &str, aad: &str) -> Result<String> {
+        let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+            return Ok(stored.to_string());
+        };
+
+        let failed = || Error::Configuration("Failed to decrypt transcription".to_string());
+        let bytes = hex::decode(encoded).map_err(|_| failed())?;
+        String::from_utf8(self.open_bytes(&bytes, aad)?).map_err(|_| failed())
+    }
+
+    // The nonce followed by the sealed bytes, for compressed transcriptions
+    // stored as bytea
+    pub fn seal_bytes(&self, plaintext: &[u8], aad: &str) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| Error::Configuration("Failed to generate nonce".to_string()))?;
+
+        let mut sealed = plaintext.to_vec();
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(aad.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| Error::Configuration("Failed to encrypt transcription".to_string()))?;
+
+        let mut stored = nonce.to_vec();
+        stored.append(&mut sealed);
+        Ok(stored)
+    }
+
+    pub fn open_bytes(&self, stored: &[u8], aad: &str) -> Result<Vec<u8>> {
+        let failed = || Error::Configuration("Failed to decrypt transcription".to_string());
+        if stored.len() < NONCE_LEN {
+            return Err(failed());
+        }
+        let (nonce, sealed) = stored.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| failed())?;
+
+        let mut sealed = sealed.to_vec();
+        let plaintext = self
+            .key
+            .open_in_place(nonce, Aad::from(aad.as_bytes()), &mut sealed)
+            .map_err(|_| failed())?;
+        Ok(plaintext.to_vec())
+    }
+
+    // A compressed transcription's marker is left as it is; the text itself
+    // is sealed in compressed_transcriptions
+    pub fn seal(&self, text: &str, aad: &str) -> Result<String> {
+        if text == compression::MARKER {
+            return Ok(text.to_string());
+        }
+        self.encrypt(text, aad)
+    }
+
+    pub fn seal_call(&self, call: &mut Call) -> Result<()> {
+        if let Some(text) = &call.transcription {
+            call.transcription = Some(self.seal(text, &call.filename)?);
+        }
+        if let Some(text) = &call.public_transcription {
+            call.public_transcription = Some(self.seal(text, &call.filename)?);
+        }
+        Ok(())
+    }
+
+    pub fn open_call(&self, call: &mut Call) -> Result<()> {
+        if let Some(text) = &call.transcription {
+            call.transcription = Some(self.decrypt(text, &call.filename)?);
+        }
+        if let Some(text) = &call.public_transcription {
+            call.public_transcription = Some(self.decrypt(text, &call.filename)?);
+        }
+        Ok(())
+    }
+
+    // Archived notification payloads carry the transcription, so the whole
+    // payload is sealed as one string, bound to its call
+    pub fn seal_payload(
+        &self,
+        payload: serde_json::Value,
+        call_id: &str,
+    ) -> Result<serde_json::Value> {
+        let json = serde_json::to_string(&payload)?;
+        Ok(serde_json::Value::String(self.encrypt(&json, call_id)?))
+    }
+
+    pub fn open_payload(
+        &self,
+        payload: serde_json::Value,
+        call_id: &str,
+    ) -> Result<serde_json::Value> {
+        match payload {
+            serde_json::Value::String(s) if s.starts_with(ENCRYPTED_PREFIX) => {
+                Ok(serde_json::from_str(&self.decrypt(&s, call_id)?)?)
+            }
+            payload => Ok(payload),
+        }
+    }
+}
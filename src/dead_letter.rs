@@ -0,0 +1,370 @@
+use crate::common::{FileData, UploadData, UploadedFile};
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{FailedJob, JobStage, MetadataVersion};
+use crate::renotify;
+use crate::schema::failed_jobs;
+use crate::trace::TraceContext;
+use crate::worker::{self, Job};
+
+use axum::{body::Bytes, http::HeaderMap};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{delete, insert_into, prelude::*, update};
+use object_store::{ObjectStore, path::Path};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+// How often failed jobs whose next attempt is due are put back on the queue
+const RETRY_SCAN_INTERVAL: Duration = Duration::from_secs(30);
+// A requeued job is not picked up again for this long, in case the process
+// stops before the job finishes
+const IN_FLIGHT_LEASE: TimeDelta = TimeDelta::hours(1);
+const MAX_BACKOFF: TimeDelta = TimeDelta::hours(24);
+
+fn backoff(c: &ProcessorConfig, attempts: i32) -> TimeDelta {
+    let base = TimeDelta::from_std(c.env.failed_job_backoff).unwrap_or(MAX_BACKOFF);
+    base.checked_mul(1 << (attempts - 1).clamp(0, 16))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+// None once FAILED_JOB_RETRIES automatic retries have been used up
+fn next_attempt(c: &ProcessorConfig, attempts: i32) -> Option<DateTime<Utc>> {
+    (attempts <= c.env.failed_job_retries as i32).then(|| Utc::now() + backoff(c, attempts))
+}
+
+// Moves a failed job's audio out of staging, or out of memory, into
+// FAILED_JOB_PREFIX, where reconcile doesn't take it for a stale upload
+// however long it waits
+async fn keep_audio(c: &ProcessorConfig, audio: &UploadedFile) -> Result<Path> {
+    if let FileData::Staged(location) = &audio.data
+        && location.prefix_matches(&Path::parse(&c.env.failed_job_prefix)?)
+    {
+        return Ok(location.clone());
+    }
+    let kept = Path::parse(format!(
+        "{}/{}/{}",
+        c.env.failed_job_prefix,
+        Utc::now().timestamp_micros(),
+        audio.name
+    ))?;
+    match &audio.data {
+        FileData::Staged(location) => c.s3_client.rename(location, &kept).await?,
+        FileData::Memory(data) => {
+            c.s3_client.put(&kept, data.clone().into()).await?;
+        }
+    }
+    Ok(kept)
+}
+
+// Undoes keep_audio for a job whose row couldn't be written
+async fn release_audio(c: &ProcessorConfig, audio: &UploadedFile, kept: &Path) {
+    let result = match &audio.data {
+        FileData::Staged(location) if location == kept => return,
+        FileData::Staged(location) => c.s3_client.rename(kept, location).await,
+        FileData::Memory(_) => c.s3_client.delete(kept).await,
+    };
+    if let Err(err) = result {
+        warn!(path = %kept, error = %err, "Failed to move failed upload back");
+    }
+}
+
+// Keeps a job that ran out of queue retries. Its metadata goes into
+// failed_jobs and its audio under FAILED_JOB_PREFIX, so a retry starts from
+// the same upload without trunk-recorder sending it again. A job that failed
+// after storing its call keeps the committed audio where it is instead, and
+// a retry only notifies it.
+pub async fn record(c: &ProcessorConfig, job: &Job, e: &Error) {
+    let audio = &job.files.audio;
+    if !c.switches.database() {
+        warn!(file = %audio.name, "Database writes disabled, dropping failed upload");
+        job.discard(c).await;
+        return;
+    }
+
+    let stage = match job.stored {
+        Some(_) => JobStage::Notify,
+        None => JobStage::Store,
+    };
+    let kept = match (&stage, &audio.data) {
+        (JobStage::Notify, FileData::Staged(location)) => Ok(location.clone()),
+        (JobStage::Notify, FileData::Memory(_)) => Err(Error::InvalidRequest(
+            "stored call has no committed audio".to_string(),
+        )),
+        (JobStage::Store, _) => keep_audio(c, audio).await,
+    };
+    let kept = match kept {
+        Ok(kept) => kept,
+        Err(err) => {
+            error!(file = %audio.name, error = %err, "Failed to keep failed upload");
+            return;
+        }
+    };
+    let result = match job.failed_id {
+        Some(id) => retried(c, id, stage, &kept, e),
+        None => insert(c, job, stage, &kept, e),
+    };
+    // without its row the audio goes back where the job expects it
+    if result.is_err() && stage == JobStage::Store {
+        release_audio(c, audio, &kept).await;
+    }
+    match result {
+        Ok(row) if row.next_attempt_at.is_some() => info!(
+            id = row.id,
+            file = %row.audio_name,
+            attempts = row.attempts,
+            next_attempt_at = %row.next_attempt_at.unwrap_or_default(),
+            "Kept failed upload for retry"
+        ),
+        Ok(row) => error!(
+            id = row.id,
+            file = %row.audio_name,
+            attempts = row.attempts,
+            "Failed upload ran out of retries"
+        ),
+        Err(err) => {
+            error!(file = %job.files.audio.name, error = %err, "Failed to keep failed upload");
+        }
+    }
+}
+
+fn insert(
+    c: &ProcessorConfig,
+    job: &Job,
+    stage: JobStage,
+    location: &Path,
+    e: &Error,
+) -> Result<FailedJob> {
+    let FileData::Memory(metadata) = &job.files.json.data else {
+        return Err(Error::InvalidRequest("metadata was not read".to_string()));
+    };
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    insert_into(failed_jobs::table)
+        .values(&FailedJob {
+            id: 0,
+            json_name: job.files.json.name.clone(),
+            metadata: String::from_utf8_lossy(metadata).into_owned(),
+            audio_name: job.files.audio.name.clone(),
+            audio_path: location.to_string(),
+            tags: serde_json::to_value(&job.files.tags)?,
+            version: job.files.version.map(|v| v.major().to_string()),
+            language: job.files.language.clone(),
+            archive_reason: job.archive,
+            error: e.to_string(),
+            attempts: 1,
+            failed_at: Utc::now(),
+            next_attempt_at: next_attempt(c, 1),
+            stage,
+        })
+        .returning(FailedJob::as_returning())
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+fn retried(
+    c: &ProcessorConfig,
+    id: i64,
+    stage: JobStage,
+    location: &Path,
+    e: &Error,
+) -> Result<FailedJob> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let attempts: i32 = failed_jobs::table
+        .find(id)
+        .select(failed_jobs::attempts)
+        .first(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    update(failed_jobs::table.find(id))
+        .set((
+            failed_jobs::audio_path.eq(location.to_string()),
+            failed_jobs::stage.eq(stage),
+            failed_jobs::error.eq(e.to_string()),
+            failed_jobs::attempts.eq(attempts + 1),
+            failed_jobs::failed_at.eq(Utc::now()),
+            failed_jobs::next_attempt_at.eq(next_attempt(c, attempts + 1)),
+        ))
+        .returning(FailedJob::as_returning())
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Drops a failed job once a retry has processed it
+pub fn resolve(c: &ProcessorConfig, id: i64) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    delete(failed_jobs::table.find(id))
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    info!(id, "Failed upload processed on retry");
+    Ok(())
+}
+
+async fn requeue(c: &ProcessorConfig, row: FailedJob) -> Result<()> {
+    let audio = UploadedFile::staged(
+        &c.s3_client,
+        row.audio_name.clone(),
+        Path::parse(&row.audio_path)?,
+    )
+    .await?;
+    let files = UploadData {
+        json: UploadedFile::in_memory(row.json_name, Bytes::from(row.metadata)),
+        audio,
+        tags: serde_json::from_value(row.tags)?,
+        version: row
+            .version
+            .as_deref()
+            .map(MetadataVersion::from_header)
+            .transpose()?,
+        language: row.language,
+    };
+    let replay_key = (files.audio.name.clone(), files.checksum());
+    let stored = match row.stage {
+        JobStage::Store => None,
+        JobStage::Notify => {
+            Some(renotify::load_stored(c, &row.audio_path, files.language.as_deref()).await?)
+        }
+    };
+
+    {
+        let mut connection = c
+            .db_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+        update(failed_jobs::table.find(row.id))
+            .set(failed_jobs::next_attempt_at.eq(Utc::now() + IN_FLIGHT_LEASE))
+            .execute(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?;
+    }
+
+    worker::enqueue(
+        c,
+        Job {
+            files,
+            archive: row.archive_reason,
+            replay_key,
+            trace: TraceContext::from_headers(&HeaderMap::new()),
+            enqueued_at: Instant::now(),
+            failed_id: Some(row.id),
+            stored,
+        },
+    )
+    .await
+}
+
+fn due(c: &ProcessorConfig) -> Result<Vec<FailedJob>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    failed_jobs::table
+        .filter(failed_jobs::next_attempt_at.le(Utc::now()))
+        .select(FailedJob::as_select())
+        .order(failed_jobs::next_attempt_at.asc())
+        .limit(c.queue.capacity as i64 / 2)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+pub async fn run_periodic(c: ProcessorConfig) {
+    let mut interval = tokio::time::interval(RETRY_SCAN_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !c.switches.database() {
+            continue;
+        }
+        let rows = match due(&c) {
+            Ok(rows) => rows,
+            Err(e) => {
+                warn!(error = %e, "Failed to load failed uploads");
+                continue;
+            }
+        };
+        for row in rows {
+            let id = row.id;
+            if let Err(e) = requeue(&c, row).await {
+                warn!(id, error = %e, "Failed to requeue failed upload");
+            }
+        }
+    }
+}
+
+pub fn list(c: &ProcessorConfig, dead: Option<bool>, limit: Option<i64>) -> Result<Vec<FailedJob>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = failed_jobs::table
+        .select(FailedJob::as_select())
+        .order(failed_jobs::failed_at.desc())
+        .limit(limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .into_boxed();
+
+    match dead {
+        Some(true) => query = query.filter(failed_jobs::next_attempt_at.is_null()),
+        Some(false) => query = query.filter(failed_jobs::next_attempt_at.is_not_null()),
+        None => {}
+    }
+
+    query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Puts a failed job back on the queue now, whether or not it has retries left
+pub async fn requeue_now(c: &ProcessorConfig, id: i64) -> Result<()> {
+    let row: FailedJob = {
+        let mut connection = c
+            .db_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+        failed_jobs::table
+            .find(id)
+            .select(FailedJob::as_select())
+            .first(&mut connection)
+            .optional()
+            .map_err(|e| Error::Database(e.to_string()))?
+            .ok_or_else(|| Error::NotFound(format!("failed job {}", id)))?
+    };
+    requeue(c, row).await
+}
+
+// Drops a failed job and the audio kept for it, unless that audio is a
+// stored call's
+pub async fn cancel(c: &ProcessorConfig, id: i64) -> Result<()> {
+    let (audio_path, stage): (String, JobStage) = {
+        let mut connection = c
+            .db_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+        delete(failed_jobs::table.find(id))
+            .returning((failed_jobs::audio_path, failed_jobs::stage))
+            .get_result(&mut connection)
+            .optional()
+            .map_err(|e| Error::Database(e.to_string()))?
+            .ok_or_else(|| Error::NotFound(format!("failed job {}", id)))?
+    };
+    if stage == JobStage::Notify {
+        return Ok(());
+    }
+    match c.s3_client.delete(&Path::parse(&audio_path)?).await {
+        Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,358 @@
+use crate::activity;
+use crate::audio;
+use crate::common::DiscordMessage;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{AudioMetadata, DeliveryType, Subscription};
+use crate::notifications::{self, Retry};
+use crate::schema;
+use crate::segments;
+use crate::trace;
+use crate::upload::create_embed;
+use crate::words;
+
+use axum::{Json, body::Bytes, extract::State, http::HeaderMap};
+use diesel::{delete, insert_into, prelude::*};
+use reqwest::Client;
+use ring::signature::{ED25519, UnparsedPublicKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+const DISCORD_API: &str = "https://discord.com/api/v10";
+const EPHEMERAL_FLAG: u64 = 1 << 6;
+
+#[derive(Debug, Deserialize)]
+struct Interaction {
+    #[serde(rename = "type")]
+    kind: u8,
+    data: Option<CommandData>,
+    member: Option<Member>,
+    user: Option<User>,
+    channel_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandData {
+    name: String,
+    #[serde(default)]
+    options: Vec<CommandOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandOption {
+    name: String,
+    value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    user: User,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Channel {
+    id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InteractionResponse {
+    #[serde(rename = "type")]
+    kind: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<InteractionResponseData>,
+}
+
+#[derive(Debug, Serialize)]
+struct InteractionResponseData {
+    content: String,
+    flags: u64,
+}
+
+impl InteractionResponse {
+    fn pong() -> Self {
+        InteractionResponse {
+            kind: 1,
+            data: None,
+        }
+    }
+    fn message(content: String) -> Self {
+        InteractionResponse {
+            kind: 4,
+            data: Some(InteractionResponseData {
+                content,
+                flags: EPHEMERAL_FLAG,
+            }),
+        }
+    }
+}
+
+impl Interaction {
+    fn user_id(&self) -> Option<String> {
+        self.member
+            .as_ref()
+            .map(|m| m.user.id.clone())
+            .or_else(|| self.user.as_ref().map(|u| u.id.clone()))
+    }
+}
+
+impl CommandData {
+    fn option(&self, name: &str) -> Option<&Value> {
+        self.options
+            .iter()
+            .find(|o| o.name == name)
+            .map(|o| &o.value)
+    }
+    fn talkgroup(&self) -> Result<i32> {
+        self.option("talkgroup")
+            .and_then(Value::as_i64)
+            .and_then(|v| i32::try_from(v).ok())
+            .ok_or_else(|| Error::MissingField("talkgroup".to_string()))
+    }
+}
+
+fn verify_signature(public_key: &str, headers: &HeaderMap, body: &[u8]) -> Result<()> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| Error::Unauthorized(format!("Missing {} header", name)))
+    };
+
+    let signature = hex::decode(header("x-signature-ed25519")?)
+        .map_err(|_| Error::Unauthorized("Malformed request signature".to_string()))?;
+    let timestamp = header("x-signature-timestamp")?;
+    let key = hex::decode(public_key)
+        .map_err(|e| Error::Configuration(format!("Invalid DISCORD_PUBLIC_KEY: {}", e)))?;
+
+    let message = [timestamp.as_bytes(), body].concat();
+    UnparsedPublicKey::new(&ED25519, key)
+        .verify(&message, &signature)
+        .map_err(|_| Error::Unauthorized("Invalid request signature".to_string()))
+}
+
+fn handle_command(i: &Interaction, c: &ProcessorConfig) -> Result<String> {
+    use schema::subscriptions::dsl::*;
+
+    let command = i
+        .data
+        .as_ref()
+        .ok_or_else(|| Error::MissingField("data".to_string()))?;
+    let user = i
+        .user_id()
+        .ok_or_else(|| Error::MissingField("user".to_string()))?;
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    match command.name.as_str() {
+        "subscribe" => {
+            let tg = command.talkgroup()?;
+            let kind = match command.option("delivery").and_then(Value::as_str) {
+                Some("thread") => DeliveryType::Thread,
+                _ => DeliveryType::Dm,
+            };
+            let channel = match kind {
+                DeliveryType::Thread => Some(
+                    i.channel_id
+                        .clone()
+                        .ok_or_else(|| Error::MissingField("channel_id".to_string()))?,
+                ),
+                DeliveryType::Dm => None,
+            };
+
+            let subscription = Subscription {
+                user_id: user.clone(),
+                talkgroup: tg,
+                delivery: kind,
+                channel_id: channel,
+            };
+
+            insert_into(subscriptions)
+                .values(&subscription)
+                .on_conflict((user_id, talkgroup))
+                .do_update()
+                .set(&subscription)
+                .execute(&mut connection)
+                .map_err(|e| Error::Database(e.to_string()))?;
+
+            info!(user = %user, talkgroup = tg, "Subscription added");
+            Ok(format!("Subscribed to talkgroup {}", tg))
+        }
+        "unsubscribe" => {
+            let tg = command.talkgroup()?;
+            let removed = delete(subscriptions.filter(user_id.eq(&user).and(talkgroup.eq(tg))))
+                .execute(&mut connection)
+                .map_err(|e| Error::Database(e.to_string()))?;
+
+            if removed == 0 {
+                Ok(format!("You are not subscribed to talkgroup {}", tg))
+            } else {
+                info!(user = %user, talkgroup = tg, "Subscription removed");
+                Ok(format!("Unsubscribed from talkgroup {}", tg))
+            }
+        }
+        "subscriptions" => {
+            let tgs: Vec<i32> = subscriptions
+                .filter(user_id.eq(&user))
+                .select(talkgroup)
+                .order(talkgroup)
+                .load(&mut connection)
+                .map_err(|e| Error::Database(e.to_string()))?;
+
+            if tgs.is_empty() {
+                Ok("You have no talkgroup subscriptions".to_string())
+            } else {
+                Ok(format!(
+                    "Subscribed talkgroups: {}",
+                    tgs.iter()
+                        .map(|tg| tg.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+        other => Err(Error::InvalidRequest(format!("Unknown command: {}", other))),
+    }
+}
+
+async fn open_dm_channel(client: &Client, token: &str, user: &str) -> Result<String> {
+    let channel: Channel =
+        trace::inject(client.post(format!("{}/users/@me/channels", DISCORD_API)))
+            .header("Authorization", format!("Bot {}", token))
+            .json(&json!({ "recipient_id": user }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+    Ok(channel.id)
+}
+
+async fn deliver(
+    c: &ProcessorConfig,
+    token: &str,
+    sub: &Subscription,
+    call_id: &str,
+    embed: &DiscordMessage,
+) -> Result<()> {
+    let (channel, destination) = match sub.delivery {
+        DeliveryType::Dm => (
+            open_dm_channel(&c.http_client, token, &sub.user_id).await?,
+            format!("dm:{}", sub.user_id),
+        ),
+        DeliveryType::Thread => {
+            let channel = sub
+                .channel_id
+                .clone()
+                .ok_or_else(|| Error::MissingField("channel_id".to_string()))?;
+            let destination = format!("thread:{}:{}", channel, sub.user_id);
+            (channel, destination)
+        }
+    };
+
+    let url = format!("{}/channels/{}/messages", DISCORD_API, channel);
+    let request = || {
+        trace::inject(c.http_client.post(&url))
+            .header("Authorization", format!("Bot {}", token))
+            .json(embed)
+    };
+
+    notifications::send(c, call_id, &destination, embed, Retry::NONE, request).await
+}
+
+pub async fn notify_subscribers(m: &AudioMetadata, c: &ProcessorConfig) -> Result<()> {
+    use schema::subscriptions::dsl::*;
+
+    let Some(token) = &c.env.discord_bot_token else {
+        return Ok(());
+    };
+
+    let subs: Vec<Subscription> = {
+        let mut connection = c
+            .db_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        subscriptions
+            .filter(talkgroup.eq(m.talkgroup.talkgroup))
+            .load(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?
+    };
+
+    let audio_url = audio::notification_url(c, &m.call.filename).await;
+    let (keywords, segments) = if subs.is_empty() {
+        (Vec::new(), Vec::new())
+    } else {
+        (
+            words::stored_keyword_hits(c, &m.call.filename),
+            segments::stored(c, &m.call.filename),
+        )
+    };
+    for sub in &subs {
+        let mut embed = create_embed(
+            m,
+            m.call.transcription.clone(),
+            audio_url.as_deref(),
+            &keywords,
+            &segments,
+        );
+        embed.footer = activity::footer(c, m);
+        let message = DiscordMessage {
+            content: match sub.delivery {
+                DeliveryType::Thread => Some(format!("<@{}>", sub.user_id)),
+                DeliveryType::Dm => None,
+            },
+            embeds: vec![embed],
+        };
+
+        if let Err(e) = deliver(c, token, sub, &m.call.filename, &message).await {
+            warn!(user = %sub.user_id, error = %e, "Failed to deliver subscription");
+        }
+    }
+
+    if !subs.is_empty() {
+        info!(
+            talkgroup = m.talkgroup.talkgroup,
+            count = subs.len(),
+            "Notified subscribers"
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn interactions(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<InteractionResponse>> {
+    let public_key = config
+        .env
+        .discord_public_key
+        .as_deref()
+        .ok_or_else(|| Error::Configuration("DISCORD_PUBLIC_KEY is not set".to_string()))?;
+
+    verify_signature(public_key, &headers, &body)?;
+
+    let interaction: Interaction = serde_json::from_slice(&body)?;
+    match interaction.kind {
+        1 => Ok(Json(InteractionResponse::pong())),
+        2 => Ok(Json(InteractionResponse::message(handle_command(
+            &interaction,
+            &config,
+        )?))),
+        other => Err(Error::InvalidRequest(format!(
+            "Unsupported interaction type: {}",
+            other
+        ))),
+    }
+}
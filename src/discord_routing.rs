@@ -0,0 +1,97 @@
+use crate::config::{ProcessorConfig, url_problem};
+use crate::error::{Error, Result};
+use crate::model::DiscordRoute;
+use crate::schema::discord_routes;
+
+use diesel::{delete, insert_into, prelude::*, upsert::excluded};
+
+// The webhook a call on `tgid` in `group` is routed to. A talkgroup route wins
+// over a group route; with neither the notifier's own URL is used.
+pub fn webhook_for(c: &ProcessorConfig, tgid: i32, group: &str) -> Result<Option<String>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let routes: Vec<DiscordRoute> = discord_routes::table
+        .filter(
+            discord_routes::talkgroup
+                .eq(tgid)
+                .or(discord_routes::talkgroup_group.eq(group)),
+        )
+        .select(DiscordRoute::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok(routes
+        .iter()
+        .find(|r| r.talkgroup.is_some())
+        .or(routes.first())
+        .map(|r| r.webhook_url.clone()))
+}
+
+pub fn list(c: &ProcessorConfig) -> Result<Vec<DiscordRoute>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    discord_routes::table
+        .select(DiscordRoute::as_select())
+        .order((
+            discord_routes::talkgroup.asc(),
+            discord_routes::talkgroup_group.asc(),
+        ))
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Adds a route, replacing the webhook of any existing route with the same key
+pub fn upsert(c: &ProcessorConfig, route: &DiscordRoute) -> Result<DiscordRoute> {
+    if route.talkgroup.is_some() == route.talkgroup_group.is_some() {
+        return Err(Error::InvalidRequest(
+            "a route needs exactly one of talkgroup or talkgroup_group".to_string(),
+        ));
+    }
+    if let Some(problem) = url_problem(&route.webhook_url) {
+        return Err(Error::InvalidRequest(format!("webhook_url: {}", problem)));
+    }
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let insert = insert_into(discord_routes::table).values(route);
+    let result = if route.talkgroup.is_some() {
+        insert
+            .on_conflict(discord_routes::talkgroup)
+            .do_update()
+            .set(discord_routes::webhook_url.eq(excluded(discord_routes::webhook_url)))
+            .returning(DiscordRoute::as_returning())
+            .get_result(&mut connection)
+    } else {
+        insert
+            .on_conflict(discord_routes::talkgroup_group)
+            .do_update()
+            .set(discord_routes::webhook_url.eq(excluded(discord_routes::webhook_url)))
+            .returning(DiscordRoute::as_returning())
+            .get_result(&mut connection)
+    };
+    result.map_err(|e| Error::Database(e.to_string()))
+}
+
+pub fn remove(c: &ProcessorConfig, id: i32) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let deleted = delete(discord_routes::table.find(id))
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    if deleted == 0 {
+        return Err(Error::NotFound(format!("discord route {}", id)));
+    }
+    Ok(())
+}
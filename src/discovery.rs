@@ -0,0 +1,205 @@
+use crate::common::*;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{ArchiveReason, AudioMetadata, DiscoveredTalkgroup, Talkgroups};
+use crate::schema::{discovered_talkgroups, talkgroups};
+
+use chrono::Utc;
+use diesel::{dsl::exists, insert_into, prelude::*, select};
+use serde::Serialize;
+use tracing::{info, warn};
+
+#[derive(Debug, Serialize)]
+pub struct DiscoveryReportEntry {
+    #[serde(flatten)]
+    pub discovered: DiscoveredTalkgroup,
+    pub metadata: Option<Talkgroups>,
+    pub resolved: bool,
+}
+
+pub fn track_talkgroup(m: &AudioMetadata, c: &ProcessorConfig) -> Result<bool> {
+    let tg = m.talkgroup.talkgroup;
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let known: bool = select(exists(talkgroups::table.find(tg)))
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    if known && !m.talkgroup.has_default_metadata() {
+        return Ok(false);
+    }
+
+    let now = Utc::now();
+    let entry = DiscoveredTalkgroup {
+        talkgroup: tg,
+        short_name: m.call.short_name.clone(),
+        first_seen: now,
+        last_seen: now,
+        call_count: 1,
+    };
+
+    let call_count: i32 = insert_into(discovered_talkgroups::table)
+        .values(&entry)
+        .on_conflict(discovered_talkgroups::talkgroup)
+        .do_update()
+        .set((
+            discovered_talkgroups::last_seen.eq(now),
+            discovered_talkgroups::call_count.eq(discovered_talkgroups::call_count + 1),
+        ))
+        .returning(discovered_talkgroups::call_count)
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    if call_count == 1 {
+        info!(talkgroup = tg, system = %entry.short_name, "Discovered new talkgroup");
+    }
+
+    Ok(call_count == 1)
+}
+
+pub async fn notify_discovery(m: &AudioMetadata, c: &ProcessorConfig) {
+    let embed = WebhookEmbed {
+        color: "16753920".to_string(),
+        timestamp: format_timestamp_from_datetime(m.call.start_time),
+        title: "New talkgroup discovered".to_string(),
+        fields: vec![
+            EmbedField {
+                name: "Talkgroup:".to_string(),
+                value: m.talkgroup.talkgroup.to_string(),
+            },
+            EmbedField {
+                name: "System:".to_string(),
+                value: m.call.short_name.clone(),
+            },
+        ],
+        footer: None,
+    };
+
+    if let Err(e) = send_embed(&c.http_client, &c.env.discord_webhook, embed).await {
+        warn!(talkgroup = m.talkgroup.talkgroup, error = %e, "Failed to send discovery notification");
+    }
+}
+
+// A stored talkgroup field an upload changed
+#[derive(Debug, Clone)]
+pub struct MetadataChange {
+    pub field: &'static str,
+    pub stored: String,
+    pub uploaded: String,
+}
+
+pub fn metadata_changes(stored: &Talkgroups, uploaded: &Talkgroups) -> Vec<MetadataChange> {
+    [
+        ("tag", &stored.talkgroup_tag, &uploaded.talkgroup_tag),
+        (
+            "description",
+            &stored.talkgroup_description,
+            &uploaded.talkgroup_description,
+        ),
+        ("group", &stored.talkgroup_group, &uploaded.talkgroup_group),
+        (
+            "group_tag",
+            &stored.talkgroup_group_tag,
+            &uploaded.talkgroup_group_tag,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, stored, uploaded)| stored != uploaded)
+    .map(|(field, stored, uploaded)| MetadataChange {
+        field,
+        stored: stored.clone(),
+        uploaded: uploaded.clone(),
+    })
+    .collect()
+}
+
+fn shown(value: &str) -> String {
+    match value {
+        "" => "(empty)".to_string(),
+        value => value.to_string(),
+    }
+}
+
+// Logs talkgroup metadata an upload is overwriting and, with
+// NOTIFY_TALKGROUP_CHANGES, posts it to DISCORD_WEBHOOK
+pub fn report_changes(c: &ProcessorConfig, m: &AudioMetadata, changes: Vec<MetadataChange>) {
+    for change in &changes {
+        warn!(
+            talkgroup = m.talkgroup.talkgroup,
+            system = %m.call.short_name,
+            field = change.field,
+            stored = %change.stored,
+            uploaded = %change.uploaded,
+            "Upload changed talkgroup metadata"
+        );
+    }
+    if !c.env.notify_talkgroup_changes
+        || !c.switches.notifications()
+        || m.call.archive_reason == Some(ArchiveReason::Backfill)
+    {
+        return;
+    }
+
+    let mut fields = vec![
+        EmbedField {
+            name: "Talkgroup:".to_string(),
+            value: m.talkgroup.talkgroup.to_string(),
+        },
+        EmbedField {
+            name: "System:".to_string(),
+            value: m.call.short_name.clone(),
+        },
+    ];
+    fields.extend(changes.iter().map(|change| EmbedField {
+        name: format!("{}:", change.field),
+        value: format!("{} → {}", shown(&change.stored), shown(&change.uploaded)),
+    }));
+    let embed = WebhookEmbed {
+        color: "3447003".to_string(),
+        timestamp: format_timestamp_from_datetime(m.call.start_time),
+        title: "Talkgroup metadata changed".to_string(),
+        fields,
+        footer: None,
+    };
+
+    let (client, url) = (c.http_client.clone(), c.env.discord_webhook.clone());
+    let tg = m.talkgroup.talkgroup;
+    c.background.spawn(async move {
+        if let Err(e) = send_embed(&client, &url, embed).await {
+            warn!(talkgroup = tg, error = %e, "Failed to send talkgroup change notification");
+        }
+    });
+}
+
+pub fn report(c: &ProcessorConfig, include_resolved: bool) -> Result<Vec<DiscoveryReportEntry>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let rows: Vec<(DiscoveredTalkgroup, Option<Talkgroups>)> = discovered_talkgroups::table
+        .left_join(talkgroups::table.on(talkgroups::talkgroup.eq(discovered_talkgroups::talkgroup)))
+        .select((
+            DiscoveredTalkgroup::as_select(),
+            Option::<Talkgroups>::as_select(),
+        ))
+        .order(discovered_talkgroups::first_seen.desc())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok(rows
+        .into_iter()
+        .map(|(discovered, metadata)| {
+            let resolved = metadata.as_ref().is_some_and(|t| !t.has_default_metadata());
+            DiscoveryReportEntry {
+                discovered,
+                metadata,
+                resolved,
+            }
+        })
+        .filter(|entry| include_resolved || !entry.resolved)
+        .collect())
+}
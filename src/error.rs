@@ -1,22 +1,42 @@
+use crate::put_queue;
+
 use axum::{
-    http::StatusCode,
+    Json,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use derive_more::From;
+use serde_json::json;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, From)]
 pub enum Error {
     MissingField(String),
+    Unauthorized(String),
+    InvalidApiKey(String),
+    InvalidRequest(String),
+    Conflict(String),
+    NotFound(String),
     Multipart(String),
     FileTooLarge {
         size: usize,
         max_size: usize,
     },
     InvalidFileType(String),
+    // an upload UPLOAD_POLICY turns away, with the rule it broke
+    PolicyViolation {
+        code: &'static str,
+        message: String,
+    },
     Configuration(String),
     Database(String),
+    Import(String),
+    Unavailable(String),
+    Audio(String),
+    Transcription(String),
+    Notification(String),
+    Workspace(String),
     #[from]
     ServerInit(std::io::Error),
     #[from]
@@ -35,17 +55,52 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let status = match &self {
             Error::MissingField(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) | Error::InvalidApiKey(_) => StatusCode::UNAUTHORIZED,
+            Error::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::FileTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::PolicyViolation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            // storage writes are backed up; the uploader should retry later
+            Error::S3Upload(object_store::Error::Generic { store, .. })
+                if *store == put_queue::QUEUE_FULL =>
+            {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
+        // the rest of an oversized body is never read, so drop the connection
+        let close = matches!(self, Error::FileTooLarge { .. });
+        // uploaders get a body they can parse to tell a bad key from other failures
+        let api_key = matches!(self, Error::InvalidApiKey(_));
+        let policy = match &self {
+            Error::PolicyViolation { code, .. } => Some(*code),
+            _ => None,
+        };
         let error_message = match self {
             Error::MissingField(msg) => format!("Missing required field or filename: {}", msg),
+            Error::Unauthorized(msg) => format!("Unauthorized: {}", msg),
+            Error::InvalidApiKey(msg) => format!("Invalid API key: {}", msg),
+            Error::InvalidRequest(msg) => format!("Invalid request: {}", msg),
+            Error::Conflict(msg) => format!("Conflict: {}", msg),
+            Error::NotFound(msg) => format!("Not found: {}", msg),
             Error::Multipart(msg) => format!("Multipart processing error: {}", msg),
             Error::FileTooLarge { size, max_size } => {
                 format!("File too large: {} bytes (max: {} bytes)", size, max_size)
             }
             Error::InvalidFileType(msg) => format!("Invalid file type: {}", msg),
+            Error::PolicyViolation { code, message } => {
+                format!("Upload rejected by policy {}: {}", code, message)
+            }
             Error::Configuration(msg) => format!("Configuration error: {}", msg),
             Error::Database(msg) => format!("Database error: {}", msg),
+            Error::Import(msg) => format!("Import error: {}", msg),
+            Error::Unavailable(msg) => format!("Service unavailable: {}", msg),
+            Error::Audio(msg) => format!("Audio processing error: {}", msg),
+            Error::Transcription(msg) => format!("Transcription error: {}", msg),
+            Error::Notification(msg) => format!("Notification error: {}", msg),
+            Error::Workspace(msg) => format!("Workspace error: {}", msg),
             Error::S3Upload(msg) => format!("S3 Upload Error: {}", msg),
             Error::PathParse(msg) => format!("Invalid object path: {}", msg),
             Error::JsonParsing(msg) => format!("Json Parsing Error: {}", msg),
@@ -54,7 +109,28 @@ impl IntoResponse for Error {
             Error::Migration(msg) => format!("DB migration error: {}", msg),
         };
         println!("{:#?} status for {:#?}", status, error_message);
-        (status, error_message).into_response()
+        let mut response = if api_key {
+            (
+                status,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                Json(json!({ "error": "invalid_api_key", "message": error_message })),
+            )
+                .into_response()
+        } else if let Some(code) = policy {
+            (
+                status,
+                Json(json!({ "error": code, "message": error_message })),
+            )
+                .into_response()
+        } else {
+            (status, error_message).into_response()
+        };
+        if close {
+            response
+                .headers_mut()
+                .insert(header::CONNECTION, HeaderValue::from_static("close"));
+        }
+        response
     }
 }
 
@@ -0,0 +1,343 @@
+use crate::auth::{Access, read_access};
+use crate::config::{ProcessorConfig, notifier_accepts};
+use crate::error::{Error, Result};
+use crate::model::AudioMetadata;
+use crate::notifier::WebhookPayload;
+use crate::scrub::scrub;
+
+use axum::{
+    extract::{
+        Query, State,
+        ws::{
+            CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code,
+            rejection::WebSocketUpgradeRejection,
+        },
+    },
+    http::HeaderMap,
+    response::{
+        Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::{SinkExt, Stream, stream};
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::{
+    broadcast::{self, Receiver, Sender, error::RecvError},
+    watch,
+};
+use tracing::{debug, info, warn};
+
+// Calls a slow client may fall behind by before it misses some
+const EVENT_BUFFER: usize = 256;
+// /ws clients only send small subscriptions, so anything bigger is refused
+const MAX_MESSAGE: usize = 64 * 1024;
+
+// Processed calls, fanned out to every /events client
+#[derive(Debug)]
+pub struct CallEvents {
+    sender: Sender<Arc<AudioMetadata>>,
+    // set on shutdown to end every feed, which would otherwise stream forever
+    closed: watch::Sender<bool>,
+}
+
+impl CallEvents {
+    pub fn new() -> Self {
+        CallEvents {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+            closed: watch::channel(false).0,
+        }
+    }
+
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+
+    pub fn publish(&self, meta: &AudioMetadata) {
+        // an error only means nobody is listening
+        let _ = self.sender.send(Arc::new(meta.clone()));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventParams {
+    // comma-separated talkgroup ids (!id excludes one) and groups, as for
+    // NOTIFIER_<NAME>_TG_ID and _TG_GROUP
+    tg_id: Option<String>,
+    tg_group: Option<String>,
+}
+
+fn list(value: Option<&str>) -> Option<Vec<String>> {
+    value.map(|v| v.split(',').map(|s| s.trim().to_string()).collect())
+}
+
+struct Feed {
+    config: ProcessorConfig,
+    receiver: Receiver<Arc<AudioMetadata>>,
+    closed: watch::Receiver<bool>,
+    groups: Option<Vec<String>>,
+    public: bool,
+    tg_id: Option<Vec<String>>,
+    tg_group: Option<Vec<String>>,
+}
+
+enum Update {
+    // a copy of the call prepared for this client
+    Call(Box<AudioMetadata>),
+    Lagged(u64),
+}
+
+impl Feed {
+    fn new(
+        config: ProcessorConfig,
+        access: &Access,
+        tg_id: Option<Vec<String>>,
+        tg_group: Option<Vec<String>>,
+    ) -> Self {
+        Feed {
+            receiver: config.events.sender.subscribe(),
+            closed: config.events.closed.subscribe(),
+            groups: access.groups().map(<[String]>::to_vec),
+            public: access.is_public(),
+            tg_id,
+            tg_group,
+            config,
+        }
+    }
+
+    fn accepts(&self, meta: &AudioMetadata) -> bool {
+        let (tgid, group) = (meta.talkgroup.talkgroup, &meta.talkgroup.talkgroup_group);
+        self.groups
+            .as_ref()
+            .is_none_or(|groups| groups.contains(group))
+            && notifier_accepts(self.tg_id.as_deref(), self.tg_group.as_deref(), tgid, group)
+    }
+
+    fn prepare(&self, meta: &AudioMetadata) -> AudioMetadata {
+        let mut meta = meta.clone();
+        // public readers see the scrubbed copy, as on /calls/recent
+        if self.public && self.config.env.pii_scrubbing {
+            let scrubbed = meta.call.public_transcription.take();
+            meta.call.transcription =
+                scrubbed.or_else(|| meta.call.transcription.as_deref().map(scrub));
+        }
+        meta.call.public_transcription = None;
+        meta
+    }
+
+    fn payload<'a>(&self, meta: &'a AudioMetadata) -> WebhookPayload<'a> {
+        WebhookPayload {
+            audio_url: self.config.env.object_url(&meta.call.filename),
+            presigned_audio_url: None,
+            keywords: &[],
+            segments: &[],
+            meta,
+        }
+    }
+
+    async fn next(&mut self) -> Option<Update> {
+        loop {
+            let message = tokio::select! {
+                message = self.receiver.recv() => message,
+                _ = self.closed.wait_for(|closed| *closed) => return None,
+            };
+            match message {
+                Ok(meta) if self.accepts(&meta) => {
+                    return Some(Update::Call(Box::new(self.prepare(&meta))));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => {
+                    debug!(missed, "Event client fell behind");
+                    return Some(Update::Lagged(missed));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            let meta = match self.next().await? {
+                Update::Call(meta) => meta,
+                Update::Lagged(missed) => {
+                    return Some(Event::default().event("lagged").data(missed.to_string()));
+                }
+            };
+            match Event::default()
+                .event("call")
+                .json_data(self.payload(&meta))
+            {
+                Ok(event) => return Some(event),
+                Err(e) => {
+                    warn!(file = %meta.call.filename, error = %e, "Failed to encode call event")
+                }
+            }
+        }
+    }
+}
+
+// Streams each call as it finishes processing, as `call` events carrying the
+// same JSON as webhook notifiers. A client that falls too far behind gets a
+// `lagged` event with the number of calls it missed.
+pub async fn events(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    Query(params): Query<EventParams>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>> {
+    let access = read_access(&headers, &config)?;
+    info!(
+        principal = access.principal().unwrap_or("public"),
+        "Event client connected"
+    );
+
+    let feed = Feed::new(
+        config,
+        &access,
+        list(params.tg_id.as_deref()),
+        list(params.tg_group.as_deref()),
+    );
+    let stream = stream::unfold(feed, |mut feed| async move {
+        feed.next_event().await.map(|event| (Ok(event), feed))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+// Subscribes a /ws client to calls on any of these talkgroups or groups,
+// replacing its previous subscription. Both empty means every call.
+#[derive(Debug, Deserialize)]
+struct Subscription {
+    #[serde(default)]
+    talkgroups: Vec<i32>,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Outgoing<'a> {
+    Call(WebhookPayload<'a>),
+    Lagged {
+        missed: u64,
+    },
+    Subscribed {
+        talkgroups: &'a [i32],
+        groups: &'a [String],
+    },
+    Error {
+        message: String,
+    },
+}
+
+fn ws_error(message: impl std::fmt::Display) -> Error {
+    Error::InvalidRequest(format!("websocket: {}", message))
+}
+
+async fn send(socket: &mut WebSocket, message: &Outgoing<'_>) -> Result<()> {
+    let text = serde_json::to_string(message)?;
+    socket
+        .send(Message::Text(text.into()))
+        .await
+        .map_err(ws_error)
+}
+
+async fn close(socket: &mut WebSocket, code: u16) -> Result<()> {
+    let frame = CloseFrame {
+        code,
+        reason: "".into(),
+    };
+    socket
+        .send(Message::Close(Some(frame)))
+        .await
+        .map_err(ws_error)
+}
+
+// Pings are answered by axum as they are read
+async fn run_live(config: ProcessorConfig, access: Access, mut socket: WebSocket) -> Result<()> {
+    // nothing is sent until the client subscribes
+    let mut feed: Option<Feed> = None;
+
+    loop {
+        let update = async {
+            match feed.as_mut() {
+                Some(feed) => feed.next().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let subscription: Subscription = match serde_json::from_str(&text) {
+                        Ok(subscription) => subscription,
+                        Err(e) => {
+                            let message = format!("expected {{\"talkgroups\":[...],\"groups\":[...]}}: {}", e);
+                            send(&mut socket, &Outgoing::Error { message }).await?;
+                            continue;
+                        }
+                    };
+                    let tg_id = (!subscription.talkgroups.is_empty())
+                        .then(|| subscription.talkgroups.iter().map(i32::to_string).collect());
+                    let tg_group = (!subscription.groups.is_empty()).then(|| subscription.groups.clone());
+                    feed = Some(Feed::new(config.clone(), &access, tg_id, tg_group));
+                    debug!(talkgroups = ?subscription.talkgroups, groups = ?subscription.groups, "Live client subscribed");
+                    send(
+                        &mut socket,
+                        &Outgoing::Subscribed {
+                            talkgroups: &subscription.talkgroups,
+                            groups: &subscription.groups,
+                        },
+                    )
+                    .await?;
+                }
+                Some(Ok(Message::Binary(_))) => {
+                    // the client may already be gone
+                    let _ = close(&mut socket, close_code::UNSUPPORTED).await;
+                    return Err(ws_error("binary messages are not supported"));
+                }
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {}
+                Some(Ok(Message::Close(frame))) => {
+                    debug!(code = frame.map(|f| f.code), "Live client closed the connection");
+                    // sends the close reply queued when it was read
+                    return socket.close().await.map_err(ws_error);
+                }
+                None => return Ok(()),
+                Some(Err(e)) => return Err(ws_error(e)),
+            },
+            update = update => match update {
+                Some(Update::Call(meta)) => {
+                    let payload = feed.as_ref().map(|feed| feed.payload(&meta));
+                    if let Some(payload) = payload {
+                        send(&mut socket, &Outgoing::Call(payload)).await?;
+                    }
+                }
+                Some(Update::Lagged(missed)) => send(&mut socket, &Outgoing::Lagged { missed }).await?,
+                None => return close(&mut socket, close_code::NORMAL).await,
+            },
+        }
+    }
+}
+
+// A WebSocket version of /events. Clients send a subscription such as
+// {"talkgroups":[100],"groups":["Fire"]} and get `call` messages (the webhook
+// JSON plus "type") for matching calls, `lagged` if they fall behind, and
+// `subscribed` or `error` in reply to each subscription.
+pub async fn live(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    upgrade: std::result::Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response> {
+    // a client without access is refused before its handshake is looked at
+    let access = read_access(&headers, &config)?;
+    let upgrade = upgrade.map_err(|e| ws_error(e.body_text()))?;
+    let principal = access.principal().unwrap_or("public").to_string();
+
+    Ok(upgrade
+        .max_message_size(MAX_MESSAGE)
+        .max_frame_size(MAX_MESSAGE)
+        .on_upgrade(move |socket| async move {
+            info!(principal = %principal, "Live client connected");
+            match run_live(config, access, socket).await {
+                Ok(()) => info!(principal = %principal, "Live client disconnected"),
+                Err(e) => debug!(principal = %principal, error = %e, "Live client dropped"),
+            }
+        }))
+}
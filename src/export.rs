@@ -0,0 +1,213 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Call, SrcList, Talkgroups};
+use crate::schema::{calls, srclist, talkgroups};
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use object_store::{ObjectStore, PutPayload, path::Path};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tracing::{info, warn};
+
+const DEFAULT_EXPORT_LIMIT: i64 = 1000;
+const MAX_EXPORT_LIMIT: i64 = 100_000;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    // 20261014_120000Metro__TO_1234_FROM_5678
+    Sdrtrunk,
+    // 20261014_120000_851012500_TG1234_RID5678
+    Dsdplus,
+}
+
+impl ExportFormat {
+    fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Sdrtrunk => "sdrtrunk",
+            ExportFormat::Dsdplus => "dsdplus",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportReport {
+    pub format: ExportFormat,
+    pub prefix: String,
+    pub duration_ms: u128,
+    pub exported: usize,
+    pub missing_objects: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportSource {
+    src: i32,
+    pos: f64,
+    emergency: bool,
+}
+
+// Sidecar written next to each exported recording
+#[derive(Debug, Serialize)]
+struct ExportMetadata<'a> {
+    system: &'a str,
+    talkgroup: i32,
+    talkgroup_tag: &'a str,
+    talkgroup_description: &'a str,
+    talkgroup_group: &'a str,
+    frequency: i32,
+    start_time: i64,
+    stop_time: i64,
+    call_length: i16,
+    emergency: bool,
+    encrypted: bool,
+    audio: String,
+    sources: Vec<ExportSource>,
+}
+
+fn export_name(format: ExportFormat, call: &Call, src: i32) -> String {
+    let timestamp = call.start_time.format("%Y%m%d_%H%M%S");
+    match format {
+        ExportFormat::Sdrtrunk => format!(
+            "{}{}__TO_{}_FROM_{}",
+            timestamp, call.short_name, call.talkgroup, src
+        ),
+        ExportFormat::Dsdplus => format!(
+            "{}_{}_TG{}_RID{}",
+            timestamp, call.freq, call.talkgroup, src
+        ),
+    }
+}
+
+fn audio_extension(filename: &str) -> &str {
+    filename.rsplit_once('.').map_or("m4a", |(_, ext)| ext)
+}
+
+// Copies each matching recording under <EXPORT_PREFIX>/<format>/<system>/<date>
+// using the file naming of the chosen tool, with a JSON sidecar alongside.
+// Audio is copied as stored; no transcoding is done.
+pub async fn export(
+    c: &ProcessorConfig,
+    format: ExportFormat,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+) -> Result<ExportReport> {
+    let start = Instant::now();
+    let prefix = format!("{}/{}", c.env.export_prefix, format.name());
+    info!(format = format.name(), prefix = %prefix, "Starting call export");
+
+    let (rows, sources): (Vec<(Call, Talkgroups)>, Vec<SrcList>) = {
+        let mut connection = c
+            .read_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let mut query = calls::table
+            .inner_join(talkgroups::table)
+            .select((Call::as_select(), Talkgroups::as_select()))
+            .order(calls::start_time.asc())
+            .limit(
+                limit
+                    .unwrap_or(DEFAULT_EXPORT_LIMIT)
+                    .clamp(1, MAX_EXPORT_LIMIT),
+            )
+            .into_boxed();
+
+        if let Some(since) = since {
+            query = query.filter(calls::start_time.ge(since));
+        }
+        if let Some(until) = until {
+            query = query.filter(calls::start_time.lt(until));
+        }
+
+        let rows: Vec<(Call, Talkgroups)> = query
+            .load(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let sources = srclist::table
+            .filter(srclist::call_id.eq_any(rows.iter().map(|(call, _)| &call.filename)))
+            .order(srclist::pos.asc())
+            .load(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        (rows, sources)
+    };
+
+    let mut report = ExportReport {
+        format,
+        prefix: prefix.clone(),
+        duration_ms: 0,
+        exported: 0,
+        missing_objects: Vec::new(),
+    };
+
+    for (call, tg) in &rows {
+        let call_sources: Vec<&SrcList> = sources
+            .iter()
+            .filter(|s| s.call_id == call.filename)
+            .collect();
+        let first_src = call_sources.first().map_or(0, |s| s.src);
+
+        let name = export_name(format, call, first_src);
+        let dir = format!(
+            "{}/{}/{}",
+            prefix,
+            call.short_name,
+            call.start_time.format("%Y/%m/%d")
+        );
+        let audio = format!("{}.{}", name, audio_extension(&call.filename));
+
+        let from = Path::parse(&call.filename)?;
+        let to = Path::parse(format!("{}/{}", dir, audio))?;
+        match c.s3_client.copy(&from, &to).await {
+            Ok(()) => {}
+            Err(object_store::Error::NotFound { .. }) => {
+                warn!(file = %call.filename, "Recording missing from storage, skipping export");
+                report.missing_objects.push(call.filename.clone());
+                continue;
+            }
+            Err(e) => return Err(Error::S3Upload(e)),
+        }
+
+        let metadata = ExportMetadata {
+            system: &call.short_name,
+            talkgroup: call.talkgroup,
+            talkgroup_tag: &tg.talkgroup_tag,
+            talkgroup_description: &tg.talkgroup_description,
+            talkgroup_group: &tg.talkgroup_group,
+            frequency: call.freq,
+            start_time: call.start_time.timestamp(),
+            stop_time: call.stop_time.timestamp(),
+            call_length: call.call_length,
+            emergency: call.emergency,
+            encrypted: call.encrypted,
+            audio,
+            sources: call_sources
+                .iter()
+                .map(|s| ExportSource {
+                    src: s.src,
+                    pos: s.pos.num_milliseconds() as f64 / 1000.0,
+                    emergency: s.emergency,
+                })
+                .collect(),
+        };
+        let sidecar = Path::parse(format!("{}/{}.json", dir, name))?;
+        c.s3_client
+            .put(&sidecar, PutPayload::from(serde_json::to_vec(&metadata)?))
+            .await?;
+
+        report.exported += 1;
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        format = format.name(),
+        exported = report.exported,
+        missing = report.missing_objects.len(),
+        duration_ms = report.duration_ms,
+        "Call export completed"
+    );
+
+    Ok(report)
+}
@@ -0,0 +1,221 @@
+use crate::audit;
+use crate::auth::{Access, require_admin};
+use crate::config::{FilterConfig, FilterDecision, ProcessorConfig, notifier_accepts};
+use crate::error::{Error, Result};
+use crate::schema::{calls, talkgroups};
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, Method, Uri},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{dsl::count_star, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const DEFAULT_DAYS: i64 = 7;
+const MAX_DAYS: i64 = 90;
+
+#[derive(Debug, Deserialize)]
+pub struct TalkgroupRules {
+    tg_id: Option<Vec<String>>,
+    tg_group: Option<Vec<String>>,
+}
+
+// A proposed FILTER_* ruleset and notifier filters, each compared with the
+// running configuration. Omitting `filter` tests the current one.
+#[derive(Debug, Deserialize)]
+pub struct FilterTestRequest {
+    days: Option<i64>,
+    filter: Option<FilterConfig>,
+    #[serde(default)]
+    notifiers: BTreeMap<String, TalkgroupRules>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuleCount {
+    pub rule: String,
+    pub transcribe: bool,
+    pub calls: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterReport {
+    pub transcribed: i64,
+    pub skipped: i64,
+    pub current_transcribed: i64,
+    pub rules: Vec<RuleCount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotifierReport {
+    pub name: String,
+    pub calls: i64,
+    // calls the registered notifier of the same name would have been sent
+    pub current_calls: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterTest {
+    pub since: DateTime<Utc>,
+    pub calls: i64,
+    pub filter: FilterReport,
+    pub notifiers: Vec<NotifierReport>,
+}
+
+fn check_ids(field: &str, ids: &[String]) -> Result<()> {
+    match ids
+        .iter()
+        .find(|id| id.strip_prefix('!').unwrap_or(id).parse::<i32>().is_err())
+    {
+        Some(id) => Err(Error::InvalidRequest(format!(
+            "{} entries must be talkgroup ids or !id, got {:?}",
+            field, id
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn transcribed(filter: &FilterConfig, tgid: i32, group: &str) -> bool {
+    filter.enabled() && filter.decide(tgid, group).transcribe
+}
+
+fn filter_report(
+    filter: &FilterConfig,
+    current: &FilterConfig,
+    usage: &[(i32, String, i64)],
+) -> FilterReport {
+    // every rule is listed, including those no call would have matched
+    let mut rules: Vec<RuleCount> = if filter.enabled() {
+        let ids = filter.tgid().into_iter().map(|id| RuleCount {
+            transcribe: !id.starts_with('!'),
+            rule: format!("tg_id:{}", id),
+            calls: 0,
+        });
+        let groups = filter.group().into_iter().map(|g| RuleCount {
+            rule: format!("tg_group:{}", g),
+            transcribe: true,
+            calls: 0,
+        });
+        ids.chain(groups).collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut report = FilterReport {
+        transcribed: 0,
+        skipped: 0,
+        current_transcribed: 0,
+        rules: Vec::new(),
+    };
+    for (tgid, group, calls) in usage {
+        let decision = if filter.enabled() {
+            filter.decide(*tgid, group)
+        } else {
+            FilterDecision::new(false, "no_filter")
+        };
+        if decision.transcribe {
+            report.transcribed += calls;
+        } else {
+            report.skipped += calls;
+        }
+        if transcribed(current, *tgid, group) {
+            report.current_transcribed += calls;
+        }
+
+        match rules.iter_mut().find(|r| r.rule == decision.rule) {
+            Some(rule) => rule.calls += calls,
+            None => rules.push(RuleCount {
+                rule: decision.rule,
+                transcribe: decision.transcribe,
+                calls: *calls,
+            }),
+        }
+    }
+    report.rules = rules;
+    report
+}
+
+// Calls per talkgroup since `since`, leaving out archived calls, which are
+// never transcribed or sent
+fn talkgroup_usage(c: &ProcessorConfig, since: DateTime<Utc>) -> Result<Vec<(i32, String, i64)>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    calls::table
+        .inner_join(talkgroups::table)
+        .filter(calls::start_time.ge(since))
+        .filter(calls::archive_reason.is_null())
+        .group_by(talkgroups::talkgroup)
+        .select((
+            talkgroups::talkgroup,
+            talkgroups::talkgroup_group,
+            count_star(),
+        ))
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Sizes a rule change before it goes live: how many of the stored calls from
+// the last `days` each proposed rule would have transcribed or sent
+pub async fn test_filters(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Json(request): Json<FilterTestRequest>,
+) -> Result<Json<FilterTest>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let current = config.filter.load_full();
+    let filter = request.filter.as_ref().unwrap_or(&current);
+    check_ids("filter.tg_id", &filter.tgid())?;
+    for (name, rules) in &request.notifiers {
+        check_ids(
+            &format!("notifiers.{}.tg_id", name),
+            rules.tg_id.as_deref().unwrap_or_default(),
+        )?;
+    }
+
+    let days = request.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+    let since = Utc::now() - TimeDelta::days(days);
+    let usage = talkgroup_usage(&config, since)?;
+
+    let count = |accepts: &dyn Fn(i32, &str) -> bool| -> i64 {
+        usage
+            .iter()
+            .filter(|(tgid, group, _)| accepts(*tgid, group))
+            .map(|(_, _, calls)| calls)
+            .sum()
+    };
+    let registered = config.notifiers.load_full();
+    let notifiers = request
+        .notifiers
+        .iter()
+        .map(|(name, rules)| NotifierReport {
+            name: name.clone(),
+            calls: count(&|tgid, group| {
+                notifier_accepts(
+                    rules.tg_id.as_deref(),
+                    rules.tg_group.as_deref(),
+                    tgid,
+                    group,
+                )
+            }),
+            current_calls: registered
+                .config(name)
+                .map(|current| count(&|tgid, group| current.accepts_talkgroup(tgid, group))),
+        })
+        .collect();
+
+    Ok(Json(FilterTest {
+        since,
+        calls: usage.iter().map(|(_, _, calls)| calls).sum(),
+        filter: filter_report(filter, &current, &usage),
+        notifiers,
+    }))
+}
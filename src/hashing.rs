@@ -0,0 +1,116 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use ring::digest::{Algorithm, Context, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+use serde::Deserialize;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+// How the keys identifying srclist and freqlist rows are derived, chosen by
+// HASH_ALGORITHM. Each row stores the version it was hashed with, so changing
+// the algorithm leaves existing rows checkable.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    // std's DefaultHasher over the derived Hash, as rows were keyed before
+    // versioning. It may change between Rust releases, so it is only used to
+    // check the rows it produced.
+    #[serde(skip_deserializing)]
+    Legacy,
+    #[default]
+    Sha256,
+    Sha1,
+}
+
+impl HashAlgorithm {
+    pub fn version(self) -> i16 {
+        match self {
+            HashAlgorithm::Legacy => 0,
+            HashAlgorithm::Sha256 => 1,
+            HashAlgorithm::Sha1 => 2,
+        }
+    }
+
+    pub fn from_version(version: i16) -> Option<Self> {
+        match version {
+            0 => Some(HashAlgorithm::Legacy),
+            1 => Some(HashAlgorithm::Sha256),
+            2 => Some(HashAlgorithm::Sha1),
+            _ => None,
+        }
+    }
+
+    // None for Legacy, which isn't a digest of the canonical encoding
+    pub fn digest(self) -> Option<&'static Algorithm> {
+        match self {
+            HashAlgorithm::Legacy => None,
+            HashAlgorithm::Sha256 => Some(&SHA256),
+            HashAlgorithm::Sha1 => Some(&SHA1_FOR_LEGACY_USE_ONLY),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Legacy => "legacy",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha1 => "sha1",
+        }
+    }
+}
+
+// A row's fields in a fixed order, each length-prefixed so adjacent fields
+// can't run together, hashed into a key that doesn't depend on the struct's
+// layout or the compiler
+#[derive(Default)]
+pub struct Canonical(Vec<u8>);
+
+impl Canonical {
+    pub fn bytes(mut self, value: &[u8]) -> Self {
+        self.0
+            .extend_from_slice(&(value.len() as u32).to_be_bytes());
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    pub fn str(self, value: &str) -> Self {
+        self.bytes(value.as_bytes())
+    }
+
+    pub fn int(self, value: i64) -> Self {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn bool(self, value: bool) -> Self {
+        self.bytes(&[value as u8])
+    }
+
+    pub fn time(self, value: DateTime<Utc>) -> Self {
+        self.int(value.timestamp_micros())
+    }
+
+    pub fn delta(self, value: TimeDelta) -> Self {
+        self.int(value.num_microseconds().unwrap_or(i64::MAX))
+    }
+
+    // None and Some("") are told apart by a presence byte
+    pub fn optional_str(self, value: Option<&str>) -> Self {
+        match value {
+            Some(value) => self.bool(true).str(value),
+            None => self.bool(false),
+        }
+    }
+
+    // The first eight bytes of the digest
+    pub fn finish(self, algorithm: &'static Algorithm) -> i64 {
+        let mut context = Context::new(algorithm);
+        context.update(&self.0);
+        let digest = context.finish();
+        let mut key = [0u8; 8];
+        key.copy_from_slice(&digest.as_ref()[..8]);
+        i64::from_be_bytes(key)
+    }
+}
+
+// A Legacy key: the fields, in struct order, through DefaultHasher
+pub fn legacy(fields: &impl Hash) -> i64 {
+    let mut s = DefaultHasher::new();
+    fields.hash(&mut s);
+    s.finish() as i64
+}
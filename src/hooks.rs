@@ -0,0 +1,76 @@
+use crate::config::ProcessorConfig;
+use crate::model::AudioMetadata;
+
+use std::process::Stdio;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{info, warn};
+
+// Runs POST_UPLOAD_HOOK in the background once an upload has been processed.
+// The call's metadata is written to the command's stdin as JSON, with the
+// common fields also set as TRUNK_PROCESSOR_* environment variables.
+pub fn spawn(c: &ProcessorConfig, meta: &AudioMetadata) {
+    let Some(hook) = c.env.post_upload_hook.clone() else {
+        return;
+    };
+    let payload = match serde_json::to_vec(meta) {
+        Ok(payload) => payload,
+        Err(e) => {
+            warn!(file = %meta.call.filename, error = %e, "Failed to serialize hook payload");
+            return;
+        }
+    };
+
+    let mut command = Command::new(&hook);
+    command
+        .env("TRUNK_PROCESSOR_CALL_ID", &meta.call.filename)
+        .env("TRUNK_PROCESSOR_TALKGROUP", meta.call.talkgroup.to_string())
+        .env("TRUNK_PROCESSOR_SYSTEM", &meta.call.short_name)
+        .env(
+            "TRUNK_PROCESSOR_START_TIME",
+            meta.call.start_time.timestamp().to_string(),
+        )
+        .env(
+            "TRUNK_PROCESSOR_TRANSCRIBED",
+            meta.call.transcription.is_some().to_string(),
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let call_id = meta.call.filename.clone();
+    let timeout = c.env.post_upload_hook_timeout;
+
+    c.background.spawn(async move {
+        let run = async {
+            let mut child = command.spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                // a hook that ignores stdin closes it early; that isn't a failure
+                let _ = stdin.write_all(&payload).await;
+            }
+            child.wait_with_output().await
+        };
+
+        match tokio::time::timeout(timeout, run).await {
+            Ok(Ok(output)) if output.status.success() => {
+                info!(file = %call_id, hook = %hook, "Post-upload hook completed");
+            }
+            Ok(Ok(output)) => warn!(
+                file = %call_id,
+                hook = %hook,
+                status = %output.status,
+                stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+                "Post-upload hook failed"
+            ),
+            Ok(Err(e)) => {
+                warn!(file = %call_id, hook = %hook, error = %e, "Failed to run post-upload hook")
+            }
+            Err(_) => warn!(
+                file = %call_id,
+                hook = %hook,
+                timeout_secs = timeout.as_secs(),
+                "Post-upload hook timed out and was killed"
+            ),
+        }
+    });
+}
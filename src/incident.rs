@@ -0,0 +1,146 @@
+use crate::audit;
+use crate::auth::require_private_read;
+use crate::common::parse_duration;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::Call;
+use crate::query::{context_window, load_context};
+use crate::workspace::Workspace;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method, Uri, header},
+    response::{IntoResponse, Response},
+};
+use chrono::TimeDelta;
+use object_store::{ObjectStore, path::Path as ObjectPath};
+use serde::Deserialize;
+use std::path::PathBuf;
+use tokio::process::Command;
+use tracing::info;
+
+const DEFAULT_GAP: TimeDelta = TimeDelta::milliseconds(500);
+const MAX_GAP: TimeDelta = TimeDelta::seconds(10);
+// everything is resampled to one mono rate so calls from different
+// recorders can be joined
+const SAMPLE_RATE: u32 = 16000;
+
+#[derive(Debug, Deserialize)]
+pub struct IncidentAudioParams {
+    window: Option<String>,
+    gap: Option<String>,
+}
+
+// One input per call, each padded with `gap` of silence and joined in order
+fn ffmpeg_args(inputs: &[PathBuf], gap: TimeDelta, output: &std::path::Path) -> Vec<String> {
+    let mut args = vec!["-hide_banner".into(), "-loglevel".into(), "error".into()];
+    for input in inputs {
+        args.extend(["-i".into(), input.display().to_string()]);
+    }
+
+    let gap_secs = gap.num_milliseconds() as f64 / 1000.0;
+    let mut filter = String::new();
+    for i in 0..inputs.len() {
+        let pad = if i + 1 < inputs.len() { gap_secs } else { 0.0 };
+        filter.push_str(&format!(
+            "[{i}:a]aresample={SAMPLE_RATE},aformat=channel_layouts=mono,apad=pad_dur={pad}[a{i}];"
+        ));
+    }
+    for i in 0..inputs.len() {
+        filter.push_str(&format!("[a{i}]"));
+    }
+    filter.push_str(&format!("concat=n={}:v=0:a=1[out]", inputs.len()));
+
+    args.extend([
+        "-filter_complex".into(),
+        filter,
+        "-map".into(),
+        "[out]".into(),
+        "-c:a".into(),
+        "aac".into(),
+        "-movflags".into(),
+        "+faststart".into(),
+        "-y".into(),
+        output.display().to_string(),
+    ]);
+    args
+}
+
+async fn concatenate(c: &ProcessorConfig, calls: &[Call], gap: TimeDelta) -> Result<Vec<u8>> {
+    let workspace = Workspace::create(c, "concat").await?;
+
+    let mut inputs = Vec::with_capacity(calls.len());
+    for (i, call) in calls.iter().enumerate() {
+        let data = c
+            .s3_client
+            .get(&ObjectPath::parse(&call.filename)?)
+            .await?
+            .bytes()
+            .await?;
+        inputs.push(workspace.write(&format!("{}.m4a", i), &data).await?);
+    }
+
+    let output = workspace.file("incident.m4a")?;
+    let result = workspace
+        .run(Command::new(&c.env.ffmpeg_path).args(ffmpeg_args(&inputs, gap, &output)))
+        .await?
+        .map_err(|e| Error::Audio(format!("failed to run {}: {}", c.env.ffmpeg_path, e)))?;
+    if !result.status.success() {
+        return Err(Error::Audio(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+
+    workspace.read("incident.m4a").await
+}
+
+// The audio of a call and its context (see /calls/{id}/context) as one m4a.
+// Not available in public mode, since each request runs ffmpeg.
+pub async fn incident_audio(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+    Query(params): Query<IncidentAudioParams>,
+) -> Result<Response> {
+    let access = require_private_read(&headers, &config)?;
+    let window = context_window(params.window.as_deref())?;
+    let gap = match params.gap.as_deref() {
+        Some(g) => parse_duration(g)?,
+        None => DEFAULT_GAP,
+    }
+    .clamp(TimeDelta::zero(), MAX_GAP);
+
+    let (call, context) = load_context(&config, &access, &id, window)?;
+    let mut calls = context;
+    calls.push(call);
+    calls.sort_by_key(|c| c.start_time);
+
+    audit::record(
+        &config,
+        &access,
+        &method,
+        &uri,
+        calls.iter().map(|c| c.filename.clone()),
+    );
+
+    let audio = concatenate(&config, &calls, gap).await?;
+    info!(call = %id, calls = calls.len(), bytes = audio.len(), "Concatenated incident audio");
+
+    let name = id.rsplit('/').next().unwrap_or(&id);
+    let name = name.strip_suffix(".m4a").unwrap_or(name);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "audio/mp4".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}-incident.m4a\"", name),
+            ),
+        ],
+        audio,
+    )
+        .into_response())
+}
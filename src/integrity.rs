@@ -0,0 +1,240 @@
+use crate::compression;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::hashing::HashAlgorithm;
+use crate::model::{Call, FreqList, IsList, SrcList};
+use crate::schema::{calls, emergencies, freqlist, srclist};
+use crate::scrub::scrub;
+
+use chrono::{DateTime, Utc};
+use diesel::{delete, dsl::not, prelude::*, update};
+use serde::Serialize;
+use std::{collections::HashSet, time::Instant};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityReport {
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u128,
+    pub calls_scanned: usize,
+    pub orphaned_srclist: usize,
+    pub orphaned_freqlist: usize,
+    pub orphaned_emergencies: usize,
+    pub srclist_hash_mismatches: usize,
+    pub freqlist_hash_mismatches: usize,
+    pub missing_scrubbed: Vec<String>,
+    pub undecryptable: Vec<String>,
+    pub repaired: bool,
+}
+
+// (stored, expected) for every row whose hash no longer matches its contents,
+// each checked with the algorithm it was keyed with. Rows from an unknown
+// hash_version, written by a newer release, are left alone.
+fn hash_mismatches<T: IsList + Clone>(rows: &[T], hashed: impl Fn(&T) -> i64) -> Vec<(i64, i64)> {
+    rows.iter()
+        .filter_map(|row| {
+            let algorithm = HashAlgorithm::from_version(row.hash_version())?;
+            let stored = hashed(row);
+            let mut fresh = row.clone();
+            fresh.calculate_hash(algorithm);
+            let expected = hashed(&fresh);
+            (stored != expected).then_some((stored, expected))
+        })
+        .collect()
+}
+
+struct Findings {
+    srclist_hashes: Vec<(i64, i64)>,
+    freqlist_hashes: Vec<(i64, i64)>,
+    scrubbed: Vec<(String, String)>,
+}
+
+fn repair(c: &ProcessorConfig, findings: &Findings) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    connection
+        .transaction(|conn| {
+            let known_calls = calls::table.select(calls::filename);
+            delete(srclist::table.filter(not(srclist::call_id.eq_any(known_calls))))
+                .execute(conn)?;
+            delete(freqlist::table.filter(not(freqlist::call_id.eq_any(known_calls))))
+                .execute(conn)?;
+            delete(emergencies::table.filter(not(emergencies::call_id.eq_any(known_calls))))
+                .execute(conn)?;
+
+            // a row whose correct hash is already taken is a duplicate of that row
+            let existing: HashSet<i64> = srclist::table
+                .select(srclist::hashed)
+                .load(conn)?
+                .into_iter()
+                .collect();
+            for (stored, expected) in &findings.srclist_hashes {
+                let row = srclist::table.filter(srclist::hashed.eq(stored));
+                if existing.contains(expected) {
+                    delete(row).execute(conn)?;
+                } else {
+                    update(row)
+                        .set(srclist::hashed.eq(expected))
+                        .execute(conn)?;
+                }
+            }
+            let existing: HashSet<i64> = freqlist::table
+                .select(freqlist::hashed)
+                .load(conn)?
+                .into_iter()
+                .collect();
+            for (stored, expected) in &findings.freqlist_hashes {
+                let row = freqlist::table.filter(freqlist::hashed.eq(stored));
+                if existing.contains(expected) {
+                    delete(row).execute(conn)?;
+                } else {
+                    update(row)
+                        .set(freqlist::hashed.eq(expected))
+                        .execute(conn)?;
+                }
+            }
+
+            for (filename, scrubbed) in &findings.scrubbed {
+                update(calls::table.find(filename))
+                    .set(calls::public_transcription.eq(scrubbed))
+                    .execute(conn)?;
+            }
+
+            diesel::result::QueryResult::Ok(())
+        })
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Checks that list and emergency rows belong to a stored call, that srclist and
+// freqlist hashes still match their rows, and that transcriptions can be
+// decrypted and decompressed and have a scrubbed copy when PII_SCRUBBING is
+// enabled.
+// There is no transcription status table; a call's transcription column is
+// the only record of whether it was transcribed.
+pub fn check(c: &ProcessorConfig, do_repair: bool) -> Result<IntegrityReport> {
+    let started_at = Utc::now();
+    let start = Instant::now();
+    info!(repair = do_repair, "Starting integrity check");
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let known_calls = calls::table.select(calls::filename);
+    let orphaned_srclist: i64 = srclist::table
+        .filter(not(srclist::call_id.eq_any(known_calls)))
+        .count()
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let orphaned_freqlist: i64 = freqlist::table
+        .filter(not(freqlist::call_id.eq_any(known_calls)))
+        .count()
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let orphaned_emergencies: i64 = emergencies::table
+        .filter(not(emergencies::call_id.eq_any(known_calls)))
+        .count()
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let src_rows: Vec<SrcList> = srclist::table
+        .select(SrcList::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let freq_rows: Vec<FreqList> = freqlist::table
+        .select(FreqList::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let transcribed: Vec<Call> = calls::table
+        .filter(calls::transcription.is_not_null())
+        .select(Call::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let calls_scanned: i64 = calls::table
+        .count()
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    drop(connection);
+
+    let mut undecryptable = Vec::new();
+    let mut scrubbed = Vec::new();
+    for mut call in transcribed {
+        if let Some(cipher) = &c.transcript_cipher
+            && cipher.open_call(&mut call).is_err()
+        {
+            undecryptable.push(call.filename);
+            continue;
+        }
+        if compression::expand(c, &mut call).is_err() {
+            undecryptable.push(call.filename);
+            continue;
+        }
+        if c.env.pii_scrubbing
+            && call.public_transcription.is_none()
+            && let Some(text) = &call.transcription
+        {
+            let mut text = scrub(text);
+            if let Some(cipher) = &c.transcript_cipher {
+                text = cipher.encrypt(&text, &call.filename)?;
+            }
+            scrubbed.push((call.filename, text));
+        }
+    }
+
+    let findings = Findings {
+        srclist_hashes: hash_mismatches(&src_rows, |r| r.hashed),
+        freqlist_hashes: hash_mismatches(&freq_rows, |r| r.hashed),
+        scrubbed,
+    };
+
+    let mut report = IntegrityReport {
+        started_at,
+        duration_ms: 0,
+        calls_scanned: calls_scanned as usize,
+        orphaned_srclist: orphaned_srclist as usize,
+        orphaned_freqlist: orphaned_freqlist as usize,
+        orphaned_emergencies: orphaned_emergencies as usize,
+        srclist_hash_mismatches: findings.srclist_hashes.len(),
+        freqlist_hash_mismatches: findings.freqlist_hashes.len(),
+        missing_scrubbed: findings.scrubbed.iter().map(|(f, _)| f.clone()).collect(),
+        undecryptable,
+        repaired: false,
+    };
+
+    let clean = report.orphaned_srclist == 0
+        && report.orphaned_freqlist == 0
+        && report.orphaned_emergencies == 0
+        && findings.srclist_hashes.is_empty()
+        && findings.freqlist_hashes.is_empty()
+        && findings.scrubbed.is_empty();
+
+    if do_repair && !clean {
+        repair(c, &findings)?;
+        report.repaired = true;
+    }
+    if !report.undecryptable.is_empty() {
+        warn!(
+            count = report.undecryptable.len(),
+            "Transcriptions failed to decrypt with TRANSCRIPTION_KEY"
+        );
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        calls = report.calls_scanned,
+        orphaned = report.orphaned_srclist + report.orphaned_freqlist + report.orphaned_emergencies,
+        hash_mismatches = report.srclist_hash_mismatches + report.freqlist_hash_mismatches,
+        missing_scrubbed = report.missing_scrubbed.len(),
+        undecryptable = report.undecryptable.len(),
+        repaired = report.repaired,
+        duration_ms = report.duration_ms,
+        "Integrity check completed"
+    );
+
+    Ok(report)
+}
@@ -0,0 +1,173 @@
+use crate::config::ProcessorConfig;
+use crate::dead_letter;
+use crate::error::{Error, Result};
+use crate::model::FailedJob;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    // failed, with a retry to come
+    Failed,
+    // failed with its retries used up, kept until retried or cancelled
+    Dead,
+}
+
+// An upload on the queue, as queue-<n>, or kept in failed_jobs, as failed-<id>.
+// A failed job put back on the queue shows as its queue entry.
+#[derive(Debug, Serialize)]
+pub struct JobView {
+    pub id: String,
+    pub status: JobStatus,
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<&'static str>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    // when it was accepted, or last failed
+    pub since: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_job: Option<i64>,
+}
+
+enum JobId {
+    Queue(u64),
+    Failed(i64),
+}
+
+fn parse_id(id: &str) -> Result<JobId> {
+    let parsed = if let Some(n) = id.strip_prefix("queue-") {
+        n.parse().ok().map(JobId::Queue)
+    } else if let Some(n) = id.strip_prefix("failed-") {
+        n.parse().ok().map(JobId::Failed)
+    } else {
+        None
+    };
+    parsed.ok_or_else(|| Error::NotFound(format!("job {}", id)))
+}
+
+fn failed_view(row: &FailedJob) -> JobView {
+    JobView {
+        id: format!("failed-{}", row.id),
+        status: match row.next_attempt_at {
+            Some(_) => JobStatus::Failed,
+            None => JobStatus::Dead,
+        },
+        file: row.audio_name.clone(),
+        priority: None,
+        attempts: row.attempts,
+        last_error: Some(row.error.clone()),
+        since: row.failed_at,
+        next_attempt_at: row.next_attempt_at,
+        failed_job: None,
+    }
+}
+
+// Queue entries first, then failed jobs, up to `limit` of them
+pub fn list(
+    c: &ProcessorConfig,
+    status: Option<JobStatus>,
+    limit: Option<i64>,
+) -> Result<Vec<JobView>> {
+    let now = Utc::now();
+    let mut failed: HashMap<i64, FailedJob> = dead_letter::list(c, None, limit)?
+        .into_iter()
+        .map(|row| (row.id, row))
+        .collect();
+
+    let mut jobs: Vec<JobView> = c
+        .queue
+        .snapshot()
+        .into_iter()
+        .map(|(id, pending)| {
+            let row = pending.failed_id.and_then(|id| failed.remove(&id));
+            JobView {
+                id: format!("queue-{}", id),
+                status: if pending.running {
+                    JobStatus::Running
+                } else {
+                    JobStatus::Queued
+                },
+                file: pending.file,
+                priority: Some(pending.class.label()),
+                attempts: row.as_ref().map_or(0, |r| r.attempts) + pending.retries as i32,
+                last_error: pending
+                    .last_error
+                    .or_else(|| row.as_ref().map(|r| r.error.clone())),
+                since: now - TimeDelta::from_std(pending.accepted.elapsed()).unwrap_or_default(),
+                next_attempt_at: None,
+                failed_job: pending.failed_id,
+            }
+        })
+        .collect();
+    let mut rows: Vec<FailedJob> = failed.into_values().collect();
+    rows.sort_by_key(|row| row.failed_at);
+    jobs.extend(rows.iter().map(failed_view));
+
+    if let Some(status) = status {
+        jobs.retain(|job| job.status == status);
+    }
+    Ok(jobs)
+}
+
+// Puts a failed job back on the queue now. Queued and running jobs are
+// already being processed.
+pub async fn retry(c: &ProcessorConfig, id: &str) -> Result<()> {
+    match parse_id(id)? {
+        JobId::Failed(failed) => {
+            if queued_retry(c, failed) {
+                return Err(Error::Conflict(format!("job {} is already queued", id)));
+            }
+            dead_letter::requeue_now(c, failed).await
+        }
+        JobId::Queue(n) => Err(match c.queue.snapshot().iter().find(|(q, _)| *q == n) {
+            Some(_) => Error::Conflict(format!("job {} is already queued", id)),
+            None => Error::NotFound(format!("job {}", id)),
+        }),
+    }
+}
+
+fn queued_retry(c: &ProcessorConfig, failed: i64) -> bool {
+    c.queue
+        .snapshot()
+        .iter()
+        .any(|(_, pending)| pending.failed_id == Some(failed))
+}
+
+// Drops a job and its audio for good. A running job is left to finish, as
+// stopping it partway could leave a call half stored.
+pub async fn cancel(c: &ProcessorConfig, id: &str) -> Result<()> {
+    match parse_id(id)? {
+        JobId::Queue(n) => {
+            let Some(job) = c.queue.cancel(n) else {
+                return Err(match c.queue.snapshot().iter().find(|(q, _)| *q == n) {
+                    Some(_) => Error::Conflict(format!("job {} is running", id)),
+                    None => Error::NotFound(format!("job {}", id)),
+                });
+            };
+            match job.failed_id {
+                Some(failed) => dead_letter::cancel(c, failed).await?,
+                None => job.discard(c).await,
+            }
+        }
+        JobId::Failed(failed) => {
+            if queued_retry(c, failed) {
+                return Err(Error::Conflict(format!(
+                    "job {} is queued for a retry, cancel its queue entry",
+                    id
+                )));
+            }
+            dead_letter::cancel(c, failed).await?;
+        }
+    }
+    info!(job = %id, "Job cancelled");
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use crate::error::{Error, Result};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{collections::BTreeMap, path::Path};
+use tracing::info;
+
+// Where a setting came from, so a bad one can be found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    File,
+    Env,
+}
+
+// Settings as environment-style NAME=value pairs: CONFIG_FILE first, with
+// the environment layered over it. Everything that reads settings goes
+// through here, so a variable can be set in either place.
+#[derive(Debug, Default)]
+pub struct Layers {
+    values: BTreeMap<String, (String, Source)>,
+}
+
+fn name(key: &str) -> String {
+    key.replace('-', "_").to_ascii_uppercase()
+}
+
+fn field_key(prefix: &str, field: &str) -> String {
+    format!("{}{}", prefix, field.to_ascii_uppercase())
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(Error::Configuration(format!(
+            "{}: lists may only hold strings, numbers and booleans",
+            key
+        ))),
+    }
+}
+
+// Tables become prefixes, so [filter] tg_id is FILTER_TG_ID, and lists are
+// joined with commas as they are written in the environment
+fn flatten(key: &str, value: &Value, out: &mut BTreeMap<String, String>) -> Result<()> {
+    match value {
+        Value::Object(table) => {
+            for (k, v) in table {
+                let k = match key {
+                    "" => name(k),
+                    key => format!("{}_{}", key, name(k)),
+                };
+                flatten(&k, v, out)?;
+            }
+        }
+        Value::Array(items) => {
+            let items = items
+                .iter()
+                .map(|item| scalar(key, item))
+                .collect::<Result<Vec<_>>>()?;
+            out.insert(key.to_string(), items.join(","));
+        }
+        Value::Null => {}
+        value => {
+            out.insert(key.to_string(), scalar(key, value)?);
+        }
+    }
+    Ok(())
+}
+
+fn parse_file(path: &Path) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::Configuration(format!("{}: {}", path.display(), e)))?;
+    let invalid = |e: &dyn std::fmt::Display| {
+        Error::Configuration(format!("{}: {}", path.display(), e.to_string().trim()))
+    };
+    let value: Value = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&text).map_err(|e| invalid(&e))?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&text).map_err(|e| invalid(&e))?,
+        Some("json") => serde_json::from_str(&text).map_err(|e| invalid(&e))?,
+        _ => {
+            return Err(Error::Configuration(format!(
+                "{}: expected a .toml, .yaml or .json file",
+                path.display()
+            )));
+        }
+    };
+    if !value.is_object() {
+        return Err(invalid(&"expected a table of settings"));
+    }
+    Ok(value)
+}
+
+impl Layers {
+    pub fn load() -> Result<Self> {
+        let mut layers = Layers::default();
+        if let Some(path) = std::env::var_os("CONFIG_FILE") {
+            layers.merge_file(Path::new(&path))?;
+        }
+        for (key, value) in std::env::vars() {
+            layers.values.insert(key, (value, Source::Env));
+        }
+        Ok(layers)
+    }
+
+    fn merge_file(&mut self, path: &Path) -> Result<()> {
+        let value = parse_file(path)?;
+        let mut settings = BTreeMap::new();
+        flatten("", &value, &mut settings)?;
+
+        // each [notifier.<name>] table registers a notifier unless NOTIFIERS
+        // lists them
+        if let Some(Value::Object(notifiers)) = value.get("notifier")
+            && !settings.contains_key("NOTIFIERS")
+        {
+            let names: Vec<&str> = notifiers.keys().map(String::as_str).collect();
+            settings.insert("NOTIFIERS".to_string(), names.join(","));
+        }
+
+        info!(file = %path.display(), settings = settings.len(), "Loaded configuration file");
+        for (key, value) in settings {
+            self.values.insert(key, (value, Source::File));
+        }
+        Ok(())
+    }
+
+    fn describe(&self, key: &str) -> String {
+        match self.values.get(key) {
+            Some((_, Source::File)) => format!("{} (from CONFIG_FILE)", key),
+            _ => key.to_string(),
+        }
+    }
+
+    // Deserializes the settings under `prefix`, as envy does from the
+    // environment, but reports every bad or missing setting rather than the
+    // first: each one is set aside and the rest tried again.
+    pub fn deserialize<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> std::result::Result<T, Vec<(String, String)>> {
+        let mut values: BTreeMap<String, String> = self
+            .values
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect();
+        let attempt = |values: &BTreeMap<String, String>| {
+            envy::prefixed(prefix).from_iter::<_, T>(values.clone())
+        };
+
+        let mut problems = Vec::new();
+        let mut missing = Vec::new();
+        for _ in 0..=values.len() + 1 {
+            let e = match attempt(&values) {
+                Ok(config) if problems.is_empty() => return Ok(config),
+                Ok(_) => return Err(problems),
+                Err(e) => e,
+            };
+            if let envy::Error::MissingValue(field) = e {
+                let key = field_key(prefix, field);
+                if missing.contains(&key) {
+                    break;
+                }
+                problems.push((key.clone(), "required but not set".to_string()));
+                // a stand-in, so the settings after it are checked too
+                values.insert(key.clone(), String::new());
+                missing.push(key);
+                continue;
+            }
+
+            // the culprit is whichever setting, once removed, no longer gives
+            // this error, other than a required one that is then missing
+            let culprit = values
+                .keys()
+                .filter(|key| !missing.contains(*key))
+                .find(|key| {
+                    let mut without = values.clone();
+                    without.remove(*key);
+                    match attempt(&without) {
+                        Ok(_) => true,
+                        Err(envy::Error::MissingValue(field)) => field_key(prefix, field) != **key,
+                        Err(other) => other != e,
+                    }
+                })
+                .cloned();
+            match culprit {
+                Some(key) => {
+                    problems.push((self.describe(&key), e.to_string()));
+                    values.remove(&key);
+                }
+                None => {
+                    problems.push((format!("{}*", prefix), e.to_string()));
+                    break;
+                }
+            }
+        }
+        Err(problems)
+    }
+}
@@ -1,27 +1,113 @@
 #![deny(unused_crate_dependencies)]
+mod activity;
+mod admin;
+mod alerts;
+mod audio;
+mod audit;
+mod auth;
+mod backfill;
+mod capture;
+mod cli;
 mod common;
+mod compression;
 mod config;
+mod crypto;
+mod dead_letter;
+mod discord;
+mod discord_routing;
+mod discovery;
 mod error;
+mod events;
+mod export;
+mod filters;
+mod hashing;
+mod hooks;
+mod incident;
+mod integrity;
+mod jobs;
+mod layers;
+mod metrics;
 mod model;
+mod monitor;
+mod mqtt;
+mod notifications;
+mod notifier;
+mod openmhz;
+mod policy;
+mod pools;
+mod priority;
+mod put_queue;
+mod query;
+mod rdio;
+mod readiness;
+mod reconcile;
+mod rename;
+mod renotify;
+mod replay;
+mod report;
+mod retention;
+mod retranscribe;
 mod schema;
+mod scrub;
+mod segments;
+mod server;
+mod shutdown;
+mod stats;
+mod subtitles;
+mod switches;
+mod testcall;
+mod tr_config;
+mod trace;
+mod transcribe;
+mod transcription_cache;
 mod upload;
+mod words;
+mod worker;
+mod workspace;
 
+use crate::admin::{
+    access_log, cancel_job, delete_capture, delete_discord_route, discovered_talkgroups,
+    export_calls, get_switches, integrity_check, invalidate_transcription_cache, list_captures,
+    list_discord_routes, list_failed_jobs, list_jobs, list_notifications, list_systems,
+    reconcile_now, reconcile_report, reload_config, rename_groups, renotify_calls, replay_capture,
+    requeue_failed_job, retranscribe_calls, retry_job, run_retention, set_discord_route,
+    set_switches, test_call, transcription_cache_stats,
+};
+use crate::audio::call_audio;
+use crate::cli::{Cli, Command};
 use crate::common::*;
+use crate::config::ProcessorConfig;
+use crate::discord::interactions;
 use crate::error::{Error, Result};
+use crate::events::{events, live};
+use crate::filters::test_filters;
+use crate::incident::incident_audio;
+use crate::metrics::metrics;
+use crate::openmhz::openmhz_upload;
+use crate::query::{call_context, list_calls, list_emergencies, recent_calls};
+use crate::readiness::readyz;
+use crate::report::incident_report;
+use crate::stats::{frequency_stats, response_time_stats};
+use crate::subtitles::{transcript_srt, transcript_vtt};
 use crate::upload::upload;
 
 use axum::{
     Router,
+    extract::DefaultBodyLimit,
     http::HeaderMap,
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use chrono::Utc;
+use clap::Parser;
 use diesel_migrations::{EmbeddedMigrations, embed_migrations};
 use pq_sys as _;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tracing::info;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+const ACCESS_LOG_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+const TRANSCRIPTION_CACHE_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
 
 async fn healthz(headers: HeaderMap) -> Result<String> {
     let timestamp = format_timestamp_from_datetime(Utc::now().to_utc());
@@ -49,13 +135,19 @@ async fn main() -> Result<()> {
         )
         .init();
 
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
+    if let Command::CheckConfig = command {
+        return cli::check_config().await;
+    }
     info!("Initializing trunk-processor");
 
     let config = config::initialize()?;
-    if config.filter.enabled() {
+    config::log_summary(&config);
+    let filter = config.filter.load();
+    if filter.enabled() {
         info!(
-            group = config.filter.group().join(", "),
-            tgid = config.filter.tgid().join(", "),
+            group = filter.group().join(", "),
+            tgid = filter.tgid().join(", "),
             "Filter values provided"
         );
     } else {
@@ -70,10 +162,161 @@ async fn main() -> Result<()> {
             .get()
             .map_err(|e| Error::Database(e.to_string()))?,
     )?;
+    if let Command::Migrate = command {
+        return Ok(());
+    }
 
+    if config.env.integrity_check {
+        integrity::check(&config, config.env.integrity_repair)?;
+    }
+
+    match command {
+        Command::Serve => serve(config).await,
+        Command::Migrate => Ok(()),
+        command => cli::run(&config, command).await,
+    }
+}
+
+async fn serve(config: ProcessorConfig) -> Result<()> {
+    workspace::sweep(&config);
+    worker::spawn(&config);
+    tokio::spawn(dead_letter::run_periodic(config.clone()));
+    if config.env.queue_max_age.is_some() || config.env.queue_max_length.is_some() {
+        tokio::spawn(worker::watch(config.clone()));
+    }
+
+    if let Some(every) = config.env.reconcile_interval_secs {
+        info!(
+            interval_secs = every.as_secs(),
+            "Scheduling storage reconciliation"
+        );
+        tokio::spawn(reconcile::run_periodic(config.clone(), every));
+    }
+
+    if let Some(every) = config.env.anomaly_interval_secs {
+        info!(
+            interval_secs = every.as_secs(),
+            "Scheduling frequency anomaly checks"
+        );
+        tokio::spawn(monitor::run_periodic(config.clone(), every));
+    }
+
+    if let Some(retention) = config
+        .env
+        .access_log_retention_days
+        .filter(|_| config.env.access_log)
+    {
+        info!(
+            retention_days = retention.as_secs() / 86400,
+            "Scheduling access log pruning"
+        );
+        tokio::spawn(audit::run_periodic(
+            config.clone(),
+            retention,
+            ACCESS_LOG_PRUNE_INTERVAL,
+        ));
+    }
+
+    if !config.retention.is_empty() {
+        info!(
+            interval_secs = config.env.retention_interval.as_secs(),
+            dry_run = config.env.retention_dry_run,
+            "Scheduling retention"
+        );
+        tokio::spawn(retention::run_periodic(
+            config.clone(),
+            config.env.retention_interval,
+        ));
+    }
+
+    if let Some(ttl) = config
+        .env
+        .transcription_cache_ttl_days
+        .filter(|_| config.env.transcription_cache)
+    {
+        info!(
+            ttl_days = ttl.as_secs() / 86400,
+            "Scheduling transcription cache pruning"
+        );
+        tokio::spawn(transcription_cache::run_periodic(
+            config.clone(),
+            ttl,
+            TRANSCRIPTION_CACHE_PRUNE_INTERVAL,
+        ));
+    }
+
+    if config.env.transcription_compress_size.is_some() {
+        tokio::spawn(compression::compress_stored(config.clone()));
+    }
+
+    tokio::spawn(config::reload_on_hangup(config.clone()));
+
+    let env = config.env.clone();
+    // the router takes the config, so shutdown keeps its own handle
+    let running = config.clone();
     let app = Router::new()
-        .route("/upload", post(upload).with_state(config))
-        .route("/healthz", get(healthz));
+        .route(
+            "/upload",
+            post(upload).layer(DefaultBodyLimit::max(config.env.upload_body_limit())),
+        )
+        .route(
+            "/openmhz/{short_name}/upload",
+            post(openmhz_upload).layer(DefaultBodyLimit::max(config.env.upload_body_limit())),
+        )
+        .route("/interactions", post(interactions))
+        .route(
+            "/admin/reconcile",
+            get(reconcile_report).post(reconcile_now),
+        )
+        .route("/admin/talkgroups/discovered", get(discovered_talkgroups))
+        .route("/admin/talkgroups/rename", post(rename_groups))
+        .route("/admin/access-log", get(access_log))
+        .route("/admin/notifications", get(list_notifications))
+        .route("/admin/export", post(export_calls))
+        .route("/admin/integrity", post(integrity_check))
+        .route("/admin/retention", post(run_retention))
+        .route("/admin/renotify", post(renotify_calls))
+        .route("/admin/retranscribe", post(retranscribe_calls))
+        .route(
+            "/admin/discord-routes",
+            get(list_discord_routes).post(set_discord_route),
+        )
+        .route("/admin/discord-routes/{id}", delete(delete_discord_route))
+        .route("/admin/systems", get(list_systems))
+        .route("/admin/switches", get(get_switches).put(set_switches))
+        .route("/admin/reload", post(reload_config))
+        .route("/admin/testcall", post(test_call))
+        .route("/admin/failed-jobs", get(list_failed_jobs))
+        .route("/admin/failed-jobs/{id}/requeue", post(requeue_failed_job))
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/{id}/retry", post(retry_job))
+        .route("/jobs/{id}/cancel", post(cancel_job))
+        .route(
+            "/admin/transcription-cache",
+            get(transcription_cache_stats).delete(invalidate_transcription_cache),
+        )
+        .route("/admin/captures", get(list_captures))
+        .route("/admin/captures/{id}", delete(delete_capture))
+        .route("/admin/captures/{id}/replay", post(replay_capture))
+        .route("/filters/test", post(test_filters))
+        .route("/api/calls", get(list_calls))
+        .route("/api/calls/{id}/audio", get(call_audio))
+        .route("/calls/recent", get(recent_calls))
+        .route("/events", get(events))
+        .route("/ws", get(live))
+        .route("/calls/{id}/context", get(call_context))
+        .route("/calls/{id}/context/audio", get(incident_audio))
+        .route("/calls/{id}/context/report", get(incident_report))
+        .route("/calls/{id}/transcript.vtt", get(transcript_vtt))
+        .route("/calls/{id}/transcript.srt", get(transcript_srt))
+        .route("/emergencies", get(list_emergencies))
+        .route("/stats/frequencies", get(frequency_stats))
+        .route("/stats/response-times", get(response_time_stats))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .layer(DefaultBodyLimit::max(config.env.body_limit))
+        .with_state(config);
 
     let bind_addr = "0.0.0.0:3000";
     info!(addr = %bind_addr, "Starting HTTP server");
@@ -81,9 +324,12 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(bind_addr)
         .await
         .map_err(Error::ServerInit)?;
-    axum::serve(listener, app)
-        .await
-        .map_err(Error::ServerInit)?;
-
+    let events = running.events.clone();
+    let shutdown = async move {
+        shutdown::signal().await;
+        events.close();
+    };
+    server::serve(&env, listener, app, shutdown).await?;
+    shutdown::drain(&running, env.shutdown_drain_timeout).await;
     Ok(())
 }
@@ -0,0 +1,263 @@
+use crate::common::ago;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::SYNTHETIC_TAG;
+use crate::priority::PriorityClass;
+use crate::schema::{calls, notifications};
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use diesel::{
+    dsl::{count_star, not},
+    prelude::*,
+};
+use std::{collections::BTreeMap, fmt::Write, sync::atomic::Ordering, time::Duration};
+use tracing::warn;
+
+const RECENT_CALLS_WINDOW: Duration = Duration::from_secs(300);
+const BACKLOG_WINDOW: Duration = Duration::from_secs(3600);
+const FAILED_NOTIFICATIONS_WINDOW: Duration = Duration::from_secs(3600);
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn gauge<I, V>(out: &mut String, name: &str, help: &str, samples: I)
+where
+    I: IntoIterator<Item = (String, V)>,
+    V: std::fmt::Display,
+{
+    series(out, "gauge", name, help, samples);
+}
+
+fn counter<I, V>(out: &mut String, name: &str, help: &str, samples: I)
+where
+    I: IntoIterator<Item = (String, V)>,
+    V: std::fmt::Display,
+{
+    series(out, "counter", name, help, samples);
+}
+
+fn series<I, V>(out: &mut String, kind: &str, name: &str, help: &str, samples: I)
+where
+    I: IntoIterator<Item = (String, V)>,
+    V: std::fmt::Display,
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+// Keeps one series per talkgroup from swamping Prometheus on large systems:
+// only the allowlisted or busiest talkgroups get a label of their own
+fn talkgroup_samples(c: &ProcessorConfig, mut counts: Vec<(i32, i64)>) -> Vec<(String, i64)> {
+    let labelled: Vec<i32> = match &c.env.metrics_talkgroups {
+        Some(allowed) => allowed.clone(),
+        None => {
+            counts.sort_by_key(|&(talkgroup, count)| (std::cmp::Reverse(count), talkgroup));
+            counts
+                .iter()
+                .take(c.env.metrics_talkgroup_top)
+                .map(|&(talkgroup, _)| talkgroup)
+                .collect()
+        }
+    };
+
+    let mut samples = BTreeMap::new();
+    let mut other = 0;
+    for (talkgroup, count) in counts {
+        if labelled.contains(&talkgroup) {
+            samples.insert(talkgroup, count);
+        } else {
+            other += count;
+        }
+    }
+    // allowlisted talkgroups stay present at zero rather than disappearing
+    if c.env.metrics_talkgroups.is_some() {
+        for talkgroup in labelled {
+            samples.entry(talkgroup).or_insert(0);
+        }
+    }
+
+    samples
+        .into_iter()
+        .map(|(talkgroup, count)| (talkgroup.to_string(), count))
+        .chain([("other".to_string(), other)])
+        .collect()
+}
+
+// Test calls from /admin/testcall aren't traffic, so are left out of these
+fn synthetic() -> serde_json::Value {
+    serde_json::json!({ SYNTHETIC_TAG: "true" })
+}
+
+// Gauges computed from Postgres on every scrape, so alerts don't depend on
+// the state of any one process
+fn database_gauges(c: &ProcessorConfig, out: &mut String) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let recent: Vec<(String, i64)> = calls::table
+        .filter(calls::start_time.ge(ago(RECENT_CALLS_WINDOW)))
+        .filter(not(calls::tags.contains(synthetic())))
+        .group_by(calls::short_name)
+        .select((calls::short_name, count_star()))
+        .order(calls::short_name)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let per_talkgroup: Vec<(i32, i64)> = calls::table
+        .filter(calls::start_time.ge(ago(BACKLOG_WINDOW)))
+        .filter(not(calls::tags.contains(synthetic())))
+        .group_by(calls::talkgroup)
+        .select((calls::talkgroup, count_star()))
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    // calls the filters skipped have no transcription either, so only the
+    // ones whose transcription failed are counted
+    let backlog: i64 = calls::table
+        .filter(calls::start_time.ge(ago(BACKLOG_WINDOW)))
+        .filter(not(calls::tags.contains(synthetic())))
+        .filter(calls::transcription_failed)
+        .filter(calls::archive_reason.is_null())
+        .count()
+        .get_result(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let failed_destinations: Vec<String> = notifications::table
+        .filter(notifications::sent_at.ge(ago(FAILED_NOTIFICATIONS_WINDOW)))
+        .filter(notifications::error.is_not_null())
+        .select(notifications::destination)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    // destinations carry user and channel ids, so only their kind is a label
+    let mut failed = BTreeMap::new();
+    for destination in &failed_destinations {
+        let kind = destination.split(':').next().unwrap_or_default();
+        *failed.entry(kind).or_insert(0) += 1;
+    }
+
+    gauge(
+        out,
+        "trunk_processor_recent_calls",
+        "Calls started in the last 5 minutes per system",
+        recent
+            .into_iter()
+            .map(|(system, count)| (format!("system=\"{}\"", escape(&system)), count)),
+    );
+    gauge(
+        out,
+        "trunk_processor_talkgroup_calls",
+        "Calls started in the last hour per talkgroup, unlabelled talkgroups counted as other",
+        talkgroup_samples(c, per_talkgroup)
+            .into_iter()
+            .map(|(talkgroup, count)| (format!("talkgroup=\"{}\"", talkgroup), count)),
+    );
+    gauge(
+        out,
+        "trunk_processor_untranscribed_calls",
+        "Calls started in the last hour whose transcription failed, excluding archived calls",
+        [(String::new(), backlog)],
+    );
+    gauge(
+        out,
+        "trunk_processor_failed_notifications",
+        "Archived notifications that failed in the last hour per destination kind",
+        failed
+            .into_iter()
+            .map(|(kind, count)| (format!("destination=\"{}\"", kind), count)),
+    );
+
+    Ok(())
+}
+
+pub async fn metrics(State(config): State<ProcessorConfig>) -> Response {
+    let mut out = String::new();
+    let pools = config.pools.all();
+    let label = |name: &str| format!("pool=\"{}\"", name);
+
+    gauge(
+        &mut out,
+        "trunk_processor_workers",
+        "Configured workers per pool",
+        pools.iter().map(|p| (label(p.name), p.size)),
+    );
+    gauge(
+        &mut out,
+        "trunk_processor_workers_busy",
+        "Workers currently running a task",
+        pools.iter().map(|p| (label(p.name), p.busy())),
+    );
+    gauge(
+        &mut out,
+        "trunk_processor_workers_waiting",
+        "Tasks waiting for a free worker",
+        pools.iter().map(|p| (label(p.name), p.waiting())),
+    );
+    gauge(
+        &mut out,
+        "trunk_processor_queue_jobs",
+        "Uploads accepted but not yet processed",
+        [
+            ("state=\"queued\"".to_string(), config.queue.queued()),
+            ("state=\"running\"".to_string(), config.queue.running()),
+        ],
+    );
+    let queued = config.queue.queued_by_class();
+    let class = |c: PriorityClass| format!("priority=\"{}\"", c.label());
+    gauge(
+        &mut out,
+        "trunk_processor_queue_jobs_queued",
+        "Uploads waiting to be processed per priority class",
+        PriorityClass::ALL.map(|c| (class(c), queued[c.index()])),
+    );
+    counter(
+        &mut out,
+        "trunk_processor_queue_started_total",
+        "Uploads taken from the queue per priority class",
+        PriorityClass::ALL.map(|c| {
+            let started = config.queue.waits(c).started.load(Ordering::Relaxed);
+            (class(c), started)
+        }),
+    );
+    counter(
+        &mut out,
+        "trunk_processor_queue_wait_seconds_total",
+        "Time uploads waited in the queue before processing per priority class",
+        PriorityClass::ALL.map(|c| {
+            let waited = config.queue.waits(c).waited_micros.load(Ordering::Relaxed);
+            (class(c), waited as f64 / 1_000_000.0)
+        }),
+    );
+    gauge(
+        &mut out,
+        "trunk_processor_queue_oldest_seconds",
+        "Age of the oldest upload not yet processed",
+        [(
+            String::new(),
+            config.queue.oldest().unwrap_or_default().as_secs_f64(),
+        )],
+    );
+
+    if let Err(e) = database_gauges(&config, &mut out) {
+        warn!(error = %e, "Failed to compute database gauges");
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out).into_response()
+}
@@ -5,10 +5,15 @@ use serde_with::{
     NoneAsEmptyString, chrono_0_4::datetime_utc_ts_seconds_from_any, serde_as,
     skip_serializing_none,
 };
-use std::hash::{DefaultHasher, Hash, Hasher};
 
 use crate::common::{map_float_sec_to_timedelta, map_int_to_bool};
-use crate::schema::{calls, freqlist, sources, srclist, talkgroups};
+use crate::error::{Error, Result};
+use crate::hashing::{Canonical, HashAlgorithm, legacy};
+use crate::schema::{
+    access_log, calls, compressed_transcriptions, discord_routes, discovered_talkgroups,
+    emergencies, failed_jobs, freqlist, notification_keys, notifications, sources, srclist,
+    subscriptions, systems, talkgroups, transcript_segments, transcript_words, transcription_cache,
+};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, diesel_derive_enum::DbEnum)]
 #[ExistingTypePath = "crate::schema::sql_types::Audiotype"]
@@ -20,6 +25,121 @@ pub enum AudioType {
     DigitalTdma,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::Deliverytype"]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryType {
+    Dm,
+    Thread,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::Archivereason"]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveReason {
+    Backfill,
+    Encrypted,
+    Manual,
+    // set aside by UPLOAD_POLICY
+    Quarantine,
+}
+
+impl ArchiveReason {
+    pub fn from_header(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "backfill" => ArchiveReason::Backfill,
+            "encrypted" => ArchiveReason::Encrypted,
+            _ => ArchiveReason::Manual,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ArchiveReason::Backfill => "backfill",
+            ArchiveReason::Encrypted => "encrypted",
+            ArchiveReason::Manual => "manual",
+            ArchiveReason::Quarantine => "quarantine",
+        }
+    }
+}
+
+// Where a failed job stopped. Once it is past storing, its call is kept and
+// only notifying it is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::Jobstage"]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    Store,
+    Notify,
+}
+
+// Layout of the call JSON trunk-recorder writes, named by the trunk-recorder
+// major version that introduced it. 4.x writes flags as 0/1, 5.x as booleans.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetadataVersion {
+    V4,
+    V5,
+}
+
+fn flag_to_int(value: &mut serde_json::Value) {
+    if let Some(flag) = value.as_bool() {
+        *value = serde_json::Value::from(flag as u8);
+    }
+}
+
+impl MetadataVersion {
+    // accepts a bare major version or a full trunk-recorder release, e.g. 5 or 5.0.1
+    pub fn from_header(value: &str) -> Result<Self> {
+        match value.trim().split('.').next() {
+            Some("4") => Ok(MetadataVersion::V4),
+            Some("5") => Ok(MetadataVersion::V5),
+            _ => Err(Error::InvalidRequest(format!(
+                "unsupported X-Metadata-Version {:?}, expected 4 or 5",
+                value
+            ))),
+        }
+    }
+
+    pub fn major(self) -> &'static str {
+        match self {
+            MetadataVersion::V4 => "4",
+            MetadataVersion::V5 => "5",
+        }
+    }
+
+    pub fn detect(json: &serde_json::Value) -> Self {
+        if json
+            .get("emergency")
+            .is_some_and(serde_json::Value::is_boolean)
+        {
+            MetadataVersion::V5
+        } else {
+            MetadataVersion::V4
+        }
+    }
+
+    pub fn parse(self, data: &[u8]) -> Result<AudioMetadataRaw> {
+        match self {
+            MetadataVersion::V4 => serde_json::from_slice(data).map_err(Error::JsonParsing),
+            MetadataVersion::V5 => {
+                let mut json: serde_json::Value =
+                    serde_json::from_slice(data).map_err(Error::JsonParsing)?;
+                for key in ["emergency", "encrypted"] {
+                    if let Some(flag) = json.get_mut(key) {
+                        flag_to_int(flag);
+                    }
+                }
+                if let Some(sources) = json.get_mut("srcList").and_then(|v| v.as_array_mut()) {
+                    for flag in sources.iter_mut().filter_map(|s| s.get_mut("emergency")) {
+                        flag_to_int(flag);
+                    }
+                }
+                serde_json::from_value(json).map_err(Error::JsonParsing)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AudioMetadataRaw {
     #[serde(flatten)]
@@ -61,6 +181,32 @@ struct SrcListRaw {
     tag: Option<String>,
 }
 
+impl AudioMetadata {
+    pub fn emergencies(&self) -> Vec<Emergency> {
+        let declaration = |src: Option<i32>, declared_at: DateTime<Utc>| Emergency {
+            id: 0,
+            call_id: self.call.filename.clone(),
+            talkgroup: self.talkgroup.talkgroup,
+            src,
+            short_name: self.call.short_name.clone(),
+            declared_at,
+        };
+
+        let mut declarations: Vec<Emergency> = self
+            .src_list
+            .iter()
+            .filter(|s| s.emergency)
+            .map(|s| declaration(Some(s.src), s.time))
+            .collect();
+
+        if self.call.emergency {
+            declarations.insert(0, declaration(None, self.call.start_time));
+        }
+
+        declarations
+    }
+}
+
 impl AudioMetadataRaw {
     pub fn split_src_list(&self) -> (Vec<SrcList>, Vec<Source>) {
         let mut src_list = Vec::new();
@@ -75,6 +221,7 @@ impl AudioMetadataRaw {
                 pos: item.pos,
                 emergency: item.emergency,
                 signal_system: item.signal_system.clone(),
+                hash_version: 0,
             });
 
             sources.push(Source {
@@ -110,6 +257,13 @@ pub struct Talkgroups {
     pub talkgroup_group: String,
 }
 
+impl Talkgroups {
+    pub fn has_default_metadata(&self) -> bool {
+        self.talkgroup_description.is_empty()
+            && (self.talkgroup_tag.is_empty() || self.talkgroup_tag == self.talkgroup.to_string())
+    }
+}
+
 #[skip_serializing_none]
 #[derive(
     AsChangeset,
@@ -149,15 +303,52 @@ pub struct Call {
     #[serde(deserialize_with = "map_int_to_bool")]
     pub encrypted: bool,
     pub call_length: i16,
-    #[serde(skip)]
+    #[serde(skip_deserializing)]
     pub talkgroup: i32,
     #[diesel(sql_type = Varchar)]
     pub audio_type: AudioType,
     pub short_name: String,
-    #[serde(skip)]
+    #[serde(skip_deserializing)]
     pub transcription: Option<String>,
-    #[serde(skip)]
+    #[serde(skip_deserializing)]
     pub filename: String,
+    #[serde(default)]
+    pub patched_talkgroups: Vec<Option<i32>>,
+    #[serde(skip)]
+    pub public_transcription: Option<String>,
+    #[serde(skip_deserializing)]
+    pub archive_reason: Option<ArchiveReason>,
+    #[serde(skip_deserializing, default = "empty_tags")]
+    pub tags: serde_json::Value,
+    // "transcribe" or "skip", and the rule that decided it
+    #[serde(skip_deserializing)]
+    pub filter_action: Option<String>,
+    #[serde(skip_deserializing)]
+    pub filter_rule: Option<String>,
+    // what X-Language: auto detected, and whether it was too unsure to use
+    #[serde(skip_deserializing)]
+    pub detected_language: Option<String>,
+    #[serde(skip_deserializing)]
+    pub language_probability: Option<f32>,
+    #[serde(skip_deserializing)]
+    pub language_fallback: bool,
+    // stored untranscribed once TRANSCRIPTION_RETRIES ran out
+    #[serde(skip_deserializing)]
+    pub transcription_failed: bool,
+}
+
+fn empty_tags() -> serde_json::Value {
+    serde_json::Value::Object(serde_json::Map::new())
+}
+
+// Tagged "true" on calls made up by /admin/testcall, which are kept out of
+// statistics
+pub const SYNTHETIC_TAG: &str = "synthetic";
+
+impl Call {
+    pub fn synthetic(&self) -> bool {
+        self.tags.get(SYNTHETIC_TAG).and_then(|v| v.as_str()) == Some("true")
+    }
 }
 
 #[skip_serializing_none]
@@ -173,7 +364,6 @@ pub struct Call {
     PartialEq,
     Serialize,
     Deserialize,
-    Hash,
 )]
 #[diesel(belongs_to(Call))]
 #[diesel(table_name = freqlist)]
@@ -193,6 +383,8 @@ pub struct FreqList {
     pub len: TimeDelta,
     pub error_count: i16,
     pub spike_count: i16,
+    #[serde(skip)]
+    pub hash_version: i16,
 }
 
 #[serde_as]
@@ -208,7 +400,6 @@ pub struct FreqList {
     PartialEq,
     Serialize,
     Deserialize,
-    Hash,
 )]
 #[diesel(belongs_to(Call))]
 #[diesel(table_name = srclist)]
@@ -228,6 +419,8 @@ pub struct SrcList {
     pub emergency: bool,
     #[serde_as(as = "NoneAsEmptyString")]
     pub signal_system: Option<String>,
+    #[serde(skip)]
+    pub hash_version: i16,
 }
 
 #[serde_as]
@@ -254,19 +447,159 @@ pub struct Source {
     pub tag: Option<String>,
 }
 
+#[derive(
+    AsChangeset,
+    Insertable,
+    Queryable,
+    Identifiable,
+    Selectable,
+    Debug,
+    Clone,
+    PartialEq,
+    Serialize,
+    Deserialize,
+)]
+#[diesel(table_name = subscriptions)]
+#[diesel(primary_key(user_id, talkgroup))]
+#[diesel(treat_none_as_null = true)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Subscription {
+    pub user_id: String,
+    pub talkgroup: i32,
+    pub delivery: DeliveryType,
+    pub channel_id: Option<String>,
+}
+
+#[derive(
+    Insertable, Queryable, Identifiable, Selectable, Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(table_name = discovered_talkgroups)]
+#[diesel(primary_key(talkgroup))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DiscoveredTalkgroup {
+    pub talkgroup: i32,
+    pub short_name: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub call_count: i32,
+}
+
+#[derive(
+    Insertable,
+    Queryable,
+    Identifiable,
+    Selectable,
+    Associations,
+    Debug,
+    Clone,
+    PartialEq,
+    Serialize,
+    Deserialize,
+)]
+#[diesel(belongs_to(Call))]
+#[diesel(belongs_to(Talkgroups, foreign_key = talkgroup))]
+#[diesel(table_name = emergencies)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Emergency {
+    #[diesel(skip_insertion)]
+    pub id: i64,
+    pub call_id: String,
+    pub talkgroup: i32,
+    pub src: Option<i32>,
+    pub short_name: String,
+    pub declared_at: DateTime<Utc>,
+}
+
+#[derive(
+    Insertable, Queryable, Identifiable, Selectable, Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(table_name = access_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AccessLogEntry {
+    #[diesel(skip_insertion)]
+    pub id: i64,
+    pub accessed_at: DateTime<Utc>,
+    pub principal: String,
+    pub method: String,
+    pub endpoint: String,
+    pub call_ids: Vec<Option<String>>,
+}
+
+#[derive(
+    Insertable, Queryable, Identifiable, Selectable, Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(table_name = notification_keys)]
+#[diesel(primary_key(call_id, destination))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct NotificationKey {
+    pub call_id: String,
+    pub destination: String,
+    pub claimed_at: DateTime<Utc>,
+}
+
+#[derive(
+    Insertable, Queryable, Identifiable, Selectable, Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(table_name = notifications)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Notification {
+    #[diesel(skip_insertion)]
+    pub id: i64,
+    pub call_id: String,
+    pub destination: String,
+    pub payload: serde_json::Value,
+    pub status: Option<i32>,
+    pub error: Option<String>,
+    pub sent_at: DateTime<Utc>,
+}
+
+// A call's transcription and scrubbed copy, zstd compressed and sealed with
+// TRANSCRIPTION_KEY when that is set
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq)]
+#[diesel(table_name = compressed_transcriptions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CompressedTranscription {
+    pub call_id: String,
+    pub transcription: Vec<u8>,
+    pub public_transcription: Option<Vec<u8>>,
+}
+
 pub trait IsList {
     fn set_call_id(&mut self, id: String);
-    fn calculate_hash(&mut self);
+    // Keys the row with `algorithm`, recording it in hash_version
+    fn calculate_hash(&mut self, algorithm: HashAlgorithm);
+    fn hash_version(&self) -> i16;
 }
 
 impl IsList for SrcList {
     fn set_call_id(&mut self, id: String) {
         self.call_id = id;
     }
-    fn calculate_hash(&mut self) {
-        let mut s = DefaultHasher::new();
-        self.hash(&mut s);
-        self.hashed = s.finish() as i64
+    fn calculate_hash(&mut self, algorithm: HashAlgorithm) {
+        self.hashed = match algorithm.digest() {
+            Some(digest) => Canonical::default()
+                .str(&self.call_id)
+                .int(self.src.into())
+                .time(self.time)
+                .delta(self.pos)
+                .bool(self.emergency)
+                .optional_str(self.signal_system.as_deref())
+                .finish(digest),
+            // the whole row with the key zeroed, as the derived Hash covered it
+            None => legacy(&(
+                &self.call_id,
+                0i64,
+                self.src,
+                self.time,
+                self.pos,
+                self.emergency,
+                &self.signal_system,
+            )),
+        };
+        self.hash_version = algorithm.version();
+    }
+    fn hash_version(&self) -> i16 {
+        self.hash_version
     }
 }
 
@@ -274,9 +607,136 @@ impl IsList for FreqList {
     fn set_call_id(&mut self, id: String) {
         self.call_id = id;
     }
-    fn calculate_hash(&mut self) {
-        let mut s = DefaultHasher::new();
-        self.hash(&mut s);
-        self.hashed = s.finish() as i64
+    fn calculate_hash(&mut self, algorithm: HashAlgorithm) {
+        self.hashed = match algorithm.digest() {
+            Some(digest) => Canonical::default()
+                .str(&self.call_id)
+                .int(self.freq.into())
+                .time(self.time)
+                .delta(self.pos)
+                .delta(self.len)
+                .int(self.error_count.into())
+                .int(self.spike_count.into())
+                .finish(digest),
+            None => legacy(&(
+                &self.call_id,
+                0i64,
+                self.freq,
+                self.time,
+                self.pos,
+                self.len,
+                self.error_count,
+                self.spike_count,
+            )),
+        };
+        self.hash_version = algorithm.version();
+    }
+    fn hash_version(&self) -> i16 {
+        self.hash_version
     }
 }
+
+#[derive(
+    Insertable, Queryable, Identifiable, Selectable, Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(table_name = failed_jobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct FailedJob {
+    #[diesel(skip_insertion)]
+    pub id: i64,
+    pub json_name: String,
+    pub metadata: String,
+    pub audio_name: String,
+    pub audio_path: String,
+    pub tags: serde_json::Value,
+    pub version: Option<String>,
+    pub language: Option<String>,
+    pub archive_reason: Option<ArchiveReason>,
+    pub error: String,
+    pub attempts: i32,
+    pub failed_at: DateTime<Utc>,
+    // none once retries are exhausted
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub stage: JobStage,
+}
+
+// Sends calls on a talkgroup, or in a talkgroup group, to their own Discord
+// webhook instead of DISCORD_WEBHOOK. Exactly one of the two keys is set.
+#[derive(
+    Insertable, Queryable, Identifiable, Selectable, Debug, Clone, PartialEq, Serialize, Deserialize,
+)]
+#[diesel(table_name = discord_routes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DiscordRoute {
+    #[diesel(skip_insertion)]
+    #[serde(default)]
+    pub id: i32,
+    pub talkgroup: Option<i32>,
+    pub talkgroup_group: Option<String>,
+    pub webhook_url: String,
+    #[diesel(skip_insertion)]
+    #[serde(skip_deserializing)]
+    pub created_at: DateTime<Utc>,
+}
+
+// A transcription kept by the audio's SHA-256, so the same audio sent to the
+// same provider and model in the same language is only transcribed once
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq, Serialize)]
+#[diesel(table_name = transcription_cache)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CachedTranscription {
+    pub sha256: String,
+    pub provider: String,
+    pub language: String,
+    // sealed with TRANSCRIPTION_KEY when it is set
+    #[serde(skip)]
+    pub transcription: String,
+    pub hits: i32,
+    pub created_at: DateTime<Utc>,
+    pub detected_language: Option<String>,
+    pub language_probability: Option<f32>,
+    pub language_fallback: bool,
+    // left out while TRANSCRIPTION_KEY is set, as they'd give the text away
+    #[serde(skip)]
+    pub words: Option<serde_json::Value>,
+}
+
+// One word of a call's transcription and when it was said, in order
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq)]
+#[diesel(table_name = transcript_words)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TranscriptWord {
+    pub call_id: String,
+    pub position: i32,
+    pub word: String,
+    pub start_ms: i32,
+    pub end_ms: i32,
+}
+
+// The part of a call's transcription said by one radio
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq)]
+#[diesel(table_name = transcript_segments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TranscriptSegment {
+    pub call_id: String,
+    pub position: i32,
+    pub src: i32,
+    pub tag: Option<String>,
+    pub start_ms: i32,
+    pub end_ms: i32,
+    pub text: String,
+}
+
+// A system from an imported trunk-recorder config.json, keyed by its shortName
+#[derive(Insertable, Queryable, Selectable, Debug, Clone, PartialEq, Serialize)]
+#[diesel(table_name = systems)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct System {
+    pub short_name: String,
+    pub system_type: String,
+    pub talkgroups_file: Option<String>,
+    pub unit_tags_file: Option<String>,
+    // from the rdioscanner plugin's systems list
+    pub rdio_system_id: Option<i32>,
+    pub imported_at: DateTime<Utc>,
+}
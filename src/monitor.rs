@@ -0,0 +1,158 @@
+use crate::alerts::{self, Alert, Severity};
+use crate::common::*;
+use crate::config::ProcessorConfig;
+use crate::stats::{FrequencyRates, frequency_rates};
+
+use chrono::Utc;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Anomaly {
+    Errors,
+    Spikes,
+}
+
+impl Anomaly {
+    fn label(&self) -> &'static str {
+        match self {
+            Anomaly::Errors => "error",
+            Anomaly::Spikes => "spike",
+        }
+    }
+    fn rates(&self, r: &FrequencyRates) -> (f64, f64) {
+        match self {
+            Anomaly::Errors => (r.errors_per_sec, r.baseline_errors_per_sec),
+            Anomaly::Spikes => (r.spikes_per_sec, r.baseline_spikes_per_sec),
+        }
+    }
+}
+
+fn detect(r: &FrequencyRates, c: &ProcessorConfig) -> Vec<Anomaly> {
+    if r.airtime_secs < c.env.anomaly_min_airtime_secs.as_secs_f64()
+        || r.baseline_airtime_secs <= 0.0
+    {
+        return Vec::new();
+    }
+
+    [Anomaly::Errors, Anomaly::Spikes]
+        .into_iter()
+        .filter(|a| {
+            let (recent, baseline) = a.rates(r);
+            recent > 0.0 && recent > baseline * c.env.anomaly_threshold
+        })
+        .collect()
+}
+
+fn anomaly_embed(r: &FrequencyRates, anomaly: Anomaly) -> WebhookEmbed {
+    let (recent, baseline) = anomaly.rates(r);
+
+    WebhookEmbed {
+        color: "15158332".to_string(),
+        timestamp: format_timestamp_from_datetime(Utc::now()),
+        title: format!(
+            "Elevated {} rate on {:.4} MHz",
+            anomaly.label(),
+            r.freq as f64 / 1_000_000.0
+        ),
+        fields: vec![
+            EmbedField {
+                name: "Recent rate:".to_string(),
+                value: format!("{:.3}/s over {:.0}s of airtime", recent, r.airtime_secs),
+            },
+            EmbedField {
+                name: "Baseline rate:".to_string(),
+                value: format!(
+                    "{:.3}/s over {:.0}s of airtime",
+                    baseline, r.baseline_airtime_secs
+                ),
+            },
+        ],
+        footer: None,
+    }
+}
+
+async fn check(c: &ProcessorConfig, alerted: &mut HashMap<(i32, &'static str), Instant>) {
+    let cooldown = c.env.anomaly_window_secs;
+
+    let rates = match frequency_rates(
+        c,
+        ago(c.env.anomaly_baseline_days),
+        ago(c.env.anomaly_window_secs),
+    ) {
+        Ok(rates) => rates,
+        Err(e) => {
+            error!(error = %e, "Failed to compute frequency rates");
+            return;
+        }
+    };
+
+    alerted.retain(|_, at| at.elapsed() < cooldown);
+
+    for r in &rates {
+        for anomaly in detect(r, c) {
+            let key = (r.freq, anomaly.label());
+            if alerted.contains_key(&key) {
+                continue;
+            }
+
+            let (recent, baseline) = anomaly.rates(r);
+            info!(
+                freq = r.freq,
+                kind = anomaly.label(),
+                recent = recent,
+                baseline = baseline,
+                "Frequency anomaly detected"
+            );
+
+            alerts::raise(
+                c,
+                Alert {
+                    name: "FrequencyAnomaly",
+                    severity: Severity::Warning,
+                    summary: format!(
+                        "Elevated {} rate on {:.4} MHz",
+                        anomaly.label(),
+                        r.freq as f64 / 1_000_000.0
+                    ),
+                    description: format!(
+                        "{:.3}/s over {:.0}s of airtime against a baseline of {:.3}/s",
+                        recent, r.airtime_secs, baseline
+                    ),
+                    labels: vec![
+                        ("freq", r.freq.to_string()),
+                        ("kind", anomaly.label().to_string()),
+                    ],
+                    expires_in: cooldown,
+                },
+            )
+            .await;
+
+            match send_embed(
+                &c.http_client,
+                &c.env.discord_webhook,
+                anomaly_embed(r, anomaly),
+            )
+            .await
+            {
+                Ok(()) => {
+                    alerted.insert(key, Instant::now());
+                }
+                Err(e) => warn!(freq = r.freq, error = %e, "Failed to send anomaly alert"),
+            }
+        }
+    }
+}
+
+pub async fn run_periodic(c: ProcessorConfig, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    let mut alerted = HashMap::new();
+
+    loop {
+        interval.tick().await;
+        check(&c, &mut alerted).await;
+    }
+}
@@ -0,0 +1,146 @@
+use crate::error::{Error, Result};
+
+use reqwest::Url;
+use rumqttc::{
+    AsyncClient, Event, EventLoop, Incoming, MqttOptions, Outgoing, QoS, TlsConfiguration,
+    Transport,
+};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::info;
+
+const DEFAULT_PORT: u16 = 1883;
+const DEFAULT_TLS_PORT: u16 = 8883;
+// How long connecting, or the broker acknowledging a publish, may take
+const TIMEOUT: Duration = Duration::from_secs(10);
+// Payloads carry the whole transcription, well past rumqttc's 10 KiB default
+const MAX_PACKET: usize = 16 * 1024 * 1024;
+
+fn mqtt_error(message: impl std::fmt::Display) -> Error {
+    Error::Notification(format!("mqtt: {}", message))
+}
+
+// An MQTT 3.1.1 publisher. One connection is kept open and re-established
+// when a publish fails.
+pub struct Publisher {
+    host: String,
+    port: u16,
+    options: MqttOptions,
+    state: Mutex<Option<Connection>>,
+}
+
+impl std::fmt::Debug for Publisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Publisher({}:{})", self.host, self.port)
+    }
+}
+
+// Publishes are made one at a time under the lock, polling the event loop
+// only until each is acknowledged
+struct Connection {
+    client: AsyncClient,
+    events: EventLoop,
+}
+
+impl Publisher {
+    // `url` is mqtt://[user:password@]host[:port], or mqtts:// for TLS
+    pub fn new(url: &str, client_id: String) -> Result<Self> {
+        let url = Url::parse(url).map_err(|e| Error::Configuration(e.to_string()))?;
+        let (tls, default_port) = match url.scheme() {
+            "mqtt" => (false, DEFAULT_PORT),
+            "mqtts" => (true, DEFAULT_TLS_PORT),
+            scheme => {
+                return Err(Error::Configuration(format!(
+                    "expected an mqtt:// or mqtts:// URL, got scheme {}",
+                    scheme
+                )));
+            }
+        };
+        let host = url
+            .host_str()
+            .ok_or_else(|| Error::Configuration("MQTT URL has no host".to_string()))?
+            .to_string();
+        let port = url.port().unwrap_or(default_port);
+
+        let mut options = MqttOptions::new(client_id, host.as_str(), port);
+        // clean session, no keep alive: the broker never drops an idle publisher
+        options
+            .set_clean_session(true)
+            .set_keep_alive(Duration::ZERO)
+            .set_max_packet_size(MAX_PACKET, MAX_PACKET);
+        if !url.username().is_empty() {
+            options.set_credentials(url.username(), url.password().unwrap_or_default());
+        }
+        if tls {
+            options.set_transport(Transport::tls_with_config(TlsConfiguration::Native));
+        }
+
+        Ok(Publisher {
+            host,
+            port,
+            options,
+            state: Mutex::new(None),
+        })
+    }
+
+    async fn send(&self, connection: &mut Connection, topic: &str, payload: &[u8]) -> Result<()> {
+        connection
+            .client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await
+            .map_err(mqtt_error)?;
+
+        let mut id = None;
+        loop {
+            match connection.events.poll().await.map_err(mqtt_error)? {
+                Event::Incoming(Incoming::ConnAck(_)) => {
+                    info!(host = %self.host, port = self.port, client_id = %self.options.client_id(), "Connected to MQTT broker");
+                }
+                Event::Outgoing(Outgoing::Publish(pkid)) => id = Some(pkid),
+                Event::Incoming(Incoming::PubAck(ack)) if id == Some(ack.pkid) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    async fn attempt(
+        &self,
+        state: &mut Option<Connection>,
+        topic: &str,
+        payload: &[u8],
+    ) -> Result<()> {
+        let connection = state.get_or_insert_with(|| {
+            let (client, events) = AsyncClient::new(self.options.clone(), 1);
+            Connection { client, events }
+        });
+        let result = tokio::time::timeout(TIMEOUT, self.send(connection, topic, payload))
+            .await
+            .unwrap_or_else(|_| Err(mqtt_error("timed out waiting for the broker")));
+        if result.is_err() {
+            *state = None;
+        }
+        result
+    }
+
+    // Publishes at QoS 1 and waits for the broker's PUBACK. A connection the
+    // broker closed since the last call is replaced and the publish tried again.
+    pub async fn publish(&self, topic: &str, payload: &[u8]) -> Result<()> {
+        let mut state = self.state.lock().await;
+        let reused = state.is_some();
+        match self.attempt(&mut state, topic, payload).await {
+            Err(_) if reused => self.attempt(&mut state, topic, payload).await,
+            result => result,
+        }
+    }
+}
+
+// Fills {short_name}, {talkgroup}, {talkgroup_group} and {talkgroup_tag} in
+// `template`. Values can't add topic levels or wildcards.
+pub fn topic(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |topic, (name, value)| {
+            let value = value.replace(['/', '+', '#'], "_");
+            topic.replace(&format!("{{{}}}", name), &value)
+        })
+}
@@ -0,0 +1,245 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Notification, NotificationKey};
+use crate::schema::{notification_keys, notifications};
+
+use chrono::Utc;
+use diesel::{delete, insert_into, prelude::*};
+use reqwest::RequestBuilder;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+fn record(c: &ProcessorConfig, entry: &Notification) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    insert_into(notifications::table)
+        .values(entry)
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok(())
+}
+
+// Records the (call, destination) key before anything is sent, so a retried
+// upload or a restart mid-delivery can never post the same notification twice.
+// The key is released again if delivery fails, so only sent ones are skipped.
+fn claim(c: &ProcessorConfig, call_id: &str, destination: &str) -> Result<bool> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let inserted = insert_into(notification_keys::table)
+        .values(&NotificationKey {
+            call_id: call_id.to_string(),
+            destination: destination.to_string(),
+            claimed_at: Utc::now(),
+        })
+        .on_conflict_do_nothing()
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok(inserted == 1)
+}
+
+// Gives up the key of a notification whose every attempt failed, so a retried
+// job or renotify sends it rather than taking it as sent
+fn release(c: &ProcessorConfig, call_id: &str, destination: &str) {
+    let result = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))
+        .and_then(|mut connection| {
+            delete(notification_keys::table.find((call_id, destination)))
+                .execute(&mut connection)
+                .map_err(|e| Error::Database(e.to_string()))
+        });
+    if let Err(e) = result {
+        warn!(call_id = %call_id, destination = %destination, error = %e, "Failed to release notification key");
+    }
+}
+
+// How many times a failed delivery is re-sent, and how long to wait between tries
+#[derive(Debug, Clone, Copy)]
+pub struct Retry {
+    pub attempts: u32,
+    pub delay: Duration,
+}
+
+impl Retry {
+    pub const NONE: Retry = Retry {
+        attempts: 0,
+        delay: Duration::ZERO,
+    };
+}
+
+// Sends a request whose body was built from `payload`, at most once per call and
+// destination, archiving the payload and the outcome when ARCHIVE_NOTIFICATIONS is enabled.
+// `request` is called again for each retry since multipart bodies can't be cloned.
+pub async fn send<T: Serialize>(
+    c: &ProcessorConfig,
+    call_id: &str,
+    destination: &str,
+    payload: &T,
+    retry: Retry,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<()> {
+    // with database writes switched off there is nowhere to claim the key, so
+    // delivery falls back to at-least-once
+    let database = c.switches.database();
+    if database && !claim(c, call_id, destination)? {
+        info!(call_id = %call_id, destination = %destination, "Notification already sent, skipping");
+        return Ok(());
+    }
+
+    let mut attempt = 0;
+    let response = loop {
+        let response = c
+            .pools
+            .notifications
+            .run(request().send())
+            .await
+            .and_then(|r| r.error_for_status());
+        match response {
+            Err(e) if attempt < retry.attempts => {
+                attempt += 1;
+                warn!(call_id = %call_id, destination = %destination, attempt, error = %e, "Notification failed, retrying");
+                tokio::time::sleep(retry.delay).await;
+            }
+            response => break response,
+        }
+    };
+
+    if database {
+        let status = match &response {
+            Ok(r) => Some(r.status().as_u16() as i32),
+            Err(e) => e.status().map(|s| s.as_u16() as i32),
+        };
+        let error = response.as_ref().err().map(|e| e.to_string());
+        archive(c, call_id, destination, payload, status, error);
+        if response.is_err() {
+            release(c, call_id, destination);
+        }
+    }
+
+    response?;
+    Ok(())
+}
+
+// As send, for destinations that aren't reached over HTTP. No status is
+// archived, only the error if the last attempt failed.
+pub async fn deliver<T, F>(
+    c: &ProcessorConfig,
+    call_id: &str,
+    destination: &str,
+    payload: &T,
+    retry: Retry,
+    attempt: impl Fn() -> F,
+) -> Result<()>
+where
+    T: Serialize,
+    F: Future<Output = Result<()>> + Send,
+{
+    let database = c.switches.database();
+    if database && !claim(c, call_id, destination)? {
+        info!(call_id = %call_id, destination = %destination, "Notification already sent, skipping");
+        return Ok(());
+    }
+
+    let mut attempts = 0;
+    let result = loop {
+        match c.pools.notifications.run(attempt()).await {
+            Err(e) if attempts < retry.attempts => {
+                attempts += 1;
+                warn!(call_id = %call_id, destination = %destination, attempt = attempts, error = %e, "Notification failed, retrying");
+                tokio::time::sleep(retry.delay).await;
+            }
+            result => break result,
+        }
+    };
+
+    if database {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        archive(c, call_id, destination, payload, None, error);
+        if result.is_err() {
+            release(c, call_id, destination);
+        }
+    }
+    result
+}
+
+// A notification that went out stays delivered however its archiving goes,
+// so failures here are only logged
+fn archive<T: Serialize>(
+    c: &ProcessorConfig,
+    call_id: &str,
+    destination: &str,
+    payload: &T,
+    status: Option<i32>,
+    error: Option<String>,
+) {
+    if !c.env.archive_notifications {
+        return;
+    }
+    let result = serde_json::to_value(payload)
+        .map_err(Error::from)
+        .and_then(|payload| match &c.transcript_cipher {
+            Some(cipher) => cipher.seal_payload(payload, call_id),
+            None => Ok(payload),
+        })
+        .and_then(|payload| {
+            record(
+                c,
+                &Notification {
+                    id: 0,
+                    call_id: call_id.to_string(),
+                    destination: destination.to_string(),
+                    payload,
+                    status,
+                    error,
+                    sent_at: Utc::now(),
+                },
+            )
+        });
+    if let Err(e) = result {
+        warn!(call_id = %call_id, destination = %destination, error = %e, "Failed to archive notification");
+    }
+}
+
+pub fn list(
+    c: &ProcessorConfig,
+    call_id: Option<&str>,
+    limit: Option<i64>,
+) -> Result<Vec<Notification>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = notifications::table
+        .select(Notification::as_select())
+        .order(notifications::sent_at.desc())
+        .limit(limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+        .into_boxed();
+
+    if let Some(call_id) = call_id {
+        query = query.filter(notifications::call_id.eq(call_id.to_string()));
+    }
+
+    let mut entries: Vec<Notification> = query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    if let Some(cipher) = &c.transcript_cipher {
+        for entry in &mut entries {
+            entry.payload = cipher.open_payload(entry.payload.take(), &entry.call_id)?;
+        }
+    }
+    Ok(entries)
+}
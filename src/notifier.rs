@@ -0,0 +1,662 @@
+use crate::activity;
+use crate::common::{DiscordMessage, Webhook};
+use crate::config::{NotifierConfig, NotifierKind, ProcessorConfig};
+use crate::discord_routing;
+use crate::error::{Error, Result};
+use crate::model::AudioMetadata;
+use crate::mqtt::{self, Publisher};
+use crate::notifications::{self, Retry};
+use crate::segments::Segment;
+use crate::trace;
+use crate::transcribe::Audio;
+use crate::upload::create_embed;
+use crate::words::KeywordHit;
+
+use chrono::{DateTime, Utc};
+use futures::future::{BoxFuture, join_all};
+use reqwest::{
+    RequestBuilder,
+    header::CONTENT_TYPE,
+    multipart::{Form, Part},
+};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+// A transcribed call on its way to the configured destinations
+pub struct Delivery<'a> {
+    pub meta: &'a AudioMetadata,
+    pub transcription: &'a str,
+    pub audio: &'a Audio<'a>,
+    // presigned, with NOTIFICATION_AUDIO_URLS
+    pub audio_url: Option<&'a str>,
+    pub keywords: &'a [KeywordHit],
+    pub segments: &'a [Segment],
+}
+
+// Objects leaving storage, reported to the notifiers with STORAGE_EVENTS so
+// churn can be followed and audited. Sent once per run, not per object.
+#[derive(Debug, Serialize)]
+pub struct StorageEvent {
+    // what happened, as <task>.<action>: retention.deleted
+    pub event: &'static str,
+    pub calls: usize,
+    pub objects: usize,
+    pub bytes: u64,
+    // calls matched by each retention policy
+    pub by_policy: BTreeMap<String, usize>,
+    // calls left in place after a failure, to be retried on the next run
+    pub failed: usize,
+    pub time: DateTime<Utc>,
+}
+
+fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+impl StorageEvent {
+    // One line for chat destinations
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{}: {} calls, {} objects, {}",
+            self.event,
+            self.calls,
+            self.objects,
+            size(self.bytes)
+        );
+        if self.failed > 0 {
+            summary.push_str(&format!(" ({} failed)", self.failed));
+        }
+        summary
+    }
+}
+
+async fn post(request: RequestBuilder) -> Result<()> {
+    request.send().await?.error_for_status()?;
+    Ok(())
+}
+
+// A destination for transcribed calls. `name` is unique across the registry and
+// is the destination key notifications are claimed and archived under.
+pub trait Notifier: Send + Sync + std::fmt::Debug {
+    fn name(&self) -> &str;
+
+    fn notify<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        delivery: &'a Delivery<'a>,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<()>>;
+
+    fn storage_event<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        event: &'a StorageEvent,
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+// Posts the Discord embed with the call audio attached
+#[derive(Debug)]
+pub struct DiscordNotifier {
+    name: String,
+    url: String,
+    routed: bool,
+}
+
+impl DiscordNotifier {
+    fn url(&self, c: &ProcessorConfig, meta: &AudioMetadata) -> String {
+        if !self.routed {
+            return self.url.clone();
+        }
+        let (tgid, group) = (meta.talkgroup.talkgroup, &meta.talkgroup.talkgroup_group);
+        match discord_routing::webhook_for(c, tgid, group) {
+            Ok(Some(url)) => url,
+            Ok(None) => self.url.clone(),
+            Err(e) => {
+                warn!(notifier = %self.name, error = %e, "Failed to look up discord route, using default webhook");
+                self.url.clone()
+            }
+        }
+    }
+}
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        delivery: &'a Delivery<'a>,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let url = self.url(c, delivery.meta);
+            let mut embed = create_embed(
+                delivery.meta,
+                Some(delivery.transcription.to_string()),
+                delivery.audio_url,
+                delivery.keywords,
+                delivery.segments,
+            );
+            embed.footer = activity::footer(c, delivery.meta);
+            let webhook = Webhook::new(vec![embed]);
+            let payload_json = serde_json::to_string(&webhook)?;
+
+            notifications::send(
+                c,
+                &delivery.meta.call.filename,
+                &self.name,
+                &webhook,
+                retry,
+                || {
+                    let file = Part::bytes(delivery.audio.data.to_vec())
+                        .file_name(delivery.audio.name.to_string());
+                    let form = Form::new()
+                        .part("file1", file)
+                        .text("payload_json", payload_json.clone());
+                    trace::inject(c.http_client.post(&url)).multipart(form)
+                },
+            )
+            .await
+        })
+    }
+
+    fn storage_event<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        event: &'a StorageEvent,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let message = DiscordMessage {
+                content: Some(event.summary()),
+                embeds: Vec::new(),
+            };
+            post(trace::inject(c.http_client.post(&self.url)).json(&message)).await
+        })
+    }
+}
+
+// Signature header on webhook bodies: `sha256=` and the hex HMAC-SHA256 of
+// the body under NOTIFIER_<NAME>_SECRET
+const SIGNATURE_HEADER: &str = "X-Trunk-Processor-Signature";
+
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    #[serde(flatten)]
+    pub meta: &'a AudioMetadata,
+    pub audio_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presigned_audio_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub keywords: &'a [KeywordHit],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub segments: &'a [Segment],
+}
+
+// Posts the call metadata, transcription included, and where its audio is
+// stored as JSON, for automation such as Node-RED or n8n
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    name: String,
+    url: String,
+    secret: Option<hmac::Key>,
+}
+
+impl WebhookNotifier {
+    fn request(&self, c: &ProcessorConfig, body: Vec<u8>) -> RequestBuilder {
+        let signature = self
+            .secret
+            .as_ref()
+            .map(|key| format!("sha256={}", hex::encode(hmac::sign(key, &body))));
+        let mut request = trace::inject(c.http_client.post(&self.url))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(signature) = signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        request
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        delivery: &'a Delivery<'a>,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            // the stored row may hold only an encrypted copy
+            let mut meta = delivery.meta.clone();
+            meta.call.transcription = Some(delivery.transcription.to_string());
+            let payload = WebhookPayload {
+                meta: &meta,
+                audio_url: c.env.object_url(&meta.call.filename),
+                presigned_audio_url: delivery.audio_url,
+                keywords: delivery.keywords,
+                segments: delivery.segments,
+            };
+            let body = serde_json::to_vec(&payload)?;
+
+            notifications::send(c, &meta.call.filename, &self.name, &payload, retry, || {
+                self.request(c, body.clone())
+            })
+            .await
+        })
+    }
+
+    fn storage_event<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        event: &'a StorageEvent,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { post(self.request(c, serde_json::to_vec(event)?)).await })
+    }
+}
+
+// Publishes the webhook payload to an MQTT broker, on a topic per system and
+// talkgroup, for subscribers such as Home Assistant
+#[derive(Debug)]
+pub struct MqttNotifier {
+    name: String,
+    topic: String,
+    storage_topic: String,
+    publisher: Publisher,
+}
+
+impl Notifier for MqttNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        delivery: &'a Delivery<'a>,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut meta = delivery.meta.clone();
+            meta.call.transcription = Some(delivery.transcription.to_string());
+            let payload = WebhookPayload {
+                meta: &meta,
+                audio_url: c.env.object_url(&meta.call.filename),
+                presigned_audio_url: delivery.audio_url,
+                keywords: delivery.keywords,
+                segments: delivery.segments,
+            };
+            let body = serde_json::to_vec(&payload)?;
+            let talkgroup = meta.talkgroup.talkgroup.to_string();
+            let topic = mqtt::topic(
+                &self.topic,
+                &[
+                    ("short_name", &meta.call.short_name),
+                    ("talkgroup", &talkgroup),
+                    ("talkgroup_group", &meta.talkgroup.talkgroup_group),
+                    ("talkgroup_tag", &meta.talkgroup.talkgroup_tag),
+                ],
+            );
+
+            notifications::deliver(c, &meta.call.filename, &self.name, &payload, retry, || {
+                self.publisher.publish(&topic, &body)
+            })
+            .await
+        })
+    }
+
+    fn storage_event<'a>(
+        &'a self,
+        _c: &'a ProcessorConfig,
+        event: &'a StorageEvent,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.publisher
+                .publish(&self.storage_topic, &serde_json::to_vec(event)?)
+                .await
+        })
+    }
+}
+
+const SLACK_API: &str = "https://slack.com/api";
+// Block Kit's limits on header and section text
+const SLACK_HEADER_LIMIT: usize = 150;
+const SLACK_TEXT_LIMIT: usize = 3000;
+
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    if text.chars().count() <= limit {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(limit - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+// The Discord embed's title and fields as Block Kit blocks
+fn slack_message(delivery: &Delivery) -> Value {
+    let embed = create_embed(
+        delivery.meta,
+        Some(delivery.transcription.to_string()),
+        delivery.audio_url,
+        delivery.keywords,
+        delivery.segments,
+    );
+    let mut blocks = vec![json!({
+        "type": "header",
+        "text": {"type": "plain_text", "text": truncate(&embed.title, SLACK_HEADER_LIMIT)},
+    })];
+    for field in &embed.fields {
+        let text = format!(
+            "*{}*\n{}",
+            slack_escape(&field.name),
+            slack_escape(&field.value)
+        );
+        blocks.push(json!({
+            "type": "section",
+            "text": {"type": "mrkdwn", "text": truncate(&text, SLACK_TEXT_LIMIT)},
+        }));
+    }
+    json!({"text": embed.title, "blocks": blocks})
+}
+
+#[derive(Debug, Deserialize)]
+struct SlackResponse {
+    ok: bool,
+    error: Option<String>,
+    upload_url: Option<String>,
+    file_id: Option<String>,
+}
+
+// Web API methods answer 200 with ok: false when they fail
+async fn slack_api(request: RequestBuilder) -> Result<SlackResponse> {
+    let response: SlackResponse = request.send().await?.error_for_status()?.json().await?;
+    if !response.ok {
+        return Err(Error::Notification(format!(
+            "slack: {}",
+            response.error.as_deref().unwrap_or("unknown error")
+        )));
+    }
+    Ok(response)
+}
+
+// Posts Block Kit messages to a Slack incoming webhook. With a bot token and
+// channel the call audio is uploaded to the channel as well, recorded as its
+// own `<name>:audio` notification.
+#[derive(Debug)]
+pub struct SlackNotifier {
+    name: String,
+    url: String,
+    upload: Option<(String, String)>,
+}
+
+impl SlackNotifier {
+    // Reserves an upload URL, sends the audio to it and shares the file to
+    // the channel, each step checked for Slack's ok
+    async fn upload_file(
+        c: &ProcessorConfig,
+        audio: &Audio<'_>,
+        token: &str,
+        channel: &str,
+    ) -> Result<()> {
+        let reserved = slack_api(
+            c.http_client
+                .post(format!("{}/files.getUploadURLExternal", SLACK_API))
+                .bearer_auth(token)
+                .form(&[
+                    ("filename", audio.name.to_string()),
+                    ("length", audio.data.len().to_string()),
+                ]),
+        )
+        .await?;
+        let (Some(upload_url), Some(file_id)) = (reserved.upload_url, reserved.file_id) else {
+            return Err(Error::Notification(
+                "slack: no upload_url in files.getUploadURLExternal response".to_string(),
+            ));
+        };
+        c.http_client
+            .post(&upload_url)
+            .body(audio.data.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        slack_api(
+            c.http_client
+                .post(format!("{}/files.completeUploadExternal", SLACK_API))
+                .bearer_auth(token)
+                .json(&json!({
+                    "files": [{"id": file_id, "title": audio.name}],
+                    "channel_id": channel,
+                })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    // The key is claimed before the upload URL is reserved, so a retry never
+    // uploads an audio file that was already shared
+    async fn upload_audio(
+        &self,
+        c: &ProcessorConfig,
+        delivery: &Delivery<'_>,
+        token: &str,
+        channel: &str,
+        retry: Retry,
+    ) -> Result<()> {
+        let audio = delivery.audio;
+        notifications::deliver(
+            c,
+            &delivery.meta.call.filename,
+            &format!("{}:audio", self.name),
+            &json!({"filename": audio.name, "channel_id": channel}),
+            retry,
+            || Self::upload_file(c, audio, token, channel),
+        )
+        .await
+    }
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn notify<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        delivery: &'a Delivery<'a>,
+        retry: Retry,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let message = slack_message(delivery);
+            notifications::send(
+                c,
+                &delivery.meta.call.filename,
+                &self.name,
+                &message,
+                retry,
+                || trace::inject(c.http_client.post(&self.url)).json(&message),
+            )
+            .await?;
+
+            match &self.upload {
+                Some((token, channel)) => {
+                    self.upload_audio(c, delivery, token, channel, retry).await
+                }
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn storage_event<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        event: &'a StorageEvent,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let message = json!({ "text": slack_escape(&event.summary()) });
+            post(trace::inject(c.http_client.post(&self.url)).json(&message)).await
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Registered {
+    notifier: Box<dyn Notifier>,
+    config: NotifierConfig,
+}
+
+#[derive(Debug, Default)]
+pub struct Notifiers {
+    entries: Vec<Registered>,
+}
+
+impl Notifiers {
+    pub fn register(&mut self, name: &str, config: NotifierConfig) -> Result<()> {
+        if self.entries.iter().any(|e| e.notifier.name() == name) {
+            return Err(Error::Configuration(format!(
+                "notifier {} is registered twice",
+                name
+            )));
+        }
+
+        let (name, url) = (name.to_string(), config.url.clone());
+        let notifier: Box<dyn Notifier> = match config.kind {
+            NotifierKind::Discord => Box::new(DiscordNotifier {
+                name,
+                url,
+                routed: config.routed,
+            }),
+            NotifierKind::Mqtt => Box::new(MqttNotifier {
+                publisher: Publisher::new(
+                    &url,
+                    format!("trunk-processor-{}-{}", name, std::process::id()),
+                )?,
+                name,
+                topic: config.topic.clone(),
+                storage_topic: config.storage_topic.clone(),
+            }),
+            NotifierKind::Slack => Box::new(SlackNotifier {
+                name,
+                url,
+                upload: config.token.clone().zip(config.channel.clone()),
+            }),
+            NotifierKind::Webhook => Box::new(WebhookNotifier {
+                name,
+                url,
+                secret: config
+                    .secret
+                    .as_ref()
+                    .map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            }),
+        };
+        self.entries.push(Registered { notifier, config });
+        Ok(())
+    }
+
+    pub fn config(&self, name: &str) -> Option<&NotifierConfig> {
+        self.entries
+            .iter()
+            .find(|e| e.notifier.name() == name)
+            .map(|e| &e.config)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.iter().map(|e| e.notifier.name()).collect()
+    }
+
+    // Sends to every destination whose filters accept the call. All of them are
+    // attempted; the first failure is returned once they have finished.
+    pub async fn dispatch(&self, c: &ProcessorConfig, delivery: &Delivery<'_>) -> Result<()> {
+        self.dispatch_to(c, delivery, &self.names()).await
+    }
+
+    // As dispatch, limited to the named destinations
+    pub async fn dispatch_to(
+        &self,
+        c: &ProcessorConfig,
+        delivery: &Delivery<'_>,
+        names: &[&str],
+    ) -> Result<()> {
+        let sends = self
+            .entries
+            .iter()
+            .filter(|e| names.contains(&e.notifier.name()))
+            .filter(|e| e.config.receives(delivery.meta))
+            .map(|e| async move {
+                if !e.config.accepts(delivery.meta) {
+                    info!(
+                        file = %delivery.meta.call.filename,
+                        notifier = e.notifier.name(),
+                        "Sending canary sample"
+                    );
+                }
+                let retry = Retry {
+                    attempts: e.config.retries,
+                    delay: e.config.retry_delay,
+                };
+                let result = e.notifier.notify(c, delivery, retry).await;
+                if let Err(err) = &result {
+                    warn!(
+                        file = %delivery.meta.call.filename,
+                        notifier = e.notifier.name(),
+                        error = %err,
+                        "Failed to notify destination"
+                    );
+                }
+                result
+            });
+
+        join_all(sends).await.into_iter().collect()
+    }
+
+    // Sends a storage event to every destination with STORAGE_EVENTS. Failures
+    // are logged, since the storage change has already happened.
+    pub async fn announce(&self, c: &ProcessorConfig, event: &StorageEvent) {
+        info!(
+            event = event.event,
+            calls = event.calls,
+            objects = event.objects,
+            bytes = event.bytes,
+            "Storage event"
+        );
+        let sends = self
+            .entries
+            .iter()
+            .filter(|e| e.config.storage_events)
+            .map(|e| async move {
+                if let Err(err) = e.notifier.storage_event(c, event).await {
+                    warn!(
+                        event = event.event,
+                        notifier = e.notifier.name(),
+                        error = %err,
+                        "Failed to send storage event"
+                    );
+                }
+            });
+        join_all(sends).await;
+    }
+}
@@ -0,0 +1,400 @@
+use crate::auth::key_matches;
+use crate::common::{FileData, UploadData, UploadedFile};
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{ArchiveReason, MetadataVersion};
+use crate::policy;
+use crate::trace::{self, TraceContext};
+use crate::upload::{
+    Stored, already_processed, authorize_system, complete, discard_incoming, ingest, read_audio,
+};
+use crate::worker::{self, Job};
+
+use axum::{
+    body::Bytes,
+    extract::{Multipart, Path as UrlPath, State},
+    http::{StatusCode, header::HeaderMap},
+};
+use object_store::{ObjectStore, path::Path};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::HashMap, time::Instant};
+use tokio::sync::oneshot;
+use tracing::{Instrument, Span, error, info, info_span, warn};
+
+#[derive(Debug, Deserialize)]
+struct OpenMhzSource {
+    src: i32,
+    #[serde(default)]
+    pos: f64,
+    #[serde(default)]
+    emergency: u8,
+    #[serde(default)]
+    signal_system: String,
+    #[serde(default)]
+    tag: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenMhzFreq {
+    freq: i32,
+    time: i64,
+    #[serde(default)]
+    pos: f64,
+    #[serde(default)]
+    len: f64,
+    #[serde(default)]
+    error_count: i16,
+    #[serde(default)]
+    spike_count: i16,
+}
+
+struct OpenMhzUpload {
+    audio: UploadedFile,
+    fields: HashMap<String, String>,
+}
+
+impl OpenMhzUpload {
+    fn text(&self, name: &str) -> Result<&str> {
+        self.fields
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| Error::MissingField(name.to_string()))
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<T> {
+        self.text(name)?
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidRequest(format!("{} must be a number", name)))
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&self, name: &str) -> Result<Vec<T>> {
+        match self.fields.get(name).map(|s| s.trim()) {
+            None | Some("") => Ok(Vec::new()),
+            Some(text) => serde_json::from_str(text).map_err(Error::JsonParsing),
+        }
+    }
+}
+
+// The audio is only streamed into staging once an api_key field before it
+// is accepted, or no key is needed
+async fn read_multipart(
+    mut m: Multipart,
+    c: &ProcessorConfig,
+    short_name: &str,
+    staged: &mut Option<Path>,
+) -> Result<OpenMhzUpload> {
+    let mut audio = None;
+    let mut fields = HashMap::new();
+    let mut authorized = authorize(c, None, short_name).is_ok();
+
+    while let Some(mut field) = m
+        .next_field()
+        .await
+        .map_err(|e| Error::Multipart(e.to_string()))?
+    {
+        let name = field
+            .name()
+            .ok_or_else(|| Error::Multipart("Field missing name".to_string()))?
+            .to_string();
+
+        if name != "call" {
+            let text = field
+                .text()
+                .await
+                .map_err(|e| Error::Multipart(e.to_string()))?;
+            if name == "api_key" && !authorized {
+                authorized = authorize(c, Some(&text), short_name).is_ok();
+            }
+            fields.insert(name, text);
+            continue;
+        }
+        if audio.is_some() {
+            return Err(Error::InvalidRequest("call file sent twice".to_string()));
+        }
+
+        let file_name = field
+            .file_name()
+            .ok_or_else(|| Error::MissingField("Missing filename for field: call".to_string()))?
+            .to_string();
+        if !file_name.ends_with(".m4a") {
+            return Err(Error::InvalidFileType(
+                "Audio file must have .m4a extension".to_string(),
+            ));
+        }
+
+        let file = read_audio(&mut field, c, file_name, authorized).await?;
+        if let FileData::Staged(location) = &file.data {
+            *staged = Some(location.clone());
+        }
+        audio = Some(file);
+    }
+
+    Ok(OpenMhzUpload {
+        audio: audio.ok_or_else(|| Error::MissingField("call".to_string()))?,
+        fields,
+    })
+}
+
+// Rebuilds trunk-recorder's call JSON from the OpenMHz form fields. OpenMHz
+// carries no talkgroup names, so those are left for the talkgroup table or
+// discovery to fill in.
+fn convert(upload: &OpenMhzUpload, short_name: &str) -> Result<serde_json::Value> {
+    let start_time: i64 = upload.number("start_time")?;
+    let stop_time: i64 = upload.number("stop_time")?;
+    let sources: Vec<OpenMhzSource> = upload.json("source_list")?;
+    let freqs: Vec<OpenMhzFreq> = upload.json("freq_list")?;
+    let patches: Vec<i32> = upload.json("patch_list")?;
+
+    Ok(json!({
+        "freq": upload.number::<i32>("freq")?,
+        "freq_error": 0,
+        "signal": 0,
+        "noise": 0,
+        "source_num": 0,
+        "recorder_num": 0,
+        "tdma_slot": 0,
+        "phase2_tdma": 0,
+        "start_time": start_time,
+        "stop_time": stop_time,
+        "emergency": upload.number::<u8>("emergency").unwrap_or(0),
+        "priority": 0,
+        "mode": 0,
+        "duplex": 0,
+        "encrypted": 0,
+        "call_length": upload.number::<i16>("call_length").unwrap_or((stop_time - start_time) as i16),
+        "talkgroup": upload.number::<i32>("talkgroup_num")?,
+        "talkgroup_tag": "",
+        "talkgroup_description": "",
+        "talkgroup_group_tag": "",
+        "talkgroup_group": "",
+        "audio_type": "digital",
+        "short_name": short_name,
+        "patched_talkgroups": patches,
+        "freqList": freqs.iter().map(|f| json!({
+            "freq": f.freq,
+            "time": f.time,
+            "pos": f.pos,
+            "len": f.len,
+            "error_count": f.error_count,
+            "spike_count": f.spike_count,
+        })).collect::<Vec<_>>(),
+        "srcList": sources.iter().map(|s| json!({
+            "src": s.src,
+            "time": start_time + s.pos as i64,
+            "pos": s.pos,
+            "emergency": s.emergency,
+            "signal_system": s.signal_system,
+            "tag": s.tag,
+        })).collect::<Vec<_>>(),
+    }))
+}
+
+// OPENMHZ_API_KEY is accepted for every system. Otherwise, with API_KEYS set,
+// api_key must be a key allowed to upload for the system in the URL.
+fn authorize(c: &ProcessorConfig, provided: Option<&str>, short_name: &str) -> Result<()> {
+    match &c.env.openmhz_api_key {
+        Some(key) if key_matches(provided, key) => Ok(()),
+        Some(_) if c.upload_keys.is_empty() => {
+            Err(Error::Unauthorized("Invalid api_key".to_string()))
+        }
+        _ => authorize_system(c, provided, short_name),
+    }
+}
+
+// Stores, transcribes and notifies the upload, returning whether it finished.
+// Past UPLOAD_DEADLINE the stage it is in finishes in the background and the
+// rest goes on the upload queue, which retries it like any queued upload.
+async fn process(
+    config: &ProcessorConfig,
+    mut files: UploadData,
+    archive: Option<ArchiveReason>,
+    ctx: TraceContext,
+) -> Result<bool> {
+    let Some(deadline) = config.env.upload_deadline else {
+        let stored = match ingest(config, &mut files, archive).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                discard_incoming(config, &files).await;
+                return Err(e);
+            }
+        };
+        complete(config, &stored, archive).await?;
+        remember(config, &files);
+        return Ok(true);
+    };
+
+    let c = config.clone();
+    let (sender, receiver) = oneshot::channel();
+    let work = async move {
+        let stored = match ingest(&c, &mut files, archive).await {
+            Ok(stored) => stored,
+            // nobody is waiting once the deadline has passed
+            Err(e) => match sender.send(Err(e)) {
+                Ok(()) => return discard_incoming(&c, &files).await,
+                Err(_) => return hand_off(&c, files, archive, None).await,
+            },
+        };
+        if sender.is_closed() {
+            return hand_off(&c, files, archive, Some(stored)).await;
+        }
+        let result = complete(&c, &stored, archive).await;
+        if result.is_ok() {
+            remember(&c, &files);
+        }
+        if let Err(Err(_)) = sender.send(result) {
+            hand_off(&c, files, archive, Some(stored)).await;
+        }
+    };
+    let work = trace::scope(ctx, work).instrument(Span::current());
+    config.background.spawn(work);
+
+    match tokio::time::timeout(deadline, receiver).await {
+        Ok(Ok(result)) => result.map(|()| true),
+        Ok(Err(_)) => Err(Error::Unavailable(
+            "upload processing stopped unexpectedly".to_string(),
+        )),
+        Err(_) => {
+            warn!(
+                deadline_secs = deadline.as_secs(),
+                "OpenMHz upload passed its deadline, queueing the rest"
+            );
+            Ok(false)
+        }
+    }
+}
+
+fn remember(c: &ProcessorConfig, files: &UploadData) {
+    if let Some(cache) = &c.replay_cache {
+        cache
+            .lock()
+            .expect("replay cache poisoned")
+            .insert(files.audio.name.clone(), files.checksum());
+    }
+}
+
+// Queues what is left of an upload that passed its deadline: storing it
+// again if that failed, or notifying its stored call
+async fn hand_off(
+    c: &ProcessorConfig,
+    files: UploadData,
+    archive: Option<ArchiveReason>,
+    stored: Option<Stored>,
+) {
+    let job = Job {
+        replay_key: (files.audio.name.clone(), files.checksum()),
+        files,
+        archive,
+        trace: trace::current().unwrap_or_else(|| TraceContext::from_headers(&HeaderMap::new())),
+        enqueued_at: Instant::now(),
+        failed_id: None,
+        stored,
+    };
+    let file = job.files.audio.name.clone();
+    match worker::enqueue(c, job).await {
+        Ok(()) => info!(file = %file, "Queued OpenMHz upload past its deadline"),
+        Err(e) => {
+            error!(file = %file, error = %e, "Failed to queue OpenMHz upload past its deadline")
+        }
+    }
+}
+
+async fn handle_openmhz(
+    config: ProcessorConfig,
+    short_name: String,
+    m: Multipart,
+    ctx: TraceContext,
+) -> Result<(StatusCode, String)> {
+    let upload_start = Instant::now();
+    info!(system = %short_name, "Starting OpenMHz upload processing");
+
+    let mut staged = None;
+    let result = async {
+        let upload = read_multipart(m, &config, &short_name, &mut staged).await?;
+
+        authorize(
+            &config,
+            upload.fields.get("api_key").map(String::as_str),
+            &short_name,
+        )?;
+
+        let metadata = convert(&upload, &short_name)?;
+        let stem = upload
+            .audio
+            .name
+            .strip_suffix(".m4a")
+            .unwrap_or(&upload.audio.name)
+            .to_string();
+
+        Ok(UploadData {
+            json: UploadedFile::in_memory(
+                format!("{}.json", stem),
+                Bytes::from(serde_json::to_vec(&metadata)?),
+            ),
+            audio: upload.audio,
+            tags: [("source".to_string(), "openmhz".to_string())].into(),
+            version: Some(MetadataVersion::V4),
+            language: None,
+        })
+    }
+    .await;
+
+    let mut files = match result {
+        Ok(files) => files,
+        Err(e) => {
+            if let Some(location) = staged
+                && let Err(err) = config.s3_client.delete(&location).await
+            {
+                warn!(path = %location, error = %err, "Failed to discard streamed file");
+            }
+            return Err(e);
+        }
+    };
+    let checked = files
+        .deserialize_json()
+        .and_then(|meta| policy::check(&config, &meta, &files, None));
+    let archive = match checked {
+        Ok(quarantined) => quarantined.map(|violation| policy::quarantine(&mut files, &violation)),
+        Err(e) => {
+            discard_incoming(&config, &files).await;
+            return Err(e);
+        }
+    };
+
+    if already_processed(&config, &files).await? {
+        return Ok((StatusCode::OK, "Upload already processed".to_string()));
+    }
+
+    // trunk-recorder's OpenMHz uploader treats anything but a 200 as failed, so
+    // these uploads are processed before responding rather than queued, and
+    // still answered 200 when the rest is queued past the deadline
+    if !process(&config, files, archive, ctx).await? {
+        return Ok((
+            StatusCode::OK,
+            "Upload accepted, still processing".to_string(),
+        ));
+    }
+
+    info!(
+        duration_ms = upload_start.elapsed().as_millis(),
+        "OpenMHz upload processing completed successfully"
+    );
+    Ok((StatusCode::OK, "Upload successful".to_string()))
+}
+
+// Accepts trunk-recorder's OpenMHz uploader: point its openmhzServer at
+// http://<host>/openmhz and uploads arrive at /openmhz/<short_name>/upload
+pub async fn openmhz_upload(
+    State(config): State<ProcessorConfig>,
+    UrlPath(short_name): UrlPath<String>,
+    headers: HeaderMap,
+    m: Multipart,
+) -> Result<(StatusCode, String)> {
+    let ctx = TraceContext::from_headers(&headers);
+    let span = info_span!("upload", trace_id = %ctx.trace_id(), span_id = %ctx.span_id());
+
+    trace::scope(ctx.clone(), handle_openmhz(config, short_name, m, ctx))
+        .instrument(span)
+        .await
+}
@@ -0,0 +1,222 @@
+use crate::common::UploadData;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{ArchiveReason, AudioMetadata};
+use crate::schema::systems;
+
+use chrono::{TimeDelta, Utc};
+use diesel::prelude::*;
+use tracing::{info, warn};
+
+// Tag a quarantined call is stored with, naming the rule it broke
+pub const QUARANTINE_TAG: &str = "quarantine";
+
+// What an upload is checked for before anything of it is stored, in the
+// order they are checked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    EmptyAudio,
+    // a short_name neither UPLOAD_SYSTEMS nor an imported trunk-recorder
+    // config names
+    UnknownSystem,
+    // no talkgroup number, or no names for it besides the number
+    MissingTalkgroup,
+    // a start time further than UPLOAD_TIME_TOLERANCE from now
+    ClockSkew,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 4] = [
+        Rule::EmptyAudio,
+        Rule::UnknownSystem,
+        Rule::MissingTalkgroup,
+        Rule::ClockSkew,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Rule::EmptyAudio => "empty_audio",
+            Rule::UnknownSystem => "unknown_system",
+            Rule::MissingTalkgroup => "missing_talkgroup",
+            Rule::ClockSkew => "clock_skew",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Reject,
+    // stored and archived, so it is neither transcribed nor notified
+    Quarantine,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadPolicy {
+    actions: [Action; Rule::ALL.len()],
+}
+
+// Only an empty recording is turned away unless UPLOAD_POLICY says otherwise,
+// as there is nothing in it to keep
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        let mut actions = [Action::Allow; Rule::ALL.len()];
+        actions[Rule::EmptyAudio.index()] = Action::Reject;
+        UploadPolicy { actions }
+    }
+}
+
+impl UploadPolicy {
+    pub fn action(&self, rule: Rule) -> Action {
+        self.actions[rule.index()]
+    }
+
+    // rule=action for each rule that isn't allowed, for the startup summary
+    pub fn enforced(&self) -> Vec<String> {
+        Rule::ALL
+            .iter()
+            .filter(|rule| self.action(**rule) != Action::Allow)
+            .map(|rule| format!("{}={:?}", rule.code(), self.action(*rule)).to_ascii_lowercase())
+            .collect()
+    }
+}
+
+// Entries are rule=action, where action is allow, reject or quarantine.
+// Rules left out keep their default.
+pub fn parse(entries: &[String]) -> Result<UploadPolicy> {
+    let mut policy = UploadPolicy::default();
+    for entry in entries {
+        let (code, action) = entry.split_once('=').ok_or_else(|| {
+            Error::Configuration(format!("expected rule=action, got {:?}", entry))
+        })?;
+        let rule = Rule::ALL
+            .into_iter()
+            .find(|rule| rule.code() == code.trim())
+            .ok_or_else(|| {
+                Error::Configuration(format!(
+                    "unknown rule {:?}, expected one of {}",
+                    code,
+                    Rule::ALL.map(Rule::code).join(", ")
+                ))
+            })?;
+        policy.actions[rule.index()] = match action.trim() {
+            "allow" => Action::Allow,
+            "reject" => Action::Reject,
+            "quarantine" => Action::Quarantine,
+            _ => {
+                return Err(Error::Configuration(format!(
+                    "invalid action {:?} for {}, expected allow, reject or quarantine",
+                    action, code
+                )));
+            }
+        };
+    }
+    Ok(policy)
+}
+
+// A rule an upload broke, and how
+#[derive(Debug)]
+pub struct Violation {
+    pub rule: Rule,
+    pub detail: String,
+}
+
+impl From<Violation> for Error {
+    fn from(violation: Violation) -> Self {
+        Error::PolicyViolation {
+            code: violation.rule.code(),
+            message: violation.detail,
+        }
+    }
+}
+
+// UPLOAD_SYSTEMS and the systems imported from trunk-recorder configs. With
+// neither, every system is taken as known.
+fn known_system(c: &ProcessorConfig, short_name: &str) -> Result<bool> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let mut known: Vec<String> = systems::table
+        .select(systems::short_name)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    known.extend(c.env.upload_systems.iter().flatten().cloned());
+    Ok(known.is_empty() || known.iter().any(|s| s == short_name))
+}
+
+fn broken(
+    c: &ProcessorConfig,
+    rule: Rule,
+    meta: &AudioMetadata,
+    files: &UploadData,
+    archive: Option<ArchiveReason>,
+) -> Result<Option<String>> {
+    Ok(match rule {
+        Rule::EmptyAudio => {
+            (files.audio.size == 0).then(|| format!("{} is empty", files.audio.name))
+        }
+        Rule::UnknownSystem => (!known_system(c, &meta.call.short_name)?)
+            .then(|| format!("unknown system {:?}", meta.call.short_name)),
+        Rule::MissingTalkgroup => (meta.talkgroup.talkgroup <= 0
+            || meta.talkgroup.has_default_metadata())
+        .then(|| format!("talkgroup {} has no metadata", meta.talkgroup.talkgroup)),
+        // backfilled calls are old by design
+        Rule::ClockSkew if archive == Some(ArchiveReason::Backfill) => None,
+        Rule::ClockSkew => {
+            let skew = meta.call.start_time - Utc::now();
+            let tolerance =
+                TimeDelta::from_std(c.env.upload_time_tolerance).unwrap_or(TimeDelta::MAX);
+            (skew.abs() > tolerance).then(|| {
+                format!(
+                    "start time {} is {}s from now, past a tolerance of {}s",
+                    meta.call.start_time,
+                    skew.num_seconds(),
+                    tolerance.num_seconds()
+                )
+            })
+        }
+    })
+}
+
+// Checks an upload against UPLOAD_POLICY before it is stored. A rejected
+// upload is an error naming the rule; a quarantined one is returned to be
+// stored archived. Rejections are checked first, so an upload breaking one
+// rule that quarantines and another that rejects is turned away.
+pub fn check(
+    c: &ProcessorConfig,
+    meta: &AudioMetadata,
+    files: &UploadData,
+    archive: Option<ArchiveReason>,
+) -> Result<Option<Violation>> {
+    for action in [Action::Reject, Action::Quarantine] {
+        for rule in Rule::ALL
+            .into_iter()
+            .filter(|rule| c.upload_policy.action(*rule) == action)
+        {
+            let Some(detail) = broken(c, rule, meta, files, archive)? else {
+                continue;
+            };
+            if action == Action::Reject {
+                warn!(file = %files.audio.name, rule = rule.code(), detail = %detail, "Upload rejected by policy");
+                return Err(Violation { rule, detail }.into());
+            }
+            info!(file = %files.audio.name, rule = rule.code(), detail = %detail, "Upload quarantined by policy");
+            return Ok(Some(Violation { rule, detail }));
+        }
+    }
+    Ok(None)
+}
+
+// Marks an upload to be stored archived, tagged with the rule it broke
+pub fn quarantine(files: &mut UploadData, violation: &Violation) -> ArchiveReason {
+    files.tags.insert(
+        QUARANTINE_TAG.to_string(),
+        violation.rule.code().to_string(),
+    );
+    ArchiveReason::Quarantine
+}
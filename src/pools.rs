@@ -0,0 +1,85 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use tokio::sync::Semaphore;
+
+#[derive(Debug)]
+pub struct WorkerPool {
+    pub name: &'static str,
+    pub size: usize,
+    semaphore: Semaphore,
+    waiting: AtomicUsize,
+}
+
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl WorkerPool {
+    pub fn new(name: &'static str, size: usize) -> Self {
+        WorkerPool {
+            name,
+            size,
+            semaphore: Semaphore::new(size),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    // Runs `f` once one of the pool's workers is free
+    pub async fn run<F: Future>(&self, f: F) -> F::Output {
+        let permit = {
+            self.waiting.fetch_add(1, Ordering::Relaxed);
+            let _waiting = Waiting(&self.waiting);
+            self.semaphore
+                .acquire()
+                .await
+                .expect("worker pool semaphore closed")
+        };
+
+        let output = f.await;
+        drop(permit);
+        output
+    }
+
+    // As `run`, unless every worker is busy and `limit` tasks are already
+    // waiting, when `f` is dropped without running
+    pub async fn try_run<F: Future>(&self, limit: usize, f: F) -> Option<F::Output> {
+        if self.semaphore.available_permits() == 0 && self.waiting() >= limit {
+            return None;
+        }
+        Some(self.run(f).await)
+    }
+
+    pub fn busy(&self) -> usize {
+        self.size - self.semaphore.available_permits()
+    }
+
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct WorkerPools {
+    pub transcription: WorkerPool,
+    pub storage: WorkerPool,
+    pub notifications: WorkerPool,
+    // every PUT and multipart part written to storage, see PutQueue
+    pub puts: Arc<WorkerPool>,
+}
+
+impl WorkerPools {
+    pub fn all(&self) -> [&WorkerPool; 4] {
+        [
+            &self.transcription,
+            &self.storage,
+            &self.notifications,
+            &self.puts,
+        ]
+    }
+}
@@ -0,0 +1,104 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{ArchiveReason, AudioMetadata};
+
+use std::collections::HashMap;
+
+// Which uploads are processed first when the queue backs up, lowest first.
+// Archived uploads aren't transcribed, so nothing waits on them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PriorityClass {
+    Archive,
+    Routine,
+    // on a talkgroup QUEUE_PRIORITY_TALKGROUPS weighs above 0
+    High,
+    Emergency,
+}
+
+impl PriorityClass {
+    pub const ALL: [PriorityClass; 4] = [
+        PriorityClass::Emergency,
+        PriorityClass::High,
+        PriorityClass::Routine,
+        PriorityClass::Archive,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            PriorityClass::Archive => "archive",
+            PriorityClass::Routine => "routine",
+            PriorityClass::High => "high",
+            PriorityClass::Emergency => "emergency",
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// Ordered by class, then talkgroup weight, then trunk-recorder's priority,
+// where 1 is the most important and 0 unset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority {
+    pub class: PriorityClass,
+    weight: i32,
+    level: i16,
+}
+
+impl Priority {
+    // Every upload alike, so the queue is first in, first out
+    pub const FIFO: Priority = Priority {
+        class: PriorityClass::Routine,
+        weight: 0,
+        level: 0,
+    };
+}
+
+// Entries are talkgroup=weight. Weights above 0 make a talkgroup's calls high
+// priority and below 0 sort them after the rest of routine traffic.
+pub fn parse(entries: &[String]) -> Result<HashMap<i32, i32>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (talkgroup, weight) = entry.split_once('=').ok_or_else(|| {
+                Error::Configuration(format!("expected talkgroup=weight, got {:?}", entry))
+            })?;
+            let talkgroup = talkgroup
+                .trim()
+                .parse()
+                .map_err(|_| Error::Configuration(format!("invalid talkgroup {:?}", talkgroup)))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| Error::Configuration(format!("invalid weight {:?}", weight)))?;
+            Ok((talkgroup, weight))
+        })
+        .collect()
+}
+
+pub fn of(c: &ProcessorConfig, m: &AudioMetadata, archive: Option<ArchiveReason>) -> Priority {
+    let weight = c
+        .talkgroup_weights
+        .get(&m.talkgroup.talkgroup)
+        .copied()
+        .unwrap_or(0);
+    let class = if archive.is_some() {
+        PriorityClass::Archive
+    } else if m.call.emergency {
+        PriorityClass::Emergency
+    } else if weight > 0 {
+        PriorityClass::High
+    } else {
+        PriorityClass::Routine
+    };
+    let level = match m.call.priority {
+        level if level > 0 => -level,
+        _ => i16::MIN,
+    };
+    Priority {
+        class,
+        weight,
+        level,
+    }
+}
@@ -0,0 +1,175 @@
+use crate::pools::WorkerPool;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures::stream::BoxStream;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore,
+    PutMultipartOptions, PutOptions, PutPayload, PutResult, Result, UploadPart, path::Path,
+};
+use std::{fmt, ops::Range, sync::Arc};
+use tracing::warn;
+
+// Named as the store in the error a full queue gives, so it can be told apart
+pub const QUEUE_FULL: &str = "PutQueue";
+
+// Storage with every write, a PUT or one part of a multipart upload, run
+// through the `puts` pool: at most STORAGE_PUT_WORKERS at once across all
+// uploads, so a burst doesn't open a connection per file on a slow uplink.
+// Past STORAGE_PUT_QUEUE writes waiting, new ones fail rather than queue.
+// Reads, lists and deletes go straight through.
+#[derive(Debug)]
+pub struct PutQueue {
+    inner: Arc<dyn ObjectStore>,
+    pool: Arc<WorkerPool>,
+    capacity: usize,
+}
+
+impl PutQueue {
+    pub fn new(inner: Arc<dyn ObjectStore>, pool: Arc<WorkerPool>, capacity: usize) -> Self {
+        PutQueue {
+            inner,
+            pool,
+            capacity,
+        }
+    }
+}
+
+async fn queued<T>(
+    pool: &WorkerPool,
+    capacity: usize,
+    f: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match pool.try_run(capacity, f).await {
+        Some(result) => result,
+        None => {
+            warn!(
+                waiting = pool.waiting(),
+                "Storage write queue full, rejecting write"
+            );
+            Err(object_store::Error::Generic {
+                store: QUEUE_FULL,
+                source: format!("more than {} writes waiting for storage", capacity).into(),
+            })
+        }
+    }
+}
+
+impl fmt::Display for PutQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PutQueue({}, {})", self.pool.size, self.inner)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for PutQueue {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        queued(
+            &self.pool,
+            self.capacity,
+            self.inner.put_opts(location, payload, opts),
+        )
+        .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOptions,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let upload = self.inner.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(QueuedUpload {
+            upload,
+            pool: self.pool.clone(),
+            capacity: self.capacity,
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<u64>) -> Result<Bytes> {
+        self.inner.get_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<u64>]) -> Result<Vec<Bytes>> {
+        self.inner.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        self.inner.delete_stream(locations)
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'static, Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'static, Result<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename_if_not_exists(from, to).await
+    }
+}
+
+#[derive(Debug)]
+struct QueuedUpload {
+    upload: Box<dyn MultipartUpload>,
+    pool: Arc<WorkerPool>,
+    capacity: usize,
+}
+
+#[async_trait]
+impl MultipartUpload for QueuedUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let part = self.upload.put_part(data);
+        let (pool, capacity) = (self.pool.clone(), self.capacity);
+        Box::pin(async move { queued(&pool, capacity, part).await })
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        self.upload.complete().await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.upload.abort().await
+    }
+}
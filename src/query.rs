@@ -0,0 +1,493 @@
+use crate::audit;
+use crate::auth::{Access, read_access, require_private_read};
+use crate::common::parse_duration;
+use crate::compression;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Call, Emergency, Talkgroups};
+use crate::schema::{calls, emergencies, sources, srclist, talkgroups};
+use crate::scrub::scrub;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method, Uri},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{pg::Pg, prelude::*};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+const DEFAULT_CONTEXT_WINDOW: TimeDelta = TimeDelta::minutes(5);
+const MAX_CONTEXT_WINDOW: TimeDelta = TimeDelta::hours(6);
+
+#[derive(Debug, Deserialize)]
+pub struct EmergencyParams {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    talkgroup: Option<i32>,
+    src: Option<i32>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmergencyEntry {
+    #[serde(flatten)]
+    pub emergency: Emergency,
+    pub talkgroup_metadata: Talkgroups,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContextParams {
+    window: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallContext {
+    pub call: Call,
+    pub window_secs: i64,
+    pub calls: Vec<Call>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentCallsParams {
+    talkgroup: Option<i32>,
+    tag: Option<String>,
+    q: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListCallsParams {
+    talkgroup: Option<i32>,
+    group: Option<String>,
+    src: Option<i32>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    emergency: Option<bool>,
+    transcribed: Option<bool>,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallPage {
+    pub calls: Vec<CallEntry>,
+    // pass as `cursor` for the next page; absent on the last one
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CallEntry {
+    #[serde(flatten)]
+    pub call: Call,
+    pub talkgroup_metadata: Talkgroups,
+}
+
+fn visible_talkgroups(
+    groups: &[String],
+) -> talkgroups::BoxedQuery<'static, Pg, diesel::sql_types::Int4> {
+    talkgroups::table
+        .filter(talkgroups::talkgroup_group.eq_any(groups.to_vec()))
+        .select(talkgroups::talkgroup)
+        .into_boxed()
+}
+
+pub fn reveal(c: &ProcessorConfig, access: &Access, call: &mut Call) -> Result<()> {
+    if let Some(cipher) = &c.transcript_cipher {
+        cipher.open_call(call)?;
+    }
+    compression::expand(c, call)?;
+
+    // public readers only ever see the scrubbed copy; rows stored before
+    // scrubbing was enabled are scrubbed on the way out
+    if c.env.pii_scrubbing && access.is_public() {
+        let scrubbed = call.public_transcription.take();
+        call.transcription = scrubbed.or_else(|| call.transcription.as_deref().map(scrub));
+    }
+    Ok(())
+}
+
+fn parse_tags(s: &str) -> Result<serde_json::Value> {
+    s.split(',')
+        .map(|pair| {
+            let (key, value) = pair.split_once(':').ok_or_else(|| {
+                Error::InvalidRequest(format!("tag must be key:value, got {}", pair))
+            })?;
+            Ok((
+                key.trim().to_ascii_lowercase(),
+                serde_json::Value::String(value.trim().to_string()),
+            ))
+        })
+        .collect::<Result<serde_json::Map<_, _>>>()
+        .map(serde_json::Value::Object)
+}
+
+// `%text%` for ILIKE, with the text's own wildcards taken literally
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+// Where a page ended: the last call's start time and filename, which order
+// calls uniquely. Opaque to clients.
+fn encode_cursor(call: &Call) -> String {
+    hex::encode(format!(
+        "{}/{}",
+        call.start_time.timestamp_micros(),
+        call.filename
+    ))
+}
+
+fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, String)> {
+    let invalid = || Error::InvalidRequest("invalid cursor".to_string());
+    let text =
+        String::from_utf8(hex::decode(cursor).map_err(|_| invalid())?).map_err(|_| invalid())?;
+    let (micros, filename) = text.split_once('/').ok_or_else(invalid)?;
+    let start_time = micros
+        .parse()
+        .ok()
+        .and_then(DateTime::from_timestamp_micros)
+        .ok_or_else(invalid)?;
+    Ok((start_time, filename.to_string()))
+}
+
+fn clamp_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+pub async fn list_emergencies(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<EmergencyParams>,
+) -> Result<Json<Vec<EmergencyEntry>>> {
+    let access = require_private_read(&headers, &config)?;
+
+    let mut connection = config
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = emergencies::table
+        .inner_join(talkgroups::table)
+        .select((Emergency::as_select(), Talkgroups::as_select()))
+        .order(emergencies::declared_at.desc())
+        .limit(clamp_limit(params.limit))
+        .into_boxed();
+
+    if let Some(groups) = access.groups() {
+        query = query.filter(talkgroups::talkgroup_group.eq_any(groups.to_vec()));
+    }
+    if let Some(since) = params.since {
+        query = query.filter(emergencies::declared_at.ge(since));
+    }
+    if let Some(until) = params.until {
+        query = query.filter(emergencies::declared_at.lt(until));
+    }
+    if let Some(tg) = params.talkgroup {
+        query = query.filter(emergencies::talkgroup.eq(tg));
+    }
+    if let Some(src) = params.src {
+        query = query.filter(emergencies::src.eq(src));
+    }
+
+    let rows: Vec<(Emergency, Talkgroups)> = query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    audit::record(
+        &config,
+        &access,
+        &method,
+        &uri,
+        rows.iter().map(|(e, _)| e.call_id.clone()),
+    );
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|(emergency, talkgroup_metadata)| EmergencyEntry {
+                emergency,
+                talkgroup_metadata,
+            })
+            .collect(),
+    ))
+}
+
+pub fn context_window(window: Option<&str>) -> Result<TimeDelta> {
+    Ok(match window {
+        Some(w) => parse_duration(w)?,
+        None => DEFAULT_CONTEXT_WINDOW,
+    }
+    .clamp(TimeDelta::zero(), MAX_CONTEXT_WINDOW))
+}
+
+// A call the reader may see, not yet revealed
+pub fn load_call(config: &ProcessorConfig, access: &Access, id: &str) -> Result<Call> {
+    let mut connection = config
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut anchor = calls::table.find(id).select(Call::as_select()).into_boxed();
+    if let Some(groups) = access.groups() {
+        anchor = anchor.filter(calls::talkgroup.eq_any(visible_talkgroups(groups)));
+    }
+
+    anchor
+        .first(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))?
+        .ok_or_else(|| Error::NotFound(format!("call {}", id)))
+}
+
+// A call and the calls on its talkgroup, or patched with it, within `window`
+// either side, oldest first. Neither is revealed yet.
+pub fn load_context(
+    config: &ProcessorConfig,
+    access: &Access,
+    id: &str,
+    window: TimeDelta,
+) -> Result<(Call, Vec<Call>)> {
+    let call = load_call(config, access, id)?;
+    let mut connection = config
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut tgs: Vec<i32> = call.patched_talkgroups.iter().flatten().copied().collect();
+    tgs.push(call.talkgroup);
+    let patched: Vec<Option<i32>> = tgs.iter().copied().map(Some).collect();
+
+    let mut query = calls::table
+        .filter(
+            calls::talkgroup
+                .eq_any(&tgs)
+                .or(calls::patched_talkgroups.overlaps_with(&patched)),
+        )
+        .filter(calls::start_time.ge(call.start_time - window))
+        .filter(calls::start_time.le(call.start_time + window))
+        .filter(calls::filename.ne(call.filename.clone()))
+        .select(Call::as_select())
+        .order(calls::start_time.asc())
+        .limit(MAX_LIMIT)
+        .into_boxed();
+    if let Some(groups) = access.groups() {
+        query = query.filter(calls::talkgroup.eq_any(visible_talkgroups(groups)));
+    }
+
+    let context: Vec<Call> = query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok((call, context))
+}
+
+pub async fn call_context(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+    Query(params): Query<ContextParams>,
+) -> Result<Json<CallContext>> {
+    let access = read_access(&headers, &config)?;
+    let window = context_window(params.window.as_deref())?;
+    let (mut call, mut context) = load_context(&config, &access, &id, window)?;
+
+    audit::record(
+        &config,
+        &access,
+        &method,
+        &uri,
+        std::iter::once(&call)
+            .chain(&context)
+            .map(|c| c.filename.clone()),
+    );
+
+    reveal(&config, &access, &mut call)?;
+    for item in context.iter_mut() {
+        reveal(&config, &access, item)?;
+    }
+
+    Ok(Json(CallContext {
+        call,
+        window_secs: window.num_seconds(),
+        calls: context,
+    }))
+}
+
+pub async fn recent_calls(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<RecentCallsParams>,
+) -> Result<Json<Vec<CallEntry>>> {
+    let access = read_access(&headers, &config)?;
+
+    let mut connection = config
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = calls::table
+        .inner_join(talkgroups::table)
+        .select((Call::as_select(), Talkgroups::as_select()))
+        .order(calls::start_time.desc())
+        .limit(clamp_limit(params.limit))
+        .into_boxed();
+
+    if let Some(groups) = access.groups() {
+        query = query.filter(talkgroups::talkgroup_group.eq_any(groups.to_vec()));
+    }
+    if let Some(tg) = params.talkgroup {
+        query = query.filter(calls::talkgroup.eq(tg));
+    }
+    if let Some(tag) = params.tag.as_deref() {
+        query = query.filter(calls::tags.contains(parse_tags(tag)?));
+    }
+    if let Some(q) = params.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let pattern = like_pattern(q);
+        // calls where a unit whose alias matches transmitted
+        let units = srclist::table
+            .inner_join(sources::table)
+            .filter(sources::tag.ilike(pattern.clone()))
+            .select(srclist::call_id);
+        let metadata = talkgroups::talkgroup_tag
+            .ilike(pattern.clone())
+            .or(talkgroups::talkgroup_description.ilike(pattern.clone()))
+            .or(calls::filename.eq_any(units));
+
+        // encrypted transcriptions can't be matched in the database, and public
+        // readers only match the scrubbed copy they would be shown
+        query = if config.transcript_cipher.is_some() {
+            query.filter(metadata)
+        } else if config.env.pii_scrubbing && access.is_public() {
+            query.filter(metadata.or(calls::public_transcription.ilike(pattern).assume_not_null()))
+        } else {
+            query.filter(metadata.or(calls::transcription.ilike(pattern).assume_not_null()))
+        };
+    }
+
+    let rows: Vec<(Call, Talkgroups)> = query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    audit::record(
+        &config,
+        &access,
+        &method,
+        &uri,
+        rows.iter().map(|(c, _)| c.filename.clone()),
+    );
+
+    rows.into_iter()
+        .map(|(mut call, talkgroup_metadata)| {
+            reveal(&config, &access, &mut call)?;
+            Ok(CallEntry {
+                call,
+                talkgroup_metadata,
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Json)
+}
+
+// Calls newest first, a page at a time
+pub async fn list_calls(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<ListCallsParams>,
+) -> Result<Json<CallPage>> {
+    let access = read_access(&headers, &config)?;
+    let limit = clamp_limit(params.limit);
+
+    let mut connection = config
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    // one extra row says whether there is another page
+    let mut query = calls::table
+        .inner_join(talkgroups::table)
+        .select((Call::as_select(), Talkgroups::as_select()))
+        .order((calls::start_time.desc(), calls::filename.desc()))
+        .limit(limit + 1)
+        .into_boxed();
+
+    if let Some(groups) = access.groups() {
+        query = query.filter(talkgroups::talkgroup_group.eq_any(groups.to_vec()));
+    }
+    if let Some(tg) = params.talkgroup {
+        query = query.filter(calls::talkgroup.eq(tg));
+    }
+    if let Some(group) = params.group {
+        query = query.filter(talkgroups::talkgroup_group.eq(group));
+    }
+    if let Some(src) = params.src {
+        let transmitted = srclist::table
+            .filter(srclist::src.eq(src))
+            .select(srclist::call_id);
+        query = query.filter(calls::filename.eq_any(transmitted));
+    }
+    if let Some(since) = params.since {
+        query = query.filter(calls::start_time.ge(since));
+    }
+    if let Some(until) = params.until {
+        query = query.filter(calls::start_time.lt(until));
+    }
+    if let Some(emergency) = params.emergency {
+        query = query.filter(calls::emergency.eq(emergency));
+    }
+    match params.transcribed {
+        Some(true) => query = query.filter(calls::transcription.is_not_null()),
+        Some(false) => query = query.filter(calls::transcription.is_null()),
+        None => {}
+    }
+    if let Some(cursor) = params.cursor.as_deref() {
+        let (start_time, filename) = decode_cursor(cursor)?;
+        query = query.filter(
+            calls::start_time.lt(start_time).or(calls::start_time
+                .eq(start_time)
+                .and(calls::filename.lt(filename))),
+        );
+    }
+
+    let mut rows: Vec<(Call, Talkgroups)> = query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let next_cursor = if rows.len() as i64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|(call, _)| encode_cursor(call))
+    } else {
+        None
+    };
+
+    audit::record(
+        &config,
+        &access,
+        &method,
+        &uri,
+        rows.iter().map(|(c, _)| c.filename.clone()),
+    );
+
+    let calls = rows
+        .into_iter()
+        .map(|(mut call, talkgroup_metadata)| {
+            reveal(&config, &access, &mut call)?;
+            Ok(CallEntry {
+                call,
+                talkgroup_metadata,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Json(CallPage { calls, next_cursor }))
+}
@@ -0,0 +1,413 @@
+use crate::common::{UploadData, UploadedFile};
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{ArchiveReason, MetadataVersion};
+use crate::upload::ingest;
+
+use axum::body::Bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::{Connection, OpenFlags, Row, types::ValueRef};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+use tracing::{info, warn};
+
+fn sqlite(e: rusqlite::Error) -> Error {
+    Error::Import(format!("could not read the rdio-scanner database: {}", e))
+}
+
+#[derive(Debug)]
+struct SystemRow {
+    id: i64,
+    label: String,
+    talkgroups: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TalkgroupEntry {
+    id: i32,
+    label: Option<String>,
+    name: Option<String>,
+    group_id: Option<i64>,
+    tag_id: Option<i64>,
+}
+
+#[derive(Debug)]
+struct CallRow {
+    id: i64,
+    date_time: serde_json::Value,
+    frequency: Option<i32>,
+    frequencies: Option<String>,
+    source: Option<i32>,
+    sources: Option<String>,
+    system: i64,
+    talkgroup: i32,
+    patches: Option<String>,
+    audio_name: Option<String>,
+    audio_type: Option<String>,
+    audio: Option<Vec<u8>>,
+}
+
+const CALLS_QUERY: &str = "select id, dateTime, frequency, frequencies, source, sources, \
+     system, talkgroup, patches, audioName, audioType, audio \
+     from rdioScannerCalls order by id";
+
+impl CallRow {
+    fn read(row: &Row<'_>) -> rusqlite::Result<Self> {
+        // dateTime's type has changed across rdio-scanner versions
+        let date_time = match row.get_ref(1)? {
+            ValueRef::Integer(secs) => json!(secs),
+            ValueRef::Real(secs) => json!(secs as i64),
+            ValueRef::Text(text) => json!(String::from_utf8_lossy(text)),
+            _ => serde_json::Value::Null,
+        };
+        let audio = match row.get_ref(11)? {
+            ValueRef::Blob(data) | ValueRef::Text(data) => Some(data.to_vec()),
+            _ => None,
+        };
+        Ok(CallRow {
+            id: row.get(0)?,
+            date_time,
+            frequency: row.get(2)?,
+            frequencies: row.get(3)?,
+            source: row.get(4)?,
+            sources: row.get(5)?,
+            system: row.get(6)?,
+            talkgroup: row.get(7)?,
+            patches: row.get(8)?,
+            audio_name: row.get(9)?,
+            audio_type: row.get(10)?,
+            audio,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RdioFrequency {
+    freq: Option<i32>,
+    #[serde(default)]
+    pos: f64,
+    #[serde(default)]
+    len: f64,
+    #[serde(default)]
+    error_count: i16,
+    #[serde(default)]
+    spike_count: i16,
+}
+
+#[derive(Debug, Deserialize)]
+struct RdioSource {
+    src: i32,
+    #[serde(default)]
+    pos: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub calls_read: usize,
+    pub imported: usize,
+    pub skipped: Vec<i64>,
+    pub failed: Vec<i64>,
+    pub duration_ms: u128,
+}
+
+struct Talkgroup {
+    tag: String,
+    description: String,
+    group: String,
+    group_tag: String,
+}
+
+struct System {
+    short_name: String,
+    talkgroups: HashMap<i32, Talkgroup>,
+}
+
+fn short_name(label: &str) -> String {
+    label
+        .trim()
+        .to_ascii_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn labels(db: &Connection, table: &str) -> Result<HashMap<i64, String>> {
+    let mut statement = db
+        .prepare(&format!("select _id, label from {}", table))
+        .map_err(sqlite)?;
+    statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(sqlite)?
+        .collect::<rusqlite::Result<_>>()
+        .map_err(sqlite)
+}
+
+fn load_systems(db: &Connection) -> Result<HashMap<i64, System>> {
+    let groups = labels(db, "rdioScannerGroups")?;
+    let tags = labels(db, "rdioScannerTags")?;
+
+    let mut statement = db
+        .prepare("select id, label, talkgroups from rdioScannerSystems")
+        .map_err(sqlite)?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok(SystemRow {
+                id: row.get(0)?,
+                label: row.get(1)?,
+                talkgroups: row.get(2)?,
+            })
+        })
+        .map_err(sqlite)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(sqlite)?;
+
+    let mut systems = HashMap::new();
+    for row in rows {
+        let entries: Vec<TalkgroupEntry> = match &row.talkgroups {
+            Some(text) => serde_json::from_str(text)?,
+            None => Vec::new(),
+        };
+        let talkgroups = entries
+            .into_iter()
+            .map(|tg| {
+                let group = tg.group_id.and_then(|id| groups.get(&id)).cloned();
+                let tag = tg.tag_id.and_then(|id| tags.get(&id)).cloned();
+                let entry = Talkgroup {
+                    tag: tg.label.unwrap_or_default(),
+                    description: tg.name.unwrap_or_default(),
+                    group: group.unwrap_or_default(),
+                    group_tag: tag.unwrap_or_default(),
+                };
+                (tg.id, entry)
+            })
+            .collect();
+
+        systems.insert(
+            row.id,
+            System {
+                short_name: short_name(&row.label),
+                talkgroups,
+            },
+        );
+    }
+
+    Ok(systems)
+}
+
+// rdio-scanner has stored dateTime as unix seconds, RFC 3339 and SQLite's own
+// "YYYY-MM-DD HH:MM:SS" text across versions
+fn parse_date_time(value: &serde_json::Value) -> Option<DateTime<Utc>> {
+    if let Some(secs) = value.as_i64() {
+        return DateTime::from_timestamp(secs, 0);
+    }
+    let text = value.as_str()?.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Some(dt.to_utc());
+    }
+    if let Ok(dt) = DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f %z") {
+        return Some(dt.to_utc());
+    }
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")
+        .ok()
+        .map(|dt| dt.and_utc())
+}
+
+fn extension(row: &CallRow) -> &'static str {
+    let from_name = row
+        .audio_name
+        .as_deref()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match (from_name.as_deref(), row.audio_type.as_deref()) {
+        (Some("mp3"), _) | (_, Some("audio/mpeg")) => "mp3",
+        (Some("wav"), _) | (_, Some("audio/wav")) => "wav",
+        _ => "m4a",
+    }
+}
+
+fn read_audio(row: &CallRow, audio_dir: Option<&PathBuf>) -> Result<Option<Bytes>> {
+    if let Some(data) = row.audio.as_ref().filter(|d| !d.is_empty()) {
+        return Ok(Some(Bytes::copy_from_slice(data)));
+    }
+
+    let (Some(dir), Some(name)) = (audio_dir, row.audio_name.as_deref()) else {
+        return Ok(None);
+    };
+    match std::fs::read(dir.join(name)) {
+        Ok(data) => Ok(Some(Bytes::from(data))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::Import(format!("could not read {}: {}", name, e))),
+    }
+}
+
+// Rebuilds the trunk-recorder metadata for a call so it can go through the
+// same ingest path as an upload
+fn convert(row: &CallRow, system: &System, start: DateTime<Utc>) -> Result<serde_json::Value> {
+    let frequencies: Vec<RdioFrequency> = match &row.frequencies {
+        Some(text) if !text.is_empty() => serde_json::from_str(text)?,
+        _ => Vec::new(),
+    };
+    let sources: Vec<RdioSource> = match &row.sources {
+        Some(text) if !text.is_empty() => serde_json::from_str(text)?,
+        _ => row
+            .source
+            .map(|src| vec![RdioSource { src, pos: 0.0 }])
+            .unwrap_or_default(),
+    };
+    let patches: Vec<i32> = match &row.patches {
+        Some(text) if !text.is_empty() => serde_json::from_str(text)?,
+        _ => Vec::new(),
+    };
+
+    let freq = row
+        .frequency
+        .or_else(|| frequencies.iter().find_map(|f| f.freq))
+        .unwrap_or_default();
+    let length = frequencies
+        .iter()
+        .map(|f| f.pos + f.len)
+        .fold(0.0, f64::max)
+        .round() as i64;
+    let talkgroup = system.talkgroups.get(&row.talkgroup);
+
+    Ok(json!({
+        "freq": freq,
+        "freq_error": 0,
+        "signal": 0,
+        "noise": 0,
+        "source_num": 0,
+        "recorder_num": 0,
+        "tdma_slot": 0,
+        "phase2_tdma": 0,
+        "start_time": start.timestamp(),
+        "stop_time": start.timestamp() + length,
+        "emergency": 0,
+        "priority": 0,
+        "mode": 0,
+        "duplex": 0,
+        "encrypted": 0,
+        "call_length": length,
+        "talkgroup": row.talkgroup,
+        "talkgroup_tag": talkgroup.map(|t| t.tag.as_str()).unwrap_or_default(),
+        "talkgroup_description": talkgroup.map(|t| t.description.as_str()).unwrap_or_default(),
+        "talkgroup_group_tag": talkgroup.map(|t| t.group_tag.as_str()).unwrap_or_default(),
+        "talkgroup_group": talkgroup.map(|t| t.group.as_str()).unwrap_or_default(),
+        "audio_type": "digital",
+        "short_name": system.short_name,
+        "patched_talkgroups": patches,
+        "freqList": frequencies.iter().map(|f| json!({
+            "freq": f.freq.unwrap_or(freq),
+            "time": start.timestamp() + f.pos as i64,
+            "pos": f.pos,
+            "len": f.len,
+            "error_count": f.error_count,
+            "spike_count": f.spike_count,
+        })).collect::<Vec<_>>(),
+        "srcList": sources.iter().map(|s| json!({
+            "src": s.src,
+            "time": start.timestamp() + s.pos as i64,
+            "pos": s.pos,
+            "emergency": 0,
+            "signal_system": "",
+            "tag": "",
+        })).collect::<Vec<_>>(),
+    }))
+}
+
+async fn import_call(
+    c: &ProcessorConfig,
+    row: &CallRow,
+    systems: &HashMap<i64, System>,
+    audio_dir: Option<&PathBuf>,
+) -> Result<bool> {
+    let Some(system) = systems.get(&row.system) else {
+        warn!(
+            id = row.id,
+            system = row.system,
+            "Call references unknown system, skipping"
+        );
+        return Ok(false);
+    };
+    let Some(start) = parse_date_time(&row.date_time) else {
+        warn!(id = row.id, date_time = %row.date_time, "Call has unreadable dateTime, skipping");
+        return Ok(false);
+    };
+    let Some(audio) = read_audio(row, audio_dir)? else {
+        warn!(id = row.id, "Call has no audio, skipping");
+        return Ok(false);
+    };
+
+    let metadata = convert(row, system, start)?;
+    let stem = format!(
+        "{}-{}_{}",
+        row.talkgroup,
+        start.timestamp(),
+        metadata["freq"]
+    );
+
+    let mut files = UploadData {
+        json: UploadedFile::in_memory(
+            format!("{}.json", stem),
+            Bytes::from(serde_json::to_vec(&metadata)?),
+        ),
+        audio: UploadedFile::in_memory(format!("{}.{}", stem, extension(row)), audio),
+        tags: [("imported".to_string(), "rdio-scanner".to_string())].into(),
+        version: Some(MetadataVersion::V4),
+        language: None,
+    };
+
+    ingest(c, &mut files, Some(ArchiveReason::Backfill)).await?;
+    Ok(true)
+}
+
+// Imports every call in an rdio-scanner database as a backfill: audio is
+// archived to storage and rows written, without transcription or notifications.
+// Audio is read from the database, or from `audio_dir` by audioName when the
+// blob is empty.
+pub async fn import(
+    c: &ProcessorConfig,
+    db: &str,
+    audio_dir: Option<PathBuf>,
+) -> Result<ImportReport> {
+    let start = Instant::now();
+    info!(db = %db, "Starting rdio-scanner import");
+
+    let connection =
+        Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(sqlite)?;
+    let systems = load_systems(&connection)?;
+
+    // one statement stepped a row at a time, so only the call being imported
+    // is held in memory
+    let mut statement = connection.prepare(CALLS_QUERY).map_err(sqlite)?;
+    let mut rows = statement.query([]).map_err(sqlite)?;
+    let mut report = ImportReport::default();
+
+    while let Some(row) = rows.next().map_err(sqlite)? {
+        let row = CallRow::read(row).map_err(sqlite)?;
+        report.calls_read += 1;
+
+        match import_call(c, &row, &systems, audio_dir.as_ref()).await {
+            Ok(true) => report.imported += 1,
+            Ok(false) => report.skipped.push(row.id),
+            Err(e) => {
+                warn!(id = row.id, error = %e, "Failed to import call");
+                report.failed.push(row.id);
+            }
+        }
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        read = report.calls_read,
+        imported = report.imported,
+        skipped = report.skipped.len(),
+        failed = report.failed.len(),
+        duration_ms = report.duration_ms,
+        "rdio-scanner import completed"
+    );
+
+    Ok(report)
+}
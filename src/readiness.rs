@@ -0,0 +1,177 @@
+use crate::alerts::{self, Alert, Severity};
+use crate::common::format_timestamp_from_datetime;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::worker;
+
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use diesel::{
+    PgConnection, RunQueryDsl,
+    r2d2::{ConnectionManager, Pool},
+    sql_query,
+};
+use object_store::{ObjectStore, path::Path};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+// An object that is never written, so looking it up only shows the bucket
+// can be reached with these credentials
+const STORAGE_PROBE: &str = ".trunk-processor-check";
+// Readiness is probed far more often than this, so a failing dependency's
+// alert stays up until the probes stop failing
+const READINESS_ALERT_EXPIRY: Duration = Duration::from_secs(5 * 60);
+
+// A HEAD of an object that isn't there: any answer but NotFound means storage
+// can't be used
+pub async fn storage(c: &ProcessorConfig) -> Result<()> {
+    match c.s3_client.head(&Path::from(STORAGE_PROBE)).await {
+        Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Blocks for up to the pool's connection timeout
+pub fn database(pool: &Pool<ConnectionManager<PgConnection>>) -> Result<()> {
+    let mut connection = pool.get().map_err(|e| Error::Database(e.to_string()))?;
+    sql_query("SELECT 1")
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(())
+}
+
+// Any HTTP answer, even an error status, shows the endpoint is up
+async fn transcription(c: &ProcessorConfig, url: &str) -> Result<()> {
+    c.http_client
+        .head(url)
+        .timeout(c.env.readiness_timeout)
+        .send()
+        .await
+        .map_err(|e| Error::Unavailable(e.to_string()))?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct Dependency {
+    ok: bool,
+    // whether the service is unready while this is down
+    required: bool,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+async fn check(
+    c: &ProcessorConfig,
+    required: bool,
+    f: impl Future<Output = Result<()>>,
+) -> Dependency {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(c.env.readiness_timeout, f).await {
+        Ok(result) => result,
+        Err(_) => Err(Error::Unavailable(format!(
+            "no answer within {}s",
+            c.env.readiness_timeout.as_secs_f64()
+        ))),
+    };
+    Dependency {
+        ok: result.is_ok(),
+        required,
+        duration_ms: start.elapsed().as_millis(),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+async fn check_database(
+    c: &ProcessorConfig,
+    pool: &Pool<ConnectionManager<PgConnection>>,
+) -> Dependency {
+    let pool = pool.clone();
+    check(c, true, async move {
+        tokio::task::spawn_blocking(move || database(&pool))
+            .await
+            .map_err(|e| Error::Unavailable(e.to_string()))?
+    })
+    .await
+}
+
+// Unready while the database or storage can't be reached, or the queue is
+// behind with QUEUE_READINESS, so a load balancer can route uploads
+// elsewhere. Each dependency is reported whether or not it is required.
+pub async fn readyz(State(config): State<ProcessorConfig>) -> Response {
+    let timestamp = format_timestamp_from_datetime(Utc::now().to_utc());
+    let c = &config;
+
+    let (database, storage) =
+        tokio::join!(check_database(c, &c.db_pool), check(c, true, storage(c)));
+    let mut checks = BTreeMap::from([("database", database), ("storage", storage)]);
+    if c.env.database_read_url.is_some() {
+        checks.insert("read_database", check_database(c, &c.read_pool).await);
+    }
+    if c.env.readiness_transcription
+        && let Some(url) = &c.env.transcription_endpoint
+    {
+        checks.insert(
+            "transcription",
+            check(c, false, transcription(c, url)).await,
+        );
+    }
+    if c.env.queue_readiness {
+        let backlog = worker::backlog(c);
+        checks.insert(
+            "queue",
+            Dependency {
+                ok: backlog.is_none(),
+                required: true,
+                duration_ms: 0,
+                error: backlog,
+            },
+        );
+    }
+
+    // the queue raises its own alert when it falls behind
+    for (name, dependency) in checks.iter().filter(|(name, d)| **name != "queue" && !d.ok) {
+        alerts::spawn(
+            c,
+            Alert {
+                name: "DependencyUnavailable",
+                severity: if dependency.required {
+                    Severity::Critical
+                } else {
+                    Severity::Warning
+                },
+                summary: format!("Readiness check for {} is failing", name),
+                description: dependency.error.clone().unwrap_or_default(),
+                labels: vec![("dependency", name.to_string())],
+                expires_in: READINESS_ALERT_EXPIRY,
+            },
+        );
+    }
+
+    let down: Vec<&str> = checks
+        .iter()
+        .filter(|(_, d)| d.required && !d.ok)
+        .map(|(name, _)| *name)
+        .collect();
+    let (status, body) = if down.is_empty() {
+        (
+            StatusCode::OK,
+            serde_json::json!({"status": "ready", "timestamp": timestamp, "checks": checks}),
+        )
+    } else {
+        let reason = format!("{} not ready", down.join(", "));
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::json!({"status": "unready", "reason": reason, "timestamp": timestamp, "checks": checks}),
+        )
+    };
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,185 @@
+use crate::alerts::{self, Alert, Severity};
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::retention;
+use crate::schema;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use futures::TryStreamExt;
+use object_store::{ObjectStore, path::Path};
+use serde::Serialize;
+use std::{collections::HashSet, time::Duration, time::Instant};
+use tracing::{error, info};
+
+const STALE_STAGING_AGE: TimeDelta = TimeDelta::hours(1);
+// A call's row is written before its audio is moved into place, so rows this
+// recent may just be mid-upload rather than missing their object
+const IN_FLIGHT_GRACE: TimeDelta = TimeDelta::minutes(10);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconcileReport {
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u128,
+    pub objects_scanned: usize,
+    pub rows_scanned: usize,
+    pub orphaned_objects: Vec<String>,
+    pub missing_objects: Vec<String>,
+    pub repaired: bool,
+}
+
+fn audio_key_for(key: &str) -> Option<String> {
+    if key.ends_with(".m4a") {
+        Some(key.to_string())
+    } else {
+        key.strip_suffix(".json")
+            .map(|stem| format!("{}.m4a", stem))
+    }
+}
+
+async fn repair(report: &ReconcileReport, c: &ProcessorConfig) -> Result<()> {
+    for key in &report.orphaned_objects {
+        c.s3_client.delete(&Path::parse(key)?).await?;
+    }
+
+    retention::delete_rows(c, &report.missing_objects)
+}
+
+pub async fn reconcile(c: &ProcessorConfig, do_repair: bool) -> Result<ReconcileReport> {
+    use schema::calls::dsl::*;
+
+    let started_at = Utc::now();
+    let start = Instant::now();
+    info!(repair = do_repair, "Starting storage reconciliation");
+
+    let staging = Path::parse(&c.env.staging_prefix)?;
+    let export = Path::parse(&c.env.export_prefix)?;
+    let captures = Path::parse(&c.env.capture_prefix)?;
+    let failed = Path::parse(&c.env.failed_job_prefix)?;
+    let objects: Vec<_> = c.s3_client.list(None).try_collect().await?;
+
+    let (rows, recent, held): (HashSet<String>, HashSet<String>, HashSet<String>) = {
+        let mut connection = c
+            .db_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+
+        let rows = calls
+            .select(filename)
+            .load::<String>(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?
+            .into_iter()
+            .collect();
+        let recent = schema::upload_checksums::table
+            .filter(schema::upload_checksums::stored_at.gt(started_at - IN_FLIGHT_GRACE))
+            .select(schema::upload_checksums::call_id)
+            .load::<String>(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?
+            .into_iter()
+            .collect();
+        // audio still waiting to be processed, which can sit in staging
+        // for longer than STALE_STAGING_AGE through a backlog
+        let mut held: HashSet<String> = schema::failed_jobs::table
+            .select(schema::failed_jobs::audio_path)
+            .load::<String>(&mut connection)
+            .map_err(|e| Error::Database(e.to_string()))?
+            .into_iter()
+            .collect();
+        held.extend(c.queue.audio_paths());
+        (rows, recent, held)
+    };
+
+    let mut audio_keys = HashSet::new();
+    let mut orphaned_objects = Vec::new();
+
+    for object in &objects {
+        let key = object.location.to_string();
+
+        if object.location.prefix_matches(&staging) {
+            if started_at - object.last_modified > STALE_STAGING_AGE && !held.contains(&key) {
+                orphaned_objects.push(key);
+            }
+            continue;
+        }
+
+        if [&export, &captures, &failed]
+            .iter()
+            .any(|prefix| object.location.prefix_matches(prefix))
+        {
+            continue;
+        }
+
+        let Some(audio_key) = audio_key_for(&key) else {
+            continue;
+        };
+        if !rows.contains(&audio_key) {
+            orphaned_objects.push(key.clone());
+        }
+        if key == audio_key {
+            audio_keys.insert(audio_key);
+        }
+    }
+
+    let mut missing_objects: Vec<String> = rows
+        .iter()
+        .filter(|row| !audio_keys.contains(*row) && !recent.contains(*row))
+        .cloned()
+        .collect();
+    orphaned_objects.sort();
+    missing_objects.sort();
+
+    let mut report = ReconcileReport {
+        started_at,
+        duration_ms: 0,
+        objects_scanned: objects.len(),
+        rows_scanned: rows.len(),
+        orphaned_objects,
+        missing_objects,
+        repaired: false,
+    };
+
+    if do_repair && !(report.orphaned_objects.is_empty() && report.missing_objects.is_empty()) {
+        repair(&report, c).await?;
+        report.repaired = true;
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        objects = report.objects_scanned,
+        rows = report.rows_scanned,
+        orphaned = report.orphaned_objects.len(),
+        missing = report.missing_objects.len(),
+        repaired = report.repaired,
+        duration_ms = report.duration_ms,
+        "Storage reconciliation completed"
+    );
+
+    Ok(report)
+}
+
+pub async fn run_periodic(c: ProcessorConfig, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        match reconcile(&c, c.env.reconcile_repair).await {
+            Ok(report) => *c.reconcile_report.write().await = Some(report),
+            Err(e) => {
+                error!(error = %e, "Storage reconciliation failed");
+                alerts::raise(
+                    &c,
+                    Alert {
+                        name: "ReconciliationFailed",
+                        severity: Severity::Critical,
+                        summary: "Storage reconciliation failed".to_string(),
+                        description: e.to_string(),
+                        labels: Vec::new(),
+                        expires_in: every * 2,
+                    },
+                )
+                .await;
+            }
+        }
+    }
+}
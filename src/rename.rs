@@ -0,0 +1,168 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::schema::{discord_routes, talkgroups};
+
+use diesel::{prelude::*, result::Error as DieselError, update};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+use tracing::info;
+
+// A mapping file of old name to new name, as in
+// {"groups": {"Sheriff": "County Sheriff"}, "group_tags": {"Law Talk": "Law Tac"}}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Renames {
+    #[serde(default)]
+    groups: BTreeMap<String, String>,
+    #[serde(default)]
+    group_tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RenameReport {
+    pub dry_run: bool,
+    // talkgroups renamed, per old group or tag name
+    pub groups: BTreeMap<String, usize>,
+    pub group_tags: BTreeMap<String, usize>,
+    pub discord_routes: usize,
+    // old names nothing was stored under, usually a typo in the mapping
+    pub unmatched: Vec<String>,
+    // where the running configuration still names an old group. These are
+    // read from the environment and CONFIG_FILE, so they are left to be
+    // changed there.
+    pub still_configured: Vec<String>,
+}
+
+pub fn read(path: &Path) -> Result<Renames> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| Error::InvalidRequest(format!("could not read {}: {}", path.display(), e)))?;
+    serde_json::from_str(&text).map_err(|e| Error::InvalidRequest(e.to_string()))
+}
+
+// An old name can't also be a new one, or the order renames ran in would
+// decide whether two groups were swapped or merged
+fn validate(renames: &BTreeMap<String, String>, what: &str) -> Result<()> {
+    for (from, to) in renames {
+        if from.trim().is_empty() || to.trim().is_empty() {
+            return Err(Error::InvalidRequest(format!(
+                "{} names can't be empty",
+                what
+            )));
+        }
+        if renames.contains_key(to) && from != to {
+            return Err(Error::InvalidRequest(format!(
+                "{} {:?} is renamed to {:?}, which is itself renamed",
+                what, from, to
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn still_configured(c: &ProcessorConfig, renames: &Renames) -> Vec<String> {
+    let renamed = |group: &String| renames.groups.contains_key(group);
+    let mut found = Vec::new();
+    for group in c.filter.load().group().iter().filter(|g| renamed(g)) {
+        found.push(format!("FILTER_TG_GROUP: {}", group));
+    }
+    let notifiers = c.notifiers.load_full();
+    for name in notifiers.names() {
+        let groups = notifiers.config(name).and_then(|n| n.tg_group.as_deref());
+        for group in groups.unwrap_or_default().iter().filter(|g| renamed(g)) {
+            found.push(format!("notifier {}: {}", name, group));
+        }
+    }
+    for group in c.env.public_groups.iter().flatten().filter(|g| renamed(g)) {
+        found.push(format!("PUBLIC_GROUPS: {}", group));
+    }
+    for key in &c.read_keys {
+        for group in key.groups.iter().flatten().filter(|g| renamed(g)) {
+            found.push(format!("READ_KEYS {}: {}", key.id, group));
+        }
+    }
+    found
+}
+
+// Renames groups and group tags of every stored talkgroup, and the discord
+// routes keyed by group, in one transaction. trunk-recorder's talkgroupsFile
+// has to be changed as well, or the next call on a talkgroup stores its old
+// names again.
+pub fn apply(c: &ProcessorConfig, renames: &Renames, dry_run: bool) -> Result<RenameReport> {
+    validate(&renames.groups, "group")?;
+    validate(&renames.group_tags, "group tag")?;
+    if renames.groups.is_empty() && renames.group_tags.is_empty() {
+        return Err(Error::InvalidRequest("no renames given".to_string()));
+    }
+
+    let mut report = RenameReport {
+        dry_run,
+        still_configured: still_configured(c, renames),
+        ..Default::default()
+    };
+    let mut conflicts = Vec::new();
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let result = connection.transaction(|conn| {
+        for (from, to) in &renames.groups {
+            let talkgroups = update(talkgroups::table.filter(talkgroups::talkgroup_group.eq(from)))
+                .set(talkgroups::talkgroup_group.eq(to))
+                .execute(conn)?;
+
+            // routes are unique per group, so a group merged into one that
+            // already has a route would need one of them dropped
+            let existing = discord_routes::table
+                .filter(discord_routes::talkgroup_group.eq_any([from, to]))
+                .count()
+                .get_result::<i64>(conn)?;
+            let routes = if existing > 1 {
+                conflicts.push(format!("{:?} and {:?} both have a discord route", from, to));
+                0
+            } else {
+                update(discord_routes::table.filter(discord_routes::talkgroup_group.eq(from)))
+                    .set(discord_routes::talkgroup_group.eq(to))
+                    .execute(conn)?
+            };
+
+            report.discord_routes += routes;
+            if talkgroups == 0 && routes == 0 {
+                report.unmatched.push(from.clone());
+            }
+            report.groups.insert(from.clone(), talkgroups);
+        }
+        for (from, to) in &renames.group_tags {
+            let talkgroups =
+                update(talkgroups::table.filter(talkgroups::talkgroup_group_tag.eq(from)))
+                    .set(talkgroups::talkgroup_group_tag.eq(to))
+                    .execute(conn)?;
+            if talkgroups == 0 {
+                report.unmatched.push(from.clone());
+            }
+            report.group_tags.insert(from.clone(), talkgroups);
+        }
+
+        if dry_run || !conflicts.is_empty() {
+            return Err(DieselError::RollbackTransaction);
+        }
+        Ok(())
+    });
+    match result {
+        Err(DieselError::RollbackTransaction) if !conflicts.is_empty() => {
+            return Err(Error::Conflict(conflicts.join(", ")));
+        }
+        Ok(()) | Err(DieselError::RollbackTransaction) => {}
+        Err(e) => return Err(Error::Database(e.to_string())),
+    }
+
+    info!(
+        dry_run,
+        groups = report.groups.len(),
+        group_tags = report.group_tags.len(),
+        discord_routes = report.discord_routes,
+        still_configured = report.still_configured.len(),
+        "Talkgroup rename completed"
+    );
+    Ok(report)
+}
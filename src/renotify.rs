@@ -0,0 +1,314 @@
+use crate::audio;
+use crate::auth::Access;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{AudioMetadata, Call, FreqList, Source, SrcList, Talkgroups};
+use crate::notifier::Delivery;
+use crate::query::reveal;
+use crate::schema::{calls, freqlist, notification_keys, sources, srclist, talkgroups};
+use crate::segments;
+use crate::transcribe;
+use crate::upload::{Notice, Stored};
+use crate::words;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use futures::future::join_all;
+use object_store::{ObjectStore, path::Path};
+use serde::Serialize;
+use std::{collections::HashSet, time::Instant};
+use tracing::{info, warn};
+
+const DEFAULT_WINDOW: TimeDelta = TimeDelta::hours(24);
+const DEFAULT_LIMIT: i64 = 1000;
+const MAX_LIMIT: i64 = 10000;
+
+#[derive(Debug, Serialize)]
+pub struct DestinationReport {
+    pub destination: String,
+    pub already_sent: usize,
+    // calls sent, or that would be sent on a dry run
+    pub pending: usize,
+    pub failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenotifyReport {
+    pub since: DateTime<Utc>,
+    pub dry_run: bool,
+    pub duration_ms: u128,
+    pub calls: usize,
+    pub destinations: Vec<DestinationReport>,
+}
+
+// The stored rows a call was delivered from originally
+fn load_metadata(c: &ProcessorConfig, call: Call, talkgroup: Talkgroups) -> Result<AudioMetadata> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let src_list: Vec<SrcList> = srclist::table
+        .filter(srclist::call_id.eq(&call.filename))
+        .order(srclist::pos.asc())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let freq_list: Vec<FreqList> = freqlist::table
+        .filter(freqlist::call_id.eq(&call.filename))
+        .order(freqlist::pos.asc())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let sources = sources::table
+        .filter(sources::src.eq_any(src_list.iter().map(|s| s.src)))
+        .load::<Source>(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok(AudioMetadata {
+        call,
+        talkgroup,
+        freq_list,
+        src_list,
+        sources,
+    })
+}
+
+// A stored call as ingest returned it, with its audio fetched back for the
+// notifiers if it was transcribed
+async fn stored(
+    c: &ProcessorConfig,
+    mut call: Call,
+    talkgroup: Talkgroups,
+    language: Option<&str>,
+) -> Result<Stored> {
+    reveal(c, &Access::Admin, &mut call)?;
+    let filename = call.filename.clone();
+    let notice = match call.transcription.clone() {
+        Some(transcription) => Some(Notice {
+            transcription,
+            audio: c
+                .s3_client
+                .get(&Path::parse(&filename)?)
+                .await?
+                .bytes()
+                .await?,
+            language: transcribe::language(c, talkgroup.talkgroup, language).to_string(),
+            keywords: words::stored_keyword_hits(c, &filename),
+            segments: segments::stored(c, &filename),
+        }),
+        None => None,
+    };
+    Ok(Stored {
+        meta: load_metadata(c, call, talkgroup)?,
+        discovered: false,
+        notice,
+    })
+}
+
+// Loads a call stored by a job that failed afterwards, so retrying the job
+// only notifies it
+pub async fn load_stored(
+    c: &ProcessorConfig,
+    filename: &str,
+    language: Option<&str>,
+) -> Result<Stored> {
+    let (call, talkgroup) = {
+        let mut connection = c
+            .db_pool
+            .get()
+            .map_err(|e| Error::Database(e.to_string()))?;
+        calls::table
+            .inner_join(talkgroups::table)
+            .filter(calls::filename.eq(filename))
+            .select((Call::as_select(), Talkgroups::as_select()))
+            .first::<(Call, Talkgroups)>(&mut connection)
+            .optional()
+            .map_err(|e| Error::Database(e.to_string()))?
+            .ok_or_else(|| Error::NotFound(format!("call {}", filename)))?
+    };
+    stored(c, call, talkgroup, language).await
+}
+
+// (call, destination) pairs with a notification key
+type Claimed = HashSet<(String, String)>;
+
+// Sends one stored call to each pending destination, returning which succeeded
+async fn deliver(
+    c: &ProcessorConfig,
+    call: Call,
+    talkgroup: Talkgroups,
+    pending: &[&str],
+) -> Result<Vec<bool>> {
+    let stored = stored(c, call, talkgroup, None).await?;
+    let Some(notice) = &stored.notice else {
+        return Ok(vec![false; pending.len()]);
+    };
+    let filename = &stored.meta.call.filename;
+    let audio = notice.audio(filename);
+    let audio_url = audio::notification_url(c, filename).await;
+    let delivery = Delivery {
+        meta: &stored.meta,
+        transcription: &notice.transcription,
+        audio: &audio,
+        audio_url: audio_url.as_deref(),
+        keywords: &notice.keywords,
+        segments: &notice.segments,
+    };
+
+    let notifiers = c.notifiers.load_full();
+    let results = join_all(
+        pending
+            .iter()
+            .map(|name| notifiers.dispatch_to(c, &delivery, std::slice::from_ref(name))),
+    )
+    .await;
+    Ok(results.iter().map(Result::is_ok).collect())
+}
+
+// Transcribed calls in the window, oldest first, with the destinations that
+// already hold a notification key for them
+fn load_calls(
+    c: &ProcessorConfig,
+    since: DateTime<Utc>,
+    until: Option<DateTime<Utc>>,
+    limit: i64,
+) -> Result<(Vec<(Call, Talkgroups)>, Claimed)> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = calls::table
+        .inner_join(talkgroups::table)
+        .filter(calls::start_time.ge(since))
+        .filter(calls::archive_reason.is_null())
+        .filter(calls::transcription.is_not_null())
+        .select((Call::as_select(), Talkgroups::as_select()))
+        .order(calls::start_time.asc())
+        .limit(limit)
+        .into_boxed();
+    if let Some(until) = until {
+        query = query.filter(calls::start_time.lt(until));
+    }
+    let rows: Vec<(Call, Talkgroups)> = query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let claimed = notification_keys::table
+        .filter(notification_keys::call_id.eq_any(rows.iter().map(|(call, _)| &call.filename)))
+        .select((notification_keys::call_id, notification_keys::destination))
+        .load::<(String, String)>(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    Ok((rows, claimed.into_iter().collect()))
+}
+
+// Delivers stored calls to destinations added since they were processed, such
+// as a new per-group webhook. A destination that already claimed a call (see
+// notifications::send) is left alone, so nothing is sent to it twice.
+pub async fn renotify(
+    c: &ProcessorConfig,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    destinations: Option<&str>,
+    dry_run: bool,
+    limit: Option<i64>,
+) -> Result<RenotifyReport> {
+    let start = Instant::now();
+    let notifiers = c.notifiers.load_full();
+    let registered = notifiers.names();
+    let names: Vec<&str> = match destinations {
+        Some(list) => list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                registered
+                    .iter()
+                    .find(|r| **r == name)
+                    .copied()
+                    .ok_or_else(|| Error::InvalidRequest(format!("unknown destination {}", name)))
+            })
+            .collect::<Result<_>>()?,
+        None => registered,
+    };
+    let enabled = c.switches.notifications() && c.switches.database();
+    if !dry_run && !enabled {
+        return Err(Error::Unavailable(
+            "notifications and database writes must be enabled to re-notify".to_string(),
+        ));
+    }
+
+    let since = since.unwrap_or_else(|| Utc::now() - DEFAULT_WINDOW);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let (rows, claimed) = load_calls(c, since, until, limit)?;
+    info!(calls = rows.len(), destinations = ?names, dry_run, "Starting re-notification");
+
+    let mut report = RenotifyReport {
+        since,
+        dry_run,
+        duration_ms: 0,
+        calls: rows.len(),
+        destinations: names
+            .iter()
+            .map(|name| DestinationReport {
+                destination: name.to_string(),
+                already_sent: 0,
+                pending: 0,
+                failed: 0,
+            })
+            .collect(),
+    };
+
+    for (call, talkgroup) in rows {
+        let mut pending = Vec::new();
+        for (name, entry) in names.iter().zip(report.destinations.iter_mut()) {
+            let accepts = notifiers.config(name).is_some_and(|config| {
+                config.accepts_talkgroup(talkgroup.talkgroup, &talkgroup.talkgroup_group)
+                    || config.samples(&call.filename)
+            });
+            if !accepts {
+                continue;
+            }
+            if claimed.contains(&(call.filename.clone(), name.to_string())) {
+                entry.already_sent += 1;
+                continue;
+            }
+            entry.pending += 1;
+            pending.push(*name);
+        }
+        if dry_run || pending.is_empty() {
+            continue;
+        }
+
+        let filename = call.filename.clone();
+        let results = match deliver(c, call, talkgroup, &pending).await {
+            Ok(results) => results,
+            Err(e) => {
+                warn!(file = %filename, error = %e, "Failed to load call for re-notification");
+                vec![false; pending.len()]
+            }
+        };
+        for (name, sent) in pending.iter().zip(results) {
+            if !sent
+                && let Some(entry) = report
+                    .destinations
+                    .iter_mut()
+                    .find(|d| d.destination == *name)
+            {
+                entry.failed += 1;
+            }
+        }
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    for entry in &report.destinations {
+        if entry.failed > 0 {
+            warn!(destination = %entry.destination, failed = entry.failed, "Some re-notifications failed");
+        }
+    }
+    info!(
+        duration_ms = report.duration_ms,
+        dry_run, "Re-notification finished"
+    );
+    Ok(report)
+}
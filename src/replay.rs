@@ -0,0 +1,51 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+type ReplayKey = (String, String);
+
+#[derive(Debug)]
+pub struct ReplayCache {
+    window: Duration,
+    capacity: usize,
+    seen: HashMap<ReplayKey, Instant>,
+    order: VecDeque<(Instant, ReplayKey)>,
+}
+
+impl ReplayCache {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        ReplayCache {
+            window,
+            capacity,
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn evict(&mut self) {
+        while let Some((seen_at, key)) = self.order.front() {
+            if seen_at.elapsed() < self.window && self.order.len() <= self.capacity {
+                break;
+            }
+            if self.seen.get(key) == Some(seen_at) {
+                self.seen.remove(key);
+            }
+            self.order.pop_front();
+        }
+    }
+
+    pub fn contains(&mut self, filename: &str, checksum: &str) -> bool {
+        self.evict();
+        self.seen
+            .contains_key(&(filename.to_string(), checksum.to_string()))
+    }
+
+    pub fn insert(&mut self, filename: String, checksum: String) {
+        let now = Instant::now();
+        let key = (filename, checksum);
+        self.seen.insert(key.clone(), now);
+        self.order.push_back((now, key));
+        self.evict();
+    }
+}
@@ -0,0 +1,298 @@
+use crate::audit;
+use crate::auth::require_private_read;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Call, Talkgroups};
+use crate::query::{context_window, load_context, reveal};
+use crate::schema::{sources, srclist, talkgroups};
+use crate::workspace::Workspace;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method, Uri, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use tokio::process::Command;
+use tracing::info;
+
+// Used unless REPORT_TEMPLATE names another file with the same placeholders
+const DEFAULT_TEMPLATE: &str = include_str!("../templates/incident_report.html");
+
+#[derive(Debug, Deserialize)]
+pub struct IncidentReportParams {
+    window: Option<String>,
+    format: Option<String>,
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+// Replaces each {{name}} with its value, already escaped. Unknown names are
+// left as they are so a mistyped placeholder shows up in the output.
+fn render(template: &str, values: &[(&str, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        match values.iter().find(|(name, _)| *name == after[..end].trim()) {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// Each call's units in the order they keyed up, with their unit tags
+fn units(c: &ProcessorConfig, calls: &[Call]) -> Result<HashMap<String, Vec<String>>> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let ids: Vec<&str> = calls.iter().map(|c| c.filename.as_str()).collect();
+    let rows: Vec<(String, i32, Option<String>)> = srclist::table
+        .left_join(sources::table)
+        .filter(srclist::call_id.eq_any(&ids))
+        .select((srclist::call_id, srclist::src, sources::tag.nullable()))
+        .order((srclist::call_id, srclist::time, srclist::pos))
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut units: HashMap<String, Vec<String>> = HashMap::new();
+    for (call, src, tag) in rows {
+        let unit = match tag.filter(|t| !t.is_empty()) {
+            Some(tag) => format!("{} ({})", src, tag),
+            None => src.to_string(),
+        };
+        let list = units.entry(call).or_default();
+        if !list.contains(&unit) {
+            list.push(unit);
+        }
+    }
+    Ok(units)
+}
+
+fn talkgroups(c: &ProcessorConfig, calls: &[Call]) -> Result<HashMap<i32, Talkgroups>> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let ids: BTreeSet<i32> = calls.iter().map(|c| c.talkgroup).collect();
+    Ok(talkgroups::table
+        .filter(talkgroups::talkgroup.eq_any(ids))
+        .select(Talkgroups::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?
+        .into_iter()
+        .map(|tg| (tg.talkgroup, tg))
+        .collect())
+}
+
+fn talkgroup_name(talkgroup: i32, tgs: &HashMap<i32, Talkgroups>) -> String {
+    match tgs.get(&talkgroup) {
+        Some(tg) if !tg.has_default_metadata() => format!("{} {}", talkgroup, tg.talkgroup_tag),
+        _ => talkgroup.to_string(),
+    }
+}
+
+fn row(
+    call: &Call,
+    anchor: &str,
+    tgs: &HashMap<i32, Talkgroups>,
+    units: &HashMap<String, Vec<String>>,
+) -> String {
+    let mut classes = Vec::new();
+    if call.filename == anchor {
+        classes.push("anchor");
+    }
+    if call.emergency {
+        classes.push("emergency");
+    }
+    let transcript = match (&call.transcription, call.archive_reason) {
+        (Some(text), _) if !text.trim().is_empty() => escape(text.trim()),
+        (_, Some(reason)) => format!("<span class=\"muted\">archived: {}</span>", reason.as_str()),
+        (Some(_), None) => "<span class=\"muted\">no speech</span>".to_string(),
+        (None, None) => "<span class=\"muted\">not transcribed</span>".to_string(),
+    };
+    let units = units
+        .get(&call.filename)
+        .map(|u| escape(&u.join(", ")))
+        .unwrap_or_default();
+    let emergency = if call.emergency { " EMERGENCY" } else { "" };
+
+    let class = if classes.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"{}\"", classes.join(" "))
+    };
+
+    format!(
+        "<tr{}><td>{}{}</td><td>{}s</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        class,
+        escape(&timestamp(call.start_time)),
+        emergency,
+        call.call_length,
+        escape(&talkgroup_name(call.talkgroup, tgs)),
+        units,
+        transcript
+    )
+}
+
+async fn template(c: &ProcessorConfig) -> Result<String> {
+    match &c.env.report_template {
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::Configuration(format!("REPORT_TEMPLATE {}: {}", path, e))),
+        None => Ok(DEFAULT_TEMPLATE.to_string()),
+    }
+}
+
+async fn pdf(c: &ProcessorConfig, renderer: &str, html: &str) -> Result<Vec<u8>> {
+    let workspace = Workspace::create(c, "report").await?;
+    let input = workspace.write("report.html", html.as_bytes()).await?;
+    let output = workspace.file("report.pdf")?;
+
+    let result = workspace
+        .run(Command::new(renderer).arg(&input).arg(&output))
+        .await?
+        .map_err(|e| Error::Configuration(format!("failed to run {}: {}", renderer, e)))?;
+    if !result.status.success() {
+        return Err(Error::Configuration(format!(
+            "{} exited with {}: {}",
+            renderer,
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    workspace.read("report.pdf").await
+}
+
+// A printable report of a call and its context (see /calls/{id}/context) for
+// records requests: every call's time, length, talkgroup, units and
+// transcript. HTML by default, or ?format=pdf through PDF_RENDERER. Not
+// available in public mode.
+pub async fn incident_report(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+    Query(params): Query<IncidentReportParams>,
+) -> Result<Response> {
+    let access = require_private_read(&headers, &config)?;
+    let renderer = match params.format.as_deref() {
+        None | Some("html") => None,
+        Some("pdf") => Some(config.env.pdf_renderer.as_deref().ok_or_else(|| {
+            Error::InvalidRequest("PDF reports need PDF_RENDERER to be set".to_string())
+        })?),
+        Some(other) => {
+            return Err(Error::InvalidRequest(format!(
+                "format must be html or pdf, got {:?}",
+                other
+            )));
+        }
+    };
+    let window = context_window(params.window.as_deref())?;
+
+    let (call, context) = load_context(&config, &access, &id, window)?;
+    let anchor = call.filename.clone();
+    let mut calls = context;
+    calls.push(call);
+    calls.sort_by_key(|c| c.start_time);
+
+    audit::record(
+        &config,
+        &access,
+        &method,
+        &uri,
+        calls.iter().map(|c| c.filename.clone()),
+    );
+    for call in calls.iter_mut() {
+        reveal(&config, &access, call)?;
+    }
+
+    let tgs = talkgroups(&config, &calls)?;
+    let units = units(&config, &calls)?;
+    let rows: Vec<String> = calls
+        .iter()
+        .map(|call| row(call, &anchor, &tgs, &units))
+        .collect();
+    let names: BTreeSet<String> = calls
+        .iter()
+        .map(|call| talkgroup_name(call.talkgroup, &tgs))
+        .collect();
+    let systems: BTreeSet<&str> = calls.iter().map(|c| c.short_name.as_str()).collect();
+    let (first, last) = (calls[0].start_time, calls[calls.len() - 1].stop_time);
+
+    let html = render(
+        &template(&config).await?,
+        &[
+            ("title", escape(&format!("Incident report: {}", anchor))),
+            ("call", escape(&anchor)),
+            (
+                "system",
+                escape(&systems.into_iter().collect::<Vec<_>>().join(", ")),
+            ),
+            (
+                "talkgroups",
+                escape(&names.into_iter().collect::<Vec<_>>().join(", ")),
+            ),
+            (
+                "period",
+                escape(&format!("{} to {}", timestamp(first), timestamp(last))),
+            ),
+            ("call_count", calls.len().to_string()),
+            ("generated_at", escape(&timestamp(Utc::now()))),
+            ("rows", rows.join("\n")),
+        ],
+    );
+    info!(call = %id, calls = calls.len(), format = if renderer.is_some() { "pdf" } else { "html" }, "Rendered incident report");
+
+    let name = id.rsplit('/').next().unwrap_or(&id);
+    let name = name.strip_suffix(".m4a").unwrap_or(name);
+    Ok(match renderer {
+        Some(renderer) => (
+            [
+                (header::CONTENT_TYPE, "application/pdf".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}-report.pdf\"", name),
+                ),
+            ],
+            pdf(&config, renderer, &html).await?,
+        )
+            .into_response(),
+        None => (
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8".to_string())],
+            html,
+        )
+            .into_response(),
+    })
+}
@@ -0,0 +1,364 @@
+use crate::audit;
+use crate::common::parse_duration;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::notifier::StorageEvent;
+use crate::schema::{
+    calls, compressed_transcriptions, emergencies, freqlist, notification_keys, notifications,
+    srclist, talkgroups, transcript_segments, transcript_words, upload_checksums,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{delete, prelude::*};
+use object_store::{ObjectStore, path::Path};
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, time::Duration, time::Instant};
+use tracing::{error, info, warn};
+
+// Calls are examined, and deleted, this many at a time
+const BATCH_SIZE: i64 = 500;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CallClass {
+    All,
+    Emergency,
+    Transcribed,
+    Untranscribed,
+    Archived,
+    Talkgroup(i32),
+    Group(String),
+}
+
+// A RETENTION_POLICIES entry: calls of `class` are deleted once older than
+// `keep`, or kept forever when it is None
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    pub class: CallClass,
+    pub keep: Option<TimeDelta>,
+}
+
+impl fmt::Display for CallClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallClass::All => write!(f, "all"),
+            CallClass::Emergency => write!(f, "emergency"),
+            CallClass::Transcribed => write!(f, "transcribed"),
+            CallClass::Untranscribed => write!(f, "untranscribed"),
+            CallClass::Archived => write!(f, "archived"),
+            CallClass::Talkgroup(tg) => write!(f, "talkgroup:{}", tg),
+            CallClass::Group(group) => write!(f, "group:{}", group),
+        }
+    }
+}
+
+fn parse_class(s: &str) -> Result<CallClass> {
+    let invalid = || Error::Configuration(format!("unknown call class {:?}", s));
+    Ok(match s.split_once(':') {
+        Some(("talkgroup", tg)) => CallClass::Talkgroup(tg.trim().parse().map_err(|_| invalid())?),
+        Some(("group", group)) if !group.trim().is_empty() => {
+            CallClass::Group(group.trim().to_string())
+        }
+        Some(_) => return Err(invalid()),
+        None => match s {
+            "all" => CallClass::All,
+            "emergency" => CallClass::Emergency,
+            "transcribed" => CallClass::Transcribed,
+            "untranscribed" => CallClass::Untranscribed,
+            "archived" => CallClass::Archived,
+            _ => return Err(invalid()),
+        },
+    })
+}
+
+// Entries are class=age, where age is a number of days, a suffixed duration
+// or `keep`. A call follows the first entry its class matches, and calls that
+// match none are kept.
+pub fn parse(entries: &[String]) -> Result<Vec<RetentionPolicy>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (class, age) = entry.split_once('=').ok_or_else(|| {
+                Error::Configuration(format!("expected class=age, got {:?}", entry))
+            })?;
+            let age = age.trim();
+            let keep = match age {
+                "keep" => None,
+                age => Some(
+                    match age.parse::<i64>() {
+                        Ok(days) => TimeDelta::try_days(days),
+                        Err(_) => parse_duration(age).ok(),
+                    }
+                    .filter(|d| *d > TimeDelta::zero())
+                    .ok_or_else(|| Error::Configuration(format!("invalid age {:?}", age)))?,
+                ),
+            };
+            Ok(RetentionPolicy {
+                class: parse_class(class.trim())?,
+                keep,
+            })
+        })
+        .collect()
+}
+
+// What the policies are matched against, without loading the whole call
+#[derive(Debug, Queryable)]
+struct Candidate {
+    filename: String,
+    start_time: DateTime<Utc>,
+    emergency: bool,
+    transcribed: bool,
+    archived: bool,
+    talkgroup: i32,
+    group: String,
+}
+
+impl Candidate {
+    fn is(&self, class: &CallClass) -> bool {
+        match class {
+            CallClass::All => true,
+            CallClass::Emergency => self.emergency,
+            CallClass::Transcribed => self.transcribed,
+            CallClass::Untranscribed => !self.transcribed,
+            CallClass::Archived => self.archived,
+            CallClass::Talkgroup(tg) => self.talkgroup == *tg,
+            CallClass::Group(group) => &self.group == group,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub examined: usize,
+    // deleted, or that would be on a dry run
+    pub removed: usize,
+    // stored objects deleted with them, and their size
+    pub objects: usize,
+    pub bytes: u64,
+    pub by_policy: BTreeMap<String, usize>,
+    pub failed: Vec<String>,
+    pub duration_ms: u128,
+}
+
+fn candidates(
+    c: &ProcessorConfig,
+    cutoff: DateTime<Utc>,
+    after: Option<&(DateTime<Utc>, String)>,
+) -> Result<Vec<Candidate>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = calls::table
+        .inner_join(talkgroups::table)
+        .filter(calls::start_time.lt(cutoff))
+        .select((
+            calls::filename,
+            calls::start_time,
+            calls::emergency,
+            calls::transcription.is_not_null(),
+            calls::archive_reason.is_not_null(),
+            calls::talkgroup,
+            talkgroups::talkgroup_group,
+        ))
+        .order((calls::start_time.asc(), calls::filename.asc()))
+        .limit(BATCH_SIZE)
+        .into_boxed();
+    if let Some((time, name)) = after {
+        query = query.filter(
+            calls::start_time.gt(*time).or(calls::start_time
+                .eq(*time)
+                .and(calls::filename.gt(name.clone()))),
+        );
+    }
+
+    query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// The metadata JSON is stored beside the audio under the same stem
+fn objects(filename: &str) -> Result<[Path; 2]> {
+    let stem = filename
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .unwrap_or(filename);
+    Ok([
+        Path::parse(filename)?,
+        Path::parse(format!("{}.json", stem))?,
+    ])
+}
+
+// Storage first, so a failure leaves the row for the next run rather than a
+// row pointing at nothing. Returns how many objects were deleted and their
+// total size.
+async fn delete_objects(c: &ProcessorConfig, filename: &str) -> Result<(usize, u64)> {
+    let (mut count, mut bytes) = (0, 0);
+    for location in objects(filename)? {
+        let size = match c.s3_client.head(&location).await {
+            Ok(meta) => meta.size,
+            Err(object_store::Error::NotFound { .. }) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        match c.s3_client.delete(&location).await {
+            Ok(()) => {
+                count += 1;
+                bytes += size;
+            }
+            Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((count, bytes))
+}
+
+// Deletes the calls and every row that refers to them, in one transaction.
+// Shared with reconcile, which drops the rows of calls whose audio is gone.
+pub fn delete_rows(c: &ProcessorConfig, filenames: &[String]) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    connection
+        .transaction(|conn| {
+            delete(srclist::table.filter(srclist::call_id.eq_any(filenames))).execute(conn)?;
+            delete(freqlist::table.filter(freqlist::call_id.eq_any(filenames))).execute(conn)?;
+            delete(emergencies::table.filter(emergencies::call_id.eq_any(filenames)))
+                .execute(conn)?;
+            delete(notifications::table.filter(notifications::call_id.eq_any(filenames)))
+                .execute(conn)?;
+            delete(notification_keys::table.filter(notification_keys::call_id.eq_any(filenames)))
+                .execute(conn)?;
+            delete(upload_checksums::table.filter(upload_checksums::call_id.eq_any(filenames)))
+                .execute(conn)?;
+            delete(transcript_words::table.filter(transcript_words::call_id.eq_any(filenames)))
+                .execute(conn)?;
+            delete(
+                compressed_transcriptions::table
+                    .filter(compressed_transcriptions::call_id.eq_any(filenames)),
+            )
+            .execute(conn)?;
+            delete(
+                transcript_segments::table.filter(transcript_segments::call_id.eq_any(filenames)),
+            )
+            .execute(conn)?;
+            delete(calls::table.filter(calls::filename.eq_any(filenames))).execute(conn)?;
+            diesel::result::QueryResult::Ok(())
+        })
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Applies RETENTION_POLICIES once. A dry run reports what would be deleted
+// without touching storage or the database.
+pub async fn run(c: &ProcessorConfig, dry_run: bool) -> Result<RetentionReport> {
+    let start = Instant::now();
+    let mut report = RetentionReport {
+        dry_run,
+        ..Default::default()
+    };
+    let policies = &c.retention;
+    // nothing younger than the shortest age can be deleted
+    let Some(shortest) = policies.iter().filter_map(|p| p.keep).min() else {
+        return Ok(report);
+    };
+    if !dry_run && !c.switches.database() {
+        return Err(Error::Unavailable(
+            "database writes are disabled".to_string(),
+        ));
+    }
+    info!(dry_run, policies = policies.len(), "Starting retention run");
+
+    let now = Utc::now();
+    let mut after = None;
+    loop {
+        let batch = candidates(c, now - shortest, after.as_ref())?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        after = Some((last.start_time, last.filename.clone()));
+        report.examined += batch.len();
+
+        let mut doomed = Vec::new();
+        for call in &batch {
+            let Some(policy) = policies.iter().find(|p| call.is(&p.class)) else {
+                continue;
+            };
+            if policy.keep.is_none_or(|keep| call.start_time >= now - keep) {
+                continue;
+            }
+            *report
+                .by_policy
+                .entry(policy.class.to_string())
+                .or_default() += 1;
+            if dry_run {
+                report.removed += 1;
+                continue;
+            }
+            match delete_objects(c, &call.filename).await {
+                Ok((objects, bytes)) => {
+                    report.objects += objects;
+                    report.bytes += bytes;
+                    doomed.push(call.filename.clone());
+                }
+                Err(e) => {
+                    warn!(file = %call.filename, error = %e, "Failed to delete expired call audio");
+                    report.failed.push(call.filename.clone());
+                }
+            }
+        }
+        if doomed.is_empty() {
+            continue;
+        }
+
+        match delete_rows(c, &doomed) {
+            Ok(()) => {
+                report.removed += doomed.len();
+                audit::record_task(c, "retention", "DELETE", "retention", doomed);
+            }
+            Err(e) => {
+                error!(calls = doomed.len(), error = %e, "Failed to delete expired calls");
+                report.failed.extend(doomed);
+            }
+        }
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        dry_run,
+        examined = report.examined,
+        removed = report.removed,
+        bytes = report.bytes,
+        failed = report.failed.len(),
+        duration_ms = report.duration_ms,
+        "Retention run completed"
+    );
+    if !dry_run && (report.removed > 0 || !report.failed.is_empty()) {
+        let event = StorageEvent {
+            event: "retention.deleted",
+            calls: report.removed,
+            objects: report.objects,
+            bytes: report.bytes,
+            by_policy: report.by_policy.clone(),
+            failed: report.failed.len(),
+            time: Utc::now(),
+        };
+        c.notifiers.load_full().announce(c, &event).await;
+    }
+    Ok(report)
+}
+
+pub async fn run_periodic(c: ProcessorConfig, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+        if !c.switches.database() {
+            continue;
+        }
+        if let Err(e) = run(&c, c.env.retention_dry_run).await {
+            error!(error = %e, "Retention run failed");
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::compression;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::schema::calls;
+use crate::scrub::scrub;
+use crate::segments;
+use crate::transcribe::{self, Audio, Transcript};
+use crate::words;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{prelude::*, update};
+use object_store::{ObjectStore, path::Path};
+use serde::Serialize;
+use std::time::Instant;
+use tracing::{info, warn};
+
+const DEFAULT_WINDOW: TimeDelta = TimeDelta::hours(24);
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+// Which calls to transcribe again: one file, or a talkgroup and time range
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub file: Option<String>,
+    pub talkgroup: Option<i32>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    // only calls whose transcription failed when they were uploaded
+    pub failed: bool,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RetranscribeReport {
+    pub dry_run: bool,
+    pub duration_ms: u128,
+    // calls selected, which are transcribed unless this is a dry run
+    pub calls: usize,
+    pub transcribed: usize,
+    pub failed: Vec<String>,
+}
+
+// Each call with its talkgroup and detected language. Archived calls were
+// never meant to be transcribed, so they are left out.
+fn load_calls(
+    c: &ProcessorConfig,
+    selection: &Selection,
+) -> Result<Vec<(String, i32, Option<String>)>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = calls::table
+        .filter(calls::archive_reason.is_null())
+        .select((calls::filename, calls::talkgroup, calls::detected_language))
+        .order(calls::start_time.asc())
+        .into_boxed();
+    if let Some(file) = &selection.file {
+        query = query.filter(calls::filename.eq(file));
+    } else {
+        let since = selection
+            .since
+            .unwrap_or_else(|| Utc::now() - DEFAULT_WINDOW);
+        query = query
+            .filter(calls::start_time.ge(since))
+            .limit(selection.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT));
+        if let Some(until) = selection.until {
+            query = query.filter(calls::start_time.lt(until));
+        }
+        if let Some(talkgroup) = selection.talkgroup {
+            query = query.filter(calls::talkgroup.eq(talkgroup));
+        }
+        if selection.failed {
+            query = query.filter(calls::transcription_failed);
+        }
+    }
+
+    query
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+fn store(c: &ProcessorConfig, filename: &str, transcript: Transcript) -> Result<()> {
+    let mut public = c.env.pii_scrubbing.then(|| scrub(&transcript.text));
+    let mut text = Some(transcript.text.clone());
+    let compressed = compression::compress(c, filename, &mut text, &mut public)?;
+    if let Some(cipher) = &c.transcript_cipher {
+        text = text.map(|t| cipher.seal(&t, filename)).transpose()?;
+        public = public.map(|p| cipher.seal(&p, filename)).transpose()?;
+    }
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let detected = transcript.detected.as_ref();
+    connection
+        .transaction(|conn| {
+            update(calls::table.find(filename))
+                .set((
+                    calls::transcription.eq(text),
+                    calls::public_transcription.eq(public),
+                    calls::detected_language.eq(detected.map(|d| d.code.clone())),
+                    calls::language_probability.eq(detected.and_then(|d| d.probability)),
+                    calls::language_fallback.eq(transcript.fallback),
+                    calls::transcription_failed.eq(false),
+                ))
+                .execute(conn)?;
+            compression::store(conn, filename, compressed.as_ref())
+        })
+        .map_err(|e| Error::Database(e.to_string()))?;
+    words::store(c, filename, &transcript.words)?;
+    segments::resplit(c, filename, &transcript.words)
+}
+
+async fn retranscribe_call(c: &ProcessorConfig, filename: &str, language: &str) -> Result<()> {
+    let data = c
+        .s3_client
+        .get(&Path::parse(filename)?)
+        .await?
+        .bytes()
+        .await?;
+    let audio = Audio {
+        name: filename.rsplit('/').next().unwrap_or(filename),
+        data,
+        language,
+    };
+    // straight to the provider: the cache would hand back the old transcription
+    let transcript = c
+        .pools
+        .transcription
+        .run(transcribe::transcribe(c, &audio))
+        .await?;
+    store(c, filename, transcript)
+}
+
+// Fetches stored calls' audio back and transcribes it again with the current
+// provider and model, replacing the transcription. Notifications are not sent
+// again.
+pub async fn retranscribe(
+    c: &ProcessorConfig,
+    selection: &Selection,
+    dry_run: bool,
+) -> Result<RetranscribeReport> {
+    let start = Instant::now();
+    if selection.file.is_some()
+        && (selection.talkgroup.is_some() || selection.since.is_some() || selection.until.is_some())
+    {
+        return Err(Error::InvalidRequest(
+            "file cannot be combined with talkgroup, since or until".to_string(),
+        ));
+    }
+    let enabled = c.switches.transcription() && c.switches.database();
+    if !dry_run && !enabled {
+        return Err(Error::Unavailable(
+            "transcription and database writes must be enabled to re-transcribe".to_string(),
+        ));
+    }
+
+    let rows = load_calls(c, selection)?;
+    if let Some(file) = &selection.file
+        && rows.is_empty()
+    {
+        return Err(Error::NotFound(format!(
+            "no transcribable call named {}",
+            file
+        )));
+    }
+    info!(calls = rows.len(), dry_run, "Starting re-transcription");
+
+    let mut report = RetranscribeReport {
+        dry_run,
+        duration_ms: 0,
+        calls: rows.len(),
+        transcribed: 0,
+        failed: Vec::new(),
+    };
+    if !dry_run {
+        for (filename, talkgroup, detected) in rows {
+            let language = detected
+                .as_deref()
+                .unwrap_or_else(|| transcribe::language(c, talkgroup, None));
+            match retranscribe_call(c, &filename, language).await {
+                Ok(()) => report.transcribed += 1,
+                Err(e) => {
+                    warn!(file = %filename, error = %e, "Failed to re-transcribe call");
+                    report.failed.push(filename);
+                }
+            }
+        }
+    }
+
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        transcribed = report.transcribed,
+        failed = report.failed.len(),
+        duration_ms = report.duration_ms,
+        dry_run,
+        "Re-transcription finished"
+    );
+    Ok(report)
+}
@@ -1,14 +1,38 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "archivereason"))]
+    pub struct Archivereason;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "audiotype"))]
     pub struct Audiotype;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "deliverytype"))]
+    pub struct Deliverytype;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "jobstage"))]
+    pub struct Jobstage;
+}
+
+diesel::table! {
+    access_log (id) {
+        id -> Int8,
+        accessed_at -> Timestamptz,
+        principal -> Varchar,
+        method -> Varchar,
+        endpoint -> Varchar,
+        call_ids -> Array<Nullable<Text>>,
+    }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::Audiotype;
+    use super::sql_types::Archivereason;
 
     calls (filename) {
         filename -> Varchar,
@@ -32,6 +56,78 @@ diesel::table! {
         audio_type -> Audiotype,
         short_name -> Varchar,
         transcription -> Nullable<Varchar>,
+        patched_talkgroups -> Array<Nullable<Int4>>,
+        public_transcription -> Nullable<Varchar>,
+        archive_reason -> Nullable<Archivereason>,
+        tags -> Jsonb,
+        filter_action -> Nullable<Varchar>,
+        filter_rule -> Nullable<Varchar>,
+        detected_language -> Nullable<Varchar>,
+        language_probability -> Nullable<Float4>,
+        language_fallback -> Bool,
+        transcription_failed -> Bool,
+    }
+}
+
+diesel::table! {
+    compressed_transcriptions (call_id) {
+        call_id -> Varchar,
+        transcription -> Bytea,
+        public_transcription -> Nullable<Bytea>,
+    }
+}
+
+diesel::table! {
+    discord_routes (id) {
+        id -> Int4,
+        talkgroup -> Nullable<Int4>,
+        talkgroup_group -> Nullable<Varchar>,
+        webhook_url -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    discovered_talkgroups (talkgroup) {
+        talkgroup -> Int4,
+        short_name -> Varchar,
+        first_seen -> Timestamptz,
+        last_seen -> Timestamptz,
+        call_count -> Int4,
+    }
+}
+
+diesel::table! {
+    emergencies (id) {
+        id -> Int8,
+        call_id -> Varchar,
+        talkgroup -> Int4,
+        src -> Nullable<Int4>,
+        short_name -> Varchar,
+        declared_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Archivereason;
+    use super::sql_types::Jobstage;
+
+    failed_jobs (id) {
+        id -> Int8,
+        json_name -> Varchar,
+        metadata -> Text,
+        audio_name -> Varchar,
+        audio_path -> Varchar,
+        tags -> Jsonb,
+        version -> Nullable<Varchar>,
+        language -> Nullable<Varchar>,
+        archive_reason -> Nullable<Archivereason>,
+        error -> Varchar,
+        attempts -> Int4,
+        failed_at -> Timestamptz,
+        next_attempt_at -> Nullable<Timestamptz>,
+        stage -> Jobstage,
     }
 }
 
@@ -45,6 +141,27 @@ diesel::table! {
         len -> Interval,
         error_count -> Int2,
         spike_count -> Int2,
+        hash_version -> Int2,
+    }
+}
+
+diesel::table! {
+    notification_keys (call_id, destination) {
+        call_id -> Varchar,
+        destination -> Varchar,
+        claimed_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    notifications (id) {
+        id -> Int8,
+        call_id -> Varchar,
+        destination -> Varchar,
+        payload -> Jsonb,
+        status -> Nullable<Int4>,
+        error -> Nullable<Varchar>,
+        sent_at -> Timestamptz,
     }
 }
 
@@ -64,6 +181,30 @@ diesel::table! {
         pos -> Interval,
         emergency -> Bool,
         signal_system -> Nullable<Varchar>,
+        hash_version -> Int2,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Deliverytype;
+
+    subscriptions (user_id, talkgroup) {
+        user_id -> Varchar,
+        talkgroup -> Int4,
+        delivery -> Deliverytype,
+        channel_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    systems (short_name) {
+        short_name -> Varchar,
+        system_type -> Varchar,
+        talkgroups_file -> Nullable<Varchar>,
+        unit_tags_file -> Nullable<Varchar>,
+        rdio_system_id -> Nullable<Int4>,
+        imported_at -> Timestamptz,
     }
 }
 
@@ -77,9 +218,83 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    transcript_segments (call_id, position) {
+        call_id -> Varchar,
+        position -> Int4,
+        src -> Int4,
+        tag -> Nullable<Varchar>,
+        start_ms -> Int4,
+        end_ms -> Int4,
+        text -> Varchar,
+    }
+}
+
+diesel::table! {
+    transcript_words (call_id, position) {
+        call_id -> Varchar,
+        position -> Int4,
+        word -> Varchar,
+        start_ms -> Int4,
+        end_ms -> Int4,
+    }
+}
+
+diesel::table! {
+    transcription_cache (sha256, provider, language) {
+        sha256 -> Varchar,
+        provider -> Varchar,
+        language -> Varchar,
+        transcription -> Text,
+        hits -> Int4,
+        created_at -> Timestamptz,
+        detected_language -> Nullable<Varchar>,
+        language_probability -> Nullable<Float4>,
+        language_fallback -> Bool,
+        words -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    upload_checksums (checksum) {
+        checksum -> Varchar,
+        call_id -> Varchar,
+        duplicates -> Int4,
+        last_duplicate_at -> Nullable<Timestamptz>,
+        stored_at -> Timestamptz,
+    }
+}
+
 diesel::joinable!(calls -> talkgroups (talkgroup));
+diesel::joinable!(compressed_transcriptions -> calls (call_id));
+diesel::joinable!(emergencies -> calls (call_id));
+diesel::joinable!(emergencies -> sources (src));
+diesel::joinable!(emergencies -> talkgroups (talkgroup));
 diesel::joinable!(freqlist -> calls (call_id));
 diesel::joinable!(srclist -> calls (call_id));
 diesel::joinable!(srclist -> sources (src));
+diesel::joinable!(transcript_segments -> calls (call_id));
+diesel::joinable!(transcript_words -> calls (call_id));
+diesel::joinable!(upload_checksums -> calls (call_id));
 
-diesel::allow_tables_to_appear_in_same_query!(calls, freqlist, sources, srclist, talkgroups,);
+diesel::allow_tables_to_appear_in_same_query!(
+    access_log,
+    calls,
+    compressed_transcriptions,
+    discord_routes,
+    discovered_talkgroups,
+    emergencies,
+    failed_jobs,
+    freqlist,
+    notification_keys,
+    notifications,
+    sources,
+    srclist,
+    subscriptions,
+    systems,
+    talkgroups,
+    transcript_segments,
+    transcript_words,
+    transcription_cache,
+    upload_checksums,
+);
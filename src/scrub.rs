@@ -0,0 +1,102 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+static PATTERNS: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        (
+            r"(?i)\b(?:dob|date of birth)\b[:\s]*\d{1,2}[/-]\d{1,2}[/-]\d{2,4}",
+            "[DOB]",
+        ),
+        (
+            r"(?:\(\d{3}\)|\b\d{3})[-.\s]?\d{3}[-.\s]?\d{4}\b",
+            "[PHONE]",
+        ),
+        (
+            r"(?i)\b\d{1,6}\s+(?:[a-z0-9]+\s+){0,3}(?:street|st|avenue|ave|road|rd|drive|dr|lane|ln|boulevard|blvd|court|ct|way|place|pl|circle|cir|highway|hwy|parkway|pkwy|terrace|trail)\b\.?",
+            "[ADDRESS]",
+        ),
+        // "tag" isn't a cue, as radio traffic says it of talkgroups and
+        // units, and a plate mixes letters and digits unless it is split in two
+        (
+            r"(?i)\b(plate|registration)((?:\s+(?:number|of|is))*)\s+(?:[a-z0-9-]*(?:[a-z][a-z0-9-]*\d|\d[a-z0-9-]*[a-z])[a-z0-9-]*|[a-z]{2,3}\s\d{3,4})\b",
+            "$1$2 [PLATE]",
+        ),
+        (
+            r"\b(?:Mr|Mrs|Ms|Miss|Dr)\.?\s+[A-Z][a-z]+(?:\s+[A-Z][a-z]+)?",
+            "[NAME]",
+        ),
+        (
+            r"\b((?i:name is|named|subject is|driver is|rp is))\s+[A-Z][a-z]+(?:\s+[A-Z][a-z]+)?",
+            "$1 [NAME]",
+        ),
+    ]
+    .into_iter()
+    .map(|(pattern, replacement)| {
+        (
+            Regex::new(pattern).expect("invalid PII pattern"),
+            replacement,
+        )
+    })
+    .collect()
+});
+
+pub fn scrub(text: &str) -> String {
+    PATTERNS
+        .iter()
+        .fold(text.to_string(), |acc, (pattern, replacement)| {
+            pattern.replace_all(&acc, *replacement).into_owned()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scrub;
+
+    #[test]
+    fn dates_of_birth() {
+        assert_eq!(scrub("DOB 04/12/1985 on file"), "[DOB] on file");
+        assert_eq!(scrub("date of birth: 4-12-85"), "[DOB]");
+        assert_eq!(scrub("arrived 04/12/1985"), "arrived 04/12/1985");
+    }
+
+    #[test]
+    fn phone_numbers() {
+        assert_eq!(scrub("callback (555) 123-4567"), "callback [PHONE]");
+        assert_eq!(scrub("call 555.123.4567 now"), "call [PHONE] now");
+        assert_eq!(scrub("unit 1234 to 5678"), "unit 1234 to 5678");
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(scrub("respond to 1234 Main Street"), "respond to [ADDRESS]");
+        assert_eq!(scrub("at 52 North Oak Ave."), "at [ADDRESS]");
+        assert_eq!(scrub("Main Street is closed"), "Main Street is closed");
+    }
+
+    #[test]
+    fn plates() {
+        assert_eq!(scrub("plate 7ABC123 stolen"), "plate [PLATE] stolen");
+        assert_eq!(
+            scrub("registration number is ABC 1234"),
+            "registration number is [PLATE]"
+        );
+        assert_eq!(scrub("tag 4521 en route"), "tag 4521 en route");
+        assert_eq!(scrub("talkgroup tag FD Disp"), "talkgroup tag FD Disp");
+        assert_eq!(scrub("plate is clear"), "plate is clear");
+        assert_eq!(scrub("registration 2024 due"), "registration 2024 due");
+    }
+
+    #[test]
+    fn titled_names() {
+        assert_eq!(scrub("Mr. John Smith is here"), "[NAME] is here");
+        assert_eq!(scrub("Dr Jones"), "[NAME]");
+        assert_eq!(scrub("mr smith"), "mr smith");
+    }
+
+    #[test]
+    fn introduced_names() {
+        assert_eq!(scrub("subject is Jane Doe"), "subject is [NAME]");
+        assert_eq!(scrub("RP is Bob"), "RP is [NAME]");
+        assert_eq!(scrub("subject is unknown"), "subject is unknown");
+    }
+}
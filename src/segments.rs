@@ -0,0 +1,178 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Source, SrcList, TranscriptSegment};
+use crate::schema::{sources, srclist, transcript_segments};
+use crate::transcribe::Word;
+
+use diesel::{delete, insert_into, prelude::*};
+use serde::Serialize;
+use tracing::warn;
+
+// What one radio said, from when its transmission started in the call until
+// the next radio's did
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Segment {
+    pub src: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub start_ms: i32,
+    pub end_ms: i32,
+    pub text: String,
+}
+
+impl Segment {
+    // "1001 (Engine 1)"
+    pub fn speaker(&self) -> String {
+        match &self.tag {
+            Some(tag) => format!("{} ({})", self.src, tag),
+            None => self.src.to_string(),
+        }
+    }
+}
+
+// Gives each word to the transmission under way when it was said, by the
+// srcList offsets. Words before the first offset go to the first radio, and
+// back-to-back transmissions from one radio are joined.
+pub fn split(
+    c: &ProcessorConfig,
+    src_list: &[SrcList],
+    sources: &[Source],
+    words: &[Word],
+) -> Vec<Segment> {
+    if !c.env.speaker_segments || src_list.is_empty() {
+        return Vec::new();
+    }
+    let mut transmissions: Vec<(i64, i32)> = src_list
+        .iter()
+        .map(|s| (s.pos.num_milliseconds(), s.src))
+        .collect();
+    transmissions.sort();
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for word in words {
+        let at = transmissions.partition_point(|(pos, _)| *pos <= i64::from(word.start_ms));
+        let src = transmissions[at.saturating_sub(1)].1;
+        match segments.last_mut() {
+            Some(segment) if segment.src == src => {
+                segment.text.push(' ');
+                segment.text.push_str(word.word.trim());
+                segment.end_ms = word.end_ms;
+            }
+            _ => segments.push(Segment {
+                src,
+                tag: sources
+                    .iter()
+                    .find(|s| s.src == src)
+                    .and_then(|s| s.tag.clone()),
+                start_ms: word.start_ms,
+                end_ms: word.end_ms,
+                text: word.word.trim().to_string(),
+            }),
+        }
+    }
+    segments
+}
+
+// One line per transmission, for the embed's transcription
+pub fn render(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|s| format!("{}: {}", s.speaker(), s.text))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Replaces the segments stored for a call, kept back with TRANSCRIPTION_KEY
+// as the words are
+pub fn store(c: &ProcessorConfig, filename: &str, segments: &[Segment]) -> Result<()> {
+    if c.transcript_cipher.is_some() || !c.switches.database() {
+        return Ok(());
+    }
+    let rows: Vec<TranscriptSegment> = segments
+        .iter()
+        .enumerate()
+        .map(|(position, segment)| TranscriptSegment {
+            call_id: filename.to_string(),
+            position: position as i32,
+            src: segment.src,
+            tag: segment.tag.clone(),
+            start_ms: segment.start_ms,
+            end_ms: segment.end_ms,
+            text: segment.text.clone(),
+        })
+        .collect();
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    connection
+        .transaction(|conn| {
+            delete(transcript_segments::table.filter(transcript_segments::call_id.eq(filename)))
+                .execute(conn)?;
+            insert_into(transcript_segments::table)
+                .values(&rows)
+                .execute(conn)?;
+            diesel::result::QueryResult::Ok(())
+        })
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Splits a stored call again from its stored srcList and unit tags, for a
+// transcription replaced after the upload
+pub fn resplit(c: &ProcessorConfig, filename: &str, words: &[Word]) -> Result<()> {
+    if !c.env.speaker_segments {
+        return Ok(());
+    }
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let src_list: Vec<SrcList> = srclist::table
+        .filter(srclist::call_id.eq(filename))
+        .select(SrcList::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let units: Vec<Source> = sources::table
+        .filter(sources::src.eq_any(src_list.iter().map(|s| s.src)))
+        .select(Source::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    store(c, filename, &split(c, &src_list, &units, words))
+}
+
+// The segments of a stored call, for notifications sent after it was
+// transcribed
+pub fn stored(c: &ProcessorConfig, filename: &str) -> Vec<Segment> {
+    if !c.env.speaker_segments {
+        return Vec::new();
+    }
+    let rows = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))
+        .and_then(|mut connection| {
+            transcript_segments::table
+                .filter(transcript_segments::call_id.eq(filename))
+                .order(transcript_segments::position.asc())
+                .select(TranscriptSegment::as_select())
+                .load(&mut connection)
+                .map_err(|e| Error::Database(e.to_string()))
+        });
+    match rows {
+        Ok(rows) => rows
+            .into_iter()
+            .map(|row| Segment {
+                src: row.src,
+                tag: row.tag,
+                start_ms: row.start_ms,
+                end_ms: row.end_ms,
+                text: row.text,
+            })
+            .collect(),
+        Err(e) => {
+            warn!(file = %filename, error = %e, "Failed to load transcript segments");
+            Vec::new()
+        }
+    }
+}
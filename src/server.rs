@@ -0,0 +1,169 @@
+use crate::config::EnvConfig;
+use crate::error::Result;
+
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use std::{pin::pin, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tower_http::timeout::RequestBodyTimeout;
+use tracing::{debug, info, warn};
+
+// How long to back off after accept fails, e.g. when out of file descriptors
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+// Connection settings from the SERVER_* variables. HTTP/2 is offered without
+// TLS by prior knowledge (h2c), so uploaders that speak it can put every
+// upload on one connection.
+#[derive(Clone)]
+enum Protocol {
+    Auto(auto::Builder<TokioExecutor>),
+    Http1(http1::Builder),
+}
+
+impl Protocol {
+    fn new(env: &EnvConfig) -> Self {
+        if env.server_disable_http2 {
+            let mut builder = http1::Builder::new();
+            builder
+                .timer(TokioTimer::new())
+                .keep_alive(!env.server_disable_keepalive)
+                .header_read_timeout(env.server_header_timeout);
+            return Protocol::Http1(builder);
+        }
+
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .keep_alive(!env.server_disable_keepalive)
+            .header_read_timeout(env.server_header_timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .max_concurrent_streams(env.server_http2_max_streams)
+            .adaptive_window(env.server_http2_adaptive_window)
+            .keep_alive_interval(env.server_http2_keepalive_interval)
+            .keep_alive_timeout(env.server_http2_keepalive_timeout);
+        Protocol::Auto(builder)
+    }
+
+    async fn serve(
+        self,
+        stream: TcpStream,
+        app: Router,
+        body_timeout: Option<Duration>,
+        shutdown: watch::Receiver<bool>,
+    ) {
+        // a body that sends nothing for SERVER_BODY_TIMEOUT is cut off, a slow
+        // one isn't. Unset never times out.
+        let app = RequestBodyTimeout::new(app, body_timeout.unwrap_or(Duration::MAX));
+        let service = TowerToHyperService::new(app);
+        let io = TokioIo::new(stream);
+
+        let result = match self {
+            Protocol::Auto(builder) => {
+                let connection = builder.serve_connection_with_upgrades(io, service);
+                until_shutdown(connection, shutdown, |c| c.graceful_shutdown()).await
+            }
+            Protocol::Http1(builder) => {
+                let connection = builder.serve_connection(io, service).with_upgrades();
+                until_shutdown(connection, shutdown, |c| c.graceful_shutdown())
+                    .await
+                    .map_err(Into::into)
+            }
+        };
+        if let Err(e) = result {
+            debug!(error = %e, "Connection closed with an error");
+        }
+    }
+}
+
+// Runs a connection to the end, or once shutdown starts has it finish the
+// requests in flight and close
+async fn until_shutdown<C, E>(
+    connection: C,
+    mut shutdown: watch::Receiver<bool>,
+    graceful: impl FnOnce(std::pin::Pin<&mut C>),
+) -> std::result::Result<(), E>
+where
+    C: Future<Output = std::result::Result<(), E>>,
+{
+    let mut connection = pin!(connection);
+    tokio::select! {
+        result = connection.as_mut() => return result,
+        _ = shutdown.wait_for(|stopping| *stopping) => {}
+    }
+    graceful(connection.as_mut());
+    connection.await
+}
+
+// Accepts connections until `shutdown` resolves, then waits up to
+// SHUTDOWN_DRAIN_TIMEOUT for the open ones to finish their requests
+pub async fn serve(
+    env: &EnvConfig,
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let protocol = Protocol::new(env);
+    info!(
+        http2 = !env.server_disable_http2,
+        keepalive = !env.server_disable_keepalive,
+        header_timeout_secs = env.server_header_timeout.as_secs(),
+        body_timeout_secs = env.server_body_timeout.map(|t| t.as_secs()),
+        "Accepting connections"
+    );
+
+    let (stop, stopping) = watch::channel(false);
+    let mut shutdown = pin!(shutdown);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = &mut shutdown => break,
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept connection");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+        // uploads are small enough that Nagle only adds latency
+        if let Err(e) = stream.set_nodelay(true) {
+            debug!(error = %e, "Failed to set TCP_NODELAY");
+        }
+        tokio::spawn(protocol.clone().serve(
+            stream,
+            app.clone(),
+            env.server_body_timeout,
+            stopping.clone(),
+        ));
+    }
+
+    drop(listener);
+    drop(stopping);
+    info!(
+        connections = stop.receiver_count(),
+        "Stopped accepting connections, finishing requests in flight"
+    );
+    stop.send_replace(true);
+    if tokio::time::timeout(env.shutdown_drain_timeout, stop.closed())
+        .await
+        .is_err()
+    {
+        warn!(
+            connections = stop.receiver_count(),
+            "Connections still open after SHUTDOWN_DRAIN_TIMEOUT, closing them"
+        );
+    }
+    Ok(())
+}
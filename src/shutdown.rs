@@ -0,0 +1,96 @@
+use crate::config::ProcessorConfig;
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::signal::unix::{SignalKind, signal as unix_signal};
+use tracing::{info, warn};
+
+// How often draining checks whether everything has finished
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Tasks left running after the request or job that started them, such as
+// POST_UPLOAD_HOOK, counted so shutdown can wait for them
+#[derive(Debug, Default)]
+pub struct Background {
+    running: Arc<AtomicUsize>,
+}
+
+struct Running(Arc<AtomicUsize>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Background {
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.running.fetch_add(1, Ordering::Relaxed);
+        let running = Running(self.running.clone());
+        tokio::spawn(async move {
+            let _running = running;
+            f.await;
+        });
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+// Resolves on the first SIGTERM or SIGINT
+pub async fn signal() {
+    let mut terminate = match unix_signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            warn!(error = %e, "Failed to listen for SIGTERM, only SIGINT shuts down");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    let name = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    };
+    info!(signal = name, "Shutting down");
+}
+
+// Stops taking uploads and waits up to `timeout` for the accepted ones to be
+// stored, transcribed and notified, and for background tasks to finish.
+// Whatever is left after that is lost.
+pub async fn drain(c: &ProcessorConfig, timeout: Duration) {
+    c.queue.close();
+    let remaining = || c.queue.unfinished() + c.background.running();
+    if remaining() == 0 {
+        return;
+    }
+    info!(
+        uploads = c.queue.unfinished(),
+        tasks = c.background.running(),
+        timeout_secs = timeout.as_secs(),
+        "Draining upload queue"
+    );
+
+    let drained = tokio::time::timeout(timeout, async {
+        while remaining() > 0 {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+    })
+    .await;
+    match drained {
+        Ok(()) => info!("Upload queue drained"),
+        Err(_) => warn!(
+            uploads = c.queue.unfinished(),
+            tasks = c.background.running(),
+            "Upload queue did not drain in time, abandoning what is left"
+        ),
+    }
+}
@@ -0,0 +1,342 @@
+use crate::audit;
+use crate::auth::{Access, require_admin};
+use crate::common::parse_duration;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, Method, Uri},
+};
+use chrono::{DateTime, TimeDelta, Utc};
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{Array, Double, Int4, Int8, Nullable, Timestamptz},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct FrequencyStatsParams {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    freq: Option<i32>,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct FrequencyUsage {
+    #[diesel(sql_type = Int4)]
+    pub freq: i32,
+    #[diesel(sql_type = Timestamptz)]
+    pub day: DateTime<Utc>,
+    #[diesel(sql_type = Int8)]
+    pub calls: i64,
+    #[diesel(sql_type = Int8)]
+    pub transmissions: i64,
+    #[diesel(sql_type = Double)]
+    pub airtime_secs: f64,
+    #[diesel(sql_type = Int8)]
+    pub error_count: i64,
+    #[diesel(sql_type = Int8)]
+    pub spike_count: i64,
+    #[diesel(sql_type = Double)]
+    pub errors_per_sec: f64,
+    #[diesel(sql_type = Double)]
+    pub spikes_per_sec: f64,
+}
+
+const FREQUENCY_USAGE_QUERY: &str = "
+    SELECT freq,
+           date_trunc('day', time) AS day,
+           count(DISTINCT call_id) AS calls,
+           count(*) AS transmissions,
+           coalesce(extract(epoch FROM sum(len)), 0)::float8 AS airtime_secs,
+           sum(error_count)::int8 AS error_count,
+           sum(spike_count)::int8 AS spike_count,
+           coalesce(sum(error_count) / nullif(extract(epoch FROM sum(len)), 0), 0)::float8 AS errors_per_sec,
+           coalesce(sum(spike_count) / nullif(extract(epoch FROM sum(len)), 0), 0)::float8 AS spikes_per_sec
+    FROM freqlist
+    WHERE time >= $1 AND time < $2 AND ($3 IS NULL OR freq = $3)
+      AND call_id NOT IN (
+          SELECT filename FROM calls
+          WHERE archive_reason = 'backfill' OR tags @> '{\"synthetic\": \"true\"}'
+      )
+    GROUP BY freq, day
+    ORDER BY day DESC, freq";
+
+pub fn frequency_usage(
+    c: &ProcessorConfig,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    freq: Option<i32>,
+) -> Result<Vec<FrequencyUsage>> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    sql_query(FREQUENCY_USAGE_QUERY)
+        .bind::<Timestamptz, _>(since)
+        .bind::<Timestamptz, _>(until)
+        .bind::<Nullable<Int4>, _>(freq)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+pub async fn frequency_stats(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<FrequencyStatsParams>,
+) -> Result<Json<Vec<FrequencyUsage>>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let until = params.until.unwrap_or_else(Utc::now);
+    let since = params.since.unwrap_or(until - TimeDelta::days(7));
+
+    Ok(Json(frequency_usage(&config, since, until, params.freq)?))
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct FrequencyRates {
+    #[diesel(sql_type = Int4)]
+    pub freq: i32,
+    #[diesel(sql_type = Double)]
+    pub airtime_secs: f64,
+    #[diesel(sql_type = Double)]
+    pub errors_per_sec: f64,
+    #[diesel(sql_type = Double)]
+    pub spikes_per_sec: f64,
+    #[diesel(sql_type = Double)]
+    pub baseline_airtime_secs: f64,
+    #[diesel(sql_type = Double)]
+    pub baseline_errors_per_sec: f64,
+    #[diesel(sql_type = Double)]
+    pub baseline_spikes_per_sec: f64,
+}
+
+const FREQUENCY_RATES_QUERY: &str = "
+    WITH usage AS (
+        SELECT freq,
+               time >= $2 AS recent,
+               coalesce(extract(epoch FROM sum(len)), 0)::float8 AS airtime,
+               sum(error_count)::float8 AS errors,
+               sum(spike_count)::float8 AS spikes
+        FROM freqlist
+        WHERE time >= $1
+          AND call_id NOT IN (
+              SELECT filename FROM calls
+              WHERE archive_reason = 'backfill' OR tags @> '{\"synthetic\": \"true\"}'
+          )
+        GROUP BY freq, recent
+    )
+    SELECT r.freq,
+           r.airtime AS airtime_secs,
+           coalesce(r.errors / nullif(r.airtime, 0), 0) AS errors_per_sec,
+           coalesce(r.spikes / nullif(r.airtime, 0), 0) AS spikes_per_sec,
+           coalesce(b.airtime, 0) AS baseline_airtime_secs,
+           coalesce(b.errors / nullif(b.airtime, 0), 0) AS baseline_errors_per_sec,
+           coalesce(b.spikes / nullif(b.airtime, 0), 0) AS baseline_spikes_per_sec
+    FROM usage r
+    LEFT JOIN usage b ON b.freq = r.freq AND NOT b.recent
+    WHERE r.recent
+    ORDER BY r.freq";
+
+pub fn frequency_rates(
+    c: &ProcessorConfig,
+    baseline_since: DateTime<Utc>,
+    recent_since: DateTime<Utc>,
+) -> Result<Vec<FrequencyRates>> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    sql_query(FREQUENCY_RATES_QUERY)
+        .bind::<Timestamptz, _>(baseline_since)
+        .bind::<Timestamptz, _>(recent_since)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+const DEFAULT_RESPONSE_WINDOW: TimeDelta = TimeDelta::minutes(10);
+const MAX_RESPONSE_WINDOW: TimeDelta = TimeDelta::hours(2);
+// Upper bounds of the response time histogram; a last bucket holds the rest
+const RESPONSE_BUCKETS_SECS: [f64; 11] = [
+    5.0, 10.0, 15.0, 30.0, 45.0, 60.0, 90.0, 120.0, 180.0, 300.0, 600.0,
+];
+
+#[derive(Debug, Deserialize)]
+pub struct ResponseTimeParams {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    dispatch: String,
+    response: Option<String>,
+    window: Option<String>,
+}
+
+#[derive(Debug, QueryableByName)]
+struct Incident {
+    #[diesel(sql_type = Nullable<Double>)]
+    response_secs: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseBucket {
+    // None for the last, unbounded bucket
+    pub le_secs: Option<f64>,
+    pub count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseTimes {
+    pub window_secs: i64,
+    pub incidents: usize,
+    pub unanswered: usize,
+    pub mean_secs: Option<f64>,
+    pub median_secs: Option<f64>,
+    pub p90_secs: Option<f64>,
+    pub buckets: Vec<ResponseBucket>,
+}
+
+// An incident starts with a call on a dispatch talkgroup that had been quiet
+// for `window`. Its response is the first call on a response talkgroup
+// beginning within `window` of the dispatch ending, from a unit that was not
+// heard in the dispatch itself.
+const RESPONSE_TIMES_QUERY: &str = "
+    WITH dispatch AS (
+        SELECT c.filename, c.stop_time
+        FROM calls c
+        WHERE c.talkgroup = ANY($3) AND c.start_time >= $1 AND c.start_time < $2
+          AND c.archive_reason IS DISTINCT FROM 'backfill'
+          AND NOT c.tags @> '{\"synthetic\": \"true\"}'
+          AND NOT EXISTS (
+              SELECT 1 FROM calls p
+              WHERE p.talkgroup = ANY($3)
+                AND p.start_time < c.start_time
+                AND p.start_time >= c.start_time - $5 * interval '1 second'
+          )
+    )
+    SELECT extract(epoch FROM r.start_time - d.stop_time)::float8 AS response_secs
+    FROM dispatch d
+    LEFT JOIN LATERAL (
+        SELECT c.start_time
+        FROM calls c
+        WHERE c.talkgroup = ANY($4)
+          AND c.filename <> d.filename
+          AND c.start_time >= d.stop_time
+          AND c.start_time < d.stop_time + $5 * interval '1 second'
+          AND c.archive_reason IS DISTINCT FROM 'backfill'
+          AND NOT c.tags @> '{\"synthetic\": \"true\"}'
+          AND NOT EXISTS (
+              SELECT 1 FROM srclist s
+              JOIN srclist ds ON ds.src = s.src AND ds.call_id = d.filename
+              WHERE s.call_id = c.filename
+          )
+        ORDER BY c.start_time
+        LIMIT 1
+    ) r ON true";
+
+fn parse_talkgroups(field: &str, list: &str) -> Result<Vec<i32>> {
+    list.split(',')
+        .map(|tg| {
+            tg.trim().parse().map_err(|_| {
+                Error::InvalidRequest(format!("{} must be talkgroup ids, got {:?}", field, tg))
+            })
+        })
+        .collect()
+}
+
+// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    let rank = ((p * sorted.len() as f64).ceil() as usize).max(1);
+    sorted.get(rank - 1).copied()
+}
+
+pub fn response_times(
+    c: &ProcessorConfig,
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    dispatch: &[i32],
+    response: &[i32],
+    window: TimeDelta,
+) -> Result<ResponseTimes> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let incidents: Vec<Incident> = sql_query(RESPONSE_TIMES_QUERY)
+        .bind::<Timestamptz, _>(since)
+        .bind::<Timestamptz, _>(until)
+        .bind::<Array<Int4>, _>(dispatch)
+        .bind::<Array<Int4>, _>(response)
+        .bind::<Double, _>(window.num_milliseconds() as f64 / 1000.0)
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut times: Vec<f64> = incidents.iter().filter_map(|i| i.response_secs).collect();
+    times.sort_by(f64::total_cmp);
+
+    let mut buckets: Vec<ResponseBucket> = RESPONSE_BUCKETS_SECS
+        .iter()
+        .map(|&le| ResponseBucket {
+            le_secs: Some(le),
+            count: 0,
+        })
+        .chain([ResponseBucket {
+            le_secs: None,
+            count: 0,
+        }])
+        .collect();
+    for t in &times {
+        let i = RESPONSE_BUCKETS_SECS
+            .iter()
+            .position(|le| t <= le)
+            .unwrap_or(RESPONSE_BUCKETS_SECS.len());
+        buckets[i].count += 1;
+    }
+
+    Ok(ResponseTimes {
+        window_secs: window.num_seconds(),
+        incidents: incidents.len(),
+        unanswered: incidents.len() - times.len(),
+        mean_secs: (!times.is_empty()).then(|| times.iter().sum::<f64>() / times.len() as f64),
+        median_secs: percentile(&times, 0.5),
+        p90_secs: percentile(&times, 0.9),
+        buckets,
+    })
+}
+
+pub async fn response_time_stats(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Query(params): Query<ResponseTimeParams>,
+) -> Result<Json<ResponseTimes>> {
+    require_admin(&headers, &config)?;
+    audit::record(&config, &Access::Admin, &method, &uri, []);
+
+    let dispatch = parse_talkgroups("dispatch", &params.dispatch)?;
+    // units usually acknowledge on the channel they were dispatched on
+    let response = match params.response.as_deref() {
+        Some(list) => parse_talkgroups("response", list)?,
+        None => dispatch.clone(),
+    };
+    let window = match params.window.as_deref() {
+        Some(w) => parse_duration(w)?,
+        None => DEFAULT_RESPONSE_WINDOW,
+    }
+    .clamp(TimeDelta::seconds(1), MAX_RESPONSE_WINDOW);
+
+    let until = params.until.unwrap_or_else(Utc::now);
+    let since = params.since.unwrap_or(until - TimeDelta::days(7));
+
+    Ok(Json(response_times(
+        &config, since, until, &dispatch, &response, window,
+    )?))
+}
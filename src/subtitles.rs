@@ -0,0 +1,205 @@
+use crate::audit;
+use crate::auth::read_access;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::query::{load_call, reveal};
+use crate::transcribe::Word;
+use crate::words;
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, Method, Uri, header},
+    response::{IntoResponse, Response},
+};
+
+// Longest caption line before a sentence is split between cues
+const MAX_CUE_CHARS: usize = 80;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SubtitleFormat {
+    Vtt,
+    Srt,
+}
+
+impl SubtitleFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            SubtitleFormat::Vtt => "text/vtt; charset=utf-8",
+            SubtitleFormat::Srt => "application/x-subrip; charset=utf-8",
+        }
+    }
+
+    fn timestamp(self, ms: i64) -> String {
+        let separator = match self {
+            SubtitleFormat::Vtt => '.',
+            SubtitleFormat::Srt => ',',
+        };
+        format!(
+            "{:02}:{:02}:{:02}{}{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            separator,
+            ms % 1000
+        )
+    }
+}
+
+struct Cue {
+    start_ms: i64,
+    end_ms: i64,
+    text: String,
+}
+
+// Sentences, with any longer than MAX_CUE_CHARS broken between words
+fn lines(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.len() + word.len() + 1 > MAX_CUE_CHARS {
+            sentences.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+        if word.ends_with(['.', '!', '?']) {
+            sentences.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        sentences.push(current);
+    }
+    sentences
+}
+
+// Lines broken as `lines` does, each timed from its first word to its last
+fn timed_cues(words: &[Word]) -> Vec<Cue> {
+    let mut cues: Vec<Cue> = Vec::new();
+    let mut current: Option<Cue> = None;
+    for word in words {
+        if let Some(cue) =
+            current.take_if(|cue| cue.text.len() + word.word.len() + 1 > MAX_CUE_CHARS)
+        {
+            cues.push(cue);
+        }
+        let cue = current.get_or_insert(Cue {
+            start_ms: word.start_ms as i64,
+            end_ms: word.end_ms as i64,
+            text: String::new(),
+        });
+        if !cue.text.is_empty() {
+            cue.text.push(' ');
+        }
+        cue.text.push_str(&word.word);
+        cue.end_ms = word.end_ms as i64;
+        if word.word.ends_with(['.', '!', '?']) {
+            cues.extend(current.take());
+        }
+    }
+    cues.extend(current);
+    cues
+}
+
+// Without word timings each line is given a share of the call proportional
+// to its length
+fn cues(text: &str, length_ms: i64) -> Vec<Cue> {
+    let lines = lines(text);
+    let total: usize = lines.iter().map(String::len).sum();
+    let mut start_ms = 0;
+    let mut seen = 0;
+
+    lines
+        .into_iter()
+        .map(|text| {
+            seen += text.len();
+            let end_ms = length_ms * seen as i64 / total.max(1) as i64;
+            let cue = Cue {
+                start_ms,
+                end_ms,
+                text,
+            };
+            start_ms = end_ms;
+            cue
+        })
+        .collect()
+}
+
+fn render(cues: &[Cue], format: SubtitleFormat) -> String {
+    let mut out = String::new();
+    if let SubtitleFormat::Vtt = format {
+        out.push_str("WEBVTT\n\n");
+    }
+    for (i, cue) in cues.iter().enumerate() {
+        if let SubtitleFormat::Srt = format {
+            out.push_str(&format!("{}\n", i + 1));
+        }
+        out.push_str(&format!(
+            "{} --> {}\n{}\n\n",
+            format.timestamp(cue.start_ms),
+            format.timestamp(cue.end_ms),
+            cue.text
+        ));
+    }
+    out
+}
+
+async fn transcript(
+    config: ProcessorConfig,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    id: String,
+    format: SubtitleFormat,
+) -> Result<Response> {
+    let access = read_access(&headers, &config)?;
+    let mut call = load_call(&config, &access, &id)?;
+    audit::record(&config, &access, &method, &uri, [call.filename.clone()]);
+    reveal(&config, &access, &mut call)?;
+    // the words are the unscrubbed transcription's
+    let words = if access.is_public() && config.env.pii_scrubbing {
+        Vec::new()
+    } else {
+        words::load(&config, &call.filename)?
+    };
+
+    let text = call
+        .transcription
+        .as_deref()
+        .ok_or_else(|| Error::NotFound(format!("transcription for call {}", id)))?;
+    let length_ms = (call.stop_time - call.start_time)
+        .num_milliseconds()
+        .max(call.call_length as i64 * 1000)
+        .max(1000);
+
+    let cues = if words.is_empty() {
+        cues(text, length_ms)
+    } else {
+        timed_cues(&words)
+    };
+    Ok((
+        [(header::CONTENT_TYPE, format.content_type())],
+        render(&cues, format),
+    )
+        .into_response())
+}
+
+pub async fn transcript_vtt(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    transcript(config, headers, method, uri, id, SubtitleFormat::Vtt).await
+}
+
+pub async fn transcript_srt(
+    State(config): State<ProcessorConfig>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    transcript(config, headers, method, uri, id, SubtitleFormat::Srt).await
+}
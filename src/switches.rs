@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::warn;
+
+// Runtime toggles for shedding transcription, notifications or database
+// writes during a partial outage. Audio and metadata are always archived to S3.
+#[derive(Debug)]
+pub struct Switches {
+    transcription: AtomicBool,
+    notifications: AtomicBool,
+    database: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SwitchState {
+    pub transcription: bool,
+    pub notifications: bool,
+    pub database: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SwitchUpdate {
+    pub transcription: Option<bool>,
+    pub notifications: Option<bool>,
+    pub database: Option<bool>,
+}
+
+impl Switches {
+    pub fn new(state: SwitchState) -> Self {
+        Switches {
+            transcription: AtomicBool::new(state.transcription),
+            notifications: AtomicBool::new(state.notifications),
+            database: AtomicBool::new(state.database),
+        }
+    }
+
+    pub fn transcription(&self) -> bool {
+        self.transcription.load(Ordering::Relaxed)
+    }
+
+    pub fn notifications(&self) -> bool {
+        self.notifications.load(Ordering::Relaxed)
+    }
+
+    pub fn database(&self) -> bool {
+        self.database.load(Ordering::Relaxed)
+    }
+
+    pub fn state(&self) -> SwitchState {
+        SwitchState {
+            transcription: self.transcription(),
+            notifications: self.notifications(),
+            database: self.database(),
+        }
+    }
+
+    pub fn apply(&self, update: &SwitchUpdate) -> SwitchState {
+        for (name, switch, value) in [
+            ("transcription", &self.transcription, update.transcription),
+            ("notifications", &self.notifications, update.notifications),
+            ("database", &self.database, update.database),
+        ] {
+            let Some(enabled) = value else {
+                continue;
+            };
+            if switch.swap(enabled, Ordering::Relaxed) != enabled {
+                warn!(
+                    switch = name,
+                    enabled = enabled,
+                    "Degradation switch changed"
+                );
+            }
+        }
+        self.state()
+    }
+}
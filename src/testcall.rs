@@ -0,0 +1,240 @@
+use crate::common::{UploadData, UploadedFile};
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{SYNTHETIC_TAG, Talkgroups};
+use crate::schema::{calls, srclist, talkgroups};
+use crate::upload::{complete, discard_incoming, ingest};
+use crate::workspace::Workspace;
+
+use axum::body::Bytes;
+use chrono::Utc;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::time::Instant;
+use tokio::process::Command;
+use tracing::info;
+
+const DEFAULT_DURATION_SECS: u32 = 5;
+const MAX_DURATION_SECS: u32 = 120;
+
+// What to make up. Anything not given is copied from the talkgroup's latest
+// call, so the test looks like its traffic to filters and destinations.
+#[derive(Debug)]
+pub struct TestCall {
+    pub talkgroup: i32,
+    pub short_name: Option<String>,
+    pub duration_secs: Option<u32>,
+    pub emergency: bool,
+    pub src: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestCallReport {
+    pub filename: String,
+    pub talkgroup: i32,
+    pub short_name: String,
+    pub filter_rule: Option<String>,
+    pub transcription: Option<String>,
+    pub duration_ms: u128,
+}
+
+// The latest call on the talkgroup: its system, frequency and first radio
+struct Latest {
+    short_name: String,
+    freq: i32,
+    src: Option<i32>,
+}
+
+fn latest(c: &ProcessorConfig, talkgroup: i32) -> Result<(Option<Talkgroups>, Option<Latest>)> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let names: Option<Talkgroups> = talkgroups::table
+        .find(talkgroup)
+        .select(Talkgroups::as_select())
+        .first(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let call: Option<(String, String, i32)> = calls::table
+        .filter(calls::talkgroup.eq(talkgroup))
+        .order(calls::start_time.desc())
+        .select((calls::filename, calls::short_name, calls::freq))
+        .first(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let Some((filename, short_name, freq)) = call else {
+        return Ok((names, None));
+    };
+    let src: Option<i32> = srclist::table
+        .filter(srclist::call_id.eq(filename))
+        .order(srclist::pos.asc())
+        .select(srclist::src)
+        .first(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok((
+        names,
+        Some(Latest {
+            short_name,
+            freq,
+            src,
+        }),
+    ))
+}
+
+// TESTCALL_AUDIO, or a 1 kHz tone from ffmpeg
+async fn audio(c: &ProcessorConfig, duration_secs: u32) -> Result<Vec<u8>> {
+    if let Some(path) = &c.env.testcall_audio {
+        return tokio::fs::read(path)
+            .await
+            .map_err(|e| Error::Audio(format!("could not read {}: {}", path, e)));
+    }
+
+    let workspace = Workspace::create(c, "testcall").await?;
+    let output = workspace.file("testcall.m4a")?;
+    let result = workspace
+        .run(
+            Command::new(&c.env.ffmpeg_path)
+                .args([
+                    "-hide_banner",
+                    "-loglevel",
+                    "error",
+                    "-f",
+                    "lavfi",
+                    "-i",
+                    &format!(
+                        "sine=frequency=1000:sample_rate=16000:duration={}",
+                        duration_secs
+                    ),
+                    "-c:a",
+                    "aac",
+                    "-movflags",
+                    "+faststart",
+                    "-y",
+                ])
+                .arg(&output),
+        )
+        .await?
+        .map_err(|e| Error::Audio(format!("failed to run {}: {}", c.env.ffmpeg_path, e)))?;
+    if !result.status.success() {
+        return Err(Error::Audio(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    workspace.read("testcall.m4a").await
+}
+
+// Makes up a call starting now and runs it through everything an upload goes
+// through, filters, transcription, storage and every destination, waiting
+// for the result. It is tagged synthetic, so statistics leave it out and
+// notifications title it as a test.
+pub async fn inject(c: &ProcessorConfig, test: &TestCall) -> Result<TestCallReport> {
+    let start = Instant::now();
+    let duration_secs = test
+        .duration_secs
+        .unwrap_or(DEFAULT_DURATION_SECS)
+        .clamp(1, MAX_DURATION_SECS);
+    let (names, latest) = latest(c, test.talkgroup)?;
+    let short_name = test
+        .short_name
+        .clone()
+        .or_else(|| latest.as_ref().map(|l| l.short_name.clone()))
+        .ok_or_else(|| {
+            Error::InvalidRequest(format!(
+                "talkgroup {} has no calls to take a system from, give one",
+                test.talkgroup
+            ))
+        })?;
+    let freq = latest.as_ref().map_or(0, |l| l.freq);
+    let src = test.src.or(latest.and_then(|l| l.src)).unwrap_or(0);
+    let names = names.unwrap_or_else(|| Talkgroups {
+        talkgroup: test.talkgroup,
+        talkgroup_tag: "Test".to_string(),
+        talkgroup_description: "Test call".to_string(),
+        talkgroup_group_tag: "Test".to_string(),
+        talkgroup_group: "Test".to_string(),
+    });
+
+    let now = Utc::now().timestamp();
+    let metadata = serde_json::json!({
+        "freq": freq,
+        "freq_error": 0,
+        "signal": 0,
+        "noise": 0,
+        "source_num": 0,
+        "recorder_num": 0,
+        "tdma_slot": 0,
+        "phase2_tdma": 0,
+        "start_time": now,
+        "stop_time": now + i64::from(duration_secs),
+        "emergency": i32::from(test.emergency),
+        "priority": 0,
+        "mode": 0,
+        "duplex": 0,
+        "encrypted": 0,
+        "call_length": duration_secs,
+        "talkgroup": test.talkgroup,
+        "talkgroup_tag": names.talkgroup_tag,
+        "talkgroup_description": names.talkgroup_description,
+        "talkgroup_group_tag": names.talkgroup_group_tag,
+        "talkgroup_group": names.talkgroup_group,
+        "audio_type": "digital",
+        "short_name": short_name,
+        "freqList": [{
+            "freq": freq, "time": now, "pos": 0.0, "len": f64::from(duration_secs),
+            "error_count": 0, "spike_count": 0,
+        }],
+        "srcList": [{
+            "src": src, "time": now, "pos": 0.0, "emergency": i32::from(test.emergency),
+            "signal_system": "", "tag": "",
+        }],
+    });
+
+    let stem = format!("{}-{}_{}", test.talkgroup, now, freq);
+    let mut files = UploadData {
+        json: UploadedFile::in_memory(
+            format!("{}.json", stem),
+            Bytes::from(serde_json::to_vec(&metadata)?),
+        ),
+        audio: UploadedFile::in_memory(
+            format!("{}.m4a", stem),
+            Bytes::from(audio(c, duration_secs).await?),
+        ),
+        tags: [
+            ("source".to_string(), "testcall".to_string()),
+            (SYNTHETIC_TAG.to_string(), "true".to_string()),
+        ]
+        .into(),
+        version: None,
+        language: None,
+    };
+
+    let stored = match ingest(c, &mut files, None).await {
+        Ok(stored) => stored,
+        Err(e) => {
+            discard_incoming(c, &files).await;
+            return Err(e);
+        }
+    };
+    complete(c, &stored, None).await?;
+    let meta = stored.meta;
+
+    let report = TestCallReport {
+        filename: meta.call.filename,
+        talkgroup: test.talkgroup,
+        short_name,
+        filter_rule: meta.call.filter_rule,
+        transcription: meta.call.transcription,
+        duration_ms: start.elapsed().as_millis(),
+    };
+    info!(
+        file = %report.filename,
+        talkgroup = report.talkgroup,
+        duration_ms = report.duration_ms,
+        "Test call processed"
+    );
+    Ok(report)
+}
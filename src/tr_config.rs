@@ -0,0 +1,364 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::{Source, System, Talkgroups};
+use crate::schema::{sources, systems, talkgroups};
+
+use chrono::Utc;
+use diesel::{insert_into, prelude::*, upsert::excluded};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecorderConfig {
+    #[serde(default)]
+    systems: Vec<RecorderSystem>,
+    #[serde(default)]
+    plugins: Vec<RecorderPlugin>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecorderSystem {
+    short_name: String,
+    #[serde(rename = "type")]
+    system_type: String,
+    talkgroups_file: Option<String>,
+    unit_tags_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecorderPlugin {
+    #[serde(default)]
+    library: String,
+    #[serde(default)]
+    systems: Vec<PluginSystem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PluginSystem {
+    short_name: String,
+    system_id: Option<i32>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SystemReport {
+    pub short_name: String,
+    pub system_type: String,
+    pub talkgroups: usize,
+    pub encrypted_talkgroups: usize,
+    pub unit_tags: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TrConfigReport {
+    pub systems: Vec<SystemReport>,
+    pub talkgroups_imported: usize,
+    pub unit_tags_imported: usize,
+    // FILTER_* values that transcribe every group with clear talkgroups and
+    // skip the encrypted ones, to copy into the environment
+    pub recommended_filters: BTreeMap<String, String>,
+    pub warnings: Vec<String>,
+    pub duration_ms: u128,
+}
+
+// One parsed row of a talkgroupsFile
+struct TalkgroupRow {
+    talkgroup: Talkgroups,
+    encrypted: bool,
+}
+
+// Splits a CSV line, honouring double-quoted fields with "" escapes
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields.iter().map(|f| f.trim().to_string()).collect()
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Error::Import(format!("could not read {}: {}", path.display(), e)))
+}
+
+// trunk-recorder accepts a header row naming the columns in any order, or the
+// older fixed Decimal,Hex,Mode,Alpha Tag,Description,Tag,Group layout
+fn parse_talkgroups(text: &str, warnings: &mut Vec<String>, file: &str) -> Vec<TalkgroupRow> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty()).peekable();
+    let header = lines
+        .peek()
+        .map(|l| csv_fields(l))
+        .filter(|fields| fields.iter().any(|f| f.eq_ignore_ascii_case("decimal")));
+    let column = |names: &[&str], legacy: usize| -> usize {
+        header
+            .as_ref()
+            .and_then(|h| {
+                h.iter()
+                    .position(|f| names.iter().any(|n| f.eq_ignore_ascii_case(n)))
+            })
+            .unwrap_or(if header.is_some() { usize::MAX } else { legacy })
+    };
+    let decimal = column(&["decimal"], 0);
+    let mode = column(&["mode"], 2);
+    let alpha = column(&["alpha tag"], 3);
+    let description = column(&["description"], 4);
+    let tag = column(&["tag"], 5);
+    let group = column(&["category", "group"], 6);
+    if header.is_some() {
+        lines.next();
+    }
+
+    let mut rows = Vec::new();
+    for (n, line) in lines.enumerate() {
+        let fields = csv_fields(line);
+        let get = |i: usize| fields.get(i).cloned().unwrap_or_default();
+        let Ok(id) = get(decimal).parse::<i32>() else {
+            warnings.push(format!(
+                "{}: skipped row {}, no decimal talkgroup",
+                file,
+                n + 1
+            ));
+            continue;
+        };
+        rows.push(TalkgroupRow {
+            talkgroup: Talkgroups {
+                talkgroup: id,
+                talkgroup_tag: get(alpha),
+                talkgroup_description: get(description),
+                talkgroup_group_tag: get(tag),
+                talkgroup_group: get(group),
+            },
+            encrypted: get(mode).to_ascii_uppercase().contains('E'),
+        });
+    }
+    rows
+}
+
+// unitTagsFile rows are radio id,tag. Newer trunk-recorder allows a regex in
+// place of the id, which has no single source to attach to.
+fn parse_unit_tags(text: &str, warnings: &mut Vec<String>, file: &str) -> Vec<Source> {
+    let mut tags = Vec::new();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let fields = csv_fields(line);
+        match (fields.first().map(|f| f.parse::<i32>()), fields.get(1)) {
+            (Some(Ok(src)), Some(tag)) if !tag.is_empty() => tags.push(Source {
+                src,
+                tag: Some(tag.clone()),
+            }),
+            _ => warnings.push(format!("{}: skipped unit tag {}", file, line.trim())),
+        }
+    }
+    tags
+}
+
+// Groups with at least one clear talkgroup are transcribed, with the encrypted
+// talkgroups in them excluded as there is no audio to transcribe
+fn recommend_filters(rows: &[TalkgroupRow]) -> BTreeMap<String, String> {
+    let groups: BTreeSet<&str> = rows
+        .iter()
+        .filter(|r| !r.encrypted && !r.talkgroup.talkgroup_group.is_empty())
+        .map(|r| r.talkgroup.talkgroup_group.as_str())
+        .collect();
+    let excluded: BTreeSet<i32> = rows
+        .iter()
+        .filter(|r| r.encrypted && groups.contains(r.talkgroup.talkgroup_group.as_str()))
+        .map(|r| r.talkgroup.talkgroup)
+        .collect();
+
+    let mut filters = BTreeMap::new();
+    if !groups.is_empty() {
+        let groups: Vec<&str> = groups.into_iter().collect();
+        filters.insert("FILTER_TG_GROUP".to_string(), groups.join(","));
+    }
+    if !excluded.is_empty() {
+        let ids: Vec<String> = excluded.iter().map(|id| format!("!{}", id)).collect();
+        filters.insert("FILTER_TG_ID".to_string(), ids.join(","));
+    }
+    filters
+}
+
+// Bootstraps a deployment from a trunk-recorder config.json: its systems are
+// recorded, and each system's talkgroupsFile and unitTagsFile loaded into the
+// talkgroup and source tables. Relative file paths are resolved against
+// `base_dir`, or the directory holding the config.
+pub fn import(
+    c: &ProcessorConfig,
+    path: &str,
+    base_dir: Option<PathBuf>,
+) -> Result<TrConfigReport> {
+    let start = Instant::now();
+    info!(config = %path, "Starting trunk-recorder config import");
+
+    let config: RecorderConfig = serde_json::from_str(&read_file(Path::new(path))?)?;
+    let base_dir = base_dir.unwrap_or_else(|| {
+        Path::new(path)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    });
+    let rdio_ids: HashMap<&str, i32> = config
+        .plugins
+        .iter()
+        .filter(|p| p.library.contains("rdioscanner"))
+        .flat_map(|p| &p.systems)
+        .filter_map(|s| Some((s.short_name.as_str(), s.system_id?)))
+        .collect();
+
+    let mut report = TrConfigReport::default();
+    let mut rows: Vec<System> = Vec::new();
+    let mut talkgroup_rows: Vec<TalkgroupRow> = Vec::new();
+    let mut unit_tags: Vec<Source> = Vec::new();
+    let mut seen: HashMap<i32, String> = HashMap::new();
+
+    for system in &config.systems {
+        let mut system_report = SystemReport {
+            short_name: system.short_name.clone(),
+            system_type: system.system_type.clone(),
+            ..Default::default()
+        };
+
+        if let Some(file) = &system.talkgroups_file {
+            let parsed = parse_talkgroups(
+                &read_file(&base_dir.join(file))?,
+                &mut report.warnings,
+                file,
+            );
+            system_report.talkgroups = parsed.len();
+            system_report.encrypted_talkgroups = parsed.iter().filter(|r| r.encrypted).count();
+            for row in parsed {
+                let id = row.talkgroup.talkgroup;
+                // talkgroups are not keyed by system, so the last system wins
+                if let Some(other) = seen.insert(id, system.short_name.clone())
+                    && other != system.short_name
+                {
+                    report.warnings.push(format!(
+                        "talkgroup {} is in both {} and {}, keeping {}",
+                        id, other, system.short_name, system.short_name
+                    ));
+                }
+                talkgroup_rows.retain(|r| r.talkgroup.talkgroup != id);
+                talkgroup_rows.push(row);
+            }
+        }
+        if let Some(file) = &system.unit_tags_file {
+            let parsed = parse_unit_tags(
+                &read_file(&base_dir.join(file))?,
+                &mut report.warnings,
+                file,
+            );
+            system_report.unit_tags = parsed.len();
+            unit_tags.retain(|t| parsed.iter().all(|p| p.src != t.src));
+            unit_tags.extend(parsed);
+        }
+
+        rows.push(System {
+            short_name: system.short_name.clone(),
+            system_type: system.system_type.clone(),
+            talkgroups_file: system.talkgroups_file.clone(),
+            unit_tags_file: system.unit_tags_file.clone(),
+            rdio_system_id: rdio_ids.get(system.short_name.as_str()).copied(),
+            imported_at: Utc::now(),
+        });
+        report.systems.push(system_report);
+    }
+    if config.systems.is_empty() {
+        report.warnings.push("config has no systems".to_string());
+    }
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let talkgroups: Vec<Talkgroups> = talkgroup_rows.iter().map(|r| r.talkgroup.clone()).collect();
+    connection
+        .transaction(|conn| {
+            for chunk in rows.chunks(1000) {
+                insert_into(systems::table)
+                    .values(chunk)
+                    .on_conflict(systems::short_name)
+                    .do_update()
+                    .set((
+                        systems::system_type.eq(excluded(systems::system_type)),
+                        systems::talkgroups_file.eq(excluded(systems::talkgroups_file)),
+                        systems::unit_tags_file.eq(excluded(systems::unit_tags_file)),
+                        systems::rdio_system_id.eq(excluded(systems::rdio_system_id)),
+                        systems::imported_at.eq(excluded(systems::imported_at)),
+                    ))
+                    .execute(conn)?;
+            }
+            for chunk in talkgroups.chunks(1000) {
+                insert_into(talkgroups::table)
+                    .values(chunk)
+                    .on_conflict(talkgroups::talkgroup)
+                    .do_update()
+                    .set((
+                        talkgroups::talkgroup_tag.eq(excluded(talkgroups::talkgroup_tag)),
+                        talkgroups::talkgroup_description
+                            .eq(excluded(talkgroups::talkgroup_description)),
+                        talkgroups::talkgroup_group_tag
+                            .eq(excluded(talkgroups::talkgroup_group_tag)),
+                        talkgroups::talkgroup_group.eq(excluded(talkgroups::talkgroup_group)),
+                    ))
+                    .execute(conn)?;
+            }
+            for chunk in unit_tags.chunks(1000) {
+                insert_into(sources::table)
+                    .values(chunk)
+                    .on_conflict(sources::src)
+                    .do_update()
+                    .set(sources::tag.eq(excluded(sources::tag)))
+                    .execute(conn)?;
+            }
+            diesel::QueryResult::Ok(())
+        })
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    report.talkgroups_imported = talkgroup_rows.len();
+    report.unit_tags_imported = unit_tags.len();
+    report.recommended_filters = recommend_filters(&talkgroup_rows);
+    for warning in &report.warnings {
+        warn!(warning = %warning, "trunk-recorder config import");
+    }
+    report.duration_ms = start.elapsed().as_millis();
+    info!(
+        systems = report.systems.len(),
+        talkgroups = report.talkgroups_imported,
+        unit_tags = report.unit_tags_imported,
+        duration_ms = report.duration_ms,
+        "trunk-recorder config import completed"
+    );
+
+    Ok(report)
+}
+
+pub fn list(c: &ProcessorConfig) -> Result<Vec<System>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    systems::table
+        .select(System::as_select())
+        .order(systems::short_name.asc())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
@@ -0,0 +1,112 @@
+use axum::http::HeaderMap;
+use reqwest::RequestBuilder;
+use ring::rand::{SecureRandom, SystemRandom};
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+// W3C trace context for the span handling a request. The trace id is taken
+// from an incoming traceparent when present, the span id is always our own.
+#[derive(Debug, Clone)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    sampled: bool,
+    tracestate: Option<String>,
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    // fall back to an all-zero (invalid) id rather than failing the request
+    let _ = SystemRandom::new().fill(&mut bytes);
+    bytes
+}
+
+fn decode<const N: usize>(s: &str) -> Option<[u8; N]> {
+    let bytes: [u8; N] = hex::decode(s).ok()?.try_into().ok()?;
+    bytes.iter().any(|b| *b != 0).then_some(bytes)
+}
+
+impl TraceContext {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let tracestate = headers
+            .get("tracestate")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        match headers
+            .get("traceparent")
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::parse)
+        {
+            Some((trace_id, sampled)) => TraceContext {
+                trace_id,
+                span_id: random(),
+                sampled,
+                tracestate,
+            },
+            None => TraceContext {
+                trace_id: random(),
+                span_id: random(),
+                sampled: true,
+                tracestate: None,
+            },
+        }
+    }
+
+    fn parse(value: &str) -> Option<([u8; 16], bool)> {
+        let mut parts = value.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = decode::<16>(parts.next()?)?;
+        decode::<8>(parts.next()?)?;
+        let flags = u8::from_str_radix(parts.next()?, 16).ok()?;
+
+        // version 00 has exactly four fields, later versions may append more
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        Some((trace_id, flags & 0x01 == 0x01))
+    }
+
+    pub fn trace_id(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    pub fn span_id(&self) -> String {
+        hex::encode(self.span_id)
+    }
+
+    fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            self.trace_id(),
+            self.span_id(),
+            self.sampled as u8
+        )
+    }
+}
+
+pub async fn scope<F: Future>(ctx: TraceContext, f: F) -> F::Output {
+    CURRENT.scope(ctx, f).await
+}
+
+// The context of the request being handled, for work that outlives it
+pub fn current() -> Option<TraceContext> {
+    CURRENT.try_with(TraceContext::clone).ok()
+}
+
+pub fn inject(request: RequestBuilder) -> RequestBuilder {
+    let Ok((traceparent, tracestate)) =
+        CURRENT.try_with(|ctx| (ctx.traceparent(), ctx.tracestate.clone()))
+    else {
+        return request;
+    };
+
+    let request = request.header("traceparent", traceparent);
+    match tracestate {
+        Some(state) => request.header("tracestate", state),
+        None => request,
+    }
+}
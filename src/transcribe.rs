@@ -0,0 +1,1073 @@
+use crate::alerts::{self, Alert, Severity};
+use crate::config::{EnvConfig, ProcessorConfig, TranscriptionProvider};
+use crate::error::{Error, Result};
+use crate::trace;
+
+use axum::body::Bytes;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use object_store::{ObjectStore, PutPayload, path::Path};
+use reqwest::{
+    StatusCode, Url,
+    header::CONTENT_TYPE,
+    multipart::{Form, Part},
+};
+use ring::{
+    digest::{SHA256, digest},
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+
+const DEEPGRAM_ENDPOINT: &str = "https://api.deepgram.com/v1/listen";
+// How often a submitted AWS Transcribe job is checked, and for how long
+const AWS_POLL_INTERVAL: Duration = Duration::from_secs(2);
+const AWS_POLL_ATTEMPTS: u32 = 300;
+// Long enough to span the gaps between calls while the provider stays down
+const GAVE_UP_ALERT_EXPIRY: Duration = Duration::from_secs(15 * 60);
+
+// X-Language value asking the provider to detect the language itself
+pub const AUTO_LANGUAGE: &str = "auto";
+
+// ISO 639-1 (or 639-3) code as the transcription endpoints expect it
+pub fn is_language_code(language: &str) -> bool {
+    (2..=3).contains(&language.len()) && language.bytes().all(|b| b.is_ascii_lowercase())
+}
+
+// Entries are talkgroup=language, the language a code or auto
+pub fn parse_languages(entries: &[String]) -> Result<HashMap<i32, String>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (talkgroup, language) = entry.split_once('=').ok_or_else(|| {
+                Error::Configuration(format!("expected talkgroup=language, got {:?}", entry))
+            })?;
+            let talkgroup = talkgroup
+                .trim()
+                .parse()
+                .map_err(|_| Error::Configuration(format!("invalid talkgroup {:?}", talkgroup)))?;
+            let language = language.trim().to_ascii_lowercase();
+            if language != AUTO_LANGUAGE && !is_language_code(&language) {
+                return Err(Error::Configuration(format!(
+                    "invalid language {:?}, expected an ISO 639 code or auto",
+                    language
+                )));
+            }
+            Ok((talkgroup, language))
+        })
+        .collect()
+}
+
+// What a call is transcribed in: the upload's X-Language, else its
+// talkgroup's TRANSCRIPTION_LANGUAGE_TALKGROUPS entry, else
+// TRANSCRIPTION_LANGUAGE
+pub fn language<'a>(c: &'a ProcessorConfig, talkgroup: i32, requested: Option<&'a str>) -> &'a str {
+    requested
+        .or_else(|| c.talkgroup_languages.get(&talkgroup).map(String::as_str))
+        .unwrap_or(&c.env.transcription_language)
+}
+
+// One call's audio on its way to a transcription provider
+pub struct Audio<'a> {
+    pub name: &'a str,
+    pub data: Bytes,
+    pub language: &'a str,
+}
+
+impl Audio<'_> {
+    fn detecting(&self) -> bool {
+        self.language == AUTO_LANGUAGE
+    }
+
+    fn in_language<'a>(&'a self, language: &'a str) -> Audio<'a> {
+        Audio {
+            name: self.name,
+            data: self.data.clone(),
+            language,
+        }
+    }
+}
+
+// A language the provider detected, with its confidence when it reports one
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetectedLanguage {
+    pub code: String,
+    pub probability: Option<f32>,
+}
+
+// When a word was said, from the start of the call
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    pub word: String,
+    pub start_ms: i32,
+    pub end_ms: i32,
+}
+
+impl Word {
+    fn from_secs(word: &str, start: f64, end: f64) -> Option<Self> {
+        let word = word.trim();
+        (!word.is_empty()).then(|| Word {
+            word: word.to_string(),
+            start_ms: (start * 1000.0).round() as i32,
+            end_ms: (end * 1000.0).round() as i32,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    pub text: String,
+    pub detected: Option<DetectedLanguage>,
+    // the detection was too unsure, so this is in TRANSCRIPTION_FALLBACK_LANGUAGE
+    pub fallback: bool,
+    // with WORD_TIMESTAMPS, from providers that report them
+    pub words: Vec<Word>,
+}
+
+impl From<String> for Transcript {
+    fn from(text: String) -> Self {
+        Transcript {
+            text,
+            ..Default::default()
+        }
+    }
+}
+
+// A speech-to-text service, chosen by TRANSCRIPTION_PROVIDER
+pub trait Transcriber: Send + Sync + std::fmt::Debug {
+    fn name(&self) -> &str;
+
+    fn transcribe<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Transcript>>;
+
+    // Detection ahead of transcribing, for providers with an endpoint for it.
+    // The rest report what they detected along with the transcript.
+    fn detect_language<'a>(
+        &'a self,
+        _c: &'a ProcessorConfig,
+        _audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Option<DetectedLanguage>>> {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+// Worth trying again: the provider timed out, couldn't be reached, or was
+// overloaded or failing
+fn transient(e: &Error) -> bool {
+    match e {
+        Error::WebhookSend(e) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.is_request()
+                || e.status().is_some_and(|status| {
+                    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+                })
+        }
+        _ => false,
+    }
+}
+
+// `base` doubled for each earlier retry, then scaled to between half and all
+// of that so retries from many calls don't arrive together
+fn backoff(base: Duration, retry: u32) -> Duration {
+    let delay = base.saturating_mul(1 << retry.min(16));
+    let mut byte = [0u8];
+    let _ = SystemRandom::new().fill(&mut byte);
+    delay.mul_f64(0.5 + f64::from(byte[0]) / 510.0)
+}
+
+// Transcribes with TRANSCRIPTION_RETRIES for transient failures, all within
+// TRANSCRIPTION_RETRY_BUDGET. Running out of either raises an alert, as the
+// provider is likely down for every call.
+pub async fn transcribe(c: &ProcessorConfig, audio: &Audio<'_>) -> Result<Transcript> {
+    let attempts = async {
+        let mut retry = 0;
+        loop {
+            match attempt(c, audio).await {
+                Err(e) if transient(&e) && retry < c.env.transcription_retries => {
+                    let delay = backoff(c.env.transcription_retry_delay, retry);
+                    retry += 1;
+                    warn!(
+                        file = %audio.name,
+                        attempt = retry,
+                        delay_ms = delay.as_millis(),
+                        error = %e,
+                        "Transcription failed, retrying"
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    };
+    let result = match tokio::time::timeout(c.env.transcription_retry_budget, attempts).await {
+        Ok(result) => result,
+        Err(_) => {
+            let e = Error::Transcription(format!(
+                "gave up after {}s",
+                c.env.transcription_retry_budget.as_secs()
+            ));
+            gave_up(c, audio, &e);
+            return Err(e);
+        }
+    };
+    if let Err(e) = &result
+        && transient(e)
+    {
+        gave_up(c, audio, e);
+    }
+    result
+}
+
+fn gave_up(c: &ProcessorConfig, audio: &Audio<'_>, e: &Error) {
+    alerts::spawn(
+        c,
+        Alert {
+            name: "TranscriptionRetriesExhausted",
+            severity: Severity::Warning,
+            summary: "Transcription gave up after using its retries".to_string(),
+            description: format!("{}: {}", audio.name, e),
+            labels: Vec::new(),
+            expires_in: GAVE_UP_ALERT_EXPIRY,
+        },
+    );
+}
+
+// Transcribes the audio, gating an auto-detected language on
+// TRANSCRIPTION_LANGUAGE_THRESHOLD. A detection less sure than that, as short
+// clips often are, is not trusted and the call is transcribed in
+// TRANSCRIPTION_FALLBACK_LANGUAGE instead. Providers that detect without
+// reporting a probability are always trusted.
+async fn attempt(c: &ProcessorConfig, audio: &Audio<'_>) -> Result<Transcript> {
+    let transcriber = &c.transcriber;
+    if !audio.detecting() {
+        return transcriber.transcribe(c, audio).await;
+    }
+    let fallback = c.env.transcription_fallback_language.as_str();
+    let unsure = |detected: &DetectedLanguage| {
+        let unsure = detected
+            .probability
+            .is_some_and(|p| p < c.env.transcription_language_threshold);
+        if unsure {
+            info!(
+                file = %audio.name,
+                language = %detected.code,
+                probability = detected.probability,
+                fallback,
+                "Detected language is below the threshold, using the fallback language"
+            );
+        }
+        unsure
+    };
+
+    if let Some(detected) = transcriber.detect_language(c, audio).await? {
+        let unsure = unsure(&detected);
+        let language = if unsure { fallback } else { &detected.code };
+        let mut transcript = transcriber
+            .transcribe(c, &audio.in_language(language))
+            .await?;
+        transcript.detected = Some(detected);
+        transcript.fallback = unsure;
+        return Ok(transcript);
+    }
+
+    let transcript = transcriber.transcribe(c, audio).await?;
+    match transcript.detected {
+        Some(detected) if unsure(&detected) => {
+            let mut retried = transcriber
+                .transcribe(c, &audio.in_language(fallback))
+                .await?;
+            retried.detected = Some(detected);
+            retried.fallback = true;
+            Ok(retried)
+        }
+        _ => Ok(transcript),
+    }
+}
+
+// Whisper reports what it detected by name; the common ones are turned back
+// into the ISO 639-1 codes used everywhere else
+fn language_code(language: &str) -> String {
+    let language = language.trim().to_ascii_lowercase();
+    match language.as_str() {
+        "english" => "en",
+        "spanish" => "es",
+        "french" => "fr",
+        "german" => "de",
+        "italian" => "it",
+        "portuguese" => "pt",
+        "chinese" => "zh",
+        "vietnamese" => "vi",
+        "korean" => "ko",
+        "japanese" => "ja",
+        "russian" => "ru",
+        "arabic" => "ar",
+        "tagalog" => "tl",
+        "polish" => "pl",
+        "dutch" => "nl",
+        _ => return language,
+    }
+    .to_string()
+}
+
+fn transcription_error(provider: &str, message: impl std::fmt::Display) -> Error {
+    Error::Transcription(format!("{}: {}", provider, message))
+}
+
+// POST /v1/audio/transcriptions as OpenAI and most self-hosted Whisper servers
+// implement it
+#[derive(Debug)]
+pub struct OpenAiTranscriber {
+    endpoint: String,
+    model: String,
+    api_key: Option<String>,
+    fallback: Option<Fallback>,
+}
+
+// Whisper's temperature fallback, done client side: a transcription that
+// comes back empty, unsure or repetitive is tried again at the next
+// TRANSCRIPTION_TEMPERATURES step
+#[derive(Debug)]
+struct Fallback {
+    temperatures: Vec<f32>,
+    logprob_threshold: f64,
+    compression_ratio_threshold: f64,
+}
+
+// Above this, with a low log probability too, a segment is taken as silence
+// rather than retried, as Whisper does
+const NO_SPEECH_THRESHOLD: f64 = 0.6;
+
+#[derive(Debug, Deserialize)]
+struct VerboseTranscription {
+    text: String,
+    // not every server returns segments; without them only empty output is retried
+    #[serde(default)]
+    segments: Vec<Segment>,
+    // sent when no language was given; only some self-hosted servers say how sure
+    language: Option<String>,
+    language_probability: Option<f32>,
+    // asked for with timestamp_granularities[]=word; faster_whisper servers
+    // give them per segment instead
+    #[serde(default)]
+    words: Vec<TimedWord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TimedWord {
+    word: String,
+    start: f64,
+    end: f64,
+}
+
+impl VerboseTranscription {
+    fn into_transcript(self, detecting: bool) -> Transcript {
+        let detected = match (detecting, self.language) {
+            (true, Some(language)) => Some(DetectedLanguage {
+                code: language_code(&language),
+                probability: self.language_probability,
+            }),
+            _ => None,
+        };
+        let mut words = self.words;
+        if words.is_empty() {
+            words = self.segments.into_iter().flat_map(|s| s.words).collect();
+        }
+        Transcript {
+            text: self.text.trim().to_string(),
+            detected,
+            fallback: false,
+            words: words
+                .iter()
+                .filter_map(|w| Word::from_secs(&w.word, w.start, w.end))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Segment {
+    avg_logprob: Option<f64>,
+    compression_ratio: Option<f64>,
+    no_speech_prob: Option<f64>,
+    #[serde(default)]
+    words: Vec<TimedWord>,
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, n) = values.fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+    (n > 0).then(|| sum / n as f64)
+}
+
+enum Verdict {
+    Accept,
+    Silence,
+    Retry(&'static str),
+}
+
+impl Fallback {
+    fn judge(&self, result: &VerboseTranscription) -> (Verdict, f64) {
+        let logprob = mean(result.segments.iter().filter_map(|s| s.avg_logprob));
+        let no_speech = mean(result.segments.iter().filter_map(|s| s.no_speech_prob));
+        let compression = result
+            .segments
+            .iter()
+            .filter_map(|s| s.compression_ratio)
+            .fold(None, |max: Option<f64>, r| {
+                Some(max.map_or(r, |m| m.max(r)))
+            });
+        let unsure = logprob.is_some_and(|l| l < self.logprob_threshold);
+
+        let verdict = if unsure && no_speech.is_some_and(|p| p > NO_SPEECH_THRESHOLD) {
+            Verdict::Silence
+        } else if result.text.trim().is_empty() {
+            Verdict::Retry("empty")
+        } else if compression.is_some_and(|r| r > self.compression_ratio_threshold) {
+            Verdict::Retry("repetitive")
+        } else if unsure {
+            Verdict::Retry("low confidence")
+        } else {
+            Verdict::Accept
+        };
+        (verdict, logprob.unwrap_or(f64::MIN))
+    }
+}
+
+impl OpenAiTranscriber {
+    async fn request(
+        &self,
+        c: &ProcessorConfig,
+        audio: &Audio<'_>,
+        format: &str,
+        temperature: Option<f32>,
+    ) -> Result<reqwest::Response> {
+        let file = Part::bytes(audio.data.to_vec()).file_name(audio.name.to_string());
+        let mut form = Form::new()
+            .part("file", file)
+            .text("model", self.model.clone())
+            .text("response_format", format.to_string());
+        if !audio.detecting() {
+            form = form.text("language", audio.language.to_string());
+        }
+        if let Some(temperature) = temperature {
+            form = form.text("temperature", temperature.to_string());
+        }
+        // asking for words alone would leave out the segments fallback judges
+        if c.env.word_timestamps && format == "verbose_json" {
+            form = form
+                .text("timestamp_granularities[]", "word")
+                .text("timestamp_granularities[]", "segment");
+        }
+
+        let mut request = trace::inject(c.http_client.post(&self.endpoint)).multipart(form);
+        if let Some(key) = &self.api_key {
+            request = request.bearer_auth(key);
+        }
+        Ok(request.send().await?.error_for_status()?)
+    }
+
+    // The first transcription that passes, else the most confident one
+    async fn transcribe_with_fallback(
+        &self,
+        c: &ProcessorConfig,
+        audio: &Audio<'_>,
+        fallback: &Fallback,
+    ) -> Result<Transcript> {
+        let mut best: Option<(f64, Transcript)> = None;
+        for &temperature in &fallback.temperatures {
+            let result: VerboseTranscription = self
+                .request(c, audio, "verbose_json", Some(temperature))
+                .await?
+                .json()
+                .await?;
+            let (verdict, logprob) = fallback.judge(&result);
+            let transcript = result.into_transcript(audio.detecting());
+            let reason = match verdict {
+                Verdict::Accept => return Ok(transcript),
+                Verdict::Silence => {
+                    return Ok(Transcript {
+                        text: String::new(),
+                        ..transcript
+                    });
+                }
+                Verdict::Retry(reason) => reason,
+            };
+            debug!(file = %audio.name, temperature, reason, "Retrying transcription at a higher temperature");
+
+            if !transcript.text.is_empty() && best.as_ref().is_none_or(|(l, _)| logprob > *l) {
+                best = Some((logprob, transcript));
+            }
+        }
+
+        warn!(file = %audio.name, "No transcription passed at any temperature, keeping the most confident");
+        Ok(best.map(|(_, transcript)| transcript).unwrap_or_default())
+    }
+}
+
+impl Transcriber for OpenAiTranscriber {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Transcript>> {
+        Box::pin(async move {
+            match &self.fallback {
+                Some(fallback) => self.transcribe_with_fallback(c, audio, fallback).await,
+                // only verbose_json says which language was detected or
+                // when each word was said
+                None if audio.detecting() || c.env.word_timestamps => Ok(self
+                    .request(c, audio, "verbose_json", None)
+                    .await?
+                    .json::<VerboseTranscription>()
+                    .await?
+                    .into_transcript(audio.detecting())),
+                None => Ok(self
+                    .request(c, audio, "text", None)
+                    .await?
+                    .text()
+                    .await?
+                    .into()),
+            }
+        })
+    }
+}
+
+// whisper-asr-webservice's /asr endpoint, as served with its faster_whisper
+// engine. The model is whatever the server was started with.
+#[derive(Debug)]
+pub struct FasterWhisperTranscriber {
+    endpoint: String,
+    // /detect-language beside /asr
+    detect_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguageDetection {
+    language_code: String,
+    // only in newer releases
+    confidence: Option<f32>,
+}
+
+impl Transcriber for FasterWhisperTranscriber {
+    fn name(&self) -> &str {
+        "faster_whisper"
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Transcript>> {
+        Box::pin(async move {
+            let file = Part::bytes(audio.data.to_vec()).file_name(audio.name.to_string());
+            let form = Form::new().part("audio_file", file);
+            let output = if c.env.word_timestamps { "json" } else { "txt" };
+            let mut query = vec![
+                ("task", "transcribe"),
+                ("output", output),
+                ("encode", "true"),
+            ];
+            if c.env.word_timestamps {
+                query.push(("word_timestamps", "true"));
+            }
+            if !audio.detecting() {
+                query.push(("language", audio.language));
+            }
+
+            let response = trace::inject(c.http_client.post(&self.endpoint))
+                .query(&query)
+                .multipart(form)
+                .send()
+                .await?
+                .error_for_status()?;
+            if !c.env.word_timestamps {
+                return Ok(response.text().await?.into());
+            }
+            Ok(response
+                .json::<VerboseTranscription>()
+                .await?
+                .into_transcript(audio.detecting()))
+        })
+    }
+
+    fn detect_language<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Option<DetectedLanguage>>> {
+        Box::pin(async move {
+            let file = Part::bytes(audio.data.to_vec()).file_name(audio.name.to_string());
+            let form = Form::new().part("audio_file", file);
+
+            let detection: LanguageDetection =
+                trace::inject(c.http_client.post(&self.detect_endpoint))
+                    .query(&[("encode", "true")])
+                    .multipart(form)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+            Ok(Some(DetectedLanguage {
+                code: language_code(&detection.language_code),
+                probability: detection.confidence,
+            }))
+        })
+    }
+}
+
+// Deepgram's pre-recorded /v1/listen API, sent the audio as the request body
+#[derive(Debug)]
+pub struct DeepgramTranscriber {
+    endpoint: String,
+    model: Option<String>,
+    api_key: String,
+}
+
+impl Transcriber for DeepgramTranscriber {
+    fn name(&self) -> &str {
+        "deepgram"
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Transcript>> {
+        Box::pin(async move {
+            let mut query = vec![("smart_format", "true")];
+            if audio.detecting() {
+                query.push(("detect_language", "true"));
+            } else {
+                query.push(("language", audio.language));
+            }
+            if let Some(model) = &self.model {
+                query.push(("model", model));
+            }
+
+            let response: Value = trace::inject(c.http_client.post(&self.endpoint))
+                .query(&query)
+                .header("Authorization", format!("Token {}", self.api_key))
+                .header(CONTENT_TYPE, "audio/mp4")
+                .body(audio.data.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            let alternative = "/results/channels/0/alternatives/0";
+            let text = response
+                .pointer(&format!("{}/transcript", alternative))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| transcription_error(self.name(), "response has no transcript"))?;
+            let detected = response
+                .pointer("/results/channels/0/detected_language")
+                .and_then(Value::as_str)
+                .map(|code| DetectedLanguage {
+                    code: code.to_string(),
+                    probability: response
+                        .pointer("/results/channels/0/language_confidence")
+                        .and_then(Value::as_f64)
+                        .map(|p| p as f32),
+                });
+            let words = match response.pointer(&format!("{}/words", alternative)) {
+                Some(Value::Array(words)) if c.env.word_timestamps => words
+                    .iter()
+                    .filter_map(|w| {
+                        let word = w
+                            .get("punctuated_word")
+                            .or_else(|| w.get("word"))?
+                            .as_str()?;
+                        Word::from_secs(word, w.get("start")?.as_f64()?, w.get("end")?.as_f64()?)
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            Ok(Transcript {
+                text,
+                detected,
+                fallback: false,
+                words,
+            })
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct AwsCredentials {
+    aws_access_key_id: String,
+    aws_secret_access_key: String,
+    aws_session_token: Option<String>,
+    aws_region: Option<String>,
+    aws_default_region: Option<String>,
+}
+
+// Amazon Transcribe batch jobs. AWS only transcribes media in S3, so the audio
+// is copied under STAGING_PREFIX/transcribe for the length of the job, using
+// the same AWS_* credentials as the bucket.
+pub struct AwsTranscriber {
+    endpoint: String,
+    host: String,
+    region: String,
+    bucket: String,
+    credentials: AwsCredentials,
+}
+
+impl std::fmt::Debug for AwsTranscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsTranscriber")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .finish_non_exhaustive()
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(digest(&SHA256, data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> hmac::Tag {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes())
+}
+
+// Transcribe wants a locale; bare language codes get the most common one
+fn aws_language(language: &str) -> Option<&str> {
+    if language.contains('-') {
+        return Some(language);
+    }
+    match language {
+        "en" => Some("en-US"),
+        "es" => Some("es-US"),
+        "fr" => Some("fr-FR"),
+        "de" => Some("de-DE"),
+        "it" => Some("it-IT"),
+        "pt" => Some("pt-BR"),
+        _ => None,
+    }
+}
+
+impl AwsTranscriber {
+    // One Transcribe API action, signed with AWS Signature Version 4
+    async fn call(&self, c: &ProcessorConfig, action: &str, body: Value) -> Result<Value> {
+        let body = serde_json::to_vec(&body)?;
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let target = format!("Transcribe.{}", action);
+
+        let mut headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", self.host.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.aws_session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        headers.push(("x-amz-target", target));
+
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let canonical_request = format!(
+            "POST\n/\n\n{}\n{}\n{}",
+            canonical_headers,
+            signed_headers,
+            sha256_hex(&body)
+        );
+        let scope = format!("{}/{}/transcribe/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+
+        let key = format!("AWS4{}", self.credentials.aws_secret_access_key);
+        let key = hmac_sha256(key.as_bytes(), &date);
+        let key = hmac_sha256(key.as_ref(), &self.region);
+        let key = hmac_sha256(key.as_ref(), "transcribe");
+        let key = hmac_sha256(key.as_ref(), "aws4_request");
+        let signature = hex::encode(hmac_sha256(key.as_ref(), &string_to_sign));
+
+        let mut request = c.http_client.post(&self.endpoint).header(
+            "Authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.credentials.aws_access_key_id, scope, signed_headers, signature
+            ),
+        );
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(transcription_error(
+                self.name(),
+                format!("{} returned {}: {}", action, status, text),
+            ));
+        }
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    async fn run_job(
+        &self,
+        c: &ProcessorConfig,
+        job: &str,
+        key: &Path,
+        language: &str,
+    ) -> Result<Transcript> {
+        let mut request = json!({
+            "TranscriptionJobName": job,
+            "Media": { "MediaFileUri": format!("s3://{}/{}", self.bucket, key) },
+            "MediaFormat": "m4a",
+        });
+        match aws_language(language) {
+            Some(code) => request["LanguageCode"] = json!(code),
+            None => request["IdentifyLanguage"] = json!(true),
+        }
+        self.call(c, "StartTranscriptionJob", request).await?;
+
+        for _ in 0..AWS_POLL_ATTEMPTS {
+            tokio::time::sleep(AWS_POLL_INTERVAL).await;
+            let response = self
+                .call(
+                    c,
+                    "GetTranscriptionJob",
+                    json!({ "TranscriptionJobName": job }),
+                )
+                .await?;
+            let status = response
+                .pointer("/TranscriptionJob/TranscriptionJobStatus")
+                .and_then(Value::as_str);
+
+            match status {
+                Some("COMPLETED") => {}
+                Some("FAILED") => {
+                    let reason = response
+                        .pointer("/TranscriptionJob/FailureReason")
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown reason");
+                    return Err(transcription_error(self.name(), reason));
+                }
+                _ => continue,
+            }
+
+            let uri = response
+                .pointer("/TranscriptionJob/Transcript/TranscriptFileUri")
+                .and_then(Value::as_str)
+                .ok_or_else(|| transcription_error(self.name(), "job has no transcript"))?;
+            let transcript: Value = c
+                .http_client
+                .get(uri)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let text = transcript
+                .pointer("/results/transcripts/0/transcript")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| transcription_error(self.name(), "transcript file is empty"))?;
+            // only set when the job identified the language itself
+            let detected = response
+                .pointer("/TranscriptionJob/IdentifiedLanguageScore")
+                .and_then(Value::as_f64)
+                .zip(
+                    response
+                        .pointer("/TranscriptionJob/LanguageCode")
+                        .and_then(Value::as_str),
+                )
+                .map(|(score, code)| DetectedLanguage {
+                    code: code.to_string(),
+                    probability: Some(score as f32),
+                });
+            let words = match transcript.pointer("/results/items") {
+                Some(Value::Array(items)) if c.env.word_timestamps => {
+                    items.iter().filter_map(aws_word).collect()
+                }
+                _ => Vec::new(),
+            };
+            return Ok(Transcript {
+                text,
+                detected,
+                fallback: false,
+                words,
+            });
+        }
+
+        Err(transcription_error(
+            self.name(),
+            format!("job {} did not finish in time", job),
+        ))
+    }
+}
+
+// A pronunciation item; punctuation items have no times
+fn aws_word(item: &Value) -> Option<Word> {
+    let secs = |field: &str| item.get(field)?.as_str()?.parse::<f64>().ok();
+    let word = item.pointer("/alternatives/0/content")?.as_str()?;
+    Word::from_secs(word, secs("start_time")?, secs("end_time")?)
+}
+
+impl Transcriber for AwsTranscriber {
+    fn name(&self) -> &str {
+        "aws_transcribe"
+    }
+
+    fn transcribe<'a>(
+        &'a self,
+        c: &'a ProcessorConfig,
+        audio: &'a Audio<'a>,
+    ) -> BoxFuture<'a, Result<Transcript>> {
+        Box::pin(async move {
+            let id = Utc::now().timestamp_micros();
+            let key = Path::parse(format!(
+                "{}/transcribe/{}/{}",
+                c.env.staging_prefix, id, audio.name
+            ))?;
+            let job: String = format!("trunk-processor-{}-{}", id, audio.name)
+                .chars()
+                .map(|ch| match ch {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '_' | '-' => ch,
+                    _ => '-',
+                })
+                .take(200)
+                .collect();
+
+            c.s3_client
+                .put(&key, PutPayload::from_bytes(audio.data.clone()))
+                .await?;
+            let result = self.run_job(c, &job, &key, audio.language).await;
+
+            if let Err(e) = c.s3_client.delete(&key).await {
+                warn!(path = %key, error = %e, "Failed to remove transcription copy");
+            }
+            if let Err(e) = self
+                .call(
+                    c,
+                    "DeleteTranscriptionJob",
+                    json!({ "TranscriptionJobName": job }),
+                )
+                .await
+            {
+                warn!(job = %job, error = %e, "Failed to delete transcription job");
+            }
+            result
+        })
+    }
+}
+
+fn required<'a>(value: &'a Option<String>, field: &str, provider: &str) -> Result<&'a str> {
+    value
+        .as_deref()
+        .ok_or_else(|| Error::Configuration(format!("{} is required by {}", field, provider)))
+}
+
+fn endpoint_host(endpoint: &str) -> Result<String> {
+    Url::parse(endpoint)
+        .ok()
+        .and_then(|url| {
+            let host = url.host_str()?;
+            Some(match url.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            })
+        })
+        .ok_or_else(|| Error::Configuration(format!("invalid endpoint {}", endpoint)))
+}
+
+pub fn init(env: &EnvConfig) -> Result<Arc<dyn Transcriber>> {
+    Ok(match env.transcription_provider {
+        TranscriptionProvider::Openai => Arc::new(OpenAiTranscriber {
+            endpoint: required(
+                &env.transcription_endpoint,
+                "TRANSCRIPTION_ENDPOINT",
+                "openai",
+            )?
+            .to_string(),
+            model: required(&env.model_name, "MODEL_NAME", "openai")?.to_string(),
+            api_key: env.transcription_api_key.clone(),
+            fallback: env
+                .transcription_temperatures
+                .clone()
+                .map(|temperatures| Fallback {
+                    temperatures,
+                    logprob_threshold: env.transcription_logprob_threshold,
+                    compression_ratio_threshold: env.transcription_compression_ratio_threshold,
+                }),
+        }),
+        TranscriptionProvider::FasterWhisper => {
+            let endpoint = required(
+                &env.transcription_endpoint,
+                "TRANSCRIPTION_ENDPOINT",
+                "faster_whisper",
+            )?;
+            let detect_endpoint = Url::parse(endpoint)
+                .and_then(|url| url.join("detect-language"))
+                .map_err(|_| Error::Configuration(format!("invalid endpoint {}", endpoint)))?;
+
+            Arc::new(FasterWhisperTranscriber {
+                endpoint: endpoint.to_string(),
+                detect_endpoint: detect_endpoint.to_string(),
+            })
+        }
+        TranscriptionProvider::Deepgram => Arc::new(DeepgramTranscriber {
+            endpoint: env
+                .transcription_endpoint
+                .clone()
+                .unwrap_or_else(|| DEEPGRAM_ENDPOINT.to_string()),
+            model: env.model_name.clone(),
+            api_key: required(
+                &env.transcription_api_key,
+                "TRANSCRIPTION_API_KEY",
+                "deepgram",
+            )?
+            .to_string(),
+        }),
+        TranscriptionProvider::AwsTranscribe => {
+            let credentials = envy::from_env::<AwsCredentials>()
+                .map_err(|e| Error::Configuration(format!("AWS credentials: {}", e)))?;
+            let region = credentials
+                .aws_region
+                .clone()
+                .or_else(|| credentials.aws_default_region.clone())
+                .ok_or_else(|| {
+                    Error::Configuration("AWS_REGION is required by aws_transcribe".to_string())
+                })?;
+            let endpoint = env
+                .transcription_endpoint
+                .clone()
+                .unwrap_or_else(|| format!("https://transcribe.{}.amazonaws.com/", region));
+
+            Arc::new(AwsTranscriber {
+                host: endpoint_host(&endpoint)?,
+                endpoint,
+                region,
+                bucket: env.bucket_name.clone(),
+                credentials,
+            })
+        }
+    })
+}
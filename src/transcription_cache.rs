@@ -0,0 +1,224 @@
+use crate::common::ago;
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::CachedTranscription;
+use crate::schema::transcription_cache;
+use crate::transcribe::{self, Audio, DetectedLanguage, Transcript};
+
+use chrono::{DateTime, Utc};
+use diesel::{delete, dsl::count_star, insert_into, prelude::*, update};
+use ring::digest::Digest;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub entries: i64,
+    pub hits: i64,
+    pub oldest: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Invalidated {
+    pub deleted: usize,
+}
+
+// Provider and model, as either changing gives a different transcription
+fn provider(c: &ProcessorConfig) -> String {
+    match &c.env.model_name {
+        Some(model) => format!("{}:{}", c.transcriber.name(), model),
+        None => c.transcriber.name().to_string(),
+    }
+}
+
+fn expires_before(c: &ProcessorConfig) -> Option<DateTime<Utc>> {
+    c.env.transcription_cache_ttl_days.map(ago)
+}
+
+fn lookup(
+    c: &ProcessorConfig,
+    sha256: &str,
+    provider: &str,
+    language: &str,
+) -> Result<Option<Transcript>> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let key = (sha256, provider, language);
+    let mut query = transcription_cache::table
+        .find(key)
+        .select((
+            transcription_cache::transcription,
+            transcription_cache::detected_language,
+            transcription_cache::language_probability,
+            transcription_cache::language_fallback,
+            transcription_cache::words,
+        ))
+        .into_boxed();
+    if let Some(before) = expires_before(c) {
+        query = query.filter(transcription_cache::created_at.ge(before));
+    }
+    let Some((stored, detected, probability, fallback, words)) = query
+        .first::<(String, Option<String>, Option<f32>, bool, Option<Value>)>(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))?
+    else {
+        return Ok(None);
+    };
+
+    update(transcription_cache::table.find(key))
+        .set(transcription_cache::hits.eq(transcription_cache::hits + 1))
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let text = match &c.transcript_cipher {
+        Some(cipher) => cipher.decrypt(&stored, sha256)?,
+        None => stored,
+    };
+    Ok(Some(Transcript {
+        text,
+        detected: detected.map(|code| DetectedLanguage { code, probability }),
+        fallback,
+        words: words
+            .and_then(|words| serde_json::from_value(words).ok())
+            .unwrap_or_default(),
+    }))
+}
+
+fn store(c: &ProcessorConfig, entry: CachedTranscription) -> Result<()> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    insert_into(transcription_cache::table)
+        .values(&entry)
+        .on_conflict((
+            transcription_cache::sha256,
+            transcription_cache::provider,
+            transcription_cache::language,
+        ))
+        .do_update()
+        .set((
+            transcription_cache::transcription.eq(&entry.transcription),
+            transcription_cache::created_at.eq(entry.created_at),
+            transcription_cache::detected_language.eq(&entry.detected_language),
+            transcription_cache::language_probability.eq(entry.language_probability),
+            transcription_cache::language_fallback.eq(entry.language_fallback),
+            transcription_cache::words.eq(&entry.words),
+        ))
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(())
+}
+
+// Transcribes the audio, or returns what the same audio was transcribed as
+// before when TRANSCRIPTION_CACHE is on. The cache failing never fails the
+// transcription.
+pub async fn transcribe(
+    c: &ProcessorConfig,
+    audio: &Audio<'_>,
+    sha256: &Digest,
+) -> Result<Transcript> {
+    if !c.env.transcription_cache || !c.switches.database() {
+        return transcribe::transcribe(c, audio).await;
+    }
+    let sha256 = hex::encode(sha256.as_ref());
+    let provider = provider(c);
+
+    match lookup(c, &sha256, &provider, audio.language) {
+        Ok(Some(transcript)) => {
+            info!(file = %audio.name, sha256 = %sha256, "Using cached transcription");
+            return Ok(transcript);
+        }
+        Ok(None) => {}
+        Err(e) => warn!(file = %audio.name, error = %e, "Failed to read transcription cache"),
+    }
+
+    let transcript = transcribe::transcribe(c, audio).await?;
+    let stored = match &c.transcript_cipher {
+        Some(cipher) => cipher.encrypt(&transcript.text, &sha256)?,
+        None => transcript.text.clone(),
+    };
+    let entry = CachedTranscription {
+        sha256,
+        provider,
+        language: audio.language.to_string(),
+        transcription: stored,
+        hits: 0,
+        created_at: Utc::now(),
+        detected_language: transcript.detected.as_ref().map(|d| d.code.clone()),
+        language_probability: transcript.detected.as_ref().and_then(|d| d.probability),
+        language_fallback: transcript.fallback,
+        words: match &c.transcript_cipher {
+            Some(_) => None,
+            None if transcript.words.is_empty() => None,
+            None => Some(serde_json::to_value(&transcript.words).map_err(Error::JsonParsing)?),
+        },
+    };
+    if let Err(e) = store(c, entry) {
+        warn!(file = %audio.name, error = %e, "Failed to cache transcription");
+    }
+    Ok(transcript)
+}
+
+pub fn stats(c: &ProcessorConfig) -> Result<CacheStats> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let (entries, hits, oldest) = transcription_cache::table
+        .select((
+            count_star(),
+            diesel::dsl::sum(transcription_cache::hits),
+            diesel::dsl::min(transcription_cache::created_at),
+        ))
+        .first::<(i64, Option<i64>, Option<DateTime<Utc>>)>(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(CacheStats {
+        entries,
+        hits: hits.unwrap_or(0),
+        oldest,
+    })
+}
+
+// Drops cached transcriptions of one audio hash, cached before a time, or
+// all of them, so the next upload of that audio is transcribed again
+pub fn invalidate(
+    c: &ProcessorConfig,
+    sha256: Option<&str>,
+    before: Option<DateTime<Utc>>,
+) -> Result<usize> {
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+
+    let mut query = delete(transcription_cache::table).into_boxed();
+    if let Some(sha256) = sha256 {
+        query = query.filter(transcription_cache::sha256.eq(sha256.to_ascii_lowercase()));
+    }
+    if let Some(before) = before {
+        query = query.filter(transcription_cache::created_at.lt(before));
+    }
+    query
+        .execute(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+pub async fn run_periodic(c: ProcessorConfig, ttl: Duration, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+
+    loop {
+        interval.tick().await;
+        match invalidate(&c, None, Some(ago(ttl))) {
+            Ok(0) => {}
+            Ok(removed) => info!(removed, "Pruned expired cached transcriptions"),
+            Err(e) => error!(error = %e, "Failed to prune transcription cache"),
+        }
+    }
+}
@@ -1,29 +1,183 @@
+use crate::audio;
+use crate::auth::{bearer_token, upload_key};
+use crate::capture;
 use crate::common::*;
-use crate::config::{FilterConfig, ProcessorConfig};
+use crate::compression;
+use crate::config::{CollisionPolicy, FilterConfig, FilterDecision, ProcessorConfig};
+use crate::discord::notify_subscribers;
+use crate::discovery::{self, notify_discovery, track_talkgroup};
 use crate::error::{Error, Result};
-use crate::model::{self, AudioMetadata};
+use crate::hashing::HashAlgorithm;
+use crate::hooks;
+use crate::model::{
+    self, ArchiveReason, AudioMetadata, MetadataVersion, SYNTHETIC_TAG, Talkgroups,
+};
+use crate::notifier::Delivery;
+use crate::policy;
+use crate::retention;
 use crate::schema;
+use crate::scrub::scrub;
+use crate::segments::{self, Segment};
+use crate::trace::{self, TraceContext};
+use crate::transcribe::{self, AUTO_LANGUAGE, Audio, is_language_code};
+use crate::transcription_cache;
+use crate::words::{self, KeywordHit};
+use crate::worker::{self, Job};
 
 use axum::{
-    extract::{Multipart, State},
-    http::header::HeaderMap,
+    body::{Bytes, to_bytes},
+    extract::{FromRequest, Multipart, Request, State, multipart::Field},
+    http::{StatusCode, header::HeaderMap},
 };
 use chrono::{DateTime, Utc};
-use diesel::{insert_into, prelude::*};
-use object_store::{self, ObjectStore, PutPayload, aws::AmazonS3, path::Path};
-use reqwest::{
-    Client,
-    multipart::{Form, Part},
+use diesel::{delete, insert_into, prelude::*, update};
+use object_store::{self, ObjectStore, PutPayload, WriteMultipart, path::Path};
+use ring::digest::{Context, Digest, SHA256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
 };
-use std::{collections::HashMap, time::Instant};
-use tracing::info;
+use tracing::{Instrument, info, info_span, warn};
+
+// Parts of a streamed file that may be in flight to storage at once
+const STREAM_CONCURRENCY: usize = 2;
+// Extra text fields longer than this are dropped rather than kept as tags
+const MAX_FIELD_TEXT: usize = 1024;
+
+// Reads a field chunk by chunk so an oversized file is rejected as soon as it
+// crosses the limit, hashing as it goes
+async fn read_field(field: &mut Field<'_>, max_file_size: usize) -> Result<(Bytes, Digest)> {
+    let mut data = Vec::new();
+    let mut context = Context::new(&SHA256);
+
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| Error::Multipart(e.to_string()))?
+    {
+        if data.len() + chunk.len() > max_file_size {
+            return Err(Error::FileTooLarge {
+                size: data.len() + chunk.len(),
+                max_size: max_file_size,
+            });
+        }
+        context.update(&chunk);
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok((Bytes::from(data), context.finish()))
+}
+
+// Pipes a field into a multipart upload at `location` as it arrives, hashing
+// and enforcing the size limit on the way, so the file is never held in memory
+async fn stream_field(
+    field: &mut Field<'_>,
+    s3: &dyn ObjectStore,
+    location: &Path,
+    max_file_size: usize,
+) -> Result<(usize, Digest)> {
+    let mut upload = WriteMultipart::new(s3.put_multipart(location).await?);
+    let mut context = Context::new(&SHA256);
+    let mut size = 0;
+
+    let result = loop {
+        let chunk = match field.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(Error::Multipart(e.to_string())),
+        };
+        if size + chunk.len() > max_file_size {
+            break Err(Error::FileTooLarge {
+                size: size + chunk.len(),
+                max_size: max_file_size,
+            });
+        }
+        if let Err(e) = upload.wait_for_capacity(STREAM_CONCURRENCY).await {
+            break Err(Error::S3Upload(e));
+        }
+        context.update(&chunk);
+        upload.write(&chunk);
+        size += chunk.len();
+    };
+
+    if let Err(e) = result {
+        if let Err(abort) = upload.abort().await {
+            warn!(path = %location, error = %abort, "Failed to abort streamed upload");
+        }
+        return Err(e);
+    }
+    upload.finish().await?;
+
+    Ok((size, context.finish()))
+}
+
+// Streams an audio field into staging once its upload is known to be allowed.
+// Until then it is held in memory, so an upload without a valid key never
+// writes to the bucket.
+pub async fn read_audio(
+    field: &mut Field<'_>,
+    c: &ProcessorConfig,
+    file_name: String,
+    authorized: bool,
+) -> Result<UploadedFile> {
+    if !authorized {
+        let (data, sha256) = read_field(field, c.env.max_upload_size).await?;
+        return Ok(UploadedFile {
+            name: file_name,
+            size: data.len(),
+            data: FileData::Memory(data),
+            sha256,
+        });
+    }
+    let location = Path::parse(format!(
+        "{}/incoming/{}/{}",
+        c.env.staging_prefix,
+        Utc::now().timestamp_micros(),
+        file_name
+    ))?;
+    let (size, sha256) =
+        stream_field(field, &c.s3_client, &location, c.env.max_upload_size).await?;
+    Ok(UploadedFile {
+        name: file_name,
+        data: FileData::Staged(location),
+        size,
+        sha256,
+    })
+}
 
-const MAX_FILE_SIZE: usize = 50 * 1024 * 1024; // 50MB
+// The upload and any text fields besides `tags`, such as the `key`, `system`
+// or `test` some upload scripts add. Audio is only streamed once the upload is
+// `authorized`, or a `key` field before it is a valid one.
+async fn multipart_to_struct(
+    m: Multipart,
+    c: &ProcessorConfig,
+    authorized: bool,
+) -> Result<(UploadData, BTreeMap<String, String>)> {
+    let mut staged = Vec::new();
+    let mut fields = BTreeMap::new();
+    let result = read_multipart(m, c, authorized, &mut staged, &mut fields).await;
+    if result.is_err() {
+        for location in &staged {
+            if let Err(e) = c.s3_client.delete(location).await {
+                warn!(path = %location, error = %e, "Failed to discard streamed file");
+            }
+        }
+    }
+    result.map(|files| (files, fields))
+}
 
-async fn multipart_to_struct(mut m: Multipart) -> Result<UploadData> {
+async fn read_multipart(
+    mut m: Multipart,
+    c: &ProcessorConfig,
+    mut authorized: bool,
+    staged: &mut Vec<Path>,
+    fields: &mut BTreeMap<String, String>,
+) -> Result<UploadData> {
+    let max_file_size = c.env.max_upload_size;
     let mut files_map: HashMap<String, UploadedFile> = HashMap::new();
+    let mut tags = BTreeMap::new();
 
-    while let Some(field) = m
+    while let Some(mut field) = m
         .next_field()
         .await
         .map_err(|e| Error::Multipart(e.to_string()))?
@@ -33,30 +187,56 @@ async fn multipart_to_struct(mut m: Multipart) -> Result<UploadData> {
             .ok_or_else(|| Error::Multipart("Field missing name".to_string()))?
             .to_string();
 
-        let file_name = field
-            .file_name()
-            .ok_or_else(|| Error::MissingField(format!("Missing filename for field: {}", name)))?
-            .to_string();
-
-        let file_data = field
-            .bytes()
-            .await
-            .map_err(|e| Error::Multipart(e.to_string()))?;
-
-        if file_data.len() > MAX_FILE_SIZE {
-            return Err(Error::FileTooLarge {
-                size: file_data.len(),
-                max_size: MAX_FILE_SIZE,
-            });
+        if name == "tags" {
+            let text = field
+                .text()
+                .await
+                .map_err(|e| Error::Multipart(e.to_string()))?;
+            let parsed: BTreeMap<String, String> = serde_json::from_str(&text).map_err(|e| {
+                Error::InvalidRequest(format!("tags must be a JSON object of strings: {}", e))
+            })?;
+            tags = parsed
+                .into_iter()
+                .map(|(k, v)| (k.to_ascii_lowercase(), v))
+                .collect();
+            continue;
         }
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            if name == "json" || name == "audio" {
+                return Err(Error::MissingField(format!(
+                    "Missing filename for field: {}",
+                    name
+                )));
+            }
+            let text = field
+                .text()
+                .await
+                .map_err(|e| Error::Multipart(e.to_string()))?;
+            if name == "key" && !authorized {
+                authorized = upload_key(c, Some(&text)).is_ok();
+            }
+            if text.len() > MAX_FIELD_TEXT {
+                warn!(field = %name, size = text.len(), "Ignoring oversized upload field");
+            } else {
+                fields.insert(name, text);
+            }
+            continue;
+        };
 
-        match name.as_str() {
+        let file = match name.as_str() {
             "json" => {
                 if !file_name.ends_with(".json") {
                     return Err(Error::InvalidFileType(
                         "JSON file must have .json extension".to_string(),
                     ));
                 }
+                let (data, sha256) = read_field(&mut field, max_file_size).await?;
+                UploadedFile {
+                    name: file_name,
+                    size: data.len(),
+                    data: FileData::Memory(data),
+                    sha256,
+                }
             }
             "audio" => {
                 if !file_name.ends_with(".m4a") {
@@ -64,27 +244,33 @@ async fn multipart_to_struct(mut m: Multipart) -> Result<UploadData> {
                         "Audio file must have .m4a extension".to_string(),
                     ));
                 }
+                let file = read_audio(&mut field, c, file_name, authorized).await?;
+                if let FileData::Staged(location) = &file.data {
+                    staged.push(location.clone());
+                }
+                file
             }
             _ => {
-                return Err(Error::InvalidFileType(
-                    "Filename must match 'Audio' or 'json'".to_string(),
-                ));
+                warn!(field = %name, file = %file_name, "Ignoring unexpected file in upload");
+                continue;
             }
-        }
+        };
 
-        files_map.insert(
-            name,
-            UploadedFile {
-                name: file_name,
-                data: file_data,
-            },
-        );
+        // a repeated field replaces the earlier one
+        if let Some(FileData::Staged(previous)) = files_map.insert(name, file).map(|f| f.data)
+            && let Err(e) = c.s3_client.delete(&previous).await
+        {
+            warn!(path = %previous, error = %e, "Failed to discard streamed file");
+        }
     }
 
-    validate_and_build(files_map)
+    validate_and_build(files_map, tags)
 }
 
-fn validate_and_build(mut fields: HashMap<String, UploadedFile>) -> Result<UploadData> {
+fn validate_and_build(
+    mut fields: HashMap<String, UploadedFile>,
+    tags: BTreeMap<String, String>,
+) -> Result<UploadData> {
     let json_file = fields
         .remove("json")
         .ok_or_else(|| Error::MissingField(String::from("json")))?;
@@ -96,9 +282,48 @@ fn validate_and_build(mut fields: HashMap<String, UploadedFile>) -> Result<Uploa
     Ok(UploadData {
         json: json_file,
         audio: audio_file,
+        tags,
+        version: None,
+        language: None,
     })
 }
 
+fn tags_from_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let key = name.as_str().strip_prefix("x-tag-")?;
+            Some((key.to_string(), value.to_str().ok()?.to_string()))
+        })
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
+
+// Tags only the processor sets: a client claiming them could keep its calls
+// out of statistics or pass them off as quarantined
+const RESERVED_TAGS: [&str; 2] = [SYNTHETIC_TAG, policy::QUARANTINE_TAG];
+
+fn strip_reserved_tags(tags: &mut BTreeMap<String, String>) {
+    for name in RESERVED_TAGS {
+        if tags.remove(name).is_some() {
+            warn!(tag = name, "Dropping reserved tag from upload");
+        }
+    }
+}
+
+// A language code, or `auto` to have the provider detect it
+fn language_from_header(value: &str) -> Result<String> {
+    let language = value.trim().to_ascii_lowercase();
+    if language == AUTO_LANGUAGE || is_language_code(&language) {
+        Ok(language)
+    } else {
+        Err(Error::InvalidRequest(format!(
+            "X-Language must be an ISO 639 language code or auto, got {:?}",
+            value
+        )))
+    }
+}
+
 fn path_from_json(j: &AudioMetadata) -> Result<String> {
     let dt: DateTime<Utc> = j.call.start_time;
 
@@ -114,14 +339,89 @@ fn path_from_json(j: &AudioMetadata) -> Result<String> {
     Ok(format!("{}/{}", system_path, date_path))
 }
 
-async fn upload_file_to_s3(s3: &AmazonS3, path: &str, file: &UploadedFile) -> Result<()> {
+// `name` with the TDMA slot before its extension, left alone if it already has it
+fn slotted_name(name: &str, slot: i16) -> String {
+    let (stem, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let marker = format!("_slot{}", slot);
+    match (stem.ends_with(&marker), ext) {
+        (true, _) => name.to_string(),
+        (false, "") => format!("{}{}", stem, marker),
+        (false, ext) => format!("{}{}.{}", stem, marker, ext),
+    }
+}
+
+// Both slots of a Phase 2 channel can carry a call with the same talkgroup and
+// start time, which trunk-recorder names identically; TDMA_SLOT_NAMES stores
+// them apart instead of treating the second as a collision
+fn name_slot(files: &mut UploadData, slot: i16) {
+    files.audio.name = slotted_name(&files.audio.name, slot);
+    files.json.name = slotted_name(&files.json.name, slot);
+}
+
+fn suffixed_name(name: &str, n: u32) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}-{}.{}", stem, n, ext),
+        None => format!("{}-{}", name, n),
+    }
+}
+
+async fn existing_differs(
+    s3: &dyn ObjectStore,
+    location: &Path,
+    file: &UploadedFile,
+) -> Result<bool> {
+    match s3.head(location).await {
+        Ok(meta) if meta.size != file.size as u64 => Ok(true),
+        Ok(_) => Ok(object_digest(s3, location).await?.as_ref() != file.sha256.as_ref()),
+        Err(object_store::Error::NotFound { .. }) => Ok(false),
+        Err(e) => Err(Error::S3Upload(e)),
+    }
+}
+
+async fn resolve_collision(c: &ProcessorConfig, path: &str, files: &mut UploadData) -> Result<()> {
+    let location = Path::parse(format!("{}/{}", path, files.audio.name))?;
+    if !existing_differs(&c.s3_client, &location, &files.audio).await? {
+        return Ok(());
+    }
+
+    match c.env.path_collision_policy {
+        CollisionPolicy::Overwrite => {
+            info!(path = %location, "Overwriting existing object with different content");
+            Ok(())
+        }
+        CollisionPolicy::Reject => Err(Error::Conflict(format!(
+            "{} already exists with different content",
+            location
+        ))),
+        CollisionPolicy::Suffix => {
+            let mut n = 1;
+            loop {
+                let audio_name = suffixed_name(&files.audio.name, n);
+                let candidate = Path::parse(format!("{}/{}", path, audio_name))?;
+                if !existing_differs(&c.s3_client, &candidate, &files.audio).await? {
+                    info!(path = %location, renamed = %candidate, "Suffixed colliding upload");
+                    files.json.name = suffixed_name(&files.json.name, n);
+                    files.audio.name = audio_name;
+                    return Ok(());
+                }
+                n += 1;
+            }
+        }
+    }
+}
+
+async fn upload_file_to_s3(s3: &dyn ObjectStore, path: &str, file: &UploadedFile) -> Result<()> {
+    // streamed files are already in storage and are moved into place on commit
+    let FileData::Memory(data) = &file.data else {
+        return Ok(());
+    };
     let object_path = format!("{}/{}", path, file.name);
     let location = Path::parse(object_path)?;
 
     // Retry logic with exponential backoff
     let max_retries = 3;
     for attempt in 0..max_retries {
-        let payload = PutPayload::from_bytes(file.data.clone());
+        let payload = PutPayload::from_bytes(data.clone());
 
         match s3.put(&location, payload).await {
             Ok(_) => return Ok(()),
@@ -136,7 +436,7 @@ async fn upload_file_to_s3(s3: &AmazonS3, path: &str, file: &UploadedFile) -> Re
     Ok(())
 }
 
-async fn upload_files(s3: &AmazonS3, path: &str, files: &UploadData) -> Result<()> {
+async fn upload_files(s3: &dyn ObjectStore, path: &str, files: &UploadData) -> Result<()> {
     let json_fut = upload_file_to_s3(s3, path, &files.json);
     let audio_fut = upload_file_to_s3(s3, path, &files.audio);
 
@@ -144,130 +444,153 @@ async fn upload_files(s3: &AmazonS3, path: &str, files: &UploadData) -> Result<(
     Ok(())
 }
 
-async fn transcribe_audio(f: &UploadedFile, c: &ProcessorConfig) -> Result<String> {
-    let file = Part::bytes(f.data.to_vec()).file_name(f.name.clone());
+fn staged_location(staging: &str, file: &UploadedFile) -> Result<Path> {
+    match &file.data {
+        FileData::Staged(location) => Ok(location.clone()),
+        FileData::Memory(_) => Ok(Path::parse(format!("{}/{}", staging, file.name))?),
+    }
+}
 
-    let form = Form::new()
-        .part("file", file)
-        .text("model", c.env.model_name.clone())
-        .text("language", "en")
-        .text("response_format", "text");
+async fn commit_files(
+    s3: &dyn ObjectStore,
+    staging: &str,
+    path: &str,
+    files: &UploadData,
+) -> Result<()> {
+    for file in [&files.json, &files.audio] {
+        let from = staged_location(staging, file)?;
+        let to = Path::parse(format!("{}/{}", path, file.name))?;
+        match s3.rename(&from, &to).await {
+            // a streamed file an earlier attempt at this upload already moved into place
+            Err(object_store::Error::NotFound { .. })
+                if matches!(file.data, FileData::Staged(_)) && s3.head(&to).await.is_ok() => {}
+            result => result?,
+        }
+    }
 
-    let res = c
-        .http_client
-        .post(&c.env.transcription_endpoint)
-        .multipart(form)
-        .send()
-        .await
-        .map_err(Error::WebhookSend)?
-        .text()
-        .await
-        .map_err(Error::WebhookSend)?;
+    info!(path = %path, "Committed staged files");
+    Ok(())
+}
+
+// Streamed files are left for the caller, which may still retry the upload
+async fn discard_files(s3: &dyn ObjectStore, staging: &str, files: &UploadData) {
+    for file in [&files.json, &files.audio] {
+        if matches!(file.data, FileData::Staged(_)) {
+            continue;
+        }
+        let location = match staged_location(staging, file) {
+            Ok(location) => location,
+            Err(_) => continue,
+        };
+        if let Err(e) = s3.delete(&location).await {
+            warn!(path = %location, error = %e, "Failed to discard staged file");
+        }
+    }
+}
 
-    Ok(res)
+// Deletes the streamed copies of an upload that will not be processed
+pub async fn discard_incoming(c: &ProcessorConfig, files: &UploadData) {
+    for file in [&files.json, &files.audio] {
+        if let FileData::Staged(location) = &file.data
+            && let Err(e) = c.s3_client.delete(location).await
+        {
+            warn!(path = %location, error = %e, "Failed to discard streamed file");
+        }
+    }
 }
 
-async fn create_webhook(m: &AudioMetadata, tr: String) -> Result<String> {
+// Keyword mentions link to the audio from when they were said. With speaker
+// segments the transcription is given per radio.
+pub fn create_embed(
+    m: &AudioMetadata,
+    tr: Option<String>,
+    audio_url: Option<&str>,
+    keywords: &[KeywordHit],
+    segments: &[Segment],
+) -> WebhookEmbed {
     let timestamp = format_timestamp_from_datetime(m.call.start_time);
 
-    let field_types = vec![
+    let mut field_types = vec![
         EmbedFieldType::Timestamp(timestamp.clone()),
         EmbedFieldType::RadioIds(m.src_list.iter().map(|x| x.src).collect()),
-        EmbedFieldType::Transcription(tr),
     ];
+    if let Some(tr) = tr {
+        let tr = match segments {
+            [] => tr,
+            segments => segments::render(segments),
+        };
+        field_types.push(EmbedFieldType::Transcription(tr));
+    }
+    if !keywords.is_empty() {
+        let mentions: Vec<String> = keywords
+            .iter()
+            .map(|hit| match audio_url {
+                Some(url) => format!("[{} at {}]({})", hit.keyword, hit.at(), hit.link(url)),
+                None => format!("{} at {}", hit.keyword, hit.at()),
+            })
+            .collect();
+        field_types.push(EmbedFieldType::Keywords(mentions.join(", ")));
+    }
+    if let Some(url) = audio_url {
+        field_types.push(EmbedFieldType::AudioUrl(url.to_string()));
+    }
 
     let fields: Vec<EmbedField> = field_types
         .into_iter()
         .map(|field_type| field_type.into_embed_field())
         .collect();
 
-    let embeds = vec![WebhookEmbed {
+    WebhookEmbed {
         color: "12110930".to_string(),
         timestamp,
         title: format!(
-            "{} - {}",
-            m.talkgroup.talkgroup_group, m.talkgroup.talkgroup_description
+            "{}{} - {}",
+            if m.call.synthetic() {
+                "Test call: "
+            } else {
+                ""
+            },
+            m.talkgroup.talkgroup_group,
+            m.talkgroup.talkgroup_description
         ),
         fields,
-    }];
-
-    let webhook = Webhook {
-        username: "Trunk Recorder".to_owned(),
-        avatar_url: "https://raw.githubusercontent.com/TrunkRecorder/trunkrecorder.github.io/refs/heads/main/static/img/radio.png".to_owned(),
-        embeds,
-    };
-
-    Ok(serde_json::to_string(&webhook)?)
-}
-
-async fn send_webhook(
-    client: &Client,
-    url: &str,
-    m: &AudioMetadata,
-    t: String,
-    f: UploadedFile,
-) -> Result<()> {
-    let webhook = create_webhook(m, t).await?;
-
-    let file = Part::bytes(f.data.to_vec()).file_name(f.name.clone());
-    let form = Form::new()
-        .part("file1", file)
-        .text("payload_json", webhook);
-
-    client
-        .post(url)
-        .multipart(form)
-        .send()
-        .await?
-        .error_for_status()?;
-
-    Ok(())
+        footer: None,
+    }
 }
 
-async fn filter_on_metadata(m: &AudioMetadata, c: &FilterConfig) -> bool {
-    let tgid_as_string = &m.talkgroup.talkgroup.to_string();
-    let deny_tgid = format!("!{}", tgid_as_string);
-
-    if !c.tgid().is_empty() {
-        // if tgid filter contains negated tgid, !do_transcription early
-        if c.tgid().contains(&deny_tgid) {
-            info!(tgid = %tgid_as_string, "Matched denied talkgroup, no transcribe");
-            return false;
-        }
-        // if tgid filter contains tgid, do_transcription
-        else if c.tgid().contains(tgid_as_string) {
-            info!(tgid = %tgid_as_string, "Matched talkgroup, transcribing");
-            return true;
-        }
-    };
-
-    if !c.group().is_empty()
-        // if group in include list, do_transcription
-        && c.group().contains(&m.talkgroup.talkgroup_group)
-    {
-        info!(group = %m.talkgroup.talkgroup_group, "Matched group, transcribing");
-        return true;
-    };
-
-    // return false if not negated by previous tgid include, or group include
-    info!(group = %m.talkgroup.talkgroup_group, tgid = %tgid_as_string, "Filter values unmatched");
-    false
+fn filter_on_metadata(m: &AudioMetadata, c: &FilterConfig) -> FilterDecision {
+    let decision = c.decide(m.talkgroup.talkgroup, &m.talkgroup.talkgroup_group);
+    info!(
+        tgid = m.talkgroup.talkgroup,
+        group = %m.talkgroup.talkgroup_group,
+        rule = %decision.rule,
+        transcribe = decision.transcribe,
+        "Filter decision"
+    );
+    decision
 }
 
-fn set_call_ids<T: model::IsList>(v: &mut [T], id: String) {
+fn set_call_ids<T: model::IsList>(v: &mut [T], id: String, algorithm: HashAlgorithm) {
     for item in v.iter_mut() {
         item.set_call_id(id.clone());
-        item.calculate_hash();
+        item.calculate_hash(algorithm);
     }
 }
 
-async fn write_to_database(m: &AudioMetadata, c: &ProcessorConfig) -> Result<()> {
+// `checksum` is the upload's, recorded so an identical one can be skipped
+async fn write_to_database(m: &AudioMetadata, checksum: &str, c: &ProcessorConfig) -> Result<()> {
     use schema::calls::dsl::*;
+    use schema::emergencies::dsl::*;
     use schema::freqlist::dsl::*;
     use schema::sources::dsl::*;
     use schema::srclist::dsl::*;
     use schema::talkgroups::dsl::*;
 
+    if !c.switches.database() {
+        info!(file = %m.call.filename, "Database writes disabled, skipping");
+        return Ok(());
+    }
+
     let mut connection = c
         .clone()
         .db_pool
@@ -284,6 +607,12 @@ async fn write_to_database(m: &AudioMetadata, c: &ProcessorConfig) -> Result<()>
             .map_err(|e| Error::Database(e.to_string()))?;
     }
 
+    let stored: Option<Talkgroups> = talkgroups
+        .find(m.talkgroup.talkgroup)
+        .select(Talkgroups::as_select())
+        .first(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))?;
     insert_into(talkgroups)
         .values(&m.talkgroup)
         .on_conflict(schema::talkgroups::talkgroup)
@@ -291,22 +620,57 @@ async fn write_to_database(m: &AudioMetadata, c: &ProcessorConfig) -> Result<()>
         .set(&m.talkgroup)
         .execute(&mut connection)
         .map_err(|e| Error::Database(e.to_string()))?;
+    if let Some(stored) = stored {
+        let changes = discovery::metadata_changes(&stored, &m.talkgroup);
+        if !changes.is_empty() {
+            discovery::report_changes(c, m, changes);
+        }
+    }
+
+    let mut call = m.call.clone();
+    let compressed = compression::compress(
+        c,
+        &call.filename,
+        &mut call.transcription,
+        &mut call.public_transcription,
+    )?;
+    if let Some(cipher) = &c.transcript_cipher {
+        cipher.seal_call(&mut call)?;
+    }
 
     connection
         .transaction(|conn| {
             let _call_id: String = insert_into(calls)
-                .values(&m.call)
+                .values(&call)
                 .on_conflict(schema::calls::filename)
                 .do_update()
-                .set(&m.call)
+                .set(&call)
                 .returning(schema::calls::filename)
                 .get_result(conn)?;
+            compression::store(conn, &_call_id, compressed.as_ref())?;
 
             let mut src_list = m.src_list.clone();
             let mut freq_list = m.freq_list.clone();
 
-            set_call_ids(&mut src_list, _call_id.clone());
-            set_call_ids(&mut freq_list, _call_id);
+            let algorithm = c.env.hash_algorithm;
+            set_call_ids(&mut src_list, _call_id.clone(), algorithm);
+            set_call_ids(&mut freq_list, _call_id.clone(), algorithm);
+
+            // rows keyed by another algorithm wouldn't conflict with these, so
+            // a call stored again would have its lists twice
+            let version = algorithm.version();
+            delete(
+                srclist
+                    .filter(schema::srclist::call_id.eq(&_call_id))
+                    .filter(schema::srclist::hash_version.ne(version)),
+            )
+            .execute(conn)?;
+            delete(
+                freqlist
+                    .filter(schema::freqlist::call_id.eq(&_call_id))
+                    .filter(schema::freqlist::hash_version.ne(version)),
+            )
+            .execute(conn)?;
 
             insert_into(srclist)
                 .values(src_list)
@@ -320,74 +684,486 @@ async fn write_to_database(m: &AudioMetadata, c: &ProcessorConfig) -> Result<()>
                 .do_nothing()
                 .execute(conn)?;
 
+            delete(emergencies.filter(schema::emergencies::call_id.eq(&m.call.filename)))
+                .execute(conn)?;
+            insert_into(emergencies)
+                .values(m.emergencies())
+                .execute(conn)?;
+
+            insert_into(schema::upload_checksums::table)
+                .values((
+                    schema::upload_checksums::checksum.eq(checksum),
+                    schema::upload_checksums::call_id.eq(&_call_id),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
             diesel::result::QueryResult::Ok(())
         })
         .map_err(|e| Error::Database(e.to_string()))
 }
 
-// ---------------------------------------------------------------------
-// --- HANDLER AND MAIN ---
-// ---------------------------------------------------------------------
+// Moves the staged files into place once the call's rows are written. If the
+// move fails the rows are taken out again, so no call points at missing audio.
+async fn commit(
+    config: &ProcessorConfig,
+    meta: &AudioMetadata,
+    staging: &str,
+    path: &str,
+    files: &UploadData,
+) -> Result<()> {
+    let result = config
+        .pools
+        .storage
+        .run(commit_files(&config.s3_client, staging, path, files))
+        .await;
+    if result.is_err()
+        && config.switches.database()
+        && let Err(e) = retention::delete_rows(config, std::slice::from_ref(&meta.call.filename))
+    {
+        warn!(file = %meta.call.filename, error = %e, "Failed to remove the rows of an uncommitted call");
+    }
+    result
+}
 
-pub async fn upload(
-    State(config): State<ProcessorConfig>,
-    headers: HeaderMap,
-    m: Multipart,
-) -> Result<String> {
-    let upload_start = Instant::now();
-    info!("Starting upload processing");
+async fn process_files(
+    meta: &mut AudioMetadata,
+    files: &UploadData,
+    path: &str,
+    staging: &str,
+    do_transcription: bool,
+    config: &ProcessorConfig,
+) -> Result<Option<Notice>> {
+    let storage = &config.pools.storage;
+    let checksum = files.checksum();
 
-    let files: UploadData = multipart_to_struct(m).await?;
+    if !do_transcription {
+        storage
+            .run(upload_files(&config.s3_client, staging, files))
+            .await?;
+
+        meta.call.transcription = None;
+        meta.call.public_transcription = None;
+        write_to_database(meta, &checksum, config).await?;
+        commit(config, meta, staging, path, files).await?;
+        return Ok(None);
+    }
 
-    let meta = &mut files.deserialize_json()?;
-    let path: String = path_from_json(meta)?;
+    // read once up front: the streamed audio is moved into place by
+    // commit_files, and the notifiers send it afterwards
+    let audio = Audio {
+        name: &files.audio.name,
+        data: files.audio.bytes(&config.s3_client).await?,
+        language: transcribe::language(config, meta.talkgroup.talkgroup, files.language.as_deref()),
+    };
+    let upload_fut = storage.run(upload_files(&config.s3_client, staging, files));
+    let transcription_fut = async {
+        let result = config
+            .pools
+            .transcription
+            .run(transcription_cache::transcribe(
+                config,
+                &audio,
+                &files.audio.sha256,
+            ))
+            .await;
+        // the call is kept either way, flagged to be transcribed later
+        Ok::<_, Error>(match result {
+            Ok(transcript) => Some(transcript),
+            Err(e) => {
+                warn!(file = %meta.call.filename, error = %e, "Transcription failed, storing the call untranscribed");
+                None
+            }
+        })
+    };
+
+    let (_, transcript) = tokio::try_join!(upload_fut, transcription_fut)?;
+    let Some(transcript) = transcript else {
+        meta.call.transcription = None;
+        meta.call.public_transcription = None;
+        meta.call.transcription_failed = true;
+        write_to_database(meta, &checksum, config).await?;
+        commit(config, meta, staging, path, files).await?;
+        return Ok(None);
+    };
+    let transcription = transcript.text;
+    let keywords = words::keyword_hits(config, &transcript.words);
+    let segments = segments::split(config, &meta.src_list, &meta.sources, &transcript.words);
+
+    meta.call.transcription = Some(transcription.clone());
+    if let Some(detected) = transcript.detected {
+        meta.call.detected_language = Some(detected.code);
+        meta.call.language_probability = detected.probability;
+    }
+    meta.call.language_fallback = transcript.fallback;
+    meta.call.public_transcription = config.env.pii_scrubbing.then(|| scrub(&transcription));
+
+    write_to_database(meta, &checksum, config).await?;
+    words::store(config, &meta.call.filename, &transcript.words)?;
+    segments::store(config, &meta.call.filename, &segments)?;
+    commit(config, meta, staging, path, files).await?;
+
+    Ok(Some(Notice {
+        transcription,
+        audio: audio.data,
+        language: audio.language.to_string(),
+        keywords,
+        segments,
+    }))
+}
+
+// A call ingest has stored, with what notifying it still needs
+pub struct Stored {
+    pub meta: AudioMetadata,
+    // its talkgroup was newly discovered
+    pub discovered: bool,
+    // set when it was transcribed, for the notifiers
+    pub notice: Option<Notice>,
+}
+
+// What the notifiers are sent with a transcribed call's metadata
+pub struct Notice {
+    pub transcription: String,
+    pub audio: Bytes,
+    pub language: String,
+    pub keywords: Vec<KeywordHit>,
+    pub segments: Vec<Segment>,
+}
+
+impl Notice {
+    // The audio as the notifiers take it, named after the stored call
+    pub fn audio<'a>(&'a self, filename: &'a str) -> Audio<'a> {
+        Audio {
+            name: filename.rsplit('/').next().unwrap_or(filename),
+            data: self.audio.clone(),
+            language: &self.language,
+        }
+    }
+}
+
+// Stores one call's audio and metadata, transcribing it unless archived. Shared
+// by the upload handler and the importers; notification is left to complete.
+pub async fn ingest(
+    config: &ProcessorConfig,
+    files: &mut UploadData,
+    archive: Option<ArchiveReason>,
+) -> Result<Stored> {
+    let mut meta = files.deserialize_json()?;
+    let path: String = path_from_json(&meta)?;
+    if config.env.tdma_slot_names && meta.call.phase2_tdma != 0 {
+        name_slot(files, meta.call.tdma_slot);
+    }
+    resolve_collision(config, &path, files).await?;
 
     meta.call.filename = path.clone() + "/" + &files.audio.name;
     meta.call.talkgroup = meta.talkgroup.talkgroup;
+    meta.call.tags = serde_json::to_value(&files.tags).map_err(Error::JsonParsing)?;
 
     info!(talkgroup = meta.talkgroup.talkgroup, path = %path, "Processed audio metadata");
 
-    let do_transcription = if headers.contains_key("archive") {
-        info!(file = %meta.call.filename, "Set to archive:");
-        false
-    } else if config.filter.enabled() {
-        filter_on_metadata(meta, &config.filter).await
+    // a test call is made up, so says nothing of what the system carries
+    let synthetic = meta.call.synthetic();
+    let discovered = !synthetic && config.switches.database() && track_talkgroup(&meta, config)?;
+
+    meta.call.archive_reason = archive;
+    if archive.is_none()
+        && !synthetic
+        && let Some(activity) = &config.activity
+    {
+        activity
+            .lock()
+            .expect("talkgroup activity poisoned")
+            .record(&meta);
+    }
+
+    let filter = config.filter.load_full();
+    let decision = if let Some(reason) = archive {
+        info!(file = %meta.call.filename, reason = ?reason, "Set to archive:");
+        FilterDecision::new(false, format!("archive:{}", reason.as_str()))
+    } else if !config.switches.transcription() {
+        info!(file = %meta.call.filename, "Transcription disabled, skipping");
+        FilterDecision::new(false, "transcription_disabled")
+    } else if filter.enabled() {
+        filter_on_metadata(&meta, &filter)
     } else {
-        false
+        FilterDecision::new(false, "no_filter")
     };
+    let do_transcription = decision.transcribe;
+    meta.call.filter_action = Some(
+        if decision.transcribe {
+            "transcribe"
+        } else {
+            "skip"
+        }
+        .to_string(),
+    );
+    meta.call.filter_rule = Some(decision.rule);
 
-    if !do_transcription {
-        let upload_fut = upload_files(&config.s3_client, &path, &files);
+    let staging = format!(
+        "{}/{}/{}",
+        config.env.staging_prefix,
+        Utc::now().timestamp_micros(),
+        path
+    );
 
-        meta.call.transcription = None;
-        let db_fut = write_to_database(meta, &config);
+    match process_files(&mut meta, files, &path, &staging, do_transcription, config).await {
+        Ok(notice) => Ok(Stored {
+            meta,
+            discovered,
+            notice,
+        }),
+        Err(e) => {
+            discard_files(&config.s3_client, &staging, files).await;
+            Err(e)
+        }
+    }
+}
+
+// Sends a transcribed call to the notifiers. Each destination claims the call
+// before sending, so this can run again for the ones that failed.
+async fn dispatch(config: &ProcessorConfig, meta: &AudioMetadata, notice: &Notice) -> Result<()> {
+    let filename = &meta.call.filename;
+    let audio = notice.audio(filename);
+    let audio_url = audio::notification_url(config, filename).await;
+    let delivery = Delivery {
+        meta,
+        transcription: &notice.transcription,
+        audio: &audio,
+        audio_url: audio_url.as_deref(),
+        keywords: &notice.keywords,
+        segments: &notice.segments,
+    };
+    config
+        .notifiers
+        .load_full()
+        .dispatch(config, &delivery)
+        .await
+}
+
+// Sends a stored call to the notifiers and subscribers and, for a new
+// talkgroup, the discovery webhook, then hands it to the post-upload hook
+pub async fn complete(
+    config: &ProcessorConfig,
+    stored: &Stored,
+    archive: Option<ArchiveReason>,
+) -> Result<()> {
+    let meta = &stored.meta;
+    let discovered = stored.discovered;
+    let notify = config.switches.notifications();
+    if notify && let Some(notice) = &stored.notice {
+        dispatch(config, meta, notice).await?;
+    }
+    if archive.is_none() && notify {
+        notify_subscribers(meta, config).await?;
+    }
+
+    if discovered
+        && notify
+        && config.env.notify_discovered_talkgroups
+        && archive != Some(ArchiveReason::Backfill)
+    {
+        notify_discovery(meta, config).await;
+    }
+
+    hooks::spawn(config, meta);
+    if archive.is_none() {
+        config.events.publish(meta);
+    }
+    Ok(())
+}
 
-        tokio::try_join!(upload_fut, db_fut)?;
-    } else if do_transcription {
-        let upload_fut = upload_files(&config.s3_client, &path, &files);
-        let transcription_fut = transcribe_audio(&files.audio, &config);
+// ---------------------------------------------------------------------
+// --- HANDLER AND MAIN ---
+// ---------------------------------------------------------------------
 
-        let (_, transcription) = tokio::try_join!(upload_fut, transcription_fut)?;
+pub async fn upload(
+    State(config): State<ProcessorConfig>,
+    request: Request,
+) -> Result<(StatusCode, String)> {
+    let ctx = TraceContext::from_headers(request.headers());
+    let span = info_span!("upload", trace_id = %ctx.trace_id(), span_id = %ctx.span_id());
 
-        meta.call.transcription = Some(transcription.clone());
+    trace::scope(ctx, receive_upload(config, request))
+        .instrument(span)
+        .await
+}
 
-        let db_fut = write_to_database(meta, &config);
-        let webhook_fut = send_webhook(
-            &config.http_client,
-            &config.env.discord_webhook,
-            meta,
-            transcription,
-            files.audio,
-        );
+async fn receive_upload(config: ProcessorConfig, request: Request) -> Result<(StatusCode, String)> {
+    info!("Starting upload processing");
 
-        tokio::try_join!(db_fut, webhook_fut)?;
+    // a wrong key in the header is refused before the body is read
+    let header_key = bearer_token(request.headers());
+    if header_key.is_some() {
+        upload_key(&config, header_key)?;
     }
 
-    let duration = Instant::now().duration_since(upload_start);
-    info!(
-        duration_ms = duration.as_millis(),
-        "Upload processing completed successfully"
-    );
+    if !config.env.upload_capture {
+        let headers = request.headers().clone();
+        let m = Multipart::from_request(request, &config)
+            .await
+            .map_err(|e| Error::InvalidRequest(e.body_text()))?;
+        return handle_upload(config, headers, m, false).await;
+    }
+
+    // the whole body is held so a failed upload can be kept as it arrived
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, config.env.upload_body_limit())
+        .await
+        .map_err(|e| Error::Multipart(e.to_string()))?;
+    let m = capture::multipart(&config, parts.headers.clone(), body.clone()).await?;
+    let result = handle_upload(config.clone(), parts.headers.clone(), m, false).await;
+    if let Err(e) = &result
+        && capture::worth_keeping(e)
+    {
+        capture::save(&config, &parts.headers, body, e).await;
+    }
+    result
+}
 
-    Ok("Upload successful".to_string())
+// The call an identical upload already stored, if any, counting this upload
+// as a duplicate of it
+fn stored_duplicate(c: &ProcessorConfig, checksum: &str) -> Result<Option<String>> {
+    use schema::upload_checksums;
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    update(upload_checksums::table.find(checksum))
+        .set((
+            upload_checksums::duplicates.eq(upload_checksums::duplicates + 1),
+            upload_checksums::last_duplicate_at.eq(Utc::now()),
+        ))
+        .returning(upload_checksums::call_id)
+        .get_result(&mut connection)
+        .optional()
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+// Whether the same upload was seen within the replay window or is already
+// stored as a call, discarding its files if so. trunk-recorder retries an
+// upload it didn't see answered, which would store and notify the call again.
+pub async fn already_processed(config: &ProcessorConfig, files: &UploadData) -> Result<bool> {
+    let checksum = files.checksum();
+    if let Some(cache) = &config.replay_cache
+        && cache
+            .lock()
+            .expect("replay cache poisoned")
+            .contains(&files.audio.name, &checksum)
+    {
+        info!(file = %files.audio.name, "Replayed upload within window, skipping");
+        discard_incoming(config, files).await;
+        return Ok(true);
+    }
+    if config.env.deduplicate_uploads
+        && config.switches.database()
+        && let Some(stored) = stored_duplicate(config, &checksum)?
+    {
+        info!(file = %files.audio.name, stored = %stored, "Duplicate of a stored call, skipping");
+        discard_incoming(config, files).await;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+// Reads, checks and queues an upload. A replayed capture skips the key check,
+// as its Authorization header was dropped when it was kept.
+pub async fn handle_upload(
+    config: ProcessorConfig,
+    headers: HeaderMap,
+    m: Multipart,
+    replayed: bool,
+) -> Result<(StatusCode, String)> {
+    let header_key = bearer_token(&headers);
+    // a header key was checked before the body was read
+    let authorized = replayed || upload_key(&config, header_key).is_ok();
+    let (mut files, mut fields) = multipart_to_struct(m, &config, authorized).await?;
+    let form_key = fields.remove("key");
+    // other fields tag the call, below the `tags` field and X-Tag-* headers
+    for (name, value) in fields {
+        files.tags.entry(name.to_ascii_lowercase()).or_insert(value);
+    }
+
+    let archive = headers
+        .get("archive")
+        .map(|v| ArchiveReason::from_header(v.to_str().unwrap_or_default()));
+    let checked = read_upload_headers(&mut files, &headers).and_then(|meta| {
+        if !replayed {
+            authorize_system(
+                &config,
+                header_key.or(form_key.as_deref()),
+                &meta.call.short_name,
+            )?;
+        }
+        policy::check(&config, &meta, &files, archive)
+    });
+    let quarantined = match checked {
+        Ok(quarantined) => quarantined,
+        Err(e) => {
+            discard_incoming(&config, &files).await;
+            return Err(e);
+        }
+    };
+
+    if already_processed(&config, &files).await? {
+        return Ok((StatusCode::OK, "Upload already processed".to_string()));
+    }
+    let upload_checksum = files.checksum();
+    let replay_key = (files.audio.name.clone(), upload_checksum);
+    let archive = match &quarantined {
+        Some(violation) => Some(policy::quarantine(&mut files, violation)),
+        None => archive,
+    };
+
+    let job = Job {
+        files,
+        archive,
+        replay_key,
+        trace: trace::current().unwrap_or_else(|| TraceContext::from_headers(&headers)),
+        enqueued_at: Instant::now(),
+        failed_id: None,
+        stored: None,
+    };
+    let file = job.files.audio.name.clone();
+    worker::enqueue(&config, job).await?;
+
+    info!(file = %file, queued = config.queue.queued(), "Upload queued");
+    let message = match quarantined {
+        Some(violation) => format!("Upload quarantined by policy {}", violation.rule.code()),
+        None => "Upload queued".to_string(),
+    };
+    Ok((StatusCode::ACCEPTED, message))
+}
+
+// Checks an API_KEYS key may upload calls for `system`, so one site's key
+// can't write into another system's archive
+pub fn authorize_system(c: &ProcessorConfig, provided: Option<&str>, system: &str) -> Result<()> {
+    let Some(key) = upload_key(c, provided)? else {
+        return Ok(());
+    };
+    if !key.allows(system) {
+        warn!(key = %key.id, system = %system, "Upload key not allowed for system, rejecting");
+        return Err(Error::InvalidApiKey(format!(
+            "key may not upload calls for system {}",
+            system
+        )));
+    }
+    info!(key = %key.id, system = %system, "Upload key accepted");
+    Ok(())
+}
+
+// Applies the per-upload headers and parses the metadata, so a bad upload is
+// rejected before it is queued
+fn read_upload_headers(files: &mut UploadData, headers: &HeaderMap) -> Result<AudioMetadata> {
+    if let Some(version) = headers.get("x-metadata-version") {
+        files.version = Some(MetadataVersion::from_header(
+            version.to_str().unwrap_or_default(),
+        )?);
+    }
+    if let Some(language) = headers.get("x-language") {
+        files.language = Some(language_from_header(language.to_str().unwrap_or_default())?);
+    }
+    files.tags.extend(tags_from_headers(headers));
+    strip_reserved_tags(&mut files.tags);
+    files.deserialize_json()
 }
@@ -0,0 +1,142 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+use crate::model::TranscriptWord;
+use crate::schema::transcript_words;
+use crate::transcribe::Word;
+
+use diesel::{delete, insert_into, prelude::*};
+use serde::Serialize;
+use tracing::warn;
+
+// Keyword mentions listed in one notification, so a chatty call can't
+// outgrow the embed field
+const MAX_KEYWORD_HITS: usize = 10;
+
+// A TRANSCRIPT_KEYWORDS phrase and when in the call it was said
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KeywordHit {
+    pub keyword: String,
+    pub start_ms: i32,
+}
+
+impl KeywordHit {
+    // "00:23"
+    pub fn at(&self) -> String {
+        let secs = self.start_ms.max(0) / 1000;
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+
+    // A media fragment, which a browser's player starts from
+    pub fn link(&self, audio_url: &str) -> String {
+        format!("{}#t={}", audio_url, self.start_ms.max(0) / 1000)
+    }
+}
+
+// Replaces the words stored for a call. None are kept while TRANSCRIPTION_KEY
+// is set, as the transcription they'd give away is only stored sealed.
+pub fn store(c: &ProcessorConfig, filename: &str, words: &[Word]) -> Result<()> {
+    if c.transcript_cipher.is_some() || !c.switches.database() {
+        return Ok(());
+    }
+    let rows: Vec<TranscriptWord> = words
+        .iter()
+        .enumerate()
+        .map(|(position, word)| TranscriptWord {
+            call_id: filename.to_string(),
+            position: position as i32,
+            word: word.word.clone(),
+            start_ms: word.start_ms,
+            end_ms: word.end_ms,
+        })
+        .collect();
+
+    let mut connection = c
+        .db_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    connection
+        .transaction(|conn| {
+            delete(transcript_words::table.filter(transcript_words::call_id.eq(filename)))
+                .execute(conn)?;
+            insert_into(transcript_words::table)
+                .values(&rows)
+                .execute(conn)?;
+            diesel::result::QueryResult::Ok(())
+        })
+        .map_err(|e| Error::Database(e.to_string()))
+}
+
+pub fn load(c: &ProcessorConfig, filename: &str) -> Result<Vec<Word>> {
+    let mut connection = c
+        .read_pool
+        .get()
+        .map_err(|e| Error::Database(e.to_string()))?;
+    let rows: Vec<TranscriptWord> = transcript_words::table
+        .filter(transcript_words::call_id.eq(filename))
+        .order(transcript_words::position.asc())
+        .select(TranscriptWord::as_select())
+        .load(&mut connection)
+        .map_err(|e| Error::Database(e.to_string()))?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Word {
+            word: row.word,
+            start_ms: row.start_ms,
+            end_ms: row.end_ms,
+        })
+        .collect())
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|ch| ch.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// Every time a TRANSCRIPT_KEYWORDS phrase was said, in order, ignoring case
+// and punctuation
+pub fn keyword_hits(c: &ProcessorConfig, words: &[Word]) -> Vec<KeywordHit> {
+    let Some(keywords) = &c.env.transcript_keywords else {
+        return Vec::new();
+    };
+    let said: Vec<String> = words.iter().map(|w| normalize(&w.word)).collect();
+
+    let mut hits = Vec::new();
+    for keyword in keywords {
+        let phrase: Vec<String> = keyword
+            .split_whitespace()
+            .map(normalize)
+            .filter(|w| !w.is_empty())
+            .collect();
+        if phrase.is_empty() {
+            continue;
+        }
+        for (i, window) in said.windows(phrase.len()).enumerate() {
+            if window == phrase.as_slice() {
+                hits.push(KeywordHit {
+                    keyword: keyword.trim().to_string(),
+                    start_ms: words[i].start_ms,
+                });
+            }
+        }
+    }
+    hits.sort_by_key(|hit| hit.start_ms);
+    hits.truncate(MAX_KEYWORD_HITS);
+    hits
+}
+
+// The keyword mentions of a stored call, for notifications sent after it was
+// transcribed
+pub fn stored_keyword_hits(c: &ProcessorConfig, filename: &str) -> Vec<KeywordHit> {
+    if c.env.transcript_keywords.is_none() {
+        return Vec::new();
+    }
+    match load(c, filename) {
+        Ok(words) => keyword_hits(c, &words),
+        Err(e) => {
+            warn!(file = %filename, error = %e, "Failed to load transcript words");
+            Vec::new()
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use crate::alerts::{self, Alert, Severity};
+use crate::common::{FileData, UploadData};
+use crate::config::ProcessorConfig;
+use crate::dead_letter;
+use crate::error::{Error, Result};
+use crate::model::ArchiveReason;
+use crate::priority::{self, Priority, PriorityClass};
+use crate::trace::{self, TraceContext};
+use crate::upload::{Stored, complete, discard_incoming, ingest};
+
+use object_store::path::Path;
+use std::{
+    cmp::Ordering as CmpOrdering,
+    collections::{BTreeMap, BinaryHeap},
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+use tracing::{Instrument, error, info, info_span, warn};
+
+// An accepted upload waiting to be stored, transcribed and notified
+pub struct Job {
+    pub files: UploadData,
+    pub archive: Option<ArchiveReason>,
+    pub replay_key: (String, String),
+    pub trace: TraceContext,
+    pub enqueued_at: Instant,
+    // the failed_jobs row this is a retry of
+    pub failed_id: Option<i64>,
+    // the call, once stored, so only notifying it is left
+    pub stored: Option<Stored>,
+}
+
+impl Job {
+    // Drops the audio of a job given up on, unless its call was stored, as
+    // the audio is then the call's
+    pub async fn discard(&self, c: &ProcessorConfig) {
+        if self.stored.is_none() {
+            discard_incoming(c, &self.files).await;
+        }
+    }
+}
+
+// How often the queue is checked against QUEUE_MAX_AGE and QUEUE_MAX_LENGTH
+const BACKLOG_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+struct Queued {
+    priority: Priority,
+    class: PriorityClass,
+    id: u64,
+    job: Job,
+}
+
+// The highest priority first, then the oldest
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Queued {}
+
+impl fmt::Debug for Queued {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Queued")
+            .field("id", &self.id)
+            .field("class", &self.class)
+            .finish()
+    }
+}
+
+// What is known of an accepted upload until it finishes, for GET /jobs
+#[derive(Clone, Debug)]
+pub struct Pending {
+    pub accepted: Instant,
+    pub file: String,
+    // where its audio was streamed to, if it was
+    pub audio_path: Option<String>,
+    pub failed_id: Option<i64>,
+    pub class: PriorityClass,
+    pub running: bool,
+    // stage retries so far and the error that caused the last
+    pub retries: u32,
+    pub last_error: Option<String>,
+}
+
+// Uploads that have started processing and how long they waited in all,
+// per priority class
+#[derive(Debug, Default)]
+pub struct Waits {
+    pub started: AtomicU64,
+    pub waited_micros: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct JobQueue {
+    jobs: Mutex<BinaryHeap<Queued>>,
+    available: Notify,
+    pub capacity: usize,
+    running: AtomicUsize,
+    next_id: AtomicU64,
+    // accepted and not yet finished, oldest first
+    pending: Mutex<BTreeMap<u64, Pending>>,
+    // set on shutdown, after which uploads are turned away
+    closed: AtomicBool,
+    waits: [Waits; PriorityClass::ALL.len()],
+}
+
+impl JobQueue {
+    pub fn new(capacity: usize) -> Self {
+        JobQueue {
+            jobs: Mutex::new(BinaryHeap::new()),
+            available: Notify::new(),
+            capacity,
+            running: AtomicUsize::new(0),
+            next_id: AtomicU64::new(0),
+            pending: Mutex::new(BTreeMap::new()),
+            closed: AtomicBool::new(false),
+            waits: Default::default(),
+        }
+    }
+
+    fn jobs(&self) -> std::sync::MutexGuard<'_, BinaryHeap<Queued>> {
+        self.jobs.lock().expect("queue jobs poisoned")
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, Pending>> {
+        self.pending.lock().expect("queue pending poisoned")
+    }
+
+    // Age of the oldest upload still waiting for or being processed
+    pub fn oldest(&self) -> Option<Duration> {
+        self.pending()
+            .first_key_value()
+            .map(|(_, pending)| pending.accepted.elapsed())
+    }
+
+    // Every upload queued or being processed, oldest first
+    pub fn snapshot(&self) -> Vec<(u64, Pending)> {
+        self.pending()
+            .iter()
+            .map(|(id, pending)| (*id, pending.clone()))
+            .collect()
+    }
+
+    // Streamed audio of uploads queued or being processed
+    pub fn audio_paths(&self) -> Vec<String> {
+        self.pending()
+            .values()
+            .filter_map(|pending| pending.audio_path.clone())
+            .collect()
+    }
+
+    // Takes a job off the queue before it starts. None if it isn't waiting,
+    // because it is already running or has finished.
+    pub fn cancel(&self, id: u64) -> Option<Job> {
+        let mut jobs = self.jobs();
+        let (cancelled, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut *jobs)
+            .into_iter()
+            .partition(|q| q.id == id);
+        *jobs = kept.into();
+        drop(jobs);
+        let queued = cancelled.into_iter().next()?;
+        self.pending().remove(&id);
+        Some(queued.job)
+    }
+
+    fn retried(&self, id: u64, e: &Error) {
+        if let Some(pending) = self.pending().get_mut(&id) {
+            pending.retries += 1;
+            pending.last_error = Some(e.to_string());
+        }
+    }
+
+    pub fn queued(&self) -> usize {
+        self.jobs().len()
+    }
+
+    // Waiting to be processed per priority class
+    pub fn queued_by_class(&self) -> [usize; PriorityClass::ALL.len()] {
+        let mut counts = [0; PriorityClass::ALL.len()];
+        for queued in self.jobs().iter() {
+            counts[queued.class.index()] += 1;
+        }
+        counts
+    }
+
+    pub fn waits(&self, class: PriorityClass) -> &Waits {
+        &self.waits[class.index()]
+    }
+
+    async fn next(&self) -> Queued {
+        loop {
+            if let Some(queued) = self.jobs().pop() {
+                let waits = self.waits(queued.class);
+                waits.started.fetch_add(1, Ordering::Relaxed);
+                waits.waited_micros.fetch_add(
+                    queued.job.enqueued_at.elapsed().as_micros() as u64,
+                    Ordering::Relaxed,
+                );
+                return queued;
+            }
+            self.available.notified().await;
+        }
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    // Queued or being processed
+    pub fn unfinished(&self) -> usize {
+        self.pending().len()
+    }
+}
+
+// Rejects the job instead of waiting when the queue is full, so trunk-recorder
+// sees the backlog and retries later. With QUEUE_PRIORITY emergency and
+// high-priority calls are taken ahead of routine ones.
+pub async fn enqueue(c: &ProcessorConfig, job: Job) -> Result<()> {
+    let queue = &c.queue;
+    let id = queue.next_id.fetch_add(1, Ordering::Relaxed);
+
+    let call_priority = match job.files.deserialize_json() {
+        Ok(meta) => priority::of(c, &meta, job.archive),
+        Err(_) => Priority::FIFO,
+    };
+    queue.pending().insert(
+        id,
+        Pending {
+            accepted: job.enqueued_at,
+            file: job.files.audio.name.clone(),
+            audio_path: match &job.files.audio.data {
+                FileData::Staged(location) => Some(location.to_string()),
+                FileData::Memory(_) => None,
+            },
+            failed_id: job.failed_id,
+            class: call_priority.class,
+            running: false,
+            retries: 0,
+            last_error: None,
+        },
+    );
+    let queued = Queued {
+        priority: if c.env.queue_priority {
+            call_priority
+        } else {
+            Priority::FIFO
+        },
+        class: call_priority.class,
+        id,
+        job,
+    };
+
+    let (e, job) = if queue.closed.load(Ordering::Relaxed) {
+        ("shutting down", queued.job)
+    } else {
+        let mut jobs = queue.jobs();
+        if jobs.len() < queue.capacity {
+            jobs.push(queued);
+            drop(jobs);
+            queue.available.notify_one();
+            return Ok(());
+        }
+        ("upload queue is full", queued.job)
+    };
+    queue.pending().remove(&id);
+    // a retried job keeps its audio for the next attempt
+    if job.failed_id.is_none() {
+        job.discard(c).await;
+    }
+    Err(Error::Unavailable(e.to_string()))
+}
+
+struct Running<'a>(&'a AtomicUsize);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Decides whether a failed stage runs again, waiting QUEUE_RETRY_DELAY first.
+// Storing starts over from the incoming audio, which is only moved once the
+// call's rows are written. Notifying repeats alone, and each destination
+// claims a call before sending it, so none is sent it twice.
+async fn retry(
+    c: &ProcessorConfig,
+    id: u64,
+    stage: &str,
+    file: &str,
+    attempt: &mut u32,
+    e: &Error,
+) -> bool {
+    if *attempt >= c.env.queue_retries {
+        return false;
+    }
+    *attempt += 1;
+    c.queue.retried(id, e);
+    warn!(file = %file, stage, attempt = *attempt, error = %e, "Job stage failed, retrying");
+    tokio::time::sleep(c.env.queue_retry_delay).await;
+    true
+}
+
+async fn process(c: &ProcessorConfig, id: u64, job: &mut Job) -> Result<()> {
+    let file = job.files.audio.name.clone();
+
+    let stored = match job.stored.take() {
+        Some(stored) => stored,
+        None => {
+            let mut attempt = 0;
+            loop {
+                match ingest(c, &mut job.files, job.archive).await {
+                    Err(e) if retry(c, id, "store", &file, &mut attempt, &e).await => continue,
+                    result => break result?,
+                }
+            }
+        }
+    };
+    // the audio now sits where the call was committed
+    job.files.audio.data = FileData::Staged(Path::parse(&stored.meta.call.filename)?);
+    let stored = &*job.stored.insert(stored);
+
+    let mut attempt = 0;
+    loop {
+        match complete(c, stored, job.archive).await {
+            Err(e) if retry(c, id, "notify", &file, &mut attempt, &e).await => continue,
+            result => break result?,
+        }
+    }
+
+    if let Some(cache) = &c.replay_cache {
+        let (name, checksum) = job.replay_key.clone();
+        cache
+            .lock()
+            .expect("replay cache poisoned")
+            .insert(name, checksum);
+    }
+    if let Some(id) = job.failed_id
+        && let Err(e) = dead_letter::resolve(c, id)
+    {
+        warn!(id, error = %e, "Failed to clear retried upload");
+    }
+    Ok(())
+}
+
+async fn run_job(c: &ProcessorConfig, id: u64, mut job: Job) {
+    let waited = job.enqueued_at.elapsed();
+    let start = Instant::now();
+    info!(file = %job.files.audio.name, waited_ms = waited.as_millis(), "Processing queued upload");
+
+    match process(c, id, &mut job).await {
+        Ok(()) => info!(
+            file = %job.files.audio.name,
+            duration_ms = start.elapsed().as_millis(),
+            "Queued upload processed"
+        ),
+        Err(e) => {
+            error!(file = %job.files.audio.name, error = %e, "Queued upload failed");
+            dead_letter::record(c, &job, &e).await;
+        }
+    }
+}
+
+async fn work(c: ProcessorConfig) {
+    loop {
+        let Queued { id, job, .. } = c.queue.next().await;
+        let _running = {
+            c.queue.running.fetch_add(1, Ordering::Relaxed);
+            Running(&c.queue.running)
+        };
+        if let Some(pending) = c.queue.pending().get_mut(&id) {
+            pending.running = true;
+        }
+
+        let ctx = job.trace.clone();
+        let span = info_span!("upload", trace_id = %ctx.trace_id(), span_id = %ctx.span_id());
+        trace::scope(ctx, run_job(&c, id, job))
+            .instrument(span)
+            .await;
+        c.queue.pending().remove(&id);
+    }
+}
+
+// Starts QUEUE_WORKERS tasks draining the upload queue
+pub fn spawn(c: &ProcessorConfig) {
+    for _ in 0..c.env.queue_workers {
+        tokio::spawn(work(c.clone()));
+    }
+    info!(
+        workers = c.env.queue_workers,
+        capacity = c.queue.capacity,
+        "Started upload queue workers"
+    );
+}
+
+// Why the queue is behind its QUEUE_MAX_AGE or QUEUE_MAX_LENGTH objective, if it is
+pub fn backlog(c: &ProcessorConfig) -> Option<String> {
+    let queued = c.queue.queued();
+    if let Some(max) = c.env.queue_max_length
+        && queued > max
+    {
+        return Some(format!("{} uploads queued, more than {}", queued, max));
+    }
+
+    let oldest = c.queue.oldest()?;
+    match c.env.queue_max_age {
+        Some(max) if oldest > max => Some(format!(
+            "oldest upload accepted {}s ago, longer than {}s",
+            oldest.as_secs(),
+            max.as_secs()
+        )),
+        _ => None,
+    }
+}
+
+// Raises UploadQueueBacklog while the queue is behind; the alert resolves on
+// its own once it stops being raised
+pub async fn watch(c: ProcessorConfig) {
+    let mut interval = tokio::time::interval(BACKLOG_CHECK_INTERVAL);
+    let mut behind = false;
+
+    loop {
+        interval.tick().await;
+        let Some(reason) = backlog(&c) else {
+            if behind {
+                info!("Upload queue caught up");
+            }
+            behind = false;
+            continue;
+        };
+
+        if !behind {
+            warn!(reason = %reason, "Upload queue is falling behind");
+        }
+        behind = true;
+        alerts::raise(
+            &c,
+            Alert {
+                name: "UploadQueueBacklog",
+                severity: Severity::Warning,
+                summary: "Uploads are waiting longer than expected to be processed".to_string(),
+                description: reason,
+                labels: Vec::new(),
+                expires_in: BACKLOG_CHECK_INTERVAL * 2,
+            },
+        )
+        .await;
+    }
+}
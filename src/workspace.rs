@@ -0,0 +1,177 @@
+use crate::config::ProcessorConfig;
+use crate::error::{Error, Result};
+
+use std::{
+    path::PathBuf,
+    process::{Output, Stdio},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+use tokio::process::Command;
+use tracing::{info, warn};
+
+pub const PREFIX: &str = "trunk-processor-";
+// Workspaces this old were left behind by a process that stopped mid-call
+const STALE_AFTER: Duration = Duration::from_secs(24 * 3600);
+// How often the workspace is measured while a tool writes into it
+const WATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn io(e: std::io::Error) -> Error {
+    Error::Workspace(e.to_string())
+}
+
+pub fn root(c: &ProcessorConfig) -> PathBuf {
+    c.env
+        .workspace_dir
+        .as_ref()
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+// A private directory for the files one stage works on, such as ffmpeg inputs
+// and outputs. No two workspaces share a directory, nothing in one may grow
+// past WORKSPACE_MAX_SIZE, and the directory is removed however the stage ends.
+#[derive(Debug)]
+pub struct Workspace {
+    path: PathBuf,
+    limit: u64,
+}
+
+impl Workspace {
+    pub async fn create(c: &ProcessorConfig, purpose: &str) -> Result<Self> {
+        let root = root(c);
+        tokio::fs::create_dir_all(&root).await.map_err(io)?;
+        let path = root.join(format!(
+            "{}{}-{}-{}",
+            PREFIX,
+            purpose,
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        // create_dir, not create_dir_all: an existing directory is never reused
+        tokio::fs::create_dir(&path).await.map_err(io)?;
+
+        Ok(Workspace {
+            path,
+            limit: c.env.workspace_max_size as u64,
+        })
+    }
+
+    // Path for `name` inside the workspace, for tools that write their own files
+    pub fn file(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(Error::Workspace(format!("invalid file name {:?}", name)));
+        }
+        Ok(self.path.join(name))
+    }
+
+    pub async fn write(&self, name: &str, data: &[u8]) -> Result<PathBuf> {
+        let used = self.size().await?;
+        if used + data.len() as u64 > self.limit {
+            return Err(self.full(used + data.len() as u64));
+        }
+        let path = self.file(name)?;
+        tokio::fs::write(&path, data).await.map_err(io)?;
+        Ok(path)
+    }
+
+    // Reads a file back, after checking whatever wrote it kept to the limit
+    pub async fn read(&self, name: &str) -> Result<Vec<u8>> {
+        let used = self.size().await?;
+        if used > self.limit {
+            return Err(self.full(used));
+        }
+        tokio::fs::read(self.file(name)?).await.map_err(io)
+    }
+
+    // Runs a tool that writes into the workspace, as Command::output does,
+    // killing it if the workspace grows past the limit before it exits. The
+    // outer error is the limit, the inner one failing to run the tool.
+    pub async fn run(&self, command: &mut Command) -> Result<std::io::Result<Output>> {
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let output = match child {
+            Ok(child) => child.wait_with_output(),
+            Err(e) => return Ok(Err(e)),
+        };
+        tokio::pin!(output);
+
+        let mut watch = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            tokio::select! {
+                result = &mut output => return Ok(result),
+                _ = watch.tick() => {
+                    // returning drops the child, which kills it
+                    let used = self.size().await?;
+                    if used > self.limit {
+                        return Err(self.full(used));
+                    }
+                }
+            }
+        }
+    }
+
+    async fn size(&self) -> Result<u64> {
+        let mut total = 0;
+        let mut entries = tokio::fs::read_dir(&self.path).await.map_err(io)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io)? {
+            total += entry.metadata().await.map_err(io)?.len();
+        }
+        Ok(total)
+    }
+
+    fn full(&self, size: u64) -> Error {
+        Error::Workspace(format!(
+            "{} would hold {} bytes, more than WORKSPACE_MAX_SIZE of {}",
+            self.path.display(),
+            size,
+            self.limit
+        ))
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            warn!(path = %self.path.display(), error = %e, "Failed to remove workspace");
+        }
+    }
+}
+
+// Removes workspaces a crashed or killed process never cleaned up
+pub fn sweep(c: &ProcessorConfig) {
+    let root = root(c);
+    let Ok(entries) = std::fs::read_dir(&root) else {
+        return;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let stale = entry.file_name().to_string_lossy().starts_with(PREFIX)
+            && entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| {
+                    SystemTime::now()
+                        .duration_since(modified)
+                        .is_ok_and(|age| age > STALE_AFTER)
+                });
+        if !stale {
+            continue;
+        }
+        match std::fs::remove_dir_all(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(e) => {
+                warn!(path = %entry.path().display(), error = %e, "Failed to remove stale workspace")
+            }
+        }
+    }
+    if removed > 0 {
+        info!(removed, root = %root.display(), "Removed stale workspaces");
+    }
+}